@@ -0,0 +1,232 @@
+use crate::storage::{rotate_files, rotated_path, unix_now};
+use cratr::ActivityEvent;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::error;
+
+// How many of the latest events are kept in memory for the feed
+const RECENT_EVENTS: usize = 1000;
+// Start a new file once the current one reaches this size
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+// Rotated files kept besides the current one (events.1.jsonl is the newest)
+const KEEP_ROTATED: usize = 3;
+
+struct Current {
+    file: File,
+    size: u64,
+}
+
+// Append-only log of what happened to which file, one JSON object per line, rotated by size
+// like the audit log. The tail of the log is kept in memory so the feed never has to read
+// the files back.
+pub struct ActivityLog {
+    path: PathBuf,
+    max_file_size: u64,
+    current: Mutex<Current>,
+    recent: Mutex<VecDeque<ActivityEvent>>,
+}
+
+impl ActivityLog {
+    // Only reads back as many files, newest first, as it takes to fill the feed
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut recent = VecDeque::with_capacity(RECENT_EVENTS);
+        for file_path in std::iter::once(path.clone()).chain((1..=KEEP_ROTATED).map(|n| rotated_path(&path, n))) {
+            if recent.len() == RECENT_EVENTS {
+                break;
+            }
+            for event in read_events(&file_path)?.into_iter().rev() {
+                if recent.len() == RECENT_EVENTS {
+                    break;
+                }
+                recent.push_front(event);
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_file_size: MAX_FILE_SIZE,
+            current: Mutex::new(Current { file, size }),
+            recent: Mutex::new(recent),
+        })
+    }
+
+    pub fn record(&self, actor: &str, action: &str, path: &str, detail: Option<String>) {
+        let event = ActivityEvent {
+            time: unix_now(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            path: path.to_string(),
+            detail,
+        };
+
+        match serde_json::to_string(&event) {
+            Ok(line) => self.append(line + "\n"),
+            Err(e) => error!("Failed to encode activity event: {}", e),
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_EVENTS {
+            recent.pop_front();
+        }
+        recent.push_back(event);
+    }
+
+    // Newest first, optionally only events about files at or below `path`
+    pub fn recent(&self, limit: usize, path: Option<&str>) -> Vec<ActivityEvent> {
+        self.recent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|event| path.is_none_or(|path| event.path == path || event.path.starts_with(&format!("{}/", path))))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn append(&self, line: String) {
+        let mut current = self.current.lock().unwrap();
+        if current.size + line.len() as u64 > self.max_file_size {
+            let rotated = current.file.flush().and_then(|_| rotate_files(&self.path, KEEP_ROTATED));
+            match rotated.and_then(|_| OpenOptions::new().create(true).append(true).open(&self.path)) {
+                Ok(file) => *current = Current { file, size: 0 },
+                Err(e) => error!("Failed to rotate activity log: {}", e),
+            }
+        }
+        match current.file.write_all(line.as_bytes()) {
+            Ok(_) => current.size += line.len() as u64,
+            Err(e) => error!("Failed to write activity log: {}", e),
+        }
+    }
+}
+
+// Every event in one log file, oldest first. A line cut short by a crash is skipped rather
+// than failing startup.
+fn read_events(path: &Path) -> io::Result<Vec<ActivityEvent>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(event) = serde_json::from_str::<ActivityEvent>(&line?) {
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    struct Scratch {
+        base: PathBuf,
+        log: ActivityLog,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-activity-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&base).unwrap();
+            let log = ActivityLog::open(base.join("events.jsonl")).unwrap();
+            Self { base, log }
+        }
+
+        fn reopen(&self) -> ActivityLog {
+            ActivityLog::open(self.base.join("events.jsonl")).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn paths(events: &[ActivityEvent]) -> Vec<&str> {
+        events.iter().map(|event| event.path.as_str()).collect()
+    }
+
+    #[test]
+    fn records_newest_first_and_survives_a_restart() {
+        let scratch = Scratch::new();
+        scratch.log.record("alice", "upload", "a.txt", None);
+        scratch.log.record("bob", "move", "docs/a.txt", Some("from a.txt".into()));
+
+        let events = scratch.log.recent(10, None);
+        assert_eq!(paths(&events), ["docs/a.txt", "a.txt"]);
+        assert_eq!((events[0].actor.as_str(), events[0].action.as_str()), ("bob", "move"));
+        assert_eq!(events[0].detail.as_deref(), Some("from a.txt"));
+
+        assert_eq!(paths(&scratch.reopen().recent(10, None)), ["docs/a.txt", "a.txt"]);
+    }
+
+    #[test]
+    fn a_torn_line_is_skipped() {
+        let scratch = Scratch::new();
+        scratch.log.record("alice", "upload", "a.txt", None);
+        let mut file = OpenOptions::new().append(true).open(scratch.base.join("events.jsonl")).unwrap();
+        file.write_all(b"{\"time\":1,\"actor\":\"al").unwrap();
+        assert_eq!(paths(&scratch.reopen().recent(10, None)), ["a.txt"]);
+    }
+
+    #[test]
+    fn filters_by_path_and_limit() {
+        let scratch = Scratch::new();
+        scratch.log.record("alice", "upload", "docs/a.txt", None);
+        scratch.log.record("alice", "upload", "docs/sub/b.txt", None);
+        scratch.log.record("alice", "upload", "docsx/c.txt", None);
+        scratch.log.record("alice", "share", "docs", Some("bob=read".into()));
+
+        assert_eq!(paths(&scratch.log.recent(10, Some("docs"))), ["docs", "docs/sub/b.txt", "docs/a.txt"]);
+        assert_eq!(paths(&scratch.log.recent(10, Some("docs/a.txt"))), ["docs/a.txt"]);
+        assert_eq!(paths(&scratch.log.recent(2, None)), ["docs", "docsx/c.txt"]);
+        assert!(scratch.log.recent(10, Some("doc")).is_empty());
+    }
+
+    #[test]
+    fn rotates_by_size_and_reads_back_across_files() {
+        let mut scratch = Scratch::new();
+        scratch.log.max_file_size = 500;
+        for n in 0..200 {
+            scratch.log.record("alice", "upload", &format!("{}.txt", n), None);
+        }
+
+        assert!(rotated_path(&scratch.log.path, KEEP_ROTATED).exists());
+        assert!(!rotated_path(&scratch.log.path, KEEP_ROTATED + 1).exists());
+        for n in 0..=KEEP_ROTATED {
+            let path = if n == 0 { scratch.log.path.clone() } else { rotated_path(&scratch.log.path, n) };
+            assert!(std::fs::metadata(path).unwrap().len() <= 500);
+        }
+
+        // What's left on disk is the newest stretch, in order, with nothing lost in between
+        let reopened = scratch.reopen();
+        let kept = reopened.recent(RECENT_EVENTS, None);
+        assert!(kept.len() > 5 && kept.len() < 200);
+        let expected: Vec<String> = (200 - kept.len()..200).rev().map(|n| format!("{}.txt", n)).collect();
+        assert_eq!(paths(&kept), expected);
+    }
+
+    #[test]
+    fn keeps_only_the_latest_events_in_memory() {
+        let scratch = Scratch::new();
+        for n in 0..RECENT_EVENTS + 10 {
+            scratch.log.record("alice", "upload", &format!("{}.txt", n), None);
+        }
+        for log in [&scratch.log, &scratch.reopen()] {
+            let events = log.recent(usize::MAX, None);
+            assert_eq!(events.len(), RECENT_EVENTS);
+            assert_eq!(events[0].path, format!("{}.txt", RECENT_EVENTS + 9));
+            assert_eq!(events[RECENT_EVENTS - 1].path, "10.txt");
+        }
+    }
+}
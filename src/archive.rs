@@ -0,0 +1,716 @@
+use crate::storage::{display_name, folder_of, join_relative, move_file, resolve, sanitize_filename, walk_files, Resolved};
+use actix_web::web::Bytes;
+use cratr::ArchiveEntryInfo;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::Stream;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// Archive data is handed to the response in chunks of roughly this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "zip" => Some(Self::Zip),
+            "tar.gz" | "tgz" | "tar" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarGz => "application/gzip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarGz => "tar.gz",
+        }
+    }
+}
+
+// A file to put in the archive: where it lives on disk and the name it gets inside
+pub struct ArchiveEntry {
+    pub source: PathBuf,
+    pub name: String,
+    pub size: u64,
+}
+
+// Expand the requested files and folders into a flat list of archive entries.
+// Folders keep their structure under their own name; UUID prefixes are stripped.
+// Files for which `readable` returns false are left out (or reported missing, if asked for
+// by name).
+pub fn collect_entries(
+    upload_dir: &str,
+    paths: &[String],
+    mut readable: impl FnMut(&str) -> bool,
+) -> Result<Vec<ArchiveEntry>, String> {
+    let mut entries = Vec::new();
+    let mut used_names = HashSet::new();
+
+    for path in paths {
+        let Resolved { path: source, relative } = resolve(upload_dir, path).ok_or_else(|| format!("Invalid path: {}", path))?;
+
+        if source.is_dir() {
+            let folder_name = relative.rsplit('/').next().unwrap_or_default().to_string();
+            for file in walk_files(&source) {
+                if !readable(&join_relative(&relative, &file.path)) {
+                    continue;
+                }
+                let inner_name = join_relative(&folder_of(&file.path), &display_name(&file.path));
+                entries.push(ArchiveEntry {
+                    source: source.join(&file.path),
+                    name: unique_name(&mut used_names, join_relative(&folder_name, &inner_name)),
+                    size: file.size,
+                });
+            }
+        } else if source.is_file() && readable(&relative) {
+            let size = std::fs::metadata(&source).map(|m| m.len()).unwrap_or(0);
+            entries.push(ArchiveEntry {
+                source,
+                name: unique_name(&mut used_names, display_name(&relative)),
+                size,
+            });
+        } else {
+            return Err(format!("File not found: {}", path));
+        }
+    }
+
+    Ok(entries)
+}
+
+// Two uploads can share a display name, so later ones become "name (1).ext", "name (2).ext", ...
+fn unique_name(used_names: &mut HashSet<String>, name: String) -> String {
+    if used_names.insert(name.clone()) {
+        return name;
+    }
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !stem.ends_with('/') => (stem.to_string(), format!(".{}", extension)),
+        _ => (name.clone(), String::new()),
+    };
+
+    let mut counter = 1;
+    loop {
+        let candidate = format!("{} ({}){}", stem, counter, extension);
+        if used_names.insert(candidate.clone()) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+// Build the archive on a blocking thread and stream it out as it is written, so nothing
+// is ever staged in a temporary file
+pub fn stream_archive(entries: Vec<ArchiveEntry>, format: ArchiveFormat) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(8);
+
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter::new(tx.clone());
+        let result = match format {
+            ArchiveFormat::Zip => write_zip(writer, &entries),
+            ArchiveFormat::TarGz => write_tar_gz(writer, &entries),
+        };
+
+        if let Err(e) = result {
+            error!("Failed to build archive: {}", e);
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+
+    futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) })
+}
+
+fn write_zip(writer: ChannelWriter, entries: &[ArchiveEntry]) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+
+    for entry in entries {
+        // Media and archives are already compressed; deflating them again only burns CPU
+        let (file_type, _) = crate::get_file_type_and_preview(&entry.name);
+        let method = match file_type.as_str() {
+            "image" | "video" | "audio" | "archive" => CompressionMethod::Stored,
+            _ => CompressionMethod::Deflated,
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .large_file(entry.size >= u32::MAX as u64);
+
+        zip.start_file(entry.name.as_str(), options).map_err(io::Error::other)?;
+        let mut file = std::fs::File::open(&entry.source)?;
+        io::copy(&mut file, &mut zip)?;
+    }
+
+    let mut writer = zip.finish().map_err(io::Error::other)?.into_inner();
+    writer.flush()
+}
+
+fn write_tar_gz(writer: ChannelWriter, entries: &[ArchiveEntry]) -> io::Result<()> {
+    let mut builder = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+
+    for entry in entries {
+        builder.append_path_with_name(&entry.source, &entry.name)?;
+    }
+
+    let mut writer = builder.into_inner()?.finish()?;
+    writer.flush()
+}
+
+// `Write` adapter that forwards buffered chunks to the HTTP response stream
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            tx,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE)));
+        // The receiver only goes away when the client disconnects
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+// --- Reading archives that are already in the store ---
+
+// At most this many entries are returned when listing an archive
+const MAX_LISTED_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarBz2,
+}
+
+impl ArchiveKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.bz2") || name.ends_with(".tbz2") || name.ends_with(".tbz") {
+            Some(Self::TarBz2)
+        } else {
+            None
+        }
+    }
+
+    // Name of the folder an archive extracts into: the display name without its archive suffix
+    pub fn folder_name(name: &str) -> String {
+        let lower = name.to_lowercase();
+        for suffix in [".tar.gz", ".tar.bz2", ".tgz", ".tbz2", ".tbz", ".tar", ".zip"] {
+            if lower.ends_with(suffix) && name.len() > suffix.len() {
+                return name[..name.len() - suffix.len()].to_string();
+            }
+        }
+        format!("{}-extracted", name)
+    }
+}
+
+fn open_tar(path: &Path, kind: ArchiveKind) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = std::fs::File::open(path)?;
+    let reader: Box<dyn Read> = match kind {
+        ArchiveKind::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+        ArchiveKind::TarBz2 => Box::new(bzip2::read::BzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+fn entry_info(name: String, size: u64, is_dir: bool) -> ArchiveEntryInfo {
+    let (file_type, can_preview) = if is_dir {
+        ("folder".to_string(), false)
+    } else {
+        crate::get_file_type_and_preview(&name)
+    };
+    ArchiveEntryInfo {
+        name,
+        size,
+        is_dir,
+        file_type,
+        can_preview,
+    }
+}
+
+// List the entries of a zip or tar archive without extracting anything
+pub fn list_entries(path: &Path, kind: ArchiveKind) -> io::Result<(Vec<ArchiveEntryInfo>, bool)> {
+    let mut entries = Vec::new();
+
+    if kind == ArchiveKind::Zip {
+        let mut zip = ZipArchive::new(std::fs::File::open(path)?).map_err(io::Error::other)?;
+        for index in 0..zip.len() {
+            if entries.len() >= MAX_LISTED_ENTRIES {
+                return Ok((entries, true));
+            }
+            let file = zip.by_index_raw(index).map_err(io::Error::other)?;
+            entries.push(entry_info(file.name().to_string(), file.size(), file.is_dir()));
+        }
+    } else {
+        let mut archive = open_tar(path, kind)?;
+        for entry in archive.entries()? {
+            if entries.len() >= MAX_LISTED_ENTRIES {
+                return Ok((entries, true));
+            }
+            let entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            entries.push(entry_info(name, entry.size(), entry.header().entry_type().is_dir()));
+        }
+    }
+
+    Ok((entries, false))
+}
+
+// Copy a single inner file into `writer`, reading at most `limit` bytes.
+// Returns false if the archive has no regular file with that name.
+pub fn copy_entry(path: &Path, kind: ArchiveKind, name: &str, writer: &mut dyn Write, limit: u64) -> io::Result<bool> {
+    if kind == ArchiveKind::Zip {
+        let mut zip = ZipArchive::new(std::fs::File::open(path)?).map_err(io::Error::other)?;
+        let mut file = match zip.by_name(name) {
+            Ok(file) if file.is_file() => file,
+            Ok(_) | Err(zip::result::ZipError::FileNotFound) => return Ok(false),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        io::copy(&mut (&mut file).take(limit), writer)?;
+        return Ok(true);
+    }
+
+    let mut archive = open_tar(path, kind)?;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() && entry.path()?.to_string_lossy() == name {
+            io::copy(&mut entry.take(limit), writer)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Stream one inner file of an archive to the client
+pub fn stream_entry(path: PathBuf, kind: ArchiveKind, name: String) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(8);
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter::new(tx.clone());
+        let result = copy_entry(&path, kind, &name, &mut writer, u64::MAX).and_then(|_| writer.flush());
+        if let Err(e) = result {
+            warn!("Failed to read archive entry {}: {}", name, e);
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+
+    futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) })
+}
+
+// A file written during extraction, relative to the upload directory
+pub struct ExtractedFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    // Where the entry was written
+    pub location: PathBuf,
+}
+
+// Only plain relative paths survive; anything absolute or containing `..` (zip-slip) is rejected.
+// Each component goes through the same sanitizer as uploaded filenames.
+fn safe_entry_path(name: &str) -> Option<PathBuf> {
+    let mut safe = PathBuf::new();
+    for component in Path::new(&name.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => {
+                let part = sanitize_filename(&part.to_string_lossy());
+                if part.is_empty() {
+                    return None;
+                }
+                safe.push(part);
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if safe.as_os_str().is_empty() {
+        None
+    } else {
+        Some(safe)
+    }
+}
+
+// Zip and tar archives can both hold several entries with the same name, or a file and a
+// folder of the same name. The destination starts out empty, so anything already there came
+// from an earlier entry; later ones get "-1", "-2", ... instead of overwriting it or failing,
+// like the folder name itself. A folder renamed this way stays renamed for the entries after.
+fn unique_entry_path(destination: &Path, relative: &Path) -> PathBuf {
+    let parts: Vec<&std::ffi::OsStr> = relative.iter().collect();
+    let mut unique = PathBuf::new();
+    for (index, part) in parts.iter().enumerate() {
+        let last = index + 1 == parts.len();
+        // A folder can go on into one an earlier entry made, but never into a file
+        let taken = |candidate: &Path| {
+            let path = destination.join(candidate);
+            if last { path.symlink_metadata().is_ok() } else { path.symlink_metadata().is_ok_and(|meta| !meta.is_dir()) }
+        };
+        let mut candidate = unique.join(part);
+        if taken(&candidate) {
+            let name = Path::new(part);
+            let (stem, extension) = match (last, name.file_stem(), name.extension()) {
+                (true, Some(stem), Some(extension)) => (stem.to_string_lossy(), format!(".{}", extension.to_string_lossy())),
+                _ => (part.to_string_lossy(), String::new()),
+            };
+            candidate = (1..)
+                .map(|counter| unique.join(format!("{}-{}{}", stem, counter, extension)))
+                .find(|candidate| !taken(candidate))
+                .unwrap_or(candidate);
+        }
+        unique = candidate;
+    }
+    unique
+}
+
+fn write_extracted(
+    reader: &mut dyn Read,
+    destination: &Path,
+    relative: &Path,
+    folder: &str,
+    budget: &mut u64,
+    extracted: &mut Vec<ExtractedFile>,
+) -> io::Result<()> {
+    let relative = &unique_entry_path(destination, relative);
+    let target = destination.join(relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Read one byte past the remaining budget so an oversized entry is detected
+    let mut file = std::fs::File::create(&target)?;
+    let mut hasher = Sha256::new();
+    let mut limited = reader.take(budget.saturating_add(1));
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut size = 0u64;
+    loop {
+        let read = limited.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        size += read as u64;
+        if size > *budget {
+            return Err(io::Error::other("Archive contents exceed the remaining storage space"));
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+    *budget -= size;
+
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    extracted.push(ExtractedFile {
+        path: join_relative(folder, &relative),
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+        location: target,
+    });
+    Ok(())
+}
+
+// Expand an archive into `destination` (which must not exist yet). `folder` is the destination
+// relative to the upload directory, `budget` the number of bytes we may write and `max_files`
+// the number of files, so an archive of millions of empty entries can't use up the inodes.
+// Links and other special entries are skipped. On failure the partially extracted folder is
+// removed again.
+pub fn extract(
+    archive_path: &Path,
+    kind: ArchiveKind,
+    destination: &Path,
+    folder: &str,
+    mut budget: u64,
+    max_files: usize,
+) -> io::Result<Vec<ExtractedFile>> {
+    std::fs::create_dir_all(destination)?;
+    let mut extracted = Vec::new();
+
+    let too_many = || io::Error::other(format!("Archive holds more than {} files", max_files));
+    let result = (|| -> io::Result<()> {
+        if kind == ArchiveKind::Zip {
+            let mut zip = ZipArchive::new(std::fs::File::open(archive_path)?).map_err(io::Error::other)?;
+            for index in 0..zip.len() {
+                let mut file = zip.by_index(index).map_err(io::Error::other)?;
+                if !file.is_file() || file.enclosed_name().is_none() {
+                    continue;
+                }
+                let Some(relative) = safe_entry_path(file.name()) else {
+                    continue;
+                };
+                if extracted.len() >= max_files {
+                    return Err(too_many());
+                }
+                write_extracted(&mut file, destination, &relative, folder, &mut budget, &mut extracted)?;
+            }
+        } else {
+            let mut archive = open_tar(archive_path, kind)?;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().to_string();
+                let Some(relative) = safe_entry_path(&name) else {
+                    continue;
+                };
+                if extracted.len() >= max_files {
+                    return Err(too_many());
+                }
+                write_extracted(&mut entry, destination, &relative, folder, &mut budget, &mut extracted)?;
+            }
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(destination);
+        return Err(e);
+    }
+    Ok(extracted)
+}
+
+// Move the extracted files that were kept from `staging` into `destination`, which becomes
+// the new folder, and drop whatever is left in `staging`. If a move fails the new folder is
+// removed again so it never shows up half filled.
+pub fn install(staging: &Path, destination: &Path, files: &[ExtractedFile]) -> io::Result<()> {
+    let result = (|| -> io::Result<()> {
+        std::fs::create_dir_all(destination)?;
+        for file in files {
+            let relative = file.location.strip_prefix(staging).map_err(io::Error::other)?;
+            let target = destination.join(relative);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            move_file(&file.location, &target)?;
+        }
+        Ok(())
+    })();
+
+    let _ = std::fs::remove_dir_all(staging);
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(destination);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    // A scratch directory for archives and the folders they are extracted into, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-archive-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&base).unwrap();
+            Self { base }
+        }
+
+        fn zip(&self, build: impl FnOnce(&mut ZipWriter<std::fs::File>)) -> PathBuf {
+            let path = self.base.join("test.zip");
+            let mut zip = ZipWriter::new(std::fs::File::create(&path).unwrap());
+            build(&mut zip);
+            zip.finish().unwrap();
+            path
+        }
+
+        fn tar(&self, build: impl FnOnce(&mut tar::Builder<std::fs::File>)) -> PathBuf {
+            let path = self.base.join("test.tar");
+            let mut builder = tar::Builder::new(std::fs::File::create(&path).unwrap());
+            build(&mut builder);
+            builder.finish().unwrap();
+            path
+        }
+
+        fn extract(&self, archive: &Path, kind: ArchiveKind, max_files: usize) -> io::Result<Vec<String>> {
+            let files = extract(archive, kind, &self.base.join("out"), "out", u64::MAX, max_files)?;
+            let mut paths: Vec<String> = files.into_iter().map(|file| file.path).collect();
+            paths.sort();
+            Ok(paths)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn add_zip_file(zip: &mut ZipWriter<std::fs::File>, name: &str, contents: &[u8]) {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+
+    fn add_tar_file(builder: &mut tar::Builder<std::fs::File>, name: &str, contents: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, contents).unwrap();
+    }
+
+    #[test]
+    fn entry_paths_stay_relative() {
+        assert_eq!(safe_entry_path("docs/report.txt"), Some(PathBuf::from("docs/report.txt")));
+        assert_eq!(safe_entry_path("./docs/./report.txt"), Some(PathBuf::from("docs/report.txt")));
+        // Backslashes are separators, as Windows tools write them
+        assert_eq!(safe_entry_path("docs\\report.txt"), Some(PathBuf::from("docs/report.txt")));
+        // A drive letter is just a folder name without its colon
+        assert_eq!(safe_entry_path("C:\\Windows\\win.ini"), Some(PathBuf::from("C/Windows/win.ini")));
+
+        for evil in [
+            "../evil.txt",
+            "docs/../../evil.txt",
+            "docs/..",
+            "..\\evil.txt",
+            "docs\\..\\..\\evil.txt",
+            "/etc/passwd",
+            "\\etc\\passwd",
+            "//server/share/evil.txt",
+            "",
+            ".",
+            "docs/\u{202E}/x",
+        ] {
+            assert_eq!(safe_entry_path(evil), None, "{:?}", evil);
+        }
+    }
+
+    #[test]
+    fn zip_slip_entries_are_skipped() {
+        let scratch = Scratch::new();
+        let archive = scratch.zip(|zip| {
+            add_zip_file(zip, "../evil.txt", b"evil");
+            add_zip_file(zip, "/absolute.txt", b"evil");
+            add_zip_file(zip, "..\\backslash.txt", b"evil");
+            add_zip_file(zip, "docs/report.txt", b"fine");
+        });
+        assert_eq!(scratch.extract(&archive, ArchiveKind::Zip, 10).unwrap(), ["out/docs/report.txt"]);
+        assert!(!scratch.base.join("evil.txt").exists());
+        assert!(!scratch.base.join("backslash.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_entries_are_skipped() {
+        let scratch = Scratch::new();
+        let archive = scratch.tar(|builder| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, "passwd", "/etc/passwd").unwrap();
+            add_tar_file(builder, "passwd", b"plain file");
+        });
+        assert_eq!(scratch.extract(&archive, ArchiveKind::Tar, 10).unwrap(), ["out/passwd"]);
+        let written = scratch.base.join("out/passwd");
+        assert!(!written.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(written).unwrap(), b"plain file");
+
+        let scratch = Scratch::new();
+        let archive = scratch.zip(|zip| {
+            zip.add_symlink("link", "/etc/passwd", SimpleFileOptions::default()).unwrap();
+            add_zip_file(zip, "file.txt", b"fine");
+        });
+        assert_eq!(scratch.extract(&archive, ArchiveKind::Zip, 10).unwrap(), ["out/file.txt"]);
+        assert!(scratch.base.join("out/link").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn duplicate_names_are_kept_side_by_side() {
+        let scratch = Scratch::new();
+        let archive = scratch.tar(|builder| {
+            add_tar_file(builder, "notes.txt", b"first");
+            add_tar_file(builder, "notes.txt", b"second");
+            add_tar_file(builder, "notes.txt", b"third");
+        });
+        assert_eq!(
+            scratch.extract(&archive, ArchiveKind::Tar, 10).unwrap(),
+            ["out/notes-1.txt", "out/notes-2.txt", "out/notes.txt"]
+        );
+        assert_eq!(std::fs::read(scratch.base.join("out/notes.txt")).unwrap(), b"first");
+        assert_eq!(std::fs::read(scratch.base.join("out/notes-2.txt")).unwrap(), b"third");
+    }
+
+    #[test]
+    fn files_and_folders_of_the_same_name_both_survive() {
+        let scratch = Scratch::new();
+        let archive = scratch.tar(|builder| {
+            add_tar_file(builder, "a", b"file a");
+            add_tar_file(builder, "a/b", b"file b");
+            add_tar_file(builder, "a/c", b"file c");
+            add_tar_file(builder, "d/e", b"file e");
+            add_tar_file(builder, "d", b"file d");
+        });
+        assert_eq!(
+            scratch.extract(&archive, ArchiveKind::Tar, 10).unwrap(),
+            ["out/a", "out/a-1/b", "out/a-1/c", "out/d-1", "out/d/e"]
+        );
+        assert_eq!(std::fs::read(scratch.base.join("out/a")).unwrap(), b"file a");
+        assert_eq!(std::fs::read(scratch.base.join("out/d-1")).unwrap(), b"file d");
+    }
+
+    #[test]
+    fn too_many_files_extract_nothing() {
+        let scratch = Scratch::new();
+        let archive = scratch.zip(|zip| {
+            for index in 0..4 {
+                add_zip_file(zip, &format!("empty-{}", index), b"");
+            }
+        });
+        assert_eq!(scratch.extract(&archive, ArchiveKind::Zip, 4).unwrap().len(), 4);
+        std::fs::remove_dir_all(scratch.base.join("out")).unwrap();
+
+        let error = scratch.extract(&archive, ArchiveKind::Zip, 3).unwrap_err();
+        assert!(error.to_string().contains("more than 3 files"), "{}", error);
+        assert!(!scratch.base.join("out").exists());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::storage::{rotate_files, rotated_path, unix_now};
+use cratr::AuditEntry;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::error;
+
+// Start a new file once the current one reaches this size
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+// Rotated files kept besides the current one (audit.1.jsonl is the newest)
+const KEEP_ROTATED: usize = 5;
+
+struct Current {
+    file: File,
+    size: u64,
+}
+
+// Append-only security log: who logged in (or failed to) from where, and who touched
+// which file. One JSON object per line, rotated by size.
+pub struct AuditLog {
+    path: PathBuf,
+    max_file_size: u64,
+    current: Mutex<Current>,
+}
+
+// What to look for when reading the log back
+#[derive(Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor.as_ref().is_none_or(|actor| entry.actor.as_ref() == Some(actor))
+            && self.action.as_ref().is_none_or(|action| entry.action == *action)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time <= until)
+    }
+}
+
+impl AuditLog {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_file_size: MAX_FILE_SIZE,
+            current: Mutex::new(Current { file, size }),
+        })
+    }
+
+    // Write one entry. Failures are reported but never interrupt the request being audited.
+    pub fn record(&self, actor: Option<&str>, action: &str, ip: Option<String>, target: Option<&str>, success: bool, detail: Option<String>) {
+        let entry = AuditEntry {
+            time: unix_now(),
+            actor: actor.map(str::to_string),
+            action: action.to_string(),
+            ip,
+            target: target.map(str::to_string),
+            success,
+            detail,
+        };
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line + "\n",
+            Err(e) => {
+                error!("Failed to encode audit entry: {}", e);
+                return;
+            }
+        };
+
+        let mut current = self.current.lock().unwrap();
+        if current.size + line.len() as u64 > self.max_file_size {
+            if let Err(e) = self.rotate(&mut current) {
+                error!("Failed to rotate audit log: {}", e);
+            }
+        }
+        match current.file.write_all(line.as_bytes()) {
+            Ok(_) => current.size += line.len() as u64,
+            Err(e) => error!("Failed to write audit log: {}", e),
+        }
+    }
+
+    // Newest first, reading rotated files only as far back as needed
+    pub fn query(&self, filter: &AuditFilter, limit: usize) -> io::Result<Vec<AuditEntry>> {
+        // Open every file under the lock so a rotation can't shuffle them mid-read, then read
+        // without it: record() runs inside request handlers and must not wait on a long query.
+        // Open handles survive a later rename, and the current file is read only up to the
+        // size it had here, so entries appended meanwhile don't show up half-written.
+        let files = {
+            let mut current = self.current.lock().unwrap();
+            current.file.flush()?;
+            let mut files = vec![(File::open(&self.path)?, Some(current.size))];
+            for n in 1..=KEEP_ROTATED {
+                match File::open(self.rotated_path(n)) {
+                    Ok(file) => files.push((file, None)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            files
+        };
+        let mut found = Vec::new();
+
+        for (file, size) in files {
+            let reader: Box<dyn Read> = match size {
+                Some(size) => Box::new(file.take(size)),
+                None => Box::new(file),
+            };
+            let mut entries: Vec<AuditEntry> = BufReader::new(reader)
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+                .filter(|entry| filter.matches(entry))
+                .collect();
+            entries.reverse();
+            found.extend(entries);
+
+            if found.len() >= limit {
+                break;
+            }
+        }
+
+        found.truncate(limit);
+        Ok(found)
+    }
+
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        current.file.flush()?;
+        rotate_files(&self.path, KEEP_ROTATED)?;
+        current.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        current.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        rotated_path(&self.path, n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    struct Scratch {
+        base: PathBuf,
+        log: AuditLog,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-audit-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&base).unwrap();
+            let log = AuditLog::open(base.join("audit.jsonl")).unwrap();
+            Self { base, log }
+        }
+
+        fn targets(&self, filter: &AuditFilter, limit: usize) -> Vec<String> {
+            self.log.query(filter, limit).unwrap().into_iter().filter_map(|entry| entry.target).collect()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn reads_back_newest_first() {
+        let scratch = Scratch::new();
+        scratch.log.record(Some("alice"), "login", Some("192.0.2.1".into()), None, true, None);
+        scratch.log.record(Some("alice"), "upload", None, Some("a.txt"), true, None);
+        scratch.log.record(None, "download", None, Some("b.txt"), false, Some("no access".into()));
+
+        let entries = scratch.log.query(&AuditFilter::default(), 10).unwrap();
+        let actions: Vec<&str> = entries.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, ["download", "upload", "login"]);
+        assert!(!entries[0].success && entries[0].actor.is_none());
+        assert_eq!(entries[0].detail.as_deref(), Some("no access"));
+        assert_eq!(entries[2].ip.as_deref(), Some("192.0.2.1"));
+
+        // Entries outlive the process that wrote them
+        let reopened = AuditLog::open(scratch.base.join("audit.jsonl")).unwrap();
+        assert_eq!(reopened.query(&AuditFilter::default(), 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn filters_by_actor_action_and_time() {
+        let scratch = Scratch::new();
+        scratch.log.record(Some("alice"), "upload", None, Some("a.txt"), true, None);
+        scratch.log.record(Some("bob"), "upload", None, Some("b.txt"), true, None);
+        scratch.log.record(Some("alice"), "delete", None, Some("c.txt"), true, None);
+
+        let by_alice = AuditFilter { actor: Some("alice".into()), ..AuditFilter::default() };
+        assert_eq!(scratch.targets(&by_alice, 10), ["c.txt", "a.txt"]);
+        let uploads = AuditFilter { action: Some("upload".into()), ..AuditFilter::default() };
+        assert_eq!(scratch.targets(&uploads, 10), ["b.txt", "a.txt"]);
+        let both = AuditFilter { actor: Some("alice".into()), action: Some("upload".into()), ..AuditFilter::default() };
+        assert_eq!(scratch.targets(&both, 10), ["a.txt"]);
+
+        let now = unix_now();
+        let recent = AuditFilter { since: Some(now - 60), until: Some(now + 60), ..AuditFilter::default() };
+        assert_eq!(scratch.targets(&recent, 10).len(), 3);
+        let future = AuditFilter { since: Some(now + 60), ..AuditFilter::default() };
+        assert!(scratch.targets(&future, 10).is_empty());
+        let past = AuditFilter { until: Some(now - 60), ..AuditFilter::default() };
+        assert!(scratch.targets(&past, 10).is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let scratch = Scratch::new();
+        for n in 0..5 {
+            scratch.log.record(Some("alice"), "upload", None, Some(&format!("{}.txt", n)), true, None);
+        }
+        assert_eq!(scratch.targets(&AuditFilter::default(), 2), ["4.txt", "3.txt"]);
+        assert!(scratch.targets(&AuditFilter::default(), 0).is_empty());
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_a_bounded_history() {
+        let mut scratch = Scratch::new();
+        scratch.log.max_file_size = 400;
+        for n in 0..100 {
+            scratch.log.record(Some("alice"), "upload", None, Some(&format!("{}.txt", n)), true, None);
+        }
+
+        assert!(scratch.log.rotated_path(KEEP_ROTATED).exists());
+        assert!(!scratch.log.rotated_path(KEEP_ROTATED + 1).exists());
+        for n in 0..=KEEP_ROTATED {
+            let path = if n == 0 { scratch.log.path.clone() } else { scratch.log.rotated_path(n) };
+            assert!(std::fs::metadata(path).unwrap().len() <= 400);
+        }
+
+        // Queries run across the files in order, and the oldest entries are gone
+        let all = scratch.targets(&AuditFilter::default(), 1000);
+        assert!(all.len() < 100);
+        let expected: Vec<String> = (100 - all.len()..100).rev().map(|n| format!("{}.txt", n)).collect();
+        assert_eq!(all, expected);
+        // A limit that ends inside a rotated file
+        assert_eq!(scratch.targets(&AuditFilter::default(), all.len() - 1), expected[..all.len() - 1]);
+    }
+}
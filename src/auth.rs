@@ -0,0 +1,161 @@
+use crate::users::UserStore;
+use cratr::Role;
+use std::sync::Arc;
+use tracing::debug;
+
+// What a provider made of a username and password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    // The password is right and the account may use cratr with this role
+    Accepted(Role),
+    // The password is right, but the account isn't allowed in (e.g. in no mapped group)
+    NoRole,
+    // The provider knows the account and the password is wrong
+    Rejected,
+    // Not an account of this provider; the next one gets to decide
+    Unknown,
+}
+
+// Somewhere passwords can be checked. Providers are asked in order and the first one that
+// knows the account decides. Checks may block on network or slow hashing, so they run off
+// the async workers.
+pub trait AuthProvider: Send + Sync {
+    // Short name recorded with accounts the provider created, e.g. "ldap"
+    fn name(&self) -> &'static str;
+
+    // Err means the provider couldn't give an answer (e.g. the directory is down)
+    fn authenticate(&self, username: &str, password: &str) -> Result<Outcome, String>;
+
+    // The name the provider's accounts are stored under, for a name as typed at login
+    fn canonical_username(&self, username: &str) -> String {
+        username.to_string()
+    }
+}
+
+// The account given on the command line and the accounts an admin added
+pub struct LocalProvider {
+    username: String,
+    password: String,
+    users: Arc<UserStore>,
+}
+
+impl LocalProvider {
+    pub fn new(username: &str, password: &str, users: Arc<UserStore>) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            users,
+        }
+    }
+
+    fn is_admin(&self, username: &str) -> bool {
+        canonical_username(username) == canonical_username(&self.username)
+    }
+}
+
+impl AuthProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Outcome, String> {
+        // Any spelling of the built-in admin's name is theirs, so it never reaches a directory
+        // that lower-cases names and may hold an entry of the same name
+        if self.is_admin(username) {
+            return Ok(if same_secret(password, &self.password) { Outcome::Accepted(Role::Admin) } else { Outcome::Rejected });
+        }
+        if !self.users.is_local(username) {
+            return Ok(Outcome::Unknown);
+        }
+        // Argon2 hashes are slow to check on purpose
+        match self.users.role(username) {
+            Some(role) if self.users.verify_password(username, password) => Ok(Outcome::Accepted(role)),
+            _ => Ok(Outcome::Rejected),
+        }
+    }
+
+    fn canonical_username(&self, username: &str) -> String {
+        if self.is_admin(username) {
+            self.username.clone()
+        } else {
+            username.to_string()
+        }
+    }
+}
+
+// A user some outside party (an identity provider or a proxy) vouches for
+#[derive(Debug)]
+pub struct Identity {
+    pub username: String,
+    // Stable ID of the person behind the name, if the party vouching has one. An account is
+    // tied to the first one it sees, so a reused or renamed name can't take it over.
+    pub subject: Option<String>,
+    // None if they're in none of the mapped groups and there is no default role
+    pub role: Option<Role>,
+}
+
+// Compare without stopping at the first difference, so timing doesn't give the secret away
+pub fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Directories and identity providers match names regardless of case, so "Alice" and "alice"
+// must end up as the same cratr account, with the same 2FA secret and the same throttle
+pub fn canonical_username(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+// Command-line value of a role, e.g. "viewer"
+pub fn parse_role(value: &str) -> Result<Role, String> {
+    Role::ALL
+        .into_iter()
+        .find(|role| role.as_str() == value.trim())
+        .ok_or_else(|| format!("unknown role \"{}\" (admin, editor, viewer or uploader)", value))
+}
+
+// Command-line mapping of an external group to a role, e.g. "staff=editor"
+pub fn parse_group_role(value: &str) -> Result<(String, Role), String> {
+    let (group, role) = value.split_once('=').ok_or("expected GROUP=ROLE")?;
+    Ok((group.trim().to_string(), parse_role(role)?))
+}
+
+// Role for someone in `groups`. Group names are compared without case, and someone in
+// several mapped groups gets the first of admin, editor, viewer, uploader.
+pub fn role_for_groups(mapping: &[(String, Role)], groups: &[String]) -> Option<Role> {
+    mapping
+        .iter()
+        .filter(|(group, _)| groups.iter().any(|name| name.eq_ignore_ascii_case(group)))
+        .map(|(_, role)| *role)
+        .min_by_key(|role| Role::ALL.iter().position(|r| r == role))
+}
+
+// Every configured provider, local accounts first
+pub struct Providers(Vec<Box<dyn AuthProvider>>);
+
+impl Providers {
+    pub fn new(providers: Vec<Box<dyn AuthProvider>>) -> Self {
+        Self(providers)
+    }
+
+    // The outcome, the provider that gave it and the account name as that provider stores
+    // it. Unknown (from the last provider) if no provider knows the account.
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<(Outcome, &'static str, String), String> {
+        let mut last = ("local", username.to_string());
+        for provider in &self.0 {
+            let outcome = provider
+                .authenticate(username, password)
+                .map_err(|e| format!("{} authentication failed: {}", provider.name(), e))?;
+            debug!(user = %username, provider = provider.name(), ?outcome, "Checked credentials");
+            let canonical = provider.canonical_username(username);
+            if outcome != Outcome::Unknown {
+                return Ok((outcome, provider.name(), canonical));
+            }
+            last = (provider.name(), canonical);
+        }
+        Ok((Outcome::Unknown, last.0, last.1))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|provider| provider.name()).collect()
+    }
+}
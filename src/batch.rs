@@ -0,0 +1,321 @@
+use crate::metadata::MetadataStore;
+use crate::storage::{display_name, join_relative, move_file, remove_empty_parents, resolve_folder, resolve_relative, sanitize_folder, unix_now};
+use cratr::{BatchItemResult, FileMetaUpdate};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+fn succeeded(id: &str, message: impl Into<String>, new_id: Option<String>) -> BatchItemResult {
+    BatchItemResult {
+        id: id.to_string(),
+        success: true,
+        message: message.into(),
+        new_id,
+    }
+}
+
+pub fn failed(id: &str, message: impl Into<String>) -> BatchItemResult {
+    BatchItemResult {
+        id: id.to_string(),
+        success: false,
+        message: message.into(),
+        new_id: None,
+    }
+}
+
+// Delete every listed file; one failure doesn't stop the rest
+pub fn delete(upload_dir: &str, metadata: &MetadataStore, ids: &[String]) -> io::Result<Vec<BatchItemResult>> {
+    let mut results = Vec::new();
+    let mut removed = Vec::new();
+
+    for id in ids {
+        let Some(filepath) = resolve_relative(upload_dir, id).filter(|path| path.is_file()) else {
+            results.push(failed(id, "File not found"));
+            continue;
+        };
+
+        match std::fs::remove_file(&filepath) {
+            Ok(_) => {
+                remove_empty_parents(upload_dir, id);
+                removed.push(id.clone());
+                results.push(succeeded(id, "Deleted", None));
+            }
+            Err(e) => results.push(failed(id, format!("Failed to delete: {}", e))),
+        }
+    }
+
+    metadata.edit(|entries| {
+        for id in &removed {
+            entries.remove(id);
+        }
+    })?;
+    Ok(results)
+}
+
+// Move or copy files into `destination`. Copies get a fresh UUID prefix so they never
+// collide with the original; moves keep their stored name. A copy whose id is in `staged`
+// is moved into place from there instead of being copied from the original, so what lands
+// in the store is exactly what was checked.
+pub fn transfer(
+    upload_dir: &str,
+    metadata: &MetadataStore,
+    ids: &[String],
+    destination: &str,
+    copy: bool,
+    staged: &HashMap<String, PathBuf>,
+) -> io::Result<Vec<BatchItemResult>> {
+    let folder = sanitize_folder(destination);
+    let target_dir = resolve_folder(upload_dir, &folder)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid destination folder"))?;
+    std::fs::create_dir_all(target_dir)?;
+
+    let mut results = Vec::new();
+    let mut changes = Vec::new();
+    let target_label = if folder.is_empty() { "the top level".to_string() } else { format!("{}/", folder) };
+
+    for id in ids {
+        let Some(source) = resolve_relative(upload_dir, id).filter(|path| path.is_file()) else {
+            results.push(failed(id, "File not found"));
+            continue;
+        };
+
+        let stored_name = id.rsplit('/').next().unwrap_or(id);
+        let new_id = if copy {
+            join_relative(&folder, &format!("{}_{}", Uuid::new_v4(), display_name(id)))
+        } else {
+            join_relative(&folder, stored_name)
+        };
+
+        if !copy && &new_id == id {
+            results.push(succeeded(id, format!("Already in {}", target_label), Some(new_id)));
+            continue;
+        }
+
+        let Some(target) = resolve_relative(upload_dir, &new_id) else {
+            results.push(failed(id, "Invalid destination folder"));
+            continue;
+        };
+        if target.exists() {
+            results.push(failed(id, format!("A file named {} already exists in {}", display_name(id), target_label)));
+            continue;
+        }
+
+        let outcome = if let Some(staged) = staged.get(id).filter(|_| copy) {
+            move_file(staged, &target)
+        } else if copy {
+            std::fs::copy(&source, &target).map(|_| ())
+        } else {
+            std::fs::rename(&source, &target)
+        };
+
+        match outcome {
+            Ok(_) => {
+                if !copy {
+                    remove_empty_parents(upload_dir, id);
+                }
+                let verb = if copy { "Copied" } else { "Moved" };
+                results.push(succeeded(id, format!("{} to {}", verb, target_label), Some(new_id.clone())));
+                changes.push((id.clone(), new_id));
+            }
+            Err(e) => results.push(failed(id, format!("Failed: {}", e))),
+        }
+    }
+
+    metadata.edit(|entries| {
+        for (old_id, new_id) in changes {
+            if copy {
+                // A copy is a new file: it keeps tags and metadata but not the original's history
+                let mut meta = entries.get(&old_id).cloned().unwrap_or_default();
+                meta.uploaded_at = Some(unix_now());
+                meta.last_accessed = None;
+                meta.starred_by.clear();
+                entries.insert(new_id, meta);
+            } else if let Some(meta) = entries.remove(&old_id) {
+                entries.insert(new_id, meta);
+            }
+        }
+    })?;
+    Ok(results)
+}
+
+// Add and remove tags on every listed file
+pub fn tag(
+    upload_dir: &str,
+    metadata: &MetadataStore,
+    ids: &[String],
+    add: &[String],
+    remove: &[String],
+) -> io::Result<Vec<BatchItemResult>> {
+    let update = FileMetaUpdate {
+        add_tags: add.to_vec(),
+        remove_tags: remove.to_vec(),
+        ..Default::default()
+    };
+
+    metadata.edit(|entries| {
+        ids.iter()
+            .map(|id| {
+                if resolve_relative(upload_dir, id).filter(|path| path.is_file()).is_none() {
+                    return failed(id, "File not found");
+                }
+
+                let meta = entries.entry(id.clone()).or_default();
+                match meta.apply(&update) {
+                    Ok(()) => succeeded(id, format!("Tags: {}", meta.tags.join(", ")), None),
+                    Err(e) => failed(id, e),
+                }
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::FileMeta;
+
+    struct Scratch {
+        base: PathBuf,
+        metadata: MetadataStore,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-batch-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(base.join("uploads")).unwrap();
+            let metadata = MetadataStore::open(base.join("metadata.json")).unwrap();
+            Self { base, metadata }
+        }
+
+        fn uploads(&self) -> String {
+            self.base.join("uploads").to_string_lossy().to_string()
+        }
+
+        fn exists(&self, id: &str) -> bool {
+            self.base.join("uploads").join(id).is_file()
+        }
+
+        // A stored file with a tag and some history
+        fn store(&self, id: &str) {
+            let path = self.base.join("uploads").join(id);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, id).unwrap();
+            let meta = FileMeta {
+                tags: vec!["report".to_string()],
+                starred_by: vec!["alice".to_string()],
+                uploaded_at: Some(1),
+                last_accessed: Some(2),
+                ..FileMeta::default()
+            };
+            self.metadata.insert(id, meta).unwrap();
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    // (id, success) of each result, in order
+    fn outcomes(results: &[BatchItemResult]) -> Vec<(&str, bool)> {
+        results.iter().map(|result| (result.id.as_str(), result.success)).collect()
+    }
+
+    #[test]
+    fn deletes_what_it_can_and_reports_the_rest() {
+        let scratch = Scratch::new();
+        scratch.store("a.txt");
+        scratch.store("docs/b.txt");
+        scratch.store("keep.txt");
+
+        let results = delete(&scratch.uploads(), &scratch.metadata, &ids(&["a.txt", "missing.txt", "docs/b.txt", "../escape.txt"])).unwrap();
+        assert_eq!(outcomes(&results), [("a.txt", true), ("missing.txt", false), ("docs/b.txt", true), ("../escape.txt", false)]);
+        assert_eq!(results[1].message, "File not found");
+
+        assert!(!scratch.exists("a.txt") && !scratch.exists("docs/b.txt") && scratch.exists("keep.txt"));
+        // The emptied folder goes too, and so does the metadata of what was deleted
+        assert!(!scratch.base.join("uploads/docs").exists());
+        let entries = scratch.metadata.snapshot();
+        assert!(entries.contains_key("keep.txt") && entries.len() == 1);
+    }
+
+    #[test]
+    fn moves_keep_their_name_and_metadata() {
+        let scratch = Scratch::new();
+        scratch.store("a.txt");
+        scratch.store("b.txt");
+        scratch.store("archive/b.txt");
+        scratch.store("archive/c.txt");
+
+        let requested = ids(&["a.txt", "b.txt", "missing.txt", "archive/c.txt"]);
+        let results = transfer(&scratch.uploads(), &scratch.metadata, &requested, "archive", false, &HashMap::new()).unwrap();
+        assert_eq!(outcomes(&results), [("a.txt", true), ("b.txt", false), ("missing.txt", false), ("archive/c.txt", true)]);
+        assert_eq!(results[0].new_id.as_deref(), Some("archive/a.txt"));
+        assert_eq!(results[1].message, "A file named b.txt already exists in archive/");
+        assert_eq!(results[3].message, "Already in archive/");
+
+        assert!(scratch.exists("archive/a.txt") && !scratch.exists("a.txt"));
+        assert!(scratch.exists("b.txt"));
+        let moved = scratch.metadata.get("archive/a.txt").unwrap();
+        assert_eq!((moved.uploaded_at, moved.starred_by.len()), (Some(1), 1));
+        assert!(scratch.metadata.get("a.txt").is_none());
+    }
+
+    #[test]
+    fn copies_get_a_new_name_and_no_history() {
+        let scratch = Scratch::new();
+        scratch.store("a.txt");
+        // A staged copy is what lands in the store, not the original
+        let staged_path = scratch.base.join("staged");
+        std::fs::write(&staged_path, "checked").unwrap();
+        let staged = HashMap::from([("a.txt".to_string(), staged_path.clone())]);
+
+        let results = transfer(&scratch.uploads(), &scratch.metadata, &ids(&["a.txt", "gone.txt"]), "", true, &staged).unwrap();
+        assert_eq!(outcomes(&results), [("a.txt", true), ("gone.txt", false)]);
+        let new_id = results[0].new_id.clone().unwrap();
+        assert_ne!(new_id, "a.txt");
+        assert_eq!(display_name(&new_id), "a.txt");
+        assert_eq!(std::fs::read_to_string(scratch.base.join("uploads").join(&new_id)).unwrap(), "checked");
+        assert!(scratch.exists("a.txt") && !staged_path.exists());
+
+        let copy = scratch.metadata.get(&new_id).unwrap();
+        assert_eq!(copy.tags, ["report"]);
+        assert!(copy.starred_by.is_empty() && copy.last_accessed.is_none() && copy.uploaded_at != Some(1));
+        assert_eq!(scratch.metadata.get("a.txt").unwrap().starred_by, ["alice"]);
+    }
+
+    #[test]
+    fn refuses_a_destination_outside_the_store() {
+        let scratch = Scratch::new();
+        scratch.store("a.txt");
+        // Sanitized to a folder inside the store rather than refused outright
+        let results = transfer(&scratch.uploads(), &scratch.metadata, &ids(&["a.txt"]), "../outside", false, &HashMap::new()).unwrap();
+        let new_id = results[0].new_id.clone().unwrap();
+        assert!(results[0].success && scratch.exists(&new_id));
+        assert!(!scratch.base.join("outside").exists());
+    }
+
+    #[test]
+    fn tags_every_file_that_exists() {
+        let scratch = Scratch::new();
+        scratch.store("a.txt");
+        scratch.store("b.txt");
+
+        let results = tag(&scratch.uploads(), &scratch.metadata, &ids(&["a.txt", "missing.txt", "b.txt"]), &ids(&["Q3 Plan"]), &ids(&["report"])).unwrap();
+        assert_eq!(outcomes(&results), [("a.txt", true), ("missing.txt", false), ("b.txt", true)]);
+        assert_eq!(results[0].message, "Tags: q3-plan");
+        assert_eq!(scratch.metadata.get("b.txt").unwrap().tags, ["q3-plan"]);
+        assert!(scratch.metadata.get("missing.txt").is_none());
+
+        // A tag with nothing usable in it is dropped, not added empty
+        let results = tag(&scratch.uploads(), &scratch.metadata, &ids(&["a.txt"]), &ids(&["!!!"]), &[]).unwrap();
+        assert!(results[0].success);
+        assert_eq!(scratch.metadata.get("a.txt").unwrap().tags, ["q3-plan"]);
+    }
+}
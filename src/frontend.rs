@@ -1,28 +1,98 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+
 use leptos::*;
 use wasm_bindgen::prelude::*;
-use gloo_net::http::Request;
+use gloo_net::http::{Request, RequestBuilder};
 use gloo_file::{FileList, File};
 use gloo_timers::future::TimeoutFuture;
 use web_sys::{Event, FormData, RequestCredentials};
 
-use crate::{FileInfo, FilesResponse, StorageInfo, ApiResponse, UploadResponse, DebugInfo, LoginRequest, LoginResponse, AuthStatus};
+use crate::{ActivityEvent, ActivityResponse, FileInfo, FileMetaResponse, FileMetaUpdate, FileQuery, FilesResponse, StorageInfo, ApiResponse, UploadResponse, DebugInfo, LoginRequest, LoginResponse, AuthStatus, ArchiveEntryInfo, ArchiveListing, BatchResponse, PreviewResponse, SearchHit, SearchResponse, StarRequest, StarResponse, TagsResponse, RecoveryCodesResponse, Role, Scope, CreateUserRequest, UpdateUserRequest, UserInfo, UsersResponse, ApiTokenInfo, CreateTokenRequest, CreateTokenResponse, TokensResponse, SessionInfo, SessionsResponse, Access, FolderShare, Grant, Group, GroupsResponse, Principal, ShareUpdate, SharesResponse, TwoFactorCode, TwoFactorEnrollment, TwoFactorPolicy, TwoFactorStatus};
+
+// Files fetched per request while scrolling through the list
+const PAGE_SIZE: usize = 100;
+// Most common tags offered as one-click filters under the search box
+const MAX_TAG_FILTERS: usize = 20;
+// Events shown in the activity feed
+const ACTIVITY_FEED_SIZE: usize = 30;
+
+thread_local! {
+    // CSRF token of the current session, which every POST has to carry
+    static CSRF_TOKEN: RefCell<Option<String>> = const { RefCell::new(None) };
+    // Origin uploaded files are loaded from, if the server keeps them off the app's own
+    static CONTENT_ORIGIN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Where the login form is: asking for the password, for a two-factor code, or walking
+// through the two-factor setup that the server requires before letting the user in
+#[derive(Clone, Copy, PartialEq)]
+enum LoginStep {
+    Password,
+    Code,
+    Enroll,
+}
+
+// Role of the logged-in account, shared through context so each section can hide the
+// actions the server would refuse anyway
+#[derive(Clone, Copy)]
+struct CurrentRole(RwSignal<Option<Role>>);
+
+impl CurrentRole {
+    fn can(self, scope: Scope) -> bool {
+        self.0.get().is_some_and(|role| role.allows(scope))
+    }
+}
+
+// State behind the paged file list: the pages loaded so far, the filters they were
+// loaded with, and where the next page starts
+#[derive(Clone, Copy)]
+pub struct FileListing {
+    pub files: RwSignal<Vec<FileInfo>>,
+    pub query: RwSignal<FileQuery>,
+    pub total: RwSignal<usize>,
+    pub next_cursor: RwSignal<Option<String>>,
+    pub loading_more: RwSignal<bool>,
+    // Bumped on every reload so pages requested for an older query are dropped
+    generation: StoredValue<u64>,
+}
+
+impl Default for FileListing {
+    fn default() -> Self {
+        Self {
+            files: create_rw_signal(Vec::new()),
+            query: create_rw_signal(FileQuery::default()),
+            total: create_rw_signal(0),
+            next_cursor: create_rw_signal(None),
+            loading_more: create_rw_signal(false),
+            generation: store_value(0),
+        }
+    }
+}
 
 #[component]
 pub fn App() -> impl IntoView {
-    let (files, set_files) = create_signal(Vec::<FileInfo>::new());
+    let listing = FileListing::default();
     let (storage_info, set_storage_info) = create_signal(None::<StorageInfo>);
     let (search_term, set_search_term) = create_signal(String::new());
+    let content_search = create_rw_signal(false);
+    let search_hits = create_rw_signal(None::<Result<Vec<SearchHit>, String>>);
     let (is_loading, set_is_loading) = create_signal(false);
     let (debug_mode, set_debug_mode) = create_signal(false);
     let (is_authenticated, set_is_authenticated) = create_signal(false);
     let (username, set_username) = create_signal(String::new());
     let (password, set_password) = create_signal(String::new());
     let (login_error, set_login_error) = create_signal(None::<String>);
+    let show_security = create_rw_signal(false);
+    // Behind an authenticating proxy there is no login form, and no logging out either
+    let proxy_login = create_rw_signal(false);
+    let role = CurrentRole(create_rw_signal(None));
+    provide_context(role);
 
     // Check authentication status on mount
     create_effect(move |_| {
         spawn_local(async move {
-            check_auth_status(set_is_authenticated).await;
+            check_auth_status(set_is_authenticated, proxy_login).await;
         });
     });
 
@@ -32,29 +102,56 @@ pub fn App() -> impl IntoView {
             spawn_local(async move {
                 // Small delay to ensure session is fully established
                 TimeoutFuture::new(100).await;
-                load_files_and_storage(set_files, set_storage_info, set_is_loading).await;
+                load_role(role.0).await;
+                if role.can(Scope::Read) {
+                    load_files_and_storage(listing, set_storage_info, set_is_loading).await;
+                }
                 load_debug_info(set_debug_mode).await;
             });
         }
     });
 
-    // Create memo for filtered files
-    let filtered_files = create_memo(move |_| {
-        let search = search_term.get().trim().to_lowercase();
-        let all_files = files.get();
-        
-        if search.is_empty() {
-            all_files
-        } else if search.starts_with("#") {
-            let file_type = &search[1..];
-            all_files.into_iter().filter(|file| {
-                file.file_type.to_lowercase().contains(file_type)
-            }).collect()
-        } else {
-            all_files.into_iter().filter(|file| {
-                file.name.to_lowercase().contains(&search)
-            }).collect()
+    // Searching happens on the server; wait for a pause in typing before asking it.
+    // Name searches filter the file list, content searches list matches with snippets.
+    let search_ticket = store_value(0u64);
+    create_effect(move |_| {
+        let search = search_term.get();
+        let contents = content_search.get();
+        search_ticket.update_value(|ticket| *ticket += 1);
+        let ticket = search_ticket.get_value();
+        spawn_local(async move {
+            TimeoutFuture::new(300).await;
+            if search_ticket.get_value() != ticket {
+                return;
+            }
+            let SearchFilters { q, file_type, tags } = parse_search(&search);
+            listing.query.update(|query| {
+                query.q = if contents { None } else { q.clone() };
+                query.file_type = file_type;
+                query.tag = (!tags.is_empty()).then(|| tags.join(","));
+            });
+
+            match q.filter(|_| contents) {
+                Some(q) => {
+                    let result = search_contents(&q).await;
+                    if search_ticket.get_value() == ticket {
+                        search_hits.set(Some(result));
+                    }
+                }
+                None => search_hits.set(None),
+            }
+        });
+    });
+
+    // Start again from the first page whenever the filters or ordering change
+    create_effect(move |previous: Option<FileQuery>| {
+        let query = listing.query.get();
+        if previous.is_some_and(|previous| previous != query) && is_authenticated.get_untracked() {
+            spawn_local(async move {
+                load_first_page(listing).await;
+            });
         }
+        query
     });
 
     view! {
@@ -62,16 +159,20 @@ pub fn App() -> impl IntoView {
             <StyleProvider />
             <Show 
                 when=move || is_authenticated.get()
-                fallback=move || view! {
-                    <LoginForm 
-                        username=username
-                        set_username=set_username
-                        password=password
-                        set_password=set_password
-                        login_error=login_error
-                        set_login_error=set_login_error
-                        set_is_authenticated=set_is_authenticated
-                    />
+                fallback=move || if proxy_login.get() {
+                    view! { <ProxyNotice /> }.into_view()
+                } else {
+                    view! {
+                        <LoginForm 
+                            username=username
+                            set_username=set_username
+                            password=password
+                            set_password=set_password
+                            login_error=login_error
+                            set_login_error=set_login_error
+                            set_is_authenticated=set_is_authenticated
+                        />
+                    }.into_view()
                 }
             >
                 <div class="main-grid">
@@ -85,51 +186,79 @@ pub fn App() -> impl IntoView {
                                     "drag, drop, and manage your files with style"
                                 </p>
                             </div>
-                            <button 
-                                type="button"
-                                class="logout-btn border-container"
-                                on:click=move |_| {
-                                    spawn_local(async move {
-                                        logout_user(set_is_authenticated).await;
-                                    });
-                                }
-                            >
-                                "logout"
-                            </button>
+                            <div>
+                                <button
+                                    type="button"
+                                    class="security-btn border-container"
+                                    on:click=move |_| show_security.update(|show| *show = !*show)
+                                >
+                                    "security"
+                                </button>
+                                <button 
+                                    type="button"
+                                    class="logout-btn border-container"
+                                    style:display=move || if proxy_login.get() { "none" } else { "inline-block" }
+                                    on:click=move |_| {
+                                        role.0.set(None);
+                                        spawn_local(async move {
+                                            logout_user(set_is_authenticated).await;
+                                        });
+                                    }
+                                >
+                                    "logout"
+                                </button>
+                            </div>
                         </div>
+                        <Show when=move || show_security.get()>
+                            <SecurityPanel />
+                        </Show>
                     </div>
                     
-                    <div class="storage-section border-container">
-                        <StorageSection storage_info=storage_info />
-                    </div>
+                    <Show when=move || role.can(Scope::Read)>
+                        <div class="storage-section border-container">
+                            <StorageSection storage_info=storage_info />
+                        </div>
+                    </Show>
                     
-                    <div class="upload-section border-container">
-                        <UploadSection 
-                            debug_mode=debug_mode
-                            on_upload_complete=move || {
-                                spawn_local(async move {
-                                    load_files_and_storage(set_files, set_storage_info, set_is_loading).await;
-                                });
-                            }
-                        />
-                    </div>
+                    <Show when=move || role.can(Scope::Upload)>
+                        <div class="upload-section border-container">
+                            <UploadSection 
+                                debug_mode=debug_mode
+                                on_upload_complete=move || {
+                                    if role.can(Scope::Read) {
+                                        spawn_local(async move {
+                                            load_files_and_storage(listing, set_storage_info, set_is_loading).await;
+                                        });
+                                    }
+                                }
+                            />
+                        </div>
+                    </Show>
                     
-                    <div class="search-section border-container">
-                        <SearchSection 
-                            search_term=search_term
-                            set_search_term=set_search_term
-                        />
-                    </div>
+                    <Show when=move || role.can(Scope::Read)>
+                        <div class="search-section border-container">
+                            <SearchSection 
+                                search_term=search_term
+                                set_search_term=set_search_term
+                                content_search=content_search
+                                search_hits=search_hits
+                                listing=listing
+                            />
+                        </div>
                     
-                    <div class="files-section border-container">
-                        <FilesSection 
-                            files=filtered_files
-                            is_loading=is_loading
-                            set_files=set_files
-                            set_storage_info=set_storage_info
-                            set_is_loading=set_is_loading
-                        />
-                    </div>
+                        <div class="activity-section border-container">
+                            <ActivitySection listing=listing />
+                        </div>
+                    
+                        <div class="files-section border-container">
+                            <FilesSection 
+                                listing=listing
+                                is_loading=is_loading
+                                set_storage_info=set_storage_info
+                                set_is_loading=set_is_loading
+                            />
+                        </div>
+                    </Show>
                 </div>
             </Show>
         </div>
@@ -194,6 +323,27 @@ pub fn LoginForm(
     set_login_error: WriteSignal<Option<String>>,
     set_is_authenticated: WriteSignal<bool>,
 ) -> impl IntoView {
+    let step = create_rw_signal(LoginStep::Password);
+    let (code, set_code) = create_signal(String::new());
+    let single_sign_on = create_rw_signal(None::<String>);
+
+    spawn_local(async move {
+        if let Ok(status) = load_auth_status().await {
+            single_sign_on.set(status.single_sign_on);
+        }
+    });
+
+    // Single sign-on comes back to "/?login=..." when a second step is still due, or with
+    // "login_error=..." when it failed
+    if let Some((key, value)) = sso_result() {
+        match (key.as_str(), value.as_str()) {
+            ("login", "2fa") => step.set(LoginStep::Code),
+            ("login", "enroll") => step.set(LoginStep::Enroll),
+            ("login_error", reason) => set_login_error.set(Some(sso_error_message(reason).to_string())),
+            _ => {}
+        }
+    }
+
     view! {
         <div class="login-grid">
             <div class="login-header border-container">
@@ -212,17 +362,38 @@ pub fn LoginForm(
                     </div>
                 </Show>
                 
-                <form on:submit=move |e| {
+                <Show when=move || step.get() == LoginStep::Enroll>
+                    <TwoFactorSetup on_done=Callback::new(move |_| set_is_authenticated.set(true)) />
+                </Show>
+                
+                <form
+                    style:display=move || if step.get() == LoginStep::Enroll { "none" } else { "block" }
+                    on:submit=move |e| {
                     e.prevent_default();
                     let username_val = username.get();
                     let password_val = password.get();
+                    let code_val = code.get();
+                    let current_step = step.get();
                     
                     spawn_local(async move {
                         set_login_error.set(None);
-                        match login_user(&username_val, &password_val).await {
+                        let result = if current_step == LoginStep::Code {
+                            login_second_factor(&code_val).await
+                        } else {
+                            login_user(&username_val, &password_val).await
+                        };
+                        match result {
                             Ok(response) => {
                                 if response.authenticated {
                                     set_is_authenticated.set(true);
+                                } else if response.two_factor_required {
+                                    if current_step == LoginStep::Code {
+                                        set_login_error.set(Some(response.message));
+                                    }
+                                    set_code.set(String::new());
+                                    step.set(LoginStep::Code);
+                                } else if response.enrollment_required {
+                                    step.set(LoginStep::Enroll);
                                 } else {
                                     set_login_error.set(Some(response.message));
                                 }
@@ -233,7 +404,21 @@ pub fn LoginForm(
                         }
                     });
                 }>
-                    <div class="form-field">
+                    <Show when=move || step.get() == LoginStep::Code>
+                        <div class="form-field">
+                            <label class="field-label">"authentication code"</label>
+                            <input
+                                type="text"
+                                class="login-input border-container"
+                                autocomplete="one-time-code"
+                                prop:value=move || code.get()
+                                on:input=move |e| set_code.set(event_target_value(&e))
+                                placeholder="6-digit code or a recovery code"
+                                required
+                            />
+                        </div>
+                    </Show>
+                    <div class="form-field" style:display=move || if step.get() == LoginStep::Password { "block" } else { "none" }>
                         <label class="field-label">"username"</label>
                         <input
                             type="text"
@@ -245,7 +430,7 @@ pub fn LoginForm(
                         />
                     </div>
                     
-                    <div class="form-field">
+                    <div class="form-field" style:display=move || if step.get() == LoginStep::Password { "block" } else { "none" }>
                         <label class="field-label">"password"</label>
                         <input
                             type="password"
@@ -263,6 +448,18 @@ pub fn LoginForm(
                         </button>
                     </div>
                 </form>
+
+                <Show when=move || step.get() == LoginStep::Password && single_sign_on.get().is_some()>
+                    <div class="login-actions">
+                        <a
+                            href="/auth/oidc/login"
+                            class="login-btn border-container"
+                            style="display: block; text-align: center; text-decoration: none;"
+                        >
+                            {move || format!("log in with {}", single_sign_on.get().unwrap_or_default())}
+                        </a>
+                    </div>
+                </Show>
             </div>
             
             <div class="login-info border-container">
@@ -291,203 +488,1356 @@ pub fn LoginForm(
     }
 }
 
+// Shown instead of the login form when an authenticating proxy in front of cratr does the
+// logging in but didn't let this visitor in
 #[component]
-pub fn SearchSection(
-    search_term: ReadSignal<String>,
-    set_search_term: WriteSignal<String>,
-) -> impl IntoView {
+fn ProxyNotice() -> impl IntoView {
     view! {
-        <div>
-            <input 
-                type="text"
-                class="search-input border-container"
-                placeholder="search files..."
-                prop:value=search_term
-                on:input=move |ev| {
-                    let value = event_target_value(&ev);
-                    set_search_term.set(value);
-                }
-            />
-            <div style="color: #6c7086; font-size: 12px; margin-top: 8px;">
-                "use # to filter by type"
+        <div class="login-grid">
+            <div class="login-header border-container">
+                <h1 style="color: #cdd6f4; margin: 0 0 10px 0; font-size: 2.5rem; font-weight: 500;">
+                    "cratr"
+                </h1>
+                <p style="color: #bac2de; font-size: 1.1rem; margin: 0;">
+                    "secure file management system"
+                </p>
+            </div>
+
+            <div class="login-form-section border-container">
+                <div class="login-error border-container">
+                    "Your login proxy didn't let you into cratr. If you are logged in there, ask an admin to give your account access"
+                </div>
             </div>
         </div>
     }
 }
 
+// Enrollment in two-factor authentication: scan the QR code, prove the app works with a
+// first code, then write down the recovery codes
 #[component]
-pub fn UploadSection<F>(
-    debug_mode: ReadSignal<bool>,
-    on_upload_complete: F,
-) -> impl IntoView 
-where
-    F: Fn() + Copy + 'static,
-{
-    let (selected_files, set_selected_files) = create_signal(Vec::<File>::new());
-    let (is_uploading, set_is_uploading) = create_signal(false);
-    let file_input_ref = create_node_ref::<leptos::html::Input>();
-
-    let on_file_change = move |_ev: Event| {
-        web_sys::console::log_1(&"File input changed".into());
-        if let Some(input) = file_input_ref.get_untracked() {
-            if let Some(files) = input.files() {
-                let file_list = FileList::from(files);
-                let files_vec: Vec<File> = file_list.iter().cloned().collect();
-                web_sys::console::log_1(&format!("Selected {} files", files_vec.len()).into());
-                set_selected_files.set(files_vec);
-            } else {
-                web_sys::console::log_1(&"No files found".into());
-            }
-        } else {
-            web_sys::console::log_1(&"Input ref not found".into());
-        }
-    };
-
-    let on_choose_files_click = move |e: web_sys::MouseEvent| {
-        e.prevent_default();
-        e.stop_propagation();
-        web_sys::console::log_1(&"Choose files button clicked".into());
-        if let Some(input) = file_input_ref.get_untracked() {
-            web_sys::console::log_1(&"Triggering file input click".into());
-            input.click();
-        } else {
-            web_sys::console::log_1(&"File input ref not found".into());
-        }
-    };
-
-    let on_upload_click = move |e: web_sys::MouseEvent| {
-        e.prevent_default();
-        e.stop_propagation();
-        
-        let files = selected_files.get();
-        web_sys::console::log_1(&format!("Upload button clicked, files count: {}", files.len()).into());
-        
-        if files.is_empty() {
-            web_sys::console::log_1(&"No files selected".into());
-            return;
+fn TwoFactorSetup(on_done: Callback<()>) -> impl IntoView {
+    let enrollment = create_rw_signal(None::<TwoFactorEnrollment>);
+    let recovery_codes = create_rw_signal(None::<Vec<String>>);
+    let (code, set_code) = create_signal(String::new());
+    let (message, set_message) = create_signal(None::<String>);
+
+    spawn_local(async move {
+        match enroll_two_factor().await {
+            Ok(response) => enrollment.set(Some(response)),
+            Err(e) => set_message.set(Some(e)),
         }
+    });
 
-        if is_uploading.get() {
-            web_sys::console::log_1(&"Upload already in progress, ignoring click".into());
-            return;
-        }
-        
-        web_sys::console::log_1(&"Starting upload...".into());
-        set_is_uploading.set(true);
-        
+    let confirm = move || {
+        let code = code.get_untracked();
         spawn_local(async move {
-            web_sys::console::log_1(&"In spawn_local...".into());
-            match upload_files(files).await {
+            match confirm_two_factor(&code).await {
                 Ok(response) => {
-                    web_sys::console::log_1(&format!("Upload successful: {} (uploaded {} files)", response.message, response.files.len()).into());
-                    
-                    // Clear the upload state first
-                    set_selected_files.set(Vec::new());
-                    if let Some(input) = file_input_ref.get_untracked() {
-                        input.set_value("");
-                    }
-                    set_is_uploading.set(false);
-                    
-                    // Add a longer delay before calling the completion callback to ensure all state is settled
-                    gloo_timers::future::TimeoutFuture::new(100).await;
-                    
-                    // Call the callback to refresh data
-                    web_sys::console::log_1(&"Calling upload complete callback after successful upload".into());
-                    on_upload_complete();
-                },
-                Err(e) => {
-                    web_sys::console::log_1(&format!("Upload failed: {}", e).into());
-                    log::error!("Upload failed: {}", e);
-                    set_is_uploading.set(false);
+                    set_message.set(None);
+                    recovery_codes.set(Some(response.recovery_codes));
                 }
+                Err(e) => set_message.set(Some(e)),
             }
         });
     };
 
-    let on_submit = move |ev: web_sys::SubmitEvent| {
-        ev.prevent_default();
-        web_sys::console::log_1(&"Form submitted".into());
-        // Form submission will be handled by button click
-    };
-
     view! {
-        <div>
-            <form on:submit=on_submit>
-                <div style="margin-bottom: 15px;">
-                    <input
-                        type="file"
-                        id="fileInput"
-                        multiple
-                        ref=file_input_ref
-                        on:change=on_file_change
-                        accept="*/*"
-                        style="display: none;"
-                    />
-                    <button 
-                        type="button"
-                        class="choose-files-btn border-container"
-                        on:click=on_choose_files_click
-                    >
-                        "choose files"
-                    </button>
-                </div>
-                
-                <Show when=move || !selected_files.get().is_empty()>
-                    <div style="margin-bottom: 15px; text-align: left;">
-                        <div style="color: #bac2de; font-size: 14px; margin-bottom: 8px;">
-                            "selected:"
-                        </div>
-                        <div style="max-height: 80px; overflow-y: auto;">
-                            <For
-                                each=move || selected_files.get()
-                                key=|file| file.name()
-                                let:file
-                            >
-                                <div style="color: #a6adc8; font-size: 13px; margin: 2px 0;">
-                                    {file.name()}
-                                </div>
-                            </For>
+        <div class="two-factor-setup">
+            {move || match (recovery_codes.get(), enrollment.get()) {
+                (Some(codes), _) => view! {
+                    <div>
+                        <p class="two-factor-note">
+                            "two-factor authentication is on. save these recovery codes somewhere safe; each one works once if you lose your authenticator"
+                        </p>
+                        <div class="recovery-codes">
+                            {codes.into_iter().map(|code| view! { <code>{code}</code> }).collect_view()}
                         </div>
+                        <button type="button" class="entry-link" on:click=move |_| on_done.call(())>"done"</button>
                     </div>
-                </Show>
-                
-                <Show when=move || debug_mode.get()>
-                    <div style="margin: 10px 0; color: #6c7086; font-size: 12px;">
-                        "debug: " {move || selected_files.get().len()} " files | "
-                        {move || if is_uploading.get() { "uploading..." } else { "ready" }}
+                }.into_view(),
+                (None, Some(enrollment)) => view! {
+                    <div>
+                        <p class="two-factor-note">"scan this with your authenticator app, or enter the key by hand"</p>
+                        <img class="two-factor-qr" src=enrollment.qr_code.clone() alt="two-factor QR code" />
+                        <div class="two-factor-secret"><code>{enrollment.secret.clone()}</code></div>
+                        <div class="meta-editor">
+                            <input
+                                type="text"
+                                class="meta-input"
+                                autocomplete="one-time-code"
+                                placeholder="6-digit code"
+                                prop:value=code
+                                on:input=move |ev| set_code.set(event_target_value(&ev))
+                                on:keydown=move |ev| if ev.key() == "Enter" { confirm() }
+                            />
+                            <button type="button" class="entry-link" on:click=move |_| confirm()>"verify"</button>
+                        </div>
                     </div>
-                </Show>
-                
-                <button 
-                    type="button"
-                    class="upload-files-btn border-container"
-                    disabled=move || selected_files.get().is_empty() || is_uploading.get()
-                    on:click=on_upload_click
-                >
-                    {move || if is_uploading.get() { "uploading..." } else { "upload files" }}
-                </button>
-            </form>
+                }.into_view(),
+                (None, None) => view! { <p class="two-factor-note">"loading..."</p> }.into_view(),
+            }}
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f38ba8; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
         </div>
     }
 }
 
+// Two-factor settings and API tokens of the logged-in account, plus the switch that makes
+// two-factor authentication mandatory
 #[component]
-fn FileItem(
+fn SecurityPanel() -> impl IntoView {
+    let status = create_rw_signal(None::<TwoFactorStatus>);
+    let enrolling = create_rw_signal(false);
+    let recovery_codes = create_rw_signal(None::<Vec<String>>);
+    let (code, set_code) = create_signal(String::new());
+    let (message, set_message) = create_signal(None::<String>);
+
+    let reload = move || {
+        spawn_local(async move {
+            match two_factor_status().await {
+                Ok(response) => status.set(Some(response)),
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+    reload();
+
+    let disable = move || {
+        let code = code.get_untracked();
+        spawn_local(async move {
+            match two_factor_request::<TwoFactorStatus>("/2fa/disable", &TwoFactorCode { code }).await {
+                Ok(response) => {
+                    set_message.set(None);
+                    set_code.set(String::new());
+                    recovery_codes.set(None);
+                    status.set(Some(response));
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+
+    let new_codes = move || {
+        let code = code.get_untracked();
+        spawn_local(async move {
+            match two_factor_request::<RecoveryCodesResponse>("/2fa/recovery-codes", &TwoFactorCode { code }).await {
+                Ok(response) => {
+                    set_message.set(None);
+                    set_code.set(String::new());
+                    recovery_codes.set(Some(response.recovery_codes));
+                    reload();
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+
+    let set_required = move |required: bool| {
+        spawn_local(async move {
+            match two_factor_request::<TwoFactorStatus>("/admin/2fa", &TwoFactorPolicy { required }).await {
+                Ok(response) => {
+                    set_message.set(None);
+                    status.set(Some(response));
+                }
+                Err(e) => {
+                    set_message.set(Some(e));
+                    // Put the checkbox back the way the server still has it
+                    status.update(|_| {});
+                }
+            }
+        });
+    };
+
+    let enabled = move || status.get().is_some_and(|status| status.enabled);
+    let required = move || status.get().is_some_and(|status| status.required);
+    let role = expect_context::<CurrentRole>();
+
+    view! {
+        <div class="security-panel">
+            <Show
+                when=move || !enrolling.get()
+                fallback=move || view! {
+                    <TwoFactorSetup on_done=Callback::new(move |_| {
+                        enrolling.set(false);
+                        reload();
+                    }) />
+                }
+            >
+                <div class="two-factor-note">
+                    {move || match status.get() {
+                        Some(status) if status.enabled => format!(
+                            "two-factor authentication is on, {} recovery code{} left",
+                            status.recovery_codes_left,
+                            if status.recovery_codes_left == 1 { "" } else { "s" }
+                        ),
+                        Some(_) => "two-factor authentication is off".to_string(),
+                        None => "loading...".to_string(),
+                    }}
+                </div>
+                <Show
+                    when=enabled
+                    fallback=move || view! {
+                        <button type="button" class="entry-link" on:click=move |_| enrolling.set(true)>"set up two-factor authentication"</button>
+                    }
+                >
+                    <div class="meta-editor">
+                        <input
+                            type="text"
+                            class="meta-input"
+                            autocomplete="one-time-code"
+                            placeholder="current code"
+                            prop:value=code
+                            on:input=move |ev| set_code.set(event_target_value(&ev))
+                        />
+                        <button type="button" class="entry-link" on:click=move |_| new_codes()>"new recovery codes"</button>
+                        <Show when=move || !required()>
+                            <button type="button" class="entry-link" on:click=move |_| disable()>"turn off"</button>
+                        </Show>
+                    </div>
+                </Show>
+                <Show when=move || recovery_codes.get().is_some()>
+                    <div class="recovery-codes">
+                        {move || recovery_codes.get().unwrap_or_default().into_iter().map(|code| view! { <code>{code}</code> }).collect_view()}
+                    </div>
+                </Show>
+                <Show when=move || role.can(Scope::Admin)>
+                    <label class="two-factor-note" style="display: flex; gap: 6px; align-items: center; margin-top: 10px;">
+                        <input
+                            type="checkbox"
+                            prop:checked=required
+                            on:change=move |ev| set_required(event_target_checked(&ev))
+                        />
+                        "require two-factor authentication for every account"
+                    </label>
+                </Show>
+            </Show>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f38ba8; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
+            <ActiveSessions />
+            <ApiTokens />
+            <Show when=move || role.can(Scope::Admin)>
+                <UserAdmin />
+                <GroupAdmin />
+            </Show>
+        </div>
+    }
+}
+
+// Accounts and their roles, for admins
+#[component]
+fn UserAdmin() -> impl IntoView {
+    let users = create_rw_signal(Vec::<UserInfo>::new());
+    let (new_username, set_new_username) = create_signal(String::new());
+    let (new_password, set_new_password) = create_signal(String::new());
+    let (new_role, set_new_role) = create_signal(Role::Viewer);
+    let (message, set_message) = create_signal(None::<String>);
+
+    let reload = move || {
+        spawn_local(async move {
+            match load_users().await {
+                Ok(response) => users.set(response.users),
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+    reload();
+
+    // Run a user-management request, then show its outcome and the fresh list
+    let submit = move |url: String, body: serde_json::Value| {
+        spawn_local(async move {
+            match two_factor_request::<ApiResponse>(&url, &body).await {
+                Ok(response) => set_message.set(Some(response.message)),
+                Err(e) => set_message.set(Some(e)),
+            }
+            reload();
+        });
+    };
+
+    let create = move || {
+        let request = CreateUserRequest {
+            username: new_username.get_untracked(),
+            password: new_password.get_untracked(),
+            role: new_role.get_untracked(),
+        };
+        set_new_username.set(String::new());
+        set_new_password.set(String::new());
+        submit("/admin/users".to_string(), serde_json::to_value(request).unwrap_or_default());
+    };
+
+    view! {
+        <div class="api-tokens">
+            <div class="two-factor-note">"users"</div>
+            <For
+                each=move || users.get()
+                key=|user| (user.username.clone(), user.role)
+                children=move |user| {
+                    let name = user.username.clone();
+                    let delete_name = user.username.clone();
+                    let two_factor = if user.two_factor { "2fa on" } else { "2fa off" };
+                    view! {
+                        <div class="api-token">
+                            <span class="api-token-name">{user.username.clone()}</span>
+                            <select
+                                class="meta-input"
+                                disabled=user.builtin
+                                on:change=move |ev| {
+                                    let role = Role::ALL.into_iter().find(|role| role.as_str() == event_target_value(&ev));
+                                    let update = UpdateUserRequest { role, password: None };
+                                    submit(format!("/admin/users/{}", name), serde_json::to_value(update).unwrap_or_default());
+                                }
+                            >
+                                {Role::ALL.into_iter().map(|role| view! {
+                                    <option value=role.as_str() selected=role == user.role>{role.as_str()}</option>
+                                }).collect_view()}
+                            </select>
+                            <span class="two-factor-note">{two_factor}</span>
+                            {user.source.clone().map(|source| view! { <span class="two-factor-note">{source}</span> })}
+                            <Show when=move || !user.builtin>
+                                <button
+                                    type="button"
+                                    class="entry-link"
+                                    on:click={
+                                        let name = delete_name.clone();
+                                        move |_| submit(format!("/admin/users/{}/delete", name), serde_json::json!({}))
+                                    }
+                                >
+                                    "delete"
+                                </button>
+                            </Show>
+                        </div>
+                    }
+                }
+            />
+            <div class="meta-editor">
+                <input
+                    type="text"
+                    class="meta-input"
+                    placeholder="username"
+                    prop:value=new_username
+                    on:input=move |ev| set_new_username.set(event_target_value(&ev))
+                />
+                <input
+                    type="password"
+                    class="meta-input"
+                    autocomplete="new-password"
+                    placeholder="password"
+                    prop:value=new_password
+                    on:input=move |ev| set_new_password.set(event_target_value(&ev))
+                />
+                <select
+                    class="meta-input"
+                    on:change=move |ev| {
+                        if let Some(role) = Role::ALL.into_iter().find(|role| role.as_str() == event_target_value(&ev)) {
+                            set_new_role.set(role);
+                        }
+                    }
+                >
+                    {Role::ALL.into_iter().map(|role| view! {
+                        <option value=role.as_str() selected=role == Role::Viewer>{role.as_str()}</option>
+                    }).collect_view()}
+                </select>
+                <button type="button" class="entry-link" on:click=move |_| create()>"add user"</button>
+            </div>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f9e2af; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
+        </div>
+    }
+}
+
+// Groups that folders can be shared with, each edited as a comma-separated member list
+#[component]
+fn GroupAdmin() -> impl IntoView {
+    let groups = create_rw_signal(Vec::<Group>::new());
+    let (new_name, set_new_name) = create_signal(String::new());
+    let (new_members, set_new_members) = create_signal(String::new());
+    let (message, set_message) = create_signal(None::<String>);
+
+    let reload = move || {
+        spawn_local(async move {
+            match load_groups().await {
+                Ok(response) => groups.set(response.groups),
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+    reload();
+
+    let submit = move |url: String, body: serde_json::Value| {
+        spawn_local(async move {
+            match two_factor_request::<ApiResponse>(&url, &body).await {
+                Ok(response) => set_message.set(Some(response.message)),
+                Err(e) => set_message.set(Some(e)),
+            }
+            reload();
+        });
+    };
+
+    let save = move || {
+        let group = Group {
+            name: new_name.get_untracked(),
+            members: split_members(&new_members.get_untracked()),
+        };
+        set_new_name.set(String::new());
+        set_new_members.set(String::new());
+        submit("/admin/groups".to_string(), serde_json::to_value(group).unwrap_or_default());
+    };
+
+    view! {
+        <div class="api-tokens">
+            <div class="two-factor-note">"groups (\"everyone\" always holds every account)"</div>
+            <For
+                each=move || groups.get()
+                key=|group| (group.name.clone(), group.members.clone())
+                children=move |group| {
+                    let name = group.name.clone();
+                    let members = group.members.join(", ");
+                    view! {
+                        <div class="api-token">
+                            <span class="api-token-name">{group.name.clone()}</span>
+                            <input
+                                type="text"
+                                class="meta-input"
+                                value=members
+                                on:change={
+                                    let name = name.clone();
+                                    move |ev| {
+                                        let group = Group { name: name.clone(), members: split_members(&event_target_value(&ev)) };
+                                        submit("/admin/groups".to_string(), serde_json::to_value(group).unwrap_or_default());
+                                    }
+                                }
+                            />
+                            <button
+                                type="button"
+                                class="entry-link"
+                                on:click=move |_| submit(format!("/admin/groups/{}/delete", name), serde_json::json!({}))
+                            >
+                                "delete"
+                            </button>
+                        </div>
+                    }
+                }
+            />
+            <div class="meta-editor">
+                <input
+                    type="text"
+                    class="meta-input"
+                    placeholder="group"
+                    prop:value=new_name
+                    on:input=move |ev| set_new_name.set(event_target_value(&ev))
+                />
+                <input
+                    type="text"
+                    class="meta-input"
+                    placeholder="members, comma separated"
+                    prop:value=new_members
+                    on:input=move |ev| set_new_members.set(event_target_value(&ev))
+                />
+                <button type="button" class="entry-link" on:click=move |_| save()>"add group"</button>
+            </div>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f9e2af; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
+        </div>
+    }
+}
+
+fn split_members(members: &str) -> Vec<String> {
+    members.split(',').map(str::trim).filter(|member| !member.is_empty()).map(str::to_string).collect()
+}
+
+// Who else may use one folder: the current grants with remove buttons and a row to add one.
+// Every change is saved right away.
+#[component]
+fn FolderSharing(folder: String, on_close: Callback<()>) -> impl IntoView {
+    let grants = create_rw_signal(Vec::<Grant>::new());
+    let owner = create_rw_signal(None::<String>);
+    let (new_name, set_new_name) = create_signal(String::new());
+    let (new_is_group, set_new_is_group) = create_signal(false);
+    let (new_access, set_new_access) = create_signal(Access::Read);
+    let (message, set_message) = create_signal(None::<String>);
+    let folder = store_value(folder);
+
+    let reload = move || {
+        spawn_local(async move {
+            match load_share(&folder.get_value()).await {
+                Ok(Some(share)) => {
+                    owner.set(Some(share.owner));
+                    grants.set(share.grants);
+                }
+                Ok(None) => {
+                    owner.set(None);
+                    grants.set(Vec::new());
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+    reload();
+
+    let save = move |updated: Vec<Grant>| {
+        spawn_local(async move {
+            let url = format!("/shares/{}", folder.get_value());
+            match two_factor_request::<ApiResponse>(&url, &ShareUpdate { grants: updated }).await {
+                Ok(response) => set_message.set(Some(response.message)),
+                Err(e) => set_message.set(Some(e)),
+            }
+            reload();
+        });
+    };
+
+    let add = move || {
+        let name = new_name.get_untracked().trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        let principal = if new_is_group.get_untracked() { Principal::Group(name) } else { Principal::User(name) };
+        let mut updated = grants.get_untracked();
+        updated.retain(|grant| grant.principal != principal);
+        updated.push(Grant { principal, access: new_access.get_untracked() });
+        set_new_name.set(String::new());
+        save(updated);
+    };
+
+    view! {
+        <div class="api-tokens">
+            <div class="two-factor-note">
+                {move || match owner.get() {
+                    Some(owner) => format!("sharing {}/ (owned by {})", folder.get_value(), owner),
+                    None => format!("sharing {}/ (not private yet)", folder.get_value()),
+                }}
+            </div>
+            <For
+                each=move || grants.get()
+                key=|grant| (grant.principal.clone(), grant.access)
+                children=move |grant| {
+                    let label = match &grant.principal {
+                        Principal::User(user) => user.clone(),
+                        Principal::Group(group) => format!("group {}", group),
+                    };
+                    let principal = grant.principal.clone();
+                    view! {
+                        <div class="api-token">
+                            <span class="api-token-name">{label}</span>
+                            <span class="api-token-scopes">{grant.access.as_str()}</span>
+                            <button
+                                type="button"
+                                class="entry-link"
+                                on:click=move |_| {
+                                    let mut updated = grants.get_untracked();
+                                    updated.retain(|existing| existing.principal != principal);
+                                    save(updated);
+                                }
+                            >
+                                "remove"
+                            </button>
+                        </div>
+                    }
+                }
+            />
+            <div class="meta-editor">
+                <select class="meta-input" on:change=move |ev| set_new_is_group.set(event_target_value(&ev) == "group")>
+                    <option value="user">"user"</option>
+                    <option value="group">"group"</option>
+                </select>
+                <input
+                    type="text"
+                    class="meta-input"
+                    placeholder=move || if new_is_group.get() { "group" } else { "username" }
+                    prop:value=new_name
+                    on:input=move |ev| set_new_name.set(event_target_value(&ev))
+                />
+                <select
+                    class="meta-input"
+                    on:change=move |ev| {
+                        let access = if event_target_value(&ev) == "read-write" { Access::ReadWrite } else { Access::Read };
+                        set_new_access.set(access);
+                    }
+                >
+                    <option value="read">"read"</option>
+                    <option value="read-write">"read-write"</option>
+                </select>
+                <button type="button" class="entry-link" on:click=move |_| add()>"share"</button>
+                <button type="button" class="entry-link" on:click=move |_| on_close.call(())>"close"</button>
+            </div>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f9e2af; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
+        </div>
+    }
+}
+
+// Where the account is logged in, with a button to log out each session or all the others
+#[component]
+fn ActiveSessions() -> impl IntoView {
+    let sessions = create_rw_signal(Vec::<SessionInfo>::new());
+    let (message, set_message) = create_signal(None::<String>);
+
+    let reload = move || {
+        spawn_local(async move {
+            match load_sessions().await {
+                Ok(response) => sessions.set(response.sessions),
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+    reload();
+    let others = move || sessions.with(|sessions| sessions.iter().any(|session| !session.current));
+
+    let revoke = move |url: String| {
+        spawn_local(async move {
+            match two_factor_request::<ApiResponse>(&url, &serde_json::json!({})).await {
+                Ok(response) => {
+                    set_message.set(Some(response.message));
+                    reload();
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+
+    view! {
+        <div class="api-tokens">
+            <div class="two-factor-note">"sessions"</div>
+            <For
+                each=move || sessions.get()
+                key=|session| session.id.clone()
+                children=move |session| {
+                    let url = format!("/sessions/{}/revoke", session.id);
+                    let details = format!(
+                        "{} · logged in {} · active {}",
+                        session.ip.clone().unwrap_or_else(|| "unknown address".to_string()),
+                        time_ago(session.created),
+                        time_ago(session.last_seen),
+                    );
+                    view! {
+                        <div class="api-token">
+                            <span class="api-token-name">
+                                {device_name(session.user_agent.as_deref())}
+                                {session.current.then_some(" (this device)")}
+                            </span>
+                            <span class="two-factor-note">{details}</span>
+                            <Show when=move || !session.current>
+                                <button type="button" class="entry-link" on:click={
+                                    let url = url.clone();
+                                    move |_| revoke(url.clone())
+                                }>"log out"</button>
+                            </Show>
+                        </div>
+                    }
+                }
+            />
+            <Show when=others>
+                <button type="button" class="entry-link" on:click=move |_| revoke("/sessions/revoke-all".to_string())>
+                    "log out everywhere else"
+                </button>
+            </Show>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f38ba8; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
+        </div>
+    }
+}
+
+// "Firefox on Linux"-style summary of a User-Agent header
+fn device_name(user_agent: Option<&str>) -> String {
+    let Some(agent) = user_agent.filter(|agent| !agent.is_empty()) else {
+        return "unknown device".to_string();
+    };
+    // Order matters: Edge and Chrome also claim to be Safari, Edge also claims to be Chrome
+    let browser = [("Firefox/", "Firefox"), ("Edg/", "Edge"), ("OPR/", "Opera"), ("Chrome/", "Chrome"), ("Safari/", "Safari")]
+        .into_iter()
+        .find(|(marker, _)| agent.contains(marker))
+        .map(|(_, name)| name);
+    let system = [("Android", "Android"), ("iPhone", "iPhone"), ("iPad", "iPad"), ("Windows", "Windows"), ("Mac OS X", "macOS"), ("Linux", "Linux")]
+        .into_iter()
+        .find(|(marker, _)| agent.contains(marker))
+        .map(|(_, name)| name);
+    match (browser, system) {
+        (Some(browser), Some(system)) => format!("{} on {}", browser, system),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        // Scripts and CLI clients, e.g. "curl/8.5.0"
+        (None, None) => agent.split_whitespace().next().unwrap_or(agent).chars().take(40).collect(),
+    }
+}
+
+// Personal access tokens for scripts: the list with revoke buttons and a form for new ones
+#[component]
+fn ApiTokens() -> impl IntoView {
+    let role = expect_context::<CurrentRole>();
+    let tokens = create_rw_signal(Vec::<ApiTokenInfo>::new());
+    let scopes = create_rw_signal(role.0.get_untracked().map(|role| role.scopes()[..1].to_vec()).unwrap_or_default());
+    let new_secret = create_rw_signal(None::<String>);
+    let (name, set_name) = create_signal(String::new());
+    let (expiry, set_expiry) = create_signal("90".to_string());
+    let (message, set_message) = create_signal(None::<String>);
+
+    let reload = move || {
+        spawn_local(async move {
+            match load_tokens().await {
+                Ok(response) => tokens.set(response.tokens),
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+    reload();
+
+    let create = move || {
+        let request = CreateTokenRequest {
+            name: name.get_untracked(),
+            scopes: scopes.get_untracked(),
+            expires_in_days: expiry.get_untracked().parse().ok(),
+        };
+        spawn_local(async move {
+            match two_factor_request::<CreateTokenResponse>("/tokens", &request).await {
+                Ok(response) => {
+                    set_message.set(None);
+                    set_name.set(String::new());
+                    new_secret.set(Some(response.secret));
+                    reload();
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+
+    let revoke = move |id: String| {
+        spawn_local(async move {
+            match two_factor_request::<ApiResponse>(&format!("/tokens/{}/revoke", id), &serde_json::json!({})).await {
+                Ok(_) => {
+                    set_message.set(None);
+                    reload();
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+
+    let toggle_scope = move |scope: Scope, checked: bool| {
+        scopes.update(|scopes| {
+            scopes.retain(|s| *s != scope);
+            if checked {
+                scopes.push(scope);
+            }
+        });
+    };
+
+    view! {
+        <div class="api-tokens">
+            <div class="two-factor-note">"api tokens, sent as \"Authorization: Bearer <token>\""</div>
+            <Show when=move || new_secret.get().is_some()>
+                <p class="two-factor-note">"copy this token now, it won't be shown again"</p>
+                <div class="two-factor-secret"><code>{move || new_secret.get().unwrap_or_default()}</code></div>
+            </Show>
+            <For
+                each=move || tokens.get()
+                key=|token| token.id.clone()
+                children=move |token| {
+                    let id = token.id.clone();
+                    let scopes = token.scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(", ");
+                    let used = token.last_used.map_or("never used".to_string(), |time| format!("used {}", time_ago(time)));
+                    let expires = match token.expires {
+                        Some(time) => format!("expires in {}", time_until(time)),
+                        None => "never expires".to_string(),
+                    };
+                    view! {
+                        <div class="api-token">
+                            <span class="api-token-name">{token.name.clone()}</span>
+                            <span class="two-factor-note">{format!("{} · {} · {}", scopes, used, expires)}</span>
+                            <button type="button" class="entry-link" on:click=move |_| revoke(id.clone())>"revoke"</button>
+                        </div>
+                    }
+                }
+            />
+            <div class="meta-editor">
+                <input
+                    type="text"
+                    class="meta-input"
+                    placeholder="token name"
+                    prop:value=name
+                    on:input=move |ev| set_name.set(event_target_value(&ev))
+                />
+                <select class="meta-input" on:change=move |ev| set_expiry.set(event_target_value(&ev))>
+                    <option value="7">"7 days"</option>
+                    <option value="30">"30 days"</option>
+                    <option value="90" selected>"90 days"</option>
+                    <option value="365">"1 year"</option>
+                    <option value="never">"no expiry"</option>
+                </select>
+                <button type="button" class="entry-link" on:click=move |_| create()>"create token"</button>
+            </div>
+            <div class="api-token-scopes">
+                {Scope::ALL.into_iter().filter(|scope| role.can(*scope)).map(|scope| view! {
+                    <label class="two-factor-note">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || scopes.get().contains(&scope)
+                            on:change=move |ev| toggle_scope(scope, event_target_checked(&ev))
+                        />
+                        {scope.as_str()}
+                    </label>
+                }).collect_view()}
+            </div>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f38ba8; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
+        </div>
+    }
+}
+
+#[component]
+pub fn SearchSection(
+    search_term: ReadSignal<String>,
+    set_search_term: WriteSignal<String>,
+    content_search: RwSignal<bool>,
+    search_hits: RwSignal<Option<Result<Vec<SearchHit>, String>>>,
+    listing: FileListing,
+) -> impl IntoView {
+    let query = listing.query;
+    let known_tags = create_rw_signal(Vec::new());
+    let shared_with_me = create_rw_signal(Vec::<FolderShare>::new());
+
+    // Refresh the tag list whenever the file list is reloaded, since tags may have changed.
+    // The same goes for the folders others have shared.
+    create_effect(move |_| {
+        listing.files.track();
+        spawn_local(async move {
+            match load_tags().await {
+                Ok(response) => known_tags.set(response.tags.into_iter().take(MAX_TAG_FILTERS).collect()),
+                Err(e) => web_sys::console::log_1(&format!("Error loading tags: {}", e).into()),
+            }
+            match load_shares().await {
+                Ok(response) => shared_with_me.set(response.shared_with_me),
+                Err(e) => web_sys::console::log_1(&format!("Error loading shared folders: {}", e).into()),
+            }
+        });
+    });
+
+    view! {
+        <div>
+            <input 
+                type="text"
+                class="search-input border-container"
+                placeholder=move || if content_search.get() { "search inside files..." } else { "search files..." }
+                prop:value=search_term
+                on:input=move |ev| {
+                    let value = event_target_value(&ev);
+                    set_search_term.set(value);
+                }
+            />
+            <div style="display: flex; justify-content: space-between; align-items: center; margin-top: 8px;">
+                <span style="color: #6c7086; font-size: 12px;">"use # to filter by type, tag: to filter by tag"</span>
+                <label style="color: #bac2de; font-size: 12px; cursor: pointer;">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || content_search.get()
+                        on:change=move |ev| content_search.set(event_target_checked(&ev))
+                    />
+                    " search contents"
+                </label>
+            </div>
+            <Show when=move || !known_tags.with(|tags| tags.is_empty())>
+                <div class="tag-list" style="margin-top: 10px;">
+                    <For
+                        each=move || known_tags.get()
+                        key=|tag| (tag.tag.clone(), tag.count)
+                        let:tag
+                    >
+                        <button
+                            type="button"
+                            class="tag-chip tag-filter"
+                            class:active={
+                                let filter = format!("tag:{}", tag.tag);
+                                move || search_term.with(|search| search.split_whitespace().any(|word| word == filter))
+                            }
+                            on:click={
+                                let filter = format!("tag:{}", tag.tag);
+                                move |_| {
+                                    let search = search_term.get_untracked();
+                                    let words: Vec<&str> = search.split_whitespace().collect();
+                                    let updated = if words.contains(&filter.as_str()) {
+                                        words.into_iter().filter(|word| *word != filter).collect::<Vec<_>>().join(" ")
+                                    } else {
+                                        format!("{} {}", search.trim(), filter).trim().to_string()
+                                    };
+                                    set_search_term.set(updated);
+                                }
+                            }
+                        >
+                            {format!("#{} ({})", tag.tag, tag.count)}
+                        </button>
+                    </For>
+                </div>
+            </Show>
+            {move || search_hits.get().map(|result| match result {
+                Ok(hits) if hits.is_empty() => view! {
+                    <div class="search-results" style="color: #6c7086; font-size: 13px;">"no files contain that"</div>
+                }.into_view(),
+                Ok(hits) => view! {
+                    <div class="search-results">
+                        {hits.into_iter().map(|hit| view! { <SearchHitRow hit=hit /> }).collect_view()}
+                    </div>
+                }.into_view(),
+                Err(e) => view! {
+                    <div class="search-results" style="color: #f38ba8; font-size: 13px;">{e}</div>
+                }.into_view(),
+            })}
+            <div class="filter-row">
+                <button
+                    type="button"
+                    class="action-btn border-container"
+                    class:view-active=move || query.with(|query| {
+                        query.starred.is_none() && query.folder.is_none() && query.sort.as_deref() != Some("recent")
+                    })
+                    on:click=move |_| query.update(|query| {
+                        query.starred = None;
+                        query.folder = None;
+                        if query.sort.as_deref() == Some("recent") {
+                            query.sort = None;
+                            query.order = None;
+                        }
+                    })
+                >
+                    "all"
+                </button>
+                <button
+                    type="button"
+                    class="action-btn border-container"
+                    class:view-active=move || query.with(|query| query.starred == Some(true))
+                    on:click=move |_| query.update(|query| query.starred = Some(true))
+                >
+                    "★ starred"
+                </button>
+                <button
+                    type="button"
+                    class="action-btn border-container"
+                    class:view-active=move || query.with(|query| query.sort.as_deref() == Some("recent"))
+                    on:click=move |_| query.update(|query| {
+                        query.starred = None;
+                        query.sort = Some("recent".to_string());
+                        query.order = Some("desc".to_string());
+                    })
+                >
+                    "recent"
+                </button>
+            </div>
+            <Show when=move || !shared_with_me.with(|shares| shares.is_empty())>
+                <div class="filter-row">
+                    <span style="color: #bac2de; font-size: 12px;">"shared with me:"</span>
+                    <For
+                        each=move || shared_with_me.get()
+                        key=|share| (share.folder.clone(), share.access)
+                        let:share
+                    >
+                        <button
+                            type="button"
+                            class="tag-chip tag-filter"
+                            title=format!("shared by {}", share.owner)
+                            class:active={
+                                let folder = share.folder.clone();
+                                move || query.with(|query| query.folder.as_deref() == Some(folder.as_str()))
+                            }
+                            on:click={
+                                let folder = share.folder.clone();
+                                move |_| query.update(|query| {
+                                    query.folder = if query.folder.as_deref() == Some(folder.as_str()) {
+                                        None
+                                    } else {
+                                        Some(folder.clone())
+                                    };
+                                })
+                            }
+                        >
+                            {format!(
+                                "{}/ ({})",
+                                share.folder,
+                                share.access.map(Access::as_str).unwrap_or("read"),
+                            )}
+                        </button>
+                    </For>
+                </div>
+            </Show>
+            <div class="filter-row">
+                <select
+                    class="format-select"
+                    prop:value=move || query.with(|query| query.sort.clone().unwrap_or_else(|| "folder".to_string()))
+                    on:change=move |ev| {
+                        let sort = event_target_value(&ev);
+                        query.update(|query| query.sort = (sort != "folder").then_some(sort));
+                    }
+                >
+                    <option value="folder">"sort: folder"</option>
+                    <option value="name">"sort: name"</option>
+                    <option value="size">"sort: size"</option>
+                    <option value="modified">"sort: date"</option>
+                    <option value="type">"sort: type"</option>
+                    <option value="recent">"sort: recent"</option>
+                </select>
+                <button
+                    type="button"
+                    class="action-btn border-container"
+                    on:click=move |_| query.update(|query| {
+                        query.order = match query.order.as_deref() {
+                            Some("desc") => None,
+                            _ => Some("desc".to_string()),
+                        };
+                    })
+                >
+                    {move || if query.with(|query| query.order.as_deref() == Some("desc")) { "↓ desc" } else { "↑ asc" }}
+                </button>
+            </div>
+            <div class="filter-row">
+                <input
+                    type="number"
+                    min="0"
+                    class="filter-input"
+                    placeholder="min MB"
+                    on:change=move |ev| query.update(|query| query.min_size = parse_megabytes(&event_target_value(&ev)))
+                />
+                <input
+                    type="number"
+                    min="0"
+                    class="filter-input"
+                    placeholder="max MB"
+                    on:change=move |ev| query.update(|query| query.max_size = parse_megabytes(&event_target_value(&ev)))
+                />
+                <input
+                    type="date"
+                    class="filter-input"
+                    title="modified on or after"
+                    on:change=move |ev| {
+                        let date = event_target_value(&ev);
+                        query.update(|query| query.modified_after = (!date.is_empty()).then_some(date));
+                    }
+                />
+                <input
+                    type="date"
+                    class="filter-input"
+                    title="modified on or before"
+                    on:change=move |ev| {
+                        let date = event_target_value(&ev);
+                        query.update(|query| query.modified_before = (!date.is_empty()).then_some(date));
+                    }
+                />
+            </div>
+        </div>
+    }
+}
+
+#[component]
+pub fn ActivitySection(listing: FileListing) -> impl IntoView {
+    let events = create_rw_signal(Vec::<ActivityEvent>::new());
+
+    // Anything that changes files also reloads the list, so follow it
+    create_effect(move |_| {
+        listing.files.track();
+        spawn_local(async move {
+            match load_activity().await {
+                Ok(loaded) => events.set(loaded),
+                Err(e) => web_sys::console::log_1(&format!("Error loading activity: {}", e).into()),
+            }
+        });
+    });
+
+    view! {
+        <Show
+            when=move || !events.with(|events| events.is_empty())
+            fallback=|| view! { <div style="color: #6c7086; font-size: 14px;">"nothing has happened yet"</div> }
+        >
+            <div class="activity-feed">
+                {move || events.get().into_iter().map(|event| {
+                    let name = display_name(&event.path);
+                    view! {
+                        <div class="activity-event" title=event.path.clone()>
+                            <span class="activity-time">{time_ago(event.time)}</span>
+                            <span style="color: #89b4fa;">{event.actor}</span>
+                            " "
+                            <span class=format!("activity-action activity-{}", event.action)>{event.action.clone()}</span>
+                            " "
+                            <span style="color: #cdd6f4;">{name}</span>
+                            {event.detail.map(|detail| view! {
+                                <span style="color: #6c7086;">{format!(" ({})", detail)}</span>
+                            })}
+                        </div>
+                    }
+                }).collect_view()}
+            </div>
+        </Show>
+    }
+}
+
+// "3m ago"-style age of a Unix timestamp
+fn time_ago(time: u64) -> String {
+    let now = (js_sys::Date::now() / 1000.0) as u64;
+    let seconds = now.saturating_sub(time);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86_399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86_400),
+    }
+}
+
+// "3d"-style time left until a Unix timestamp
+fn time_until(time: u64) -> String {
+    let now = (js_sys::Date::now() / 1000.0) as u64;
+    let seconds = time.saturating_sub(now);
+    match seconds {
+        0..=3599 => format!("{}m", seconds / 60),
+        3600..=86_399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86_400),
+    }
+}
+
+// Last path component without the UUID prefix uploads get
+fn display_name(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.split_once('_') {
+        Some((prefix, rest)) if prefix.len() == 36 && !rest.is_empty() => rest.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+#[component]
+fn SearchHitRow(hit: SearchHit) -> impl IntoView {
+    let location = if hit.folder.is_empty() { String::new() } else { format!("{}/", hit.folder) };
+    let preview_url = download_url(&hit.path, false);
+    let download_url = download_url(&hit.path, true);
+
+    view! {
+        <div class="search-hit">
+            <div style="display: flex; justify-content: space-between; gap: 10px;">
+                <span style="color: #cdd6f4; word-break: break-all;">
+                    <span style="color: #6c7086;">{location}</span>
+                    {hit.name.clone()}
+                </span>
+                <span style="display: flex; gap: 10px; flex-shrink: 0;">
+                    <Show when={
+                        let file_type = hit.file_type.clone();
+                        move || hit.can_preview && is_previewable_file(&file_type)
+                    }>
+                        <a href=preview_url.clone() class="entry-link" target="_blank">"preview"</a>
+                    </Show>
+                    <a href=download_url class="entry-link" download>"download"</a>
+                </span>
+            </div>
+            // The server escapes the document text and only adds <b> around matches
+            <div class="search-snippet" inner_html=hit.snippet></div>
+        </div>
+    }
+}
+
+#[component]
+pub fn UploadSection<F>(
+    debug_mode: ReadSignal<bool>,
+    on_upload_complete: F,
+) -> impl IntoView 
+where
+    F: Fn() + Copy + 'static,
+{
+    let (selected_files, set_selected_files) = create_signal(Vec::<File>::new());
+    let (is_uploading, set_is_uploading) = create_signal(false);
+    let file_input_ref = create_node_ref::<leptos::html::Input>();
+
+    let on_file_change = move |_ev: Event| {
+        web_sys::console::log_1(&"File input changed".into());
+        if let Some(input) = file_input_ref.get_untracked() {
+            if let Some(files) = input.files() {
+                let file_list = FileList::from(files);
+                let files_vec: Vec<File> = file_list.iter().cloned().collect();
+                web_sys::console::log_1(&format!("Selected {} files", files_vec.len()).into());
+                set_selected_files.set(files_vec);
+            } else {
+                web_sys::console::log_1(&"No files found".into());
+            }
+        } else {
+            web_sys::console::log_1(&"Input ref not found".into());
+        }
+    };
+
+    let on_choose_files_click = move |e: web_sys::MouseEvent| {
+        e.prevent_default();
+        e.stop_propagation();
+        web_sys::console::log_1(&"Choose files button clicked".into());
+        if let Some(input) = file_input_ref.get_untracked() {
+            web_sys::console::log_1(&"Triggering file input click".into());
+            input.click();
+        } else {
+            web_sys::console::log_1(&"File input ref not found".into());
+        }
+    };
+
+    let on_upload_click = move |e: web_sys::MouseEvent| {
+        e.prevent_default();
+        e.stop_propagation();
+        
+        let files = selected_files.get();
+        web_sys::console::log_1(&format!("Upload button clicked, files count: {}", files.len()).into());
+        
+        if files.is_empty() {
+            web_sys::console::log_1(&"No files selected".into());
+            return;
+        }
+
+        if is_uploading.get() {
+            web_sys::console::log_1(&"Upload already in progress, ignoring click".into());
+            return;
+        }
+        
+        web_sys::console::log_1(&"Starting upload...".into());
+        set_is_uploading.set(true);
+        
+        spawn_local(async move {
+            web_sys::console::log_1(&"In spawn_local...".into());
+            match upload_files(files).await {
+                Ok(response) => {
+                    web_sys::console::log_1(&format!("Upload successful: {} (uploaded {} files)", response.message, response.files.len()).into());
+                    
+                    // Clear the upload state first
+                    set_selected_files.set(Vec::new());
+                    if let Some(input) = file_input_ref.get_untracked() {
+                        input.set_value("");
+                    }
+                    set_is_uploading.set(false);
+                    
+                    // Add a longer delay before calling the completion callback to ensure all state is settled
+                    gloo_timers::future::TimeoutFuture::new(100).await;
+                    
+                    // Call the callback to refresh data
+                    web_sys::console::log_1(&"Calling upload complete callback after successful upload".into());
+                    on_upload_complete();
+                },
+                Err(e) => {
+                    web_sys::console::log_1(&format!("Upload failed: {}", e).into());
+                    log::error!("Upload failed: {}", e);
+                    set_is_uploading.set(false);
+                }
+            }
+        });
+    };
+
+    let on_submit = move |ev: web_sys::SubmitEvent| {
+        ev.prevent_default();
+        web_sys::console::log_1(&"Form submitted".into());
+        // Form submission will be handled by button click
+    };
+
+    view! {
+        <div>
+            <form on:submit=on_submit>
+                <div style="margin-bottom: 15px;">
+                    <input
+                        type="file"
+                        id="fileInput"
+                        multiple
+                        ref=file_input_ref
+                        on:change=on_file_change
+                        accept="*/*"
+                        style="display: none;"
+                    />
+                    <button 
+                        type="button"
+                        class="choose-files-btn border-container"
+                        on:click=on_choose_files_click
+                    >
+                        "choose files"
+                    </button>
+                </div>
+                
+                <Show when=move || !selected_files.get().is_empty()>
+                    <div style="margin-bottom: 15px; text-align: left;">
+                        <div style="color: #bac2de; font-size: 14px; margin-bottom: 8px;">
+                            "selected:"
+                        </div>
+                        <div style="max-height: 80px; overflow-y: auto;">
+                            <For
+                                each=move || selected_files.get()
+                                key=|file| file.name()
+                                let:file
+                            >
+                                <div style="color: #a6adc8; font-size: 13px; margin: 2px 0;">
+                                    {file.name()}
+                                </div>
+                            </For>
+                        </div>
+                    </div>
+                </Show>
+                
+                <Show when=move || debug_mode.get()>
+                    <div style="margin: 10px 0; color: #6c7086; font-size: 12px;">
+                        "debug: " {move || selected_files.get().len()} " files | "
+                        {move || if is_uploading.get() { "uploading..." } else { "ready" }}
+                    </div>
+                </Show>
+                
+                <button 
+                    type="button"
+                    class="upload-files-btn border-container"
+                    disabled=move || selected_files.get().is_empty() || is_uploading.get()
+                    on:click=on_upload_click
+                >
+                    {move || if is_uploading.get() { "uploading..." } else { "upload files" }}
+                </button>
+            </form>
+        </div>
+    }
+}
+
+#[component]
+fn FileItem(
     file: FileInfo,
-    set_files: WriteSignal<Vec<FileInfo>>,
+    selected: RwSignal<HashSet<String>>,
+    selection_mode: RwSignal<bool>,
+    on_select: Callback<(String, bool)>,
+    listing: FileListing,
     set_storage_info: WriteSignal<Option<StorageInfo>>,
     set_is_loading: WriteSignal<bool>,
 ) -> impl IntoView {
     let file_name = file.name.clone();
     let file_path = file.path.clone();
+    let file_folder = file.folder.clone();
     let file_type = file.file_type.clone();
     let file_size = file.size;
+    let file_checksum = file.sha256.clone();
+    let file_tags = file.tags.clone();
+    let starred = create_rw_signal(file.starred);
+    let file_path_star = file_path.clone();
+    let file_properties = file.properties.clone();
     
     // Create multiple clones for different uses
     let file_path_preview = file_path.clone();
     let file_path_download = file_path.clone();
     let file_path_preview_btn = file_path.clone();
-    let file_path_delete = file_path.clone();
+    // Stored so the buttons that only some roles see can be rebuilt when the role changes
+    let file_path_delete = store_value(file_path.clone());
+    let file_path_checked = file_path.clone();
+    let file_path_toggle = file_path.clone();
+    let file_path_browse = file_path.clone();
+    let file_path_extract = store_value(file_path.clone());
+    let file_path_entries = file_path.clone();
+    let can_open = can_open_archive(&file_name);
+    let role = expect_context::<CurrentRole>();
+
+    let (archive_listing, set_archive_listing) = create_signal(None::<ArchiveListing>);
+    let (show_entries, set_show_entries) = create_signal(false);
+    let (entry_preview, set_entry_preview) = create_signal(None::<String>);
+    let (archive_message, set_archive_message) = create_signal(None::<String>);
     let file_type_preview_check = file_type.clone();
     let file_type_preview_check_2 = file_type.clone(); // Additional clone for the second Show
     let file_type_preview = file_type.clone();
@@ -496,9 +1846,46 @@ fn FileItem(
     view! {
         <div class="file-item">
             <div style="display: flex; justify-content: space-between; align-items: start; margin-bottom: 15px;">
+                <Show when=move || selection_mode.get()>
+                    <input
+                        type="checkbox"
+                        class="file-select"
+                        prop:checked={
+                            let path = file_path_checked.clone();
+                            move || selected.with(|paths| paths.contains(&path))
+                        }
+                        on:click={
+                            let path = file_path_toggle.clone();
+                            move |ev: web_sys::MouseEvent| on_select.call((path.clone(), ev.shift_key()))
+                        }
+                    />
+                </Show>
                 <div style="color: #cdd6f4; font-weight: 500; word-break: break-word; flex: 1; margin-right: 10px;">
                     {&file_name}
+                    {(!file_folder.is_empty()).then(|| view! {
+                        <div style="color: #6c7086; font-size: 12px; font-weight: 400; margin-top: 4px;">
+                            {format!("{}/", file_folder)}
+                        </div>
+                    })}
                 </div>
+                <button
+                    type="button"
+                    class="star-btn"
+                    class:starred=move || starred.get()
+                    title=move || if starred.get() { "unstar" } else { "star" }
+                    on:click=move |_| {
+                        let path = file_path_star.clone();
+                        let wanted = !starred.get_untracked();
+                        spawn_local(async move {
+                            match star_file_api(&path, wanted).await {
+                                Ok(response) => starred.set(response.starred),
+                                Err(e) => web_sys::console::log_1(&format!("Star failed: {}", e).into()),
+                            }
+                        });
+                    }
+                >
+                    {move || if starred.get() { "★" } else { "☆" }}
+                </button>
                 <span 
                     class="file-type-badge"
                     style=format!("
@@ -519,7 +1906,7 @@ fn FileItem(
                         if file_type_preview == "image" {
                             view! {
                                 <img 
-                                    src=format!("/download/{}", file_path_preview)
+                                    src=download_url(&file_path_preview, false)
                                     alt=file_name.clone()
                                     style="max-width: 100%; max-height: 250px; object-fit: contain;"
                                     loading="lazy"
@@ -532,7 +1919,7 @@ fn FileItem(
                                     style="max-width: 100%; max-height: 250px;"
                                     preload="metadata"
                                 >
-                                    <source src=format!("/download/{}", file_path_preview) />
+                                    <source src=download_url(&file_path_preview, false) />
                                     "Your browser does not support the video tag."
                                 </video>
                             }.into_view()
@@ -554,11 +1941,18 @@ fn FileItem(
             
             <div style="color: #a6adc8; margin-bottom: 20px; font-size: 14px;">
                 "size: " {format_file_size(file_size)}
+                {file_checksum.map(|checksum| view! {
+                    <div style="color: #6c7086; font-size: 12px; margin-top: 4px;" title=checksum.clone()>
+                        "sha256: " {checksum.chars().take(12).collect::<String>()} "…"
+                    </div>
+                })}
             </div>
+
+            <FileMetaPanel path=file_path.clone() tags=file_tags properties=file_properties />
             
             <div style="display: flex; gap: 10px; flex-wrap: wrap; margin-top: auto;">
                 <a 
-                    href=format!("/download/{}", file_path_download)
+                    href=download_url(&file_path_download, true)
                     class="action-btn border-container"
                     download
                 >
@@ -567,7 +1961,7 @@ fn FileItem(
                 
                 <Show when=move || is_previewable_file(&file_type_preview_btn)>
                     <a 
-                        href=format!("/download/{}", file_path_preview_btn)
+                        href=download_url(&file_path_preview_btn, false)
                         class="action-btn border-container"
                         target="_blank"
                     >
@@ -575,48 +1969,610 @@ fn FileItem(
                     </a>
                 </Show>
                 
+                <Show when=move || role.can(Scope::Delete)>
+                    <button
+                        type="button"
+                        class="action-btn delete-btn border-container"
+                        on:click={
+                            move |e| {
+                                e.prevent_default();
+                                let file_path = file_path_delete.get_value();
+                                spawn_local(async move {
+                                    match post_request(&format!("/delete/{}", file_path))
+                                        .credentials(RequestCredentials::Include)
+                                        .send().await {
+                                        Ok(_) => {
+                                            spawn_local(async move {
+                                                load_files_and_storage(listing, set_storage_info, set_is_loading).await;
+                                            });
+                                        }
+                                        Err(e) => {
+                                            web_sys::console::log_1(&format!("Delete failed: {}", e).into());
+                                        }
+                                    }
+                                });
+                            }
+                        }
+                    >
+                        "delete"
+                    </button>
+                </Show>
+
+                <Show when=move || can_open>
+                    <button
+                        type="button"
+                        class="action-btn border-container"
+                        on:click={
+                            let file_path = file_path_browse.clone();
+                            move |_| {
+                                if show_entries.get() {
+                                    set_show_entries.set(false);
+                                    return;
+                                }
+                                set_show_entries.set(true);
+                                let file_path = file_path.clone();
+                                spawn_local(async move {
+                                    match load_archive_listing(&file_path).await {
+                                        Ok(listing) => set_archive_listing.set(Some(listing)),
+                                        Err(e) => set_archive_message.set(Some(e)),
+                                    }
+                                });
+                            }
+                        }
+                    >
+                        {move || if show_entries.get() { "hide contents" } else { "browse" }}
+                    </button>
+                    <Show when=move || role.can(Scope::Write)>
+                        <button
+                            type="button"
+                            class="action-btn border-container"
+                            on:click={
+                                move |_| {
+                                    let file_path = file_path_extract.get_value();
+                                    spawn_local(async move {
+                                        match extract_archive_api(&file_path).await {
+                                            Ok(response) => {
+                                                set_archive_message.set(Some(response.message));
+                                                if response.success {
+                                                    load_files_and_storage(listing, set_storage_info, set_is_loading).await;
+                                                }
+                                            }
+                                            Err(e) => set_archive_message.set(Some(e)),
+                                        }
+                                    });
+                                }
+                            }
+                        >
+                            "extract here"
+                        </button>
+                    </Show>
+                </Show>
+            </div>
+
+            <Show when=move || archive_message.get().is_some()>
+                <div style="color: #f9e2af; font-size: 13px; margin-top: 10px;">
+                    {move || archive_message.get().unwrap_or_default()}
+                </div>
+            </Show>
+
+            <Show when=move || show_entries.get()>
+                <div class="archive-entries">
+                    {
+                        let file_path = file_path_entries.clone();
+                        move || match archive_listing.get() {
+                            None => view! { <div style="color: #6c7086;">"reading archive..."</div> }.into_view(),
+                            Some(listing) => {
+                                let file_path = file_path.clone();
+                                let entries: Vec<_> = listing.entries.into_iter().filter(|entry| !entry.is_dir).collect();
+                                view! {
+                                    <For
+                                        each=move || entries.clone()
+                                        key=|entry| entry.name.clone()
+                                        let:entry
+                                    >
+                                        <ArchiveEntryRow
+                                            archive_path=file_path.clone()
+                                            entry=entry
+                                            set_entry_preview=set_entry_preview
+                                        />
+                                    </For>
+                                }.into_view()
+                            }
+                        }
+                    }
+                    <Show when=move || entry_preview.get().is_some()>
+                        <pre class="entry-preview">{move || entry_preview.get().unwrap_or_default()}</pre>
+                    </Show>
+                </div>
+            </Show>
+        </div>
+    }
+}
+
+#[component]
+fn ArchiveEntryRow(
+    archive_path: String,
+    entry: ArchiveEntryInfo,
+    set_entry_preview: WriteSignal<Option<String>>,
+) -> impl IntoView {
+    let download_url = archive_entry_url(&archive_path, &entry.name, false);
+    let preview_url = archive_entry_url(&archive_path, &entry.name, true);
+    let can_view = entry.can_preview && (entry.file_type == "text" || entry.file_type == "code");
+
+    view! {
+        <div class="archive-entry">
+            <span style="flex: 1; word-break: break-all;">{entry.name}</span>
+            <span style="color: #6c7086;">{format_file_size(entry.size)}</span>
+            <a href=download_url class="entry-link">"get"</a>
+            <Show when=move || can_view>
                 <button
                     type="button"
-                    class="action-btn delete-btn border-container"
+                    class="entry-link"
                     on:click={
-                        move |e| {
-                            e.prevent_default();
-                            let file_path = file_path_delete.clone();
+                        let url = preview_url.clone();
+                        move |_| {
+                            let url = url.clone();
                             spawn_local(async move {
-                                match Request::post(&format!("/delete/{}", file_path))
-                                    .credentials(RequestCredentials::Include)
-                                    .send().await {
-                                    Ok(_) => {
-                                        spawn_local(async move {
-                                            load_files_and_storage(set_files, set_storage_info, set_is_loading).await;
-                                        });
-                                    }
-                                    Err(e) => {
-                                        web_sys::console::log_1(&format!("Delete failed: {}", e).into());
-                                    }
-                                }
+                                set_entry_preview.set(Some(load_entry_preview(&url).await));
                             });
                         }
                     }
                 >
-                    "delete"
+                    "view"
                 </button>
+            </Show>
+        </div>
+    }
+}
+
+// Tag chips and key/value metadata under a file, editable in place
+#[component]
+fn FileMetaPanel(
+    path: String,
+    tags: Vec<String>,
+    properties: BTreeMap<String, String>,
+) -> impl IntoView {
+    let tags = create_rw_signal(tags);
+    let properties = create_rw_signal(properties);
+    let editing = create_rw_signal(false);
+    let (new_tag, set_new_tag) = create_signal(String::new());
+    let (new_key, set_new_key) = create_signal(String::new());
+    let (new_value, set_new_value) = create_signal(String::new());
+    let (message, set_message) = create_signal(None::<String>);
+    let path = store_value(path);
+    let role = expect_context::<CurrentRole>();
+
+    let save = move |update: FileMetaUpdate| {
+        spawn_local(async move {
+            match update_file_meta_api(&path.get_value(), &update).await {
+                Ok(response) => {
+                    tags.set(response.tags);
+                    properties.set(response.properties);
+                    set_message.set(None);
+                }
+                Err(e) => set_message.set(Some(e)),
+            }
+        });
+    };
+
+    let add_tag = move || {
+        let tag = new_tag.get_untracked();
+        if tag.trim().is_empty() {
+            return;
+        }
+        set_new_tag.set(String::new());
+        save(FileMetaUpdate { add_tags: vec![tag], ..Default::default() });
+    };
+
+    let property_list = move || properties.get().into_iter().collect::<Vec<_>>();
+
+    let set_property = move || {
+        let key = new_key.get_untracked();
+        if key.trim().is_empty() {
+            return;
+        }
+        let mut set = BTreeMap::new();
+        set.insert(key, new_value.get_untracked());
+        set_new_key.set(String::new());
+        set_new_value.set(String::new());
+        save(FileMetaUpdate { set, ..Default::default() });
+    };
+
+    view! {
+        <div class="file-meta">
+            <div class="tag-list">
+                <For
+                    each=move || tags.get()
+                    key=|tag| tag.clone()
+                    let:tag
+                >
+                    <span class="tag-chip">
+                        {format!("#{}", tag)}
+                        <Show when=move || editing.get()>
+                            <button
+                                type="button"
+                                class="chip-remove"
+                                title="remove tag"
+                                on:click={
+                                    let tag = tag.clone();
+                                    move |_| save(FileMetaUpdate { remove_tags: vec![tag.clone()], ..Default::default() })
+                                }
+                            >
+                                "×"
+                            </button>
+                        </Show>
+                    </span>
+                </For>
+                <Show when=move || role.can(Scope::Write)>
+                    <button
+                        type="button"
+                        class="entry-link"
+                        on:click=move |_| editing.update(|editing| *editing = !*editing)
+                    >
+                        {move || if editing.get() { "done" } else { "edit tags" }}
+                    </button>
+                </Show>
             </div>
+            <For
+                each=property_list
+                key=|(key, value)| (key.clone(), value.clone())
+                let:property
+            >
+                <div class="meta-property">
+                    <span style="color: #6c7086;">{format!("{}: ", property.0)}</span>
+                    <span>{property.1.clone()}</span>
+                    <Show when=move || editing.get()>
+                        <button
+                            type="button"
+                            class="chip-remove"
+                            title="remove"
+                            on:click={
+                                let key = property.0.clone();
+                                move |_| save(FileMetaUpdate { unset: vec![key.clone()], ..Default::default() })
+                            }
+                        >
+                            "×"
+                        </button>
+                    </Show>
+                </div>
+            </For>
+            <Show when=move || editing.get()>
+                <div class="meta-editor">
+                    <input
+                        type="text"
+                        class="meta-input"
+                        placeholder="tag"
+                        prop:value=new_tag
+                        on:input=move |ev| set_new_tag.set(event_target_value(&ev))
+                        on:keydown=move |ev| if ev.key() == "Enter" { add_tag() }
+                    />
+                    <button type="button" class="entry-link" on:click=move |_| add_tag()>"add tag"</button>
+                </div>
+                <div class="meta-editor">
+                    <input
+                        type="text"
+                        class="meta-input"
+                        placeholder="key"
+                        prop:value=new_key
+                        on:input=move |ev| set_new_key.set(event_target_value(&ev))
+                    />
+                    <input
+                        type="text"
+                        class="meta-input"
+                        placeholder="value"
+                        prop:value=new_value
+                        on:input=move |ev| set_new_value.set(event_target_value(&ev))
+                        on:keydown=move |ev| if ev.key() == "Enter" { set_property() }
+                    />
+                    <button type="button" class="entry-link" on:click=move |_| set_property()>"set"</button>
+                </div>
+            </Show>
+            <Show when=move || message.get().is_some()>
+                <div style="color: #f38ba8; font-size: 12px; margin-top: 4px;">
+                    {move || message.get().unwrap_or_default()}
+                </div>
+            </Show>
         </div>
     }
 }
 
 #[component]
 fn FilesSection(
-    files: Memo<Vec<FileInfo>>,
+    listing: FileListing,
     is_loading: ReadSignal<bool>,
-    set_files: WriteSignal<Vec<FileInfo>>,
     set_storage_info: WriteSignal<Option<StorageInfo>>,
     set_is_loading: WriteSignal<bool>,
 ) -> impl IntoView 
 {
+    let files = listing.files;
+    let selected = create_rw_signal(HashSet::<String>::new());
+    let selection_mode = create_rw_signal(false);
+    let last_clicked = create_rw_signal(None::<String>);
+    let (archive_format, set_archive_format) = create_signal("zip".to_string());
+    let (destination, set_destination) = create_signal(String::new());
+    let (tag_input, set_tag_input) = create_signal(String::new());
+    let (batch_message, set_batch_message) = create_signal(None::<String>);
+    // Folder whose sharing settings are open
+    let sharing_folder = create_rw_signal(None::<String>);
+    let role = expect_context::<CurrentRole>();
+
+    // Fetch the next page once the user scrolls near the bottom of the list
+    let scroll_handle = window_event_listener(ev::scroll, move |_| {
+        if listing.next_cursor.get_untracked().is_none() || listing.loading_more.get_untracked() {
+            return;
+        }
+        let window = window();
+        let viewport = window.inner_height().ok().and_then(|height| height.as_f64()).unwrap_or(0.0);
+        let scrolled = window.scroll_y().unwrap_or(0.0);
+        let page_height = document()
+            .document_element()
+            .map(|element| element.scroll_height() as f64)
+            .unwrap_or(0.0);
+        if scrolled + viewport >= page_height - 400.0 {
+            spawn_local(async move {
+                load_more_files(listing).await;
+            });
+        }
+    });
+    on_cleanup(move || scroll_handle.remove());
+
+    let has_filters = move || listing.query.with(|query| {
+        query.q.is_some()
+            || query.file_type.is_some()
+            || query.tag.is_some()
+            || query.min_size.is_some()
+            || query.max_size.is_some()
+            || query.modified_after.is_some()
+            || query.modified_before.is_some()
+    });
+
+    // Drop selections for files that disappeared after a reload
+    create_effect(move |_| {
+        let current: HashSet<String> = files.get().into_iter().map(|file| file.path).collect();
+        selected.update(|paths| paths.retain(|path| current.contains(path)));
+    });
+
+    // Folders that currently hold files, each offered as a single archive download
+    let folders = create_memo(move |_| {
+        let mut folders: Vec<String> = files.get()
+            .into_iter()
+            .map(|file| file.folder)
+            .filter(|folder| !folder.is_empty())
+            .collect();
+        folders.sort();
+        folders.dedup();
+        folders
+    });
+
+    // Plain click toggles one file; shift-click selects everything between it and the last click
+    let on_select = Callback::new(move |(path, shift): (String, bool)| {
+        let anchor = last_clicked.get_untracked();
+        match anchor {
+            Some(anchor) if shift && anchor != path => {
+                let paths: Vec<String> = files.get_untracked().into_iter().map(|file| file.path).collect();
+                let start = paths.iter().position(|p| *p == anchor);
+                let end = paths.iter().position(|p| *p == path);
+                if let (Some(start), Some(end)) = (start, end) {
+                    let (from, to) = if start <= end { (start, end) } else { (end, start) };
+                    selected.update(|selected| selected.extend(paths[from..=to].iter().cloned()));
+                }
+            }
+            _ => {
+                selected.update(|selected| {
+                    if !selected.remove(&path) {
+                        selected.insert(path.clone());
+                    }
+                });
+            }
+        }
+        last_clicked.set(Some(path));
+    });
+
+    let on_download_selected = move |_| {
+        let paths: Vec<String> = selected.get().into_iter().collect();
+        if paths.is_empty() {
+            return;
+        }
+        if let Err(e) = window().location().set_href(&archive_url(&paths, &archive_format.get())) {
+            web_sys::console::log_1(&format!("Failed to start archive download: {:?}", e).into());
+        }
+    };
+
+    // Send a batch request for the current selection, report the outcome and refresh the list
+    let run_batch = move |action: &'static str, body: serde_json::Value| {
+        spawn_local(async move {
+            match batch_api(action, &body).await {
+                Ok(response) => {
+                    let failures: Vec<String> = response.results.iter()
+                        .filter(|result| !result.success)
+                        .map(|result| format!("{}: {}", result.id, result.message))
+                        .collect();
+                    let message = if failures.is_empty() {
+                        response.message
+                    } else {
+                        format!("{} ({})", response.message, failures.join("; "))
+                    };
+                    set_batch_message.set(Some(message));
+                    if action != "tag" && action != "copy" {
+                        selected.set(HashSet::new());
+                    }
+                }
+                Err(e) => set_batch_message.set(Some(e)),
+            }
+            load_files_and_storage(listing, set_storage_info, set_is_loading).await;
+        });
+    };
+
+    let selected_ids = move || selected.get().into_iter().collect::<Vec<String>>();
+
     view! {
         <div>
+            <Show when=move || !files.get().is_empty()>
+                <div class="selection-toolbar">
+                    <button
+                        type="button"
+                        class="action-btn border-container"
+                        on:click=move |_| {
+                            selection_mode.update(|mode| *mode = !*mode);
+                            selected.set(HashSet::new());
+                            last_clicked.set(None);
+                            set_batch_message.set(None);
+                        }
+                    >
+                        {move || if selection_mode.get() { "done" } else { "select" }}
+                    </button>
+                    <Show when=move || !folders.get().is_empty()>
+                        <span style="color: #bac2de; font-size: 14px;">"folders:"</span>
+                        <For
+                            each=move || folders.get()
+                            key=|folder| folder.clone()
+                            let:folder
+                        >
+                            <a
+                                href={
+                                    let folder = folder.clone();
+                                    move || archive_url(std::slice::from_ref(&folder), &archive_format.get())
+                                }
+                                class="action-btn border-container"
+                            >
+                                {format!("{}/ ⤓", folder)}
+                            </a>
+                            <Show when=move || role.can(Scope::Write)>
+                                <button
+                                    type="button"
+                                    class="entry-link"
+                                    on:click={
+                                        let folder = folder.clone();
+                                        move |_| sharing_folder.set(Some(folder.clone()))
+                                    }
+                                >
+                                    "share"
+                                </button>
+                            </Show>
+                        </For>
+                    </Show>
+                </div>
+                {move || sharing_folder.get().map(|folder| view! {
+                    <FolderSharing folder=folder on_close=Callback::new(move |_| sharing_folder.set(None)) />
+                })}
+                <Show when=move || selection_mode.get()>
+                    <div class="selection-toolbar batch-toolbar">
+                        <span style="color: #bac2de; font-size: 14px;">
+                            {move || format!("{} selected", selected.with(|paths| paths.len()))}
+                        </span>
+                        <button
+                            type="button"
+                            class="action-btn border-container"
+                            on:click=move |_| selected.set(files.get().into_iter().map(|file| file.path).collect())
+                        >
+                            "select all"
+                        </button>
+                        <button
+                            type="button"
+                            class="action-btn border-container"
+                            disabled=move || selected.with(|paths| paths.is_empty())
+                            on:click=move |_| selected.set(HashSet::new())
+                        >
+                            "clear"
+                        </button>
+                        <select
+                            class="format-select"
+                            on:change=move |ev| set_archive_format.set(event_target_value(&ev))
+                        >
+                            <option value="zip" selected=true>"zip"</option>
+                            <option value="tar.gz">"tar.gz"</option>
+                        </select>
+                        <button
+                            type="button"
+                            class="action-btn border-container"
+                            disabled=move || selected.with(|paths| paths.is_empty())
+                            on:click=on_download_selected
+                        >
+                            "download selected"
+                        </button>
+                        <Show when=move || role.can(Scope::Delete)>
+                            <button
+                                type="button"
+                                class="action-btn delete-btn border-container"
+                                disabled=move || selected.with(|paths| paths.is_empty())
+                                on:click=move |_| run_batch("delete", serde_json::json!({ "ids": selected_ids() }))
+                            >
+                                "delete selected"
+                            </button>
+                        </Show>
+                    </div>
+                    <Show when=move || role.can(Scope::Write)>
+                        <div class="selection-toolbar batch-toolbar">
+                            <input
+                                type="text"
+                                class="batch-input"
+                                placeholder="folder (empty = top level)"
+                                prop:value=destination
+                                on:input=move |ev| set_destination.set(event_target_value(&ev))
+                            />
+                            <button
+                                type="button"
+                                class="action-btn border-container"
+                                disabled=move || selected.with(|paths| paths.is_empty())
+                                on:click=move |_| run_batch("move", serde_json::json!({
+                                    "ids": selected_ids(),
+                                    "destination": destination.get()
+                                }))
+                            >
+                                "move"
+                            </button>
+                            <button
+                                type="button"
+                                class="action-btn border-container"
+                                disabled=move || selected.with(|paths| paths.is_empty())
+                                on:click=move |_| run_batch("copy", serde_json::json!({
+                                    "ids": selected_ids(),
+                                    "destination": destination.get()
+                                }))
+                            >
+                                "copy"
+                            </button>
+                            <input
+                                type="text"
+                                class="batch-input"
+                                placeholder="tag"
+                                prop:value=tag_input
+                                on:input=move |ev| set_tag_input.set(event_target_value(&ev))
+                            />
+                            <button
+                                type="button"
+                                class="action-btn border-container"
+                                disabled=move || selected.with(|paths| paths.is_empty()) || tag_input.get().trim().is_empty()
+                                on:click=move |_| run_batch("tag", serde_json::json!({
+                                    "ids": selected_ids(),
+                                    "add": [tag_input.get()]
+                                }))
+                            >
+                                "add tag"
+                            </button>
+                            <button
+                                type="button"
+                                class="action-btn border-container"
+                                disabled=move || selected.with(|paths| paths.is_empty()) || tag_input.get().trim().is_empty()
+                                on:click=move |_| run_batch("tag", serde_json::json!({
+                                    "ids": selected_ids(),
+                                    "remove": [tag_input.get()]
+                                }))
+                            >
+                                "remove tag"
+                            </button>
+                        </div>
+                    </Show>
+                    <div style="color: #6c7086; font-size: 12px; margin-bottom: 10px;">
+                        "shift-click a checkbox to select a range"
+                    </div>
+                </Show>
+                <Show when=move || batch_message.get().is_some()>
+                    <div style="color: #f9e2af; font-size: 13px; margin-bottom: 10px;">
+                        {move || batch_message.get().unwrap_or_default()}
+                    </div>
+                </Show>
+            </Show>
             <Show 
                 when=move || is_loading.get()
                 fallback=move || {
@@ -632,9 +2588,11 @@ fn FilesSection(
                                             color: #bac2de;
                                         ">
                                             <div style="font-size: 32px; margin-bottom: 10px;">"[ ]"</div>
-                                            <div>"no files uploaded yet"</div>
+                                            <div>
+                                                {move || if has_filters() { "no files match your search" } else { "no files uploaded yet" }}
+                                            </div>
                                             <div style="color: #6c7086; font-size: 14px; margin-top: 5px;">
-                                                "upload some files to get started"
+                                                {move || if has_filters() { "try a different search or clear the filters" } else { "upload some files to get started" }}
                                             </div>
                                         </div>
                                     }
@@ -648,12 +2606,32 @@ fn FilesSection(
                                     >
                                         <FileItem 
                                             file=file 
-                                            set_files=set_files
+                                            selected=selected
+                                            selection_mode=selection_mode
+                                            on_select=on_select
+                                            listing=listing
                                             set_storage_info=set_storage_info 
                                             set_is_loading=set_is_loading
                                         />
                                     </For>
                                 </div>
+                                <div class="list-footer">
+                                    <span>
+                                        {move || format!("showing {} of {} files", files.with(|files| files.len()), listing.total.get())}
+                                    </span>
+                                    <Show when=move || listing.next_cursor.get().is_some()>
+                                        <button
+                                            type="button"
+                                            class="action-btn border-container"
+                                            disabled=move || listing.loading_more.get()
+                                            on:click=move |_| spawn_local(async move {
+                                                load_more_files(listing).await;
+                                            })
+                                        >
+                                            {move || if listing.loading_more.get() { "loading..." } else { "load more" }}
+                                        </button>
+                                    </Show>
+                                </div>
                             </Show>
                         </div>
                     }
@@ -682,67 +2660,172 @@ async fn load_debug_info(set_debug_mode: WriteSignal<bool>) {
 }
 
 async fn load_files_and_storage(
-    set_files: WriteSignal<Vec<FileInfo>>,
+    listing: FileListing,
     set_storage_info: WriteSignal<Option<StorageInfo>>,
     set_is_loading: WriteSignal<bool>,
 ) {
     web_sys::console::log_1(&"Loading files and storage...".into());
     set_is_loading.set(true);
-    
-    // Make requests individually with better error handling
-    let files_result = async {
-        web_sys::console::log_1(&"Requesting files...".into());
-        match Request::get("/files").credentials(RequestCredentials::Include).send().await {
+
+    load_first_page(listing).await;
+
+    let storage_result = async {
+        web_sys::console::log_1(&"Requesting storage info...".into());
+        match Request::get("/storage").credentials(RequestCredentials::Include).send().await {
             Ok(response) => {
                 if response.status() == 200 {
-                    response.json::<FilesResponse>().await.map_err(|e| format!("Failed to parse files response: {:?}", e))
+                    response.json::<StorageInfo>().await.map_err(|e| format!("Failed to parse storage response: {:?}", e))
                 } else {
-                    Err(format!("Files request failed with status: {}", response.status()))
+                    Err(format!("Storage request failed with status: {}", response.status()))
                 }
             }
-            Err(e) => Err(format!("Files request failed: {:?}", e))
+            Err(e) => Err(format!("Storage request failed: {:?}", e))
+        }
+    }.await;
+    
+    match storage_result {
+        Ok(storage_response) => {
+            web_sys::console::log_1(&format!("Storage: {} free", storage_response.formatted_disk_free).into());
+            set_storage_info.set(Some(storage_response));
+        },
+        Err(e) => {
+            web_sys::console::log_1(&format!("Error loading storage: {}", e).into());
+            set_storage_info.set(None);
+        }
+    }
+    
+    set_is_loading.set(false);
+    web_sys::console::log_1(&"Finished loading files and storage".into());
+}
+
+// Replace the list with the first page for the current filters
+async fn load_first_page(listing: FileListing) {
+    listing.generation.update_value(|generation| *generation += 1);
+    let generation = listing.generation.get_value();
+    let query = listing.query.get_untracked();
+
+    let result = fetch_files_page(&query, None).await;
+    if listing.generation.get_value() != generation {
+        return;
+    }
+
+    match result {
+        Ok(response) => {
+            web_sys::console::log_1(&format!("Loaded {} of {} files", response.files.len(), response.total).into());
+            listing.files.set(response.files);
+            listing.total.set(response.total);
+            listing.next_cursor.set(response.next_cursor);
+        }
+        Err(e) => {
+            web_sys::console::log_1(&format!("Error loading files: {}", e).into());
+            listing.files.set(Vec::new());
+            listing.total.set(0);
+            listing.next_cursor.set(None);
+        }
+    }
+    listing.loading_more.set(false);
+}
+
+// Append the next page, if there is one and it isn't already on its way
+async fn load_more_files(listing: FileListing) {
+    let Some(cursor) = listing.next_cursor.get_untracked() else {
+        return;
+    };
+    if listing.loading_more.get_untracked() {
+        return;
+    }
+    listing.loading_more.set(true);
+    let generation = listing.generation.get_value();
+    let query = listing.query.get_untracked();
+
+    let result = fetch_files_page(&query, Some(&cursor)).await;
+    if listing.generation.get_value() != generation {
+        return;
+    }
+
+    match result {
+        Ok(response) => {
+            listing.files.update(|files| files.extend(response.files));
+            listing.total.set(response.total);
+            listing.next_cursor.set(response.next_cursor);
+        }
+        Err(e) => web_sys::console::log_1(&format!("Error loading more files: {}", e).into()),
+    }
+    listing.loading_more.set(false);
+}
+
+async fn fetch_files_page(query: &FileQuery, cursor: Option<&str>) -> Result<FilesResponse, String> {
+    let response = Request::get(&files_url(query, cursor))
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Files request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<FilesResponse>().await.map_err(|e| format!("Failed to parse files response: {:?}", e))
+    } else {
+        Err(format!("Files request failed with status: {}", response.status()))
+    }
+}
+
+fn files_url(query: &FileQuery, cursor: Option<&str>) -> String {
+    let mut params = vec![format!("limit={}", PAGE_SIZE)];
+    let mut push = |key: &str, value: Option<String>| {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            params.push(format!("{}={}", key, String::from(js_sys::encode_uri_component(&value))));
         }
-    }.await;
-    
-    let storage_result = async {
-        web_sys::console::log_1(&"Requesting storage info...".into());
-        match Request::get("/storage").credentials(RequestCredentials::Include).send().await {
-            Ok(response) => {
-                if response.status() == 200 {
-                    response.json::<StorageInfo>().await.map_err(|e| format!("Failed to parse storage response: {:?}", e))
-                } else {
-                    Err(format!("Storage request failed with status: {}", response.status()))
-                }
+    };
+    push("q", query.q.clone());
+    push("type", query.file_type.clone());
+    push("tag", query.tag.clone());
+    push("min_size", query.min_size.map(|size| size.to_string()));
+    push("max_size", query.max_size.map(|size| size.to_string()));
+    push("modified_after", query.modified_after.clone());
+    push("modified_before", query.modified_before.clone());
+    push("folder", query.folder.clone());
+    push("sort", query.sort.clone());
+    push("order", query.order.clone());
+    push("cursor", cursor.map(str::to_string));
+    format!("/files?{}", params.join("&"))
+}
+
+// What the search box asks for: "#image tag:holiday cat" looks for images tagged
+// "holiday" with "cat" in their path
+struct SearchFilters {
+    q: Option<String>,
+    file_type: Option<String>,
+    tags: Vec<String>,
+}
+
+fn parse_search(search: &str) -> SearchFilters {
+    let mut terms = Vec::new();
+    let mut file_type = None;
+    let mut tags = Vec::new();
+    for word in search.split_whitespace() {
+        if let Some(kind) = word.strip_prefix('#') {
+            if !kind.is_empty() {
+                file_type = Some(kind.to_lowercase());
             }
-            Err(e) => Err(format!("Storage request failed: {:?}", e))
-        }
-    }.await;
-    
-    // Handle results separately
-    match files_result {
-        Ok(files_response) => {
-            web_sys::console::log_1(&format!("Loaded {} files", files_response.files.len()).into());
-            set_files.set(files_response.files);
-        },
-        Err(e) => {
-            web_sys::console::log_1(&format!("Error loading files: {}", e).into());
-            set_files.set(Vec::new());
+        } else if let Some(tag) = word.strip_prefix("tag:") {
+            if !tag.is_empty() {
+                tags.push(tag.to_lowercase());
+            }
+        } else {
+            terms.push(word);
         }
     }
-    
-    match storage_result {
-        Ok(storage_response) => {
-            web_sys::console::log_1(&format!("Storage: {} free", storage_response.formatted_disk_free).into());
-            set_storage_info.set(Some(storage_response));
-        },
-        Err(e) => {
-            web_sys::console::log_1(&format!("Error loading storage: {}", e).into());
-            set_storage_info.set(None);
-        }
+    SearchFilters {
+        q: (!terms.is_empty()).then(|| terms.join(" ")),
+        file_type,
+        tags,
     }
-    
-    set_is_loading.set(false);
-    web_sys::console::log_1(&"Finished loading files and storage".into());
+}
+
+// Megabytes typed into a filter box, as bytes
+fn parse_megabytes(value: &str) -> Option<u64> {
+    value.trim().parse::<f64>().ok()
+        .filter(|mb| *mb >= 0.0)
+        .map(|mb| (mb * 1024.0 * 1024.0) as u64)
 }
 
 async fn upload_files(files: Vec<File>) -> Result<UploadResponse, String> {
@@ -764,7 +2847,7 @@ async fn upload_files(files: Vec<File>) -> Result<UploadResponse, String> {
     web_sys::console::log_1(&"- Method: POST".into());
     web_sys::console::log_1(&"- Credentials: Include".into());
     
-    let response = Request::post("/upload")
+    let response = post_request("/upload")
         .credentials(RequestCredentials::Include)
         .body(form_data)
         .map_err(|e| format!("Failed to set body: {:?}", e))?
@@ -802,8 +2885,152 @@ async fn upload_files(files: Vec<File>) -> Result<UploadResponse, String> {
     }
 }
 
+async fn batch_api(action: &str, body: &serde_json::Value) -> Result<BatchResponse, String> {
+    let response = post_request(&format!("/batch/{}", action))
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::Include)
+        .body(body.to_string())
+        .map_err(|e| format!("Request body error: {:?}", e))?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<BatchResponse>().await
+            .map_err(|e| format!("Failed to parse batch response: {:?}", e))
+    } else {
+        Err(format!("Batch {} failed with status: {}", action, response.status()))
+    }
+}
+
+async fn update_file_meta_api(path: &str, update: &FileMetaUpdate) -> Result<FileMetaResponse, String> {
+    let body = serde_json::to_string(update).map_err(|e| format!("Failed to encode update: {:?}", e))?;
+    let response = post_request(&format!("/meta/{}", path))
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::Include)
+        .body(body)
+        .map_err(|e| format!("Request body error: {:?}", e))?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<FileMetaResponse>().await
+            .map_err(|e| format!("Failed to parse metadata response: {:?}", e))
+    } else {
+        let message = response.json::<ApiResponse>().await
+            .map(|response| response.message)
+            .unwrap_or_else(|_| format!("Update failed with status: {}", response.status()));
+        Err(message)
+    }
+}
+
+async fn star_file_api(path: &str, starred: bool) -> Result<StarResponse, String> {
+    let body = serde_json::to_string(&StarRequest { starred }).map_err(|e| format!("Failed to encode request: {:?}", e))?;
+    let response = post_request(&format!("/star/{}", path))
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::Include)
+        .body(body)
+        .map_err(|e| format!("Request body error: {:?}", e))?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<StarResponse>().await.map_err(|e| format!("Failed to parse star response: {:?}", e))
+    } else {
+        Err(format!("Star failed with status: {}", response.status()))
+    }
+}
+
+async fn load_activity() -> Result<Vec<ActivityEvent>, String> {
+    let response = Request::get(&format!("/activity?limit={}", ACTIVITY_FEED_SIZE))
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Activity request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<ActivityResponse>().await
+            .map(|response| response.events)
+            .map_err(|e| format!("Failed to parse activity response: {:?}", e))
+    } else {
+        Err(format!("Activity request failed with status: {}", response.status()))
+    }
+}
+
+async fn load_tags() -> Result<TagsResponse, String> {
+    let response = Request::get("/tags")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Tags request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<TagsResponse>().await.map_err(|e| format!("Failed to parse tags response: {:?}", e))
+    } else {
+        Err(format!("Tags request failed with status: {}", response.status()))
+    }
+}
+
+async fn search_contents(q: &str) -> Result<Vec<SearchHit>, String> {
+    let url = format!("/search?q={}", String::from(js_sys::encode_uri_component(q)));
+    let response = Request::get(&url)
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Search failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<SearchResponse>().await
+            .map(|response| response.hits)
+            .map_err(|e| format!("Failed to parse search response: {:?}", e))
+    } else {
+        Err(format!("Search failed with status: {}", response.status()))
+    }
+}
+
+async fn load_archive_listing(filename: &str) -> Result<ArchiveListing, String> {
+    let response = Request::get(&format!("/archive/entries/{}", filename))
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<ArchiveListing>().await
+            .map_err(|e| format!("Failed to parse archive listing: {:?}", e))
+    } else {
+        let error = response.json::<ApiResponse>().await
+            .map(|r| r.message)
+            .unwrap_or_else(|_| format!("Archive listing failed with status: {}", response.status()));
+        Err(error)
+    }
+}
+
+async fn load_entry_preview(url: &str) -> String {
+    match Request::get(url).credentials(RequestCredentials::Include).send().await {
+        Ok(response) => match response.json::<PreviewResponse>().await {
+            Ok(preview) => preview.content.or(preview.error).unwrap_or_default(),
+            Err(e) => format!("Failed to parse preview: {:?}", e),
+        },
+        Err(e) => format!("Preview request failed: {:?}", e),
+    }
+}
+
+async fn extract_archive_api(filename: &str) -> Result<UploadResponse, String> {
+    let response = post_request(&format!("/archive/extract/{}", filename))
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    response.json::<UploadResponse>().await
+        .map_err(|e| format!("Failed to parse response: {:?}", e))
+}
+
 async fn delete_file_api(filename: &str) -> Result<ApiResponse, String> {
-    let response = Request::post(&format!("/delete/{}", filename))
+    let response = post_request(&format!("/delete/{}", filename))
         .credentials(RequestCredentials::Include)
         .send()
         .await
@@ -813,7 +3040,51 @@ async fn delete_file_api(filename: &str) -> Result<ApiResponse, String> {
         .map_err(|e| format!("Failed to parse response: {:?}", e))
 }
 
-async fn check_auth_status(set_is_authenticated: WriteSignal<bool>) {
+// The "login" or "login_error" parameter single sign-on came back with, removed from the
+// address bar so a reload doesn't show it again
+fn sso_result() -> Option<(String, String)> {
+    let window = window();
+    let search = window.location().search().ok()?;
+    let result = search
+        .trim_start_matches('?')
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| matches!(*key, "login" | "login_error"))
+        .map(|(key, value)| (key.to_string(), value.to_string()))?;
+    if let Ok(history) = window.history() {
+        let _ = history.replace_state_with_url(&JsValue::NULL, "", Some("/"));
+    }
+    Some(result)
+}
+
+fn sso_error_message(reason: &str) -> &'static str {
+    match reason {
+        "unavailable" => "Single sign-on is unavailable right now. Try again later",
+        "expired" => "The single sign-on login took too long or was already used. Try again",
+        "cancelled" => "Single sign-on was cancelled",
+        "no_role" => "Your account has no access to cratr. Ask an admin to add you to a group",
+        "account" => "This account can't log in with single sign-on. Ask an admin",
+        _ => "Single sign-on failed. Try again or ask an admin",
+    }
+}
+
+async fn load_auth_status() -> Result<AuthStatus, String> {
+    let response = Request::get("/auth/status")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        let status = response.json::<AuthStatus>().await.map_err(|e| format!("Failed to parse auth status: {:?}", e))?;
+        remember_auth_status(&status);
+        Ok(status)
+    } else {
+        Err(format!("Auth status request failed with status: {}", response.status()))
+    }
+}
+
+async fn check_auth_status(set_is_authenticated: WriteSignal<bool>, proxy_login: RwSignal<bool>) {
     web_sys::console::log_1(&"Checking authentication status...".into());
     match Request::get("/auth/status").credentials(RequestCredentials::Include).send().await {
         Ok(response) => {
@@ -821,6 +3092,8 @@ async fn check_auth_status(set_is_authenticated: WriteSignal<bool>) {
                 match response.json::<AuthStatus>().await {
                     Ok(auth_status) => {
                         web_sys::console::log_1(&format!("Auth status: authenticated={}", auth_status.authenticated).into());
+                        proxy_login.set(auth_status.proxy_login);
+                        remember_auth_status(&auth_status);
                         set_is_authenticated.set(auth_status.authenticated);
                     }
                     Err(e) => {
@@ -840,30 +3113,222 @@ async fn check_auth_status(set_is_authenticated: WriteSignal<bool>) {
     }
 }
 
-async fn login_user(username: &str, password: &str) -> Result<LoginResponse, String> {
-    let login_request = LoginRequest {
-        username: username.to_string(),
-        password: password.to_string(),
-    };
-    
-    let request_body = serde_json::to_string(&login_request)
-        .map_err(|e| format!("Serialization error: {:?}", e))?;
-    
-    let response = Request::post("/login")
-        .header("Content-Type", "application/json")
+// A POST request with the session's CSRF token attached, as the server wants on every
+// request that changes something
+fn post_request(url: &str) -> RequestBuilder {
+    let request = Request::post(url);
+    match CSRF_TOKEN.with(|token| token.borrow().clone()) {
+        Some(token) => request.header("X-CSRF-Token", &token),
+        None => request,
+    }
+}
+
+fn remember_auth_status(status: &AuthStatus) {
+    CSRF_TOKEN.with(|token| *token.borrow_mut() = status.csrf_token.clone());
+    CONTENT_ORIGIN.with(|origin| *origin.borrow_mut() = status.content_origin.clone());
+}
+
+// Where to fetch a stored file. `attachment` asks for a download even of files the browser
+// could show, since the download attribute does nothing on another origin.
+fn download_url(path: &str, attachment: bool) -> String {
+    let origin = CONTENT_ORIGIN.with(|origin| origin.borrow().clone()).unwrap_or_default();
+    let query = if attachment { "?download" } else { "" };
+    format!("{}/download/{}{}", origin, path, query)
+}
+
+async fn login_user(username: &str, password: &str) -> Result<LoginResponse, String> {
+    let login_request = LoginRequest {
+        username: username.to_string(),
+        password: password.to_string(),
+    };
+    
+    let request_body = serde_json::to_string(&login_request)
+        .map_err(|e| format!("Serialization error: {:?}", e))?;
+    
+    let response = post_request("/login")
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::Include)
+        .body(request_body)
+        .map_err(|e| format!("Request body error: {:?}", e))?
+        .send()
+        .await
+        .map_err(|e| format!("Login request failed: {:?}", e))?;
+        
+    let response = response.json::<LoginResponse>().await
+        .map_err(|e| format!("Failed to parse login response: {:?}", e))?;
+    // A login, even one waiting on its second step, comes with a new CSRF token
+    if response.success {
+        let _ = load_auth_status().await;
+    }
+    Ok(response)
+}
+
+async fn login_second_factor(code: &str) -> Result<LoginResponse, String> {
+    let body = serde_json::to_string(&TwoFactorCode { code: code.to_string() })
+        .map_err(|e| format!("Serialization error: {:?}", e))?;
+    let response = post_request("/login/2fa")
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::Include)
+        .body(body)
+        .map_err(|e| format!("Request body error: {:?}", e))?
+        .send()
+        .await
+        .map_err(|e| format!("Login request failed: {:?}", e))?;
+
+    let response = response.json::<LoginResponse>().await
+        .map_err(|e| format!("Failed to parse login response: {:?}", e))?;
+    if response.authenticated {
+        let _ = load_auth_status().await;
+    }
+    Ok(response)
+}
+
+async fn two_factor_status() -> Result<TwoFactorStatus, String> {
+    let response = Request::get("/2fa")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<TwoFactorStatus>().await.map_err(|e| format!("Failed to parse two-factor status: {:?}", e))
+    } else {
+        Err(format!("Loading two-factor status failed with status: {}", response.status()))
+    }
+}
+
+async fn load_tokens() -> Result<TokensResponse, String> {
+    let response = Request::get("/tokens")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<TokensResponse>().await.map_err(|e| format!("Failed to parse tokens: {:?}", e))
+    } else {
+        Err(format!("Loading API tokens failed with status: {}", response.status()))
+    }
+}
+
+async fn load_sessions() -> Result<SessionsResponse, String> {
+    let response = Request::get("/sessions")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<SessionsResponse>().await.map_err(|e| format!("Failed to parse sessions: {:?}", e))
+    } else {
+        Err(format!("Loading sessions failed with status: {}", response.status()))
+    }
+}
+
+async fn enroll_two_factor() -> Result<TwoFactorEnrollment, String> {
+    two_factor_request("/2fa/enroll", &serde_json::json!({})).await
+}
+
+async fn confirm_two_factor(code: &str) -> Result<RecoveryCodesResponse, String> {
+    two_factor_request("/2fa/confirm", &TwoFactorCode { code: code.to_string() }).await
+}
+
+// POST to one of the account endpoints (two-factor, tokens, users); failures carry the
+// server's message
+async fn two_factor_request<R: serde::de::DeserializeOwned>(url: &str, body: &impl serde::Serialize) -> Result<R, String> {
+    let body = serde_json::to_string(body).map_err(|e| format!("Failed to encode request: {:?}", e))?;
+    let response = post_request(url)
+        .header("Content-Type", "application/json")
+        .credentials(RequestCredentials::Include)
+        .body(body)
+        .map_err(|e| format!("Request body error: {:?}", e))?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<R>().await.map_err(|e| format!("Failed to parse response: {:?}", e))
+    } else {
+        match response.json::<ApiResponse>().await {
+            Ok(error) => Err(error.message),
+            Err(_) => Err(format!("Request failed with status: {}", response.status())),
+        }
+    }
+}
+
+// Ask the server which role the logged-in account has
+async fn load_role(role: RwSignal<Option<Role>>) {
+    match Request::get("/auth/status").credentials(RequestCredentials::Include).send().await {
+        Ok(response) => match response.json::<AuthStatus>().await {
+            Ok(auth_status) => {
+                remember_auth_status(&auth_status);
+                role.set(auth_status.role);
+            }
+            Err(e) => web_sys::console::log_1(&format!("Failed to parse auth response: {:?}", e).into()),
+        },
+        Err(e) => web_sys::console::log_1(&format!("Auth status request failed: {:?}", e).into()),
+    }
+}
+
+async fn load_users() -> Result<UsersResponse, String> {
+    let response = Request::get("/admin/users")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<UsersResponse>().await.map_err(|e| format!("Failed to parse users: {:?}", e))
+    } else {
+        Err(format!("Loading users failed with status: {}", response.status()))
+    }
+}
+
+async fn load_shares() -> Result<SharesResponse, String> {
+    let response = Request::get("/shares")
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<SharesResponse>().await.map_err(|e| format!("Failed to parse shares: {:?}", e))
+    } else {
+        Err(format!("Loading shared folders failed with status: {}", response.status()))
+    }
+}
+
+// The access list of a folder, or None if nobody owns it yet
+async fn load_share(folder: &str) -> Result<Option<FolderShare>, String> {
+    let response = Request::get(&format!("/shares/{}", folder))
+        .credentials(RequestCredentials::Include)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    match response.status() {
+        200 => response.json::<FolderShare>().await.map(Some).map_err(|e| format!("Failed to parse share: {:?}", e)),
+        404 => Ok(None),
+        status => Err(format!("Loading folder sharing failed with status: {}", status)),
+    }
+}
+
+async fn load_groups() -> Result<GroupsResponse, String> {
+    let response = Request::get("/groups")
         .credentials(RequestCredentials::Include)
-        .body(request_body)
-        .map_err(|e| format!("Request body error: {:?}", e))?
         .send()
         .await
-        .map_err(|e| format!("Login request failed: {:?}", e))?;
-        
-    response.json::<LoginResponse>().await
-        .map_err(|e| format!("Failed to parse login response: {:?}", e))
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status() == 200 {
+        response.json::<GroupsResponse>().await.map_err(|e| format!("Failed to parse groups: {:?}", e))
+    } else {
+        Err(format!("Loading groups failed with status: {}", response.status()))
+    }
 }
 
 async fn logout_user(set_is_authenticated: WriteSignal<bool>) {
-    match Request::post("/logout").credentials(RequestCredentials::Include).send().await {
+    match post_request("/logout").credentials(RequestCredentials::Include).send().await {
         Ok(_) => {
             set_is_authenticated.set(false);
         }
@@ -873,6 +3338,30 @@ async fn logout_user(set_is_authenticated: WriteSignal<bool>) {
     }
 }
 
+fn archive_url(paths: &[String], format: &str) -> String {
+    let query: Vec<String> = paths.iter()
+        .map(|path| format!("paths={}", String::from(js_sys::encode_uri_component(path))))
+        .collect();
+    format!("/archive?format={}&{}", format, query.join("&"))
+}
+
+fn archive_entry_url(filename: &str, entry: &str, preview: bool) -> String {
+    format!(
+        "/archive/entry/{}?name={}{}",
+        filename,
+        String::from(js_sys::encode_uri_component(entry)),
+        if preview { "&preview=true" } else { "" }
+    )
+}
+
+// Matches the formats the server knows how to open
+fn can_open_archive(filename: &str) -> bool {
+    let name = filename.to_lowercase();
+    [".zip", ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tbz"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
 fn format_file_size(size: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
     let mut size = size as f64;
@@ -1028,6 +3517,20 @@ body {
     color: #fab387;
 }
 
+.activity-section {
+    grid-column: 3 / span 4;
+    grid-row: 3;
+}
+.activity-section::before {
+    content: "activity";
+}
+.activity-section:hover {
+    border-color: #89b4fa;
+}
+.activity-section:hover::before {
+    color: #89b4fa;
+}
+
 .files-section {
     grid-column: 1 / span 6;
     grid-row: 4;
@@ -1127,6 +3630,267 @@ body {
     color: #f38ba8;
 }
 
+.selection-toolbar, .folder-list {
+    display: flex;
+    align-items: center;
+    gap: 10px;
+    flex-wrap: wrap;
+    margin-bottom: 10px;
+}
+
+.file-select {
+    margin: 4px 10px 0 0;
+    accent-color: #f38ba8;
+    cursor: pointer;
+}
+
+.star-btn {
+    background: none;
+    border: none;
+    color: #6c7086;
+    cursor: pointer;
+    font-size: 20px;
+    padding: 0 8px;
+    line-height: 1;
+}
+
+.star-btn:hover, .star-btn.starred {
+    color: #f9e2af;
+}
+
+.view-active {
+    border-color: #fab387;
+    color: #fab387;
+}
+
+.activity-feed {
+    max-height: 320px;
+    overflow-y: auto;
+    font-size: 13px;
+}
+
+.activity-event {
+    padding: 6px 0;
+    border-bottom: 1px solid #313244;
+    color: #bac2de;
+    word-break: break-word;
+}
+
+.activity-time {
+    color: #6c7086;
+    display: inline-block;
+    min-width: 72px;
+}
+
+.activity-upload, .activity-copy, .activity-extract {
+    color: #a6e3a1;
+}
+
+.activity-move {
+    color: #f9e2af;
+}
+
+.activity-delete {
+    color: #f38ba8;
+}
+
+.file-meta {
+    margin-bottom: 15px;
+    font-size: 13px;
+}
+
+.tag-list {
+    display: flex;
+    flex-wrap: wrap;
+    gap: 6px;
+    align-items: center;
+}
+
+.tag-chip {
+    display: inline-flex;
+    align-items: center;
+    gap: 4px;
+    padding: 2px 8px;
+    border: 1px solid #94e2d5;
+    color: #94e2d5;
+    background: transparent;
+    font-family: "DM Mono", monospace;
+    font-size: 12px;
+}
+
+.tag-filter {
+    cursor: pointer;
+}
+
+.tag-filter.active {
+    background-color: #94e2d5;
+    color: #1e1e2e;
+}
+
+.chip-remove {
+    background: none;
+    border: none;
+    color: #f38ba8;
+    cursor: pointer;
+    padding: 0;
+    font-size: 14px;
+}
+
+.meta-property {
+    color: #bac2de;
+    margin-top: 4px;
+    word-break: break-word;
+}
+
+.meta-editor {
+    display: flex;
+    gap: 6px;
+    align-items: center;
+    margin-top: 6px;
+}
+
+.meta-input {
+    background-color: #1e1e2e;
+    border: 1px solid #45475a;
+    color: #cdd6f4;
+    padding: 4px 6px;
+    font-family: "DM Mono", monospace;
+    font-size: 12px;
+    min-width: 0;
+    flex: 1;
+}
+
+.meta-input:focus {
+    outline: none;
+    border-color: #f38ba8;
+}
+
+.search-results {
+    margin-top: 12px;
+    max-height: 420px;
+    overflow-y: auto;
+}
+
+.search-hit {
+    padding: 10px 0;
+    border-bottom: 1px solid #313244;
+    font-size: 14px;
+}
+
+.search-snippet {
+    color: #a6adc8;
+    font-size: 13px;
+    margin-top: 6px;
+    white-space: pre-wrap;
+    word-break: break-word;
+}
+
+.search-snippet b {
+    color: #f9e2af;
+    font-weight: 500;
+}
+
+.filter-row {
+    display: flex;
+    flex-wrap: wrap;
+    gap: 8px;
+    margin-top: 10px;
+}
+
+.filter-input {
+    background-color: #1e1e2e;
+    border: 2px solid #45475a;
+    color: #cdd6f4;
+    padding: 8px;
+    font-family: "DM Mono", monospace;
+    font-size: 14px;
+    width: 140px;
+    color-scheme: dark;
+}
+
+.filter-input:focus {
+    outline: none;
+    border-color: #f38ba8;
+}
+
+.list-footer {
+    display: flex;
+    align-items: center;
+    justify-content: space-between;
+    margin-top: 15px;
+    color: #6c7086;
+    font-size: 13px;
+}
+
+.batch-input {
+    background-color: #1e1e2e;
+    border: 2px solid #45475a;
+    color: #cdd6f4;
+    padding: 8px;
+    font-family: "DM Mono", monospace;
+    font-size: 14px;
+    min-width: 160px;
+}
+
+.batch-input:focus {
+    outline: none;
+    border-color: #f38ba8;
+}
+
+.format-select {
+    background-color: #1e1e2e;
+    border: 2px solid #45475a;
+    color: #cdd6f4;
+    padding: 8px;
+    font-family: "DM Mono", monospace;
+    font-size: 14px;
+}
+
+.action-btn:disabled {
+    border-color: #313244;
+    color: #6c7086;
+    cursor: not-allowed;
+}
+
+.archive-entries {
+    border-top: 1px solid #45475a;
+    margin-top: 15px;
+    padding-top: 10px;
+    max-height: 300px;
+    overflow-y: auto;
+    text-align: left;
+}
+
+.archive-entry {
+    display: flex;
+    gap: 10px;
+    align-items: center;
+    color: #a6adc8;
+    font-size: 13px;
+    padding: 4px 0;
+}
+
+.entry-link {
+    background: none;
+    border: none;
+    color: #89b4fa;
+    cursor: pointer;
+    font-family: "DM Mono", monospace;
+    font-size: 13px;
+    text-decoration: none;
+    padding: 0;
+}
+
+.entry-preview {
+    background-color: #181825;
+    color: #cdd6f4;
+    padding: 10px;
+    font-size: 12px;
+    white-space: pre-wrap;
+    max-height: 200px;
+    overflow-y: auto;
+}
+
 .search-input {
     background-color: #1e1e2e;
     border: 2px solid #45475a;
@@ -1254,6 +4018,110 @@ body {
     color: #f38ba8;
 }
 
+.security-btn {
+    background-color: #1e1e2e;
+    border: 2px solid #45475a;
+    color: #cdd6f4;
+    padding: 20px 16px 8px 16px;
+    cursor: pointer;
+    font-family: "DM Mono", monospace;
+    font-size: 14px;
+    transition: border-color 0.2s ease-out;
+    margin: 4px;
+    position: relative;
+}
+
+.security-btn.border-container::before {
+    content: "security";
+    position: absolute;
+    top: -12px;
+    left: 10px;
+    background-color: #1e1e2e;
+    padding: 0 8px;
+    font-size: 12px;
+    color: #45475a;
+    transition: color 0.2s ease-out;
+}
+
+.security-btn:hover {
+    border-color: #a6e3a1;
+}
+
+.security-btn:hover.border-container::before {
+    color: #a6e3a1;
+}
+
+.security-panel {
+    margin-top: 20px;
+    padding-top: 15px;
+    border-top: 1px solid #313244;
+    font-size: 13px;
+}
+
+.two-factor-setup {
+    margin-bottom: 15px;
+    font-size: 13px;
+}
+
+.two-factor-note {
+    color: #bac2de;
+    margin: 0 0 8px 0;
+}
+
+.two-factor-qr {
+    display: block;
+    width: 200px;
+    height: 200px;
+    image-rendering: pixelated;
+    background-color: #ffffff;
+    padding: 8px;
+    margin: 10px 0;
+}
+
+.two-factor-secret code,
+.recovery-codes code {
+    color: #f9e2af;
+    font-family: "DM Mono", monospace;
+    word-break: break-all;
+}
+
+.api-tokens {
+    margin-top: 20px;
+    padding-top: 15px;
+    border-top: 1px solid #313244;
+}
+
+.api-token {
+    display: flex;
+    gap: 10px;
+    align-items: baseline;
+    flex-wrap: wrap;
+    margin-bottom: 6px;
+}
+
+.api-token-name {
+    color: #cdd6f4;
+}
+
+.api-token-scopes {
+    display: flex;
+    gap: 12px;
+    margin-top: 6px;
+}
+
+.api-token-scopes label {
+    display: flex;
+    gap: 4px;
+    align-items: center;
+}
+
+.recovery-codes {
+    display: grid;
+    grid-template-columns: repeat(auto-fill, minmax(120px, 1fr));
+    gap: 6px;
+    margin: 10px 0;
+}
+
 .storage-stats {
     display: grid;
     grid-template-columns: repeat(auto-fit, minmax(120px, 1fr));
@@ -1460,6 +4328,11 @@ body {
     .search-section, .files-section {
         grid-column: 1;
     }
+
+    .activity-section {
+        grid-column: 1;
+        grid-row: auto;
+    }
     
     .files-grid {
         grid-template-columns: 1fr;
@@ -0,0 +1,314 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{self, ContentDisposition, DispositionType, HeaderMap, HeaderValue, TryIntoHeaderValue as _};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use sha2::{Digest, Sha256};
+use std::net::{SocketAddr, ToSocketAddrs as _};
+
+// Uploaded files can't run anything or load anything, even when opened directly. PDFs are
+// left out of the sandbox, which browsers refuse to show them in.
+const DOWNLOAD_POLICY: &str = "default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'unsafe-inline'; sandbox";
+const PDF_POLICY: &str = "default-src 'none'; img-src 'self' data:; style-src 'unsafe-inline'";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ContentArgs {
+    /// Serve downloads from this origin instead of the app's own, e.g. http://files.example.org:8081,
+    /// so uploaded files can never act as the app. Use the app's host with another port, since
+    /// the login cookie has to reach it
+    #[arg(long, env = "CRATR_CONTENT_ORIGIN")]
+    pub content_origin: Option<String>,
+
+    /// Extra address to listen on for the content origin, e.g. 0.0.0.0:8081. Leave it out if a
+    /// reverse proxy sends the content origin to --bind
+    #[arg(long, env = "CRATR_CONTENT_BIND")]
+    pub content_bind: Option<String>,
+}
+
+// A second origin for raw user content. Requests for it, recognised by their Host header or
+// by arriving on its own listener, can only download files.
+pub struct ContentOrigin {
+    // scheme://host[:port], as the browser sees it
+    origin: String,
+    // host[:port], lowercase, as it appears in the Host header
+    authority: String,
+    listeners: Vec<SocketAddr>,
+}
+
+impl ContentOrigin {
+    // None unless a content origin is configured
+    pub fn from_args(args: &ContentArgs) -> Result<Option<Self>, String> {
+        let Some(origin) = &args.content_origin else {
+            if args.content_bind.is_some() {
+                return Err("--content-bind needs --content-origin".to_string());
+            }
+            return Ok(None);
+        };
+        let url = reqwest::Url::parse(origin).map_err(|e| format!("--content-origin {}: {}", origin, e))?;
+        let host = url.host_str().filter(|_| matches!(url.scheme(), "http" | "https"));
+        let (Some(host), "/", None, None) = (host, url.path(), url.query(), url.fragment()) else {
+            return Err(format!("--content-origin {} must be just a scheme, host and port", origin));
+        };
+        let authority = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        }
+        .to_ascii_lowercase();
+        let listeners = match &args.content_bind {
+            Some(bind) => bind
+                .to_socket_addrs()
+                .map_err(|e| format!("--content-bind {}: {}", bind, e))?
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(Some(Self {
+            origin: format!("{}://{}", url.scheme(), authority),
+            authority,
+            listeners,
+        }))
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn is_content_request(&self, req: &ServiceRequest) -> bool {
+        if self.listeners.contains(&req.app_config().local_addr()) {
+            return true;
+        }
+        // The raw Host header, not X-Forwarded-Host, which a script could set itself
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri().authority().map(|authority| authority.as_str()));
+        host.is_some_and(|host| host.eq_ignore_ascii_case(&self.authority))
+    }
+
+    // Where a download that arrived on the app's origin has to go instead: files that could
+    // run script are only ever served from the content origin. `path` includes the query.
+    pub fn relocate(&self, path: &str, headers: &HeaderMap) -> Option<String> {
+        let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        (!renders_safely(content_type)).then(|| format!("{}{}", self.origin, path))
+    }
+}
+
+// Policy for the app's own page. The one inline script, the loader, is allowed by its hash;
+// styles come from the app and the two font CDNs index.html links to.
+pub fn shell_policy(html: &str, content_origin: Option<&str>) -> String {
+    let scripts: String = inline_scripts(html)
+        .map(|script| format!(" 'sha256-{}'", STANDARD.encode(Sha256::digest(script.as_bytes()))))
+        .collect();
+    let content = content_origin.map(|origin| format!(" {}", origin)).unwrap_or_default();
+    format!(
+        "default-src 'self'; script-src 'self' 'wasm-unsafe-eval'{scripts}; \
+         style-src 'self' 'unsafe-inline' https://fonts.googleapis.com https://cdnjs.cloudflare.com; \
+         font-src 'self' https://fonts.gstatic.com https://cdnjs.cloudflare.com; \
+         img-src 'self' data: blob:{content}; media-src 'self' blob:{content}; connect-src 'self'; \
+         object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'"
+    )
+}
+
+// Bodies of the <script> tags without a src attribute
+fn inline_scripts(html: &str) -> impl Iterator<Item = &str> {
+    html.split("<script").skip(1).filter_map(|tag| {
+        let (attributes, rest) = tag.split_once('>')?;
+        let (body, _) = rest.split_once("</script>")?;
+        (!attributes.contains("src=")).then_some(body)
+    })
+}
+
+// Whether a browser shows this type without running anything in it
+fn renders_safely(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    match essence.split_once('/') {
+        Some(("image", subtype)) => subtype != "svg+xml",
+        Some(("audio" | "video", _)) => true,
+        _ => matches!(essence.as_str(), "text/plain" | "application/pdf"),
+    }
+}
+
+// Lock down a file served from /download: anything that isn't plainly an image, audio,
+// video, text or a PDF is sent as an attachment (HTML, SVG, XML and unknown types all could
+// run script on the origin), as is everything asked for with ?download. Nothing is sniffed
+// and nothing runs.
+pub fn harden_download(headers: &mut HeaderMap, force_attachment: bool) {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    if force_attachment || !renders_safely(&content_type) {
+        let mut disposition = headers
+            .get(header::CONTENT_DISPOSITION)
+            .and_then(|value| ContentDisposition::from_raw(value).ok())
+            .unwrap_or(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: Vec::new(),
+            });
+        disposition.disposition = DispositionType::Attachment;
+        if let Ok(value) = disposition.try_into_value() {
+            headers.insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+
+    let policy = if content_type.starts_with("application/pdf") { PDF_POLICY } else { DOWNLOAD_POLICY };
+    headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static(policy));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn hardened(content_type: &str, disposition: Option<&str>, force_attachment: bool) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        if let Some(disposition) = disposition {
+            headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_str(disposition).unwrap());
+        }
+        harden_download(&mut headers, force_attachment);
+        headers
+    }
+
+    fn value(headers: &HeaderMap, name: header::HeaderName) -> &str {
+        headers.get(name).map(|value| value.to_str().unwrap()).unwrap_or_default()
+    }
+
+    fn content_origin(origin: &str, bind: Option<&str>) -> Result<Option<ContentOrigin>, String> {
+        ContentOrigin::from_args(&ContentArgs {
+            content_origin: Some(origin.to_string()),
+            content_bind: bind.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn only_passive_types_render_inline() {
+        for safe in ["image/png", "image/jpeg; charset=binary", "audio/ogg", "video/mp4", "text/plain; charset=utf-8", "application/pdf"] {
+            assert!(renders_safely(safe), "{}", safe);
+        }
+        for active in ["text/html", "image/svg+xml", "IMAGE/SVG+XML", "application/xhtml+xml", "text/xml", "application/javascript", "application/octet-stream", ""] {
+            assert!(!renders_safely(active), "{}", active);
+        }
+    }
+
+    #[test]
+    fn active_content_is_forced_to_an_attachment() {
+        for content_type in ["text/html; charset=utf-8", "image/svg+xml", "application/octet-stream"] {
+            let headers = hardened(content_type, Some("inline; filename=\"page.html\""), false);
+            let disposition = value(&headers, header::CONTENT_DISPOSITION);
+            assert!(disposition.starts_with("attachment"), "{}: {}", content_type, disposition);
+            // The filename survives
+            assert!(disposition.contains("page.html"), "{}", disposition);
+        }
+        let headers = hardened("text/html", None, false);
+        assert_eq!(value(&headers, header::CONTENT_DISPOSITION), "attachment");
+    }
+
+    #[test]
+    fn passive_content_stays_inline_unless_asked() {
+        let headers = hardened("image/png", Some("inline; filename=\"cat.png\""), false);
+        assert!(value(&headers, header::CONTENT_DISPOSITION).starts_with("inline"));
+        let headers = hardened("image/png", Some("inline; filename=\"cat.png\""), true);
+        assert!(value(&headers, header::CONTENT_DISPOSITION).starts_with("attachment"));
+    }
+
+    #[test]
+    fn every_download_is_sandboxed_and_not_sniffed() {
+        for content_type in ["image/png", "text/html", "text/plain", "image/svg+xml"] {
+            let headers = hardened(content_type, None, false);
+            assert_eq!(value(&headers, header::X_CONTENT_TYPE_OPTIONS), "nosniff");
+            assert_eq!(value(&headers, header::CONTENT_SECURITY_POLICY), DOWNLOAD_POLICY);
+            assert!(DOWNLOAD_POLICY.ends_with("; sandbox"));
+        }
+        // Browsers won't show a PDF in a sandbox, but it still can't load anything
+        let headers = hardened("application/pdf", None, false);
+        assert_eq!(value(&headers, header::CONTENT_SECURITY_POLICY), PDF_POLICY);
+        assert!(PDF_POLICY.starts_with("default-src 'none'"));
+        assert_eq!(value(&headers, header::X_CONTENT_TYPE_OPTIONS), "nosniff");
+    }
+
+    #[test]
+    fn shell_policy_allows_exactly_the_inline_scripts() {
+        let loader = "\n  import init from './pkg/cratr.js';\n  init();\n";
+        let html = format!(
+            "<html><head><script src=\"/app.js\"></script><script type=\"module\">{}</script></head><body></body></html>",
+            loader
+        );
+        let policy = shell_policy(&html, None);
+        let hash = STANDARD.encode(Sha256::digest(loader.as_bytes()));
+        assert!(policy.contains(&format!("script-src 'self' 'wasm-unsafe-eval' 'sha256-{}';", hash)), "{}", policy);
+        assert_eq!(policy.matches("'sha256-").count(), 1);
+        assert!(policy.contains("frame-ancestors 'none'"));
+        assert!(policy.contains("object-src 'none'"));
+        assert!(!policy.contains("'unsafe-eval'"));
+
+        let policy = shell_policy(&html, Some("http://files.example.org:8081"));
+        assert!(policy.contains("img-src 'self' data: blob: http://files.example.org:8081;"));
+        assert!(policy.contains("media-src 'self' blob: http://files.example.org:8081;"));
+    }
+
+    #[test]
+    fn the_real_page_has_its_loader_hashed() {
+        let html = include_str!("../static/index.html");
+        let policy = shell_policy(html, None);
+        assert_eq!(policy.matches("'sha256-").count(), inline_scripts(html).count());
+    }
+
+    #[test]
+    fn content_origins_are_just_an_origin() {
+        let origin = content_origin("http://Files.Example.org:8081", None).unwrap().unwrap();
+        assert_eq!(origin.origin(), "http://files.example.org:8081");
+        assert!(content_origin("https://files.example.org/downloads", None).is_err());
+        assert!(content_origin("ftp://files.example.org", None).is_err());
+        assert!(content_origin("not a url", None).is_err());
+        let no_origin = ContentOrigin::from_args(&ContentArgs { content_origin: None, content_bind: Some("0.0.0.0:8081".to_string()) });
+        assert!(no_origin.is_err());
+    }
+
+    #[test]
+    fn active_downloads_move_to_the_content_origin() {
+        let origin = content_origin("http://files.example.org:8081", None).unwrap().unwrap();
+        let headers = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+            headers
+        };
+        for active in ["text/html; charset=utf-8", "image/svg+xml", "application/octet-stream"] {
+            assert_eq!(
+                origin.relocate("/download/docs/page.html?download", &headers(active)).as_deref(),
+                Some("http://files.example.org:8081/download/docs/page.html?download"),
+                "{}",
+                active
+            );
+        }
+        assert_eq!(origin.relocate("/download/page.html", &HeaderMap::new()).as_deref(), Some("http://files.example.org:8081/download/page.html"));
+        for passive in ["image/png", "text/plain", "application/pdf", "video/mp4"] {
+            assert_eq!(origin.relocate("/download/cat", &headers(passive)), None, "{}", passive);
+        }
+    }
+
+    #[test]
+    fn content_requests_are_recognised_by_host_or_listener() {
+        let origin = content_origin("http://files.example.org:8081", None).unwrap().unwrap();
+        let request = |host: &str| TestRequest::default().insert_header((header::HOST, host)).to_srv_request();
+        assert!(origin.is_content_request(&request("files.example.org:8081")));
+        assert!(origin.is_content_request(&request("FILES.example.org:8081")));
+        assert!(!origin.is_content_request(&request("files.example.org")));
+        assert!(!origin.is_content_request(&request("app.example.org:8081")));
+        // A forwarded host header is something a script could set, so it doesn't count
+        let forwarded = TestRequest::default()
+            .insert_header((header::HOST, "app.example.org"))
+            .insert_header(("X-Forwarded-Host", "files.example.org:8081"))
+            .to_srv_request();
+        assert!(!origin.is_content_request(&forwarded));
+
+        // Test requests arrive on 127.0.0.1:8080
+        let listening = content_origin("http://files.example.org", Some("127.0.0.1:8080")).unwrap().unwrap();
+        assert!(listening.is_content_request(&request("app.example.org")));
+        let elsewhere = content_origin("http://files.example.org", Some("127.0.0.1:8081")).unwrap().unwrap();
+        assert!(!elsewhere.is_content_request(&request("app.example.org")));
+    }
+}
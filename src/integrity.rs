@@ -0,0 +1,158 @@
+use crate::metadata::MetadataStore;
+use crate::storage::walk_files;
+use cratr::{ScrubIssue, ScrubReport};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+// Hash a file on disk without loading it into memory
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Re-hash every stored file and compare it against the checksum recorded at upload time
+pub fn scrub(upload_dir: &str, metadata: &MetadataStore) -> ScrubReport {
+    let mut report = ScrubReport::default();
+    let entries = metadata.snapshot();
+
+    for (path, meta) in &entries {
+        let Some(expected) = &meta.sha256 else {
+            continue;
+        };
+
+        let filepath = Path::new(upload_dir).join(path);
+        if !filepath.is_file() {
+            report.missing.push(path.clone());
+            continue;
+        }
+
+        report.checked += 1;
+        match hash_file(&filepath) {
+            Ok(actual) if &actual == expected => report.ok += 1,
+            Ok(actual) => report.corrupted.push(ScrubIssue {
+                path: path.clone(),
+                expected: expected.clone(),
+                actual: Some(actual),
+                error: None,
+            }),
+            Err(e) => report.corrupted.push(ScrubIssue {
+                path: path.clone(),
+                expected: expected.clone(),
+                actual: None,
+                error: Some(e.to_string()),
+            }),
+        }
+    }
+
+    // Files uploaded before checksums existed have nothing to compare against
+    for file in walk_files(Path::new(upload_dir)) {
+        if entries.get(&file.path).and_then(|m| m.sha256.as_ref()).is_none() {
+            report.unhashed.push(file.path);
+        }
+    }
+
+    report.missing.sort();
+    report.unhashed.sort();
+    report.corrupted.sort_by(|a, b| a.path.cmp(&b.path));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::FileMeta;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    struct Scratch {
+        base: PathBuf,
+        metadata: MetadataStore,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-integrity-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(base.join("uploads/docs")).unwrap();
+            let metadata = MetadataStore::open(base.join("metadata.json")).unwrap();
+            Self { base, metadata }
+        }
+
+        fn uploads(&self) -> String {
+            self.base.join("uploads").to_string_lossy().to_string()
+        }
+
+        // Store a file and record its checksum, as an upload does
+        fn upload(&self, path: &str, contents: &[u8]) {
+            let filepath = self.base.join("uploads").join(path);
+            std::fs::write(&filepath, contents).unwrap();
+            let meta = FileMeta { sha256: Some(hash_file(&filepath).unwrap()), ..FileMeta::default() };
+            self.metadata.insert(path, meta).unwrap();
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn hashes_match_sha256() {
+        let scratch = Scratch::new();
+        let path = scratch.base.join("uploads/hello.txt");
+        std::fs::write(&path, b"hello").unwrap();
+        assert_eq!(hash_file(&path).unwrap(), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert!(hash_file(&scratch.base.join("uploads/nothing.txt")).is_err());
+    }
+
+    #[test]
+    fn a_clean_store_scrubs_clean() {
+        let scratch = Scratch::new();
+        scratch.upload("a.txt", b"one");
+        scratch.upload("docs/b.txt", b"two");
+
+        let report = scrub(&scratch.uploads(), &scratch.metadata);
+        assert_eq!((report.checked, report.ok), (2, 2));
+        assert!(report.missing.is_empty() && report.corrupted.is_empty() && report.unhashed.is_empty());
+    }
+
+    #[test]
+    fn reports_corrupted_missing_and_unhashed_files() {
+        let scratch = Scratch::new();
+        scratch.upload("fine.txt", b"fine");
+        scratch.upload("docs/changed.txt", b"before");
+        scratch.upload("gone.txt", b"gone");
+        std::fs::write(scratch.base.join("uploads/docs/changed.txt"), b"after").unwrap();
+        std::fs::remove_file(scratch.base.join("uploads/gone.txt")).unwrap();
+        // On disk with no checksum: one never recorded, one recorded without a hash
+        std::fs::write(scratch.base.join("uploads/old.txt"), b"old").unwrap();
+        std::fs::write(scratch.base.join("uploads/docs/tagged.txt"), b"tagged").unwrap();
+        scratch.metadata.insert("docs/tagged.txt", FileMeta { tags: vec!["x".into()], ..FileMeta::default() }).unwrap();
+
+        let report = scrub(&scratch.uploads(), &scratch.metadata);
+        assert_eq!((report.checked, report.ok), (2, 1));
+        assert_eq!(report.missing, vec!["gone.txt"]);
+        assert_eq!(report.unhashed, vec!["docs/tagged.txt", "old.txt"]);
+
+        let [issue] = report.corrupted.as_slice() else {
+            panic!("expected one corrupted file, got {:?}", report.corrupted.len());
+        };
+        assert_eq!(issue.path, "docs/changed.txt");
+        assert_eq!(issue.expected, scratch.metadata.get("docs/changed.txt").unwrap().sha256.unwrap());
+        let actual = hash_file(&scratch.base.join("uploads/docs/changed.txt")).unwrap();
+        assert_eq!(issue.actual.as_deref(), Some(actual.as_str()));
+        assert!(issue.error.is_none());
+    }
+}
@@ -0,0 +1,459 @@
+use crate::auth::{canonical_username, parse_group_role, parse_role, role_for_groups, AuthProvider, Outcome};
+use cratr::Role;
+use ldap3::{dn_escape, ldap_escape, LdapConn, LdapConnSettings, Scope, SearchEntry};
+use std::time::Duration;
+
+// How long to wait for the directory before giving up on a login
+const TIMEOUT: Duration = Duration::from_secs(10);
+// LDAP result code for a wrong password or unknown bind DN
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LdapArgs {
+    /// LDAP server to check passwords against, e.g. ldaps://ldap.example.org (turns LDAP login on)
+    #[arg(long, env = "CRATR_LDAP_URL")]
+    pub ldap_url: Option<String>,
+
+    /// Upgrade a plain ldap:// connection with StartTLS
+    #[arg(long, env = "CRATR_LDAP_STARTTLS")]
+    pub ldap_starttls: bool,
+
+    /// DN to bind as, with {username} filled in, e.g. "uid={username},ou=people,dc=example,dc=org".
+    /// Without it users are looked up under --ldap-base-dn first
+    #[arg(long, env = "CRATR_LDAP_USER_DN")]
+    pub ldap_user_dn: Option<String>,
+
+    /// Where to look up users and groups, e.g. "dc=example,dc=org"
+    #[arg(long, env = "CRATR_LDAP_BASE_DN")]
+    pub ldap_base_dn: Option<String>,
+
+    /// Filter that finds a user's entry, with {username} filled in
+    #[arg(long, env = "CRATR_LDAP_USER_FILTER", default_value = "(uid={username})")]
+    pub ldap_user_filter: String,
+
+    /// Service account to look users up with (anonymous if not given)
+    #[arg(long, env = "CRATR_LDAP_BIND_DN")]
+    pub ldap_bind_dn: Option<String>,
+
+    /// Password of the service account
+    #[arg(long, env = "CRATR_LDAP_BIND_PASSWORD", hide_env_values = true)]
+    pub ldap_bind_password: Option<String>,
+
+    /// Filter that finds a user's groups, with {dn} and {username} filled in
+    #[arg(
+        long,
+        env = "CRATR_LDAP_GROUP_FILTER",
+        default_value = "(|(member={dn})(uniqueMember={dn})(memberUid={username}))"
+    )]
+    pub ldap_group_filter: String,
+
+    /// Give members of a directory group (by cn) a role, e.g. "cratr-admins=admin". Repeat for
+    /// more groups; a user in several gets the first of admin, editor, viewer, uploader
+    #[arg(long = "ldap-group-role", env = "CRATR_LDAP_GROUP_ROLES", value_delimiter = ',', value_parser = parse_group_role)]
+    pub ldap_group_roles: Vec<(String, Role)>,
+
+    /// Role for directory users in none of the mapped groups (by default they can't log in)
+    #[arg(long, env = "CRATR_LDAP_DEFAULT_ROLE", value_parser = parse_role)]
+    pub ldap_default_role: Option<Role>,
+}
+
+// Checks passwords by binding to a directory server as the user, then reads the user's
+// groups to pick a role
+pub struct LdapProvider {
+    url: String,
+    starttls: bool,
+    user_dn: Option<String>,
+    base_dn: Option<String>,
+    user_filter: String,
+    service_account: Option<(String, String)>,
+    group_filter: String,
+    group_roles: Vec<(String, Role)>,
+    default_role: Option<Role>,
+}
+
+impl LdapProvider {
+    // None unless an LDAP URL is configured
+    pub fn from_args(args: &LdapArgs) -> Result<Option<Self>, String> {
+        let Some(url) = args.ldap_url.clone() else {
+            return Ok(None);
+        };
+        if args.ldap_user_dn.is_none() && args.ldap_base_dn.is_none() {
+            return Err("LDAP needs --ldap-user-dn or --ldap-base-dn to find users".to_string());
+        }
+        if !args.ldap_group_roles.is_empty() && args.ldap_base_dn.is_none() {
+            return Err("--ldap-group-role needs --ldap-base-dn to find groups".to_string());
+        }
+        if args.ldap_group_roles.is_empty() && args.ldap_default_role.is_none() {
+            return Err("LDAP needs --ldap-group-role or --ldap-default-role, or nobody could log in".to_string());
+        }
+        let service_account = match (&args.ldap_bind_dn, &args.ldap_bind_password) {
+            (Some(dn), Some(password)) => Some((dn.clone(), password.clone())),
+            (None, None) => None,
+            _ => return Err("--ldap-bind-dn and --ldap-bind-password go together".to_string()),
+        };
+
+        Ok(Some(Self {
+            url,
+            starttls: args.ldap_starttls,
+            user_dn: args.ldap_user_dn.clone(),
+            base_dn: args.ldap_base_dn.clone(),
+            user_filter: args.ldap_user_filter.clone(),
+            service_account,
+            group_filter: args.ldap_group_filter.clone(),
+            group_roles: args.ldap_group_roles.clone(),
+            default_role: args.ldap_default_role,
+        }))
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn connect(&self) -> Result<LdapConn, String> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(TIMEOUT)
+            .set_starttls(self.starttls);
+        LdapConn::with_settings(settings, &self.url).map_err(|e| e.to_string())
+    }
+
+    // DN of the user's entry, or None if the directory has no such user
+    fn find_user(&self, conn: &mut LdapConn, username: &str) -> Result<Option<String>, String> {
+        if let Some(template) = &self.user_dn {
+            return Ok(Some(template.replace("{username}", &dn_escape(username))));
+        }
+        if let Some((dn, password)) = &self.service_account {
+            conn.with_timeout(TIMEOUT)
+                .simple_bind(dn, password)
+                .and_then(|result| result.success())
+                .map_err(|e| format!("service account bind failed: {}", e))?;
+        }
+
+        let base = self.base_dn.as_deref().unwrap_or_default();
+        let filter = self.user_filter.replace("{username}", &ldap_escape(username));
+        let (entries, _) = conn
+            .with_timeout(TIMEOUT)
+            .search(base, Scope::Subtree, &filter, vec!["1.1"])
+            .and_then(|result| result.success())
+            .map_err(|e| format!("user search failed: {}", e))?;
+        match entries.len() {
+            0 => Ok(None),
+            1 => Ok(entries.into_iter().next().map(|entry| SearchEntry::construct(entry).dn)),
+            n => Err(format!("{} entries match {}", n, filter)),
+        }
+    }
+
+    // Role from the mapped groups the user is in
+    fn role_for(&self, conn: &mut LdapConn, dn: &str, username: &str) -> Result<Option<Role>, String> {
+        let mut role = None;
+        if let (Some(base), false) = (&self.base_dn, self.group_roles.is_empty()) {
+            let filter = self
+                .group_filter
+                .replace("{dn}", &ldap_escape(dn))
+                .replace("{username}", &ldap_escape(username));
+            let (entries, _) = conn
+                .with_timeout(TIMEOUT)
+                .search(base, Scope::Subtree, &filter, vec!["cn"])
+                .and_then(|result| result.success())
+                .map_err(|e| format!("group search failed: {}", e))?;
+            let groups: Vec<String> = entries
+                .into_iter()
+                .flat_map(|entry| SearchEntry::construct(entry).attrs.remove("cn").unwrap_or_default())
+                .collect();
+            role = role_for_groups(&self.group_roles, &groups);
+        }
+        Ok(role.or(self.default_role))
+    }
+}
+
+impl AuthProvider for LdapProvider {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate(&self, username: &str, password: &str) -> Result<Outcome, String> {
+        let username = &canonical_username(username);
+        // An empty password makes a simple bind anonymous, which most servers accept
+        if username.is_empty() || password.is_empty() {
+            return Ok(Outcome::Rejected);
+        }
+
+        let mut conn = self.connect()?;
+        let outcome = (|| {
+            let Some(dn) = self.find_user(&mut conn, username)? else {
+                return Ok(Outcome::Unknown);
+            };
+            let bind = conn
+                .with_timeout(TIMEOUT)
+                .simple_bind(&dn, password)
+                .map_err(|e| format!("bind failed: {}", e))?;
+            match bind.rc {
+                0 => {}
+                INVALID_CREDENTIALS => return Ok(Outcome::Rejected),
+                _ => return Err(format!("bind failed: {}", bind)),
+            }
+            Ok(match self.role_for(&mut conn, &dn, username)? {
+                Some(role) => Outcome::Accepted(role),
+                None => Outcome::NoRole,
+            })
+        })();
+        let _ = conn.unbind();
+        outcome
+    }
+
+    // uid matching is case-insensitive, so any spelling binds as the same directory entry
+    fn canonical_username(&self, username: &str) -> String {
+        canonical_username(username)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{LocalProvider, Providers};
+    use crate::users::UserStore;
+    use std::sync::Arc;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    const BASE: &str = "dc=example,dc=org";
+    const USERS: &[(&str, &str)] = &[("alice", "alice-pw"), ("bob", "bob-pw"), ("dave", "dave-pw"), ("admin", "admin-pw")];
+    const GROUPS: &[(&str, &[&str])] = &[("cratr-admins", &["alice"]), ("staff", &["bob"])];
+    const SERVICE_ACCOUNT: (&str, &str) = ("cn=cratr,dc=example,dc=org", "service-pw");
+
+    fn user_dn(username: &str) -> String {
+        format!("uid={},ou=people,{}", username, BASE)
+    }
+
+    // A stand-in for a directory server holding the users and groups above. It answers simple
+    // binds, and searches whose filter is equality matches joined with & or |.
+    fn mock_directory() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || serve(stream));
+            }
+        });
+        url
+    }
+
+    fn serve(mut stream: TcpStream) {
+        while let Some(message) = read_message(&mut stream) {
+            let parts = children(&message);
+            let id = parts[0].1;
+            let (operation, request) = parts[1];
+            let mut reply = Vec::new();
+            match operation {
+                // BindRequest: version, name, simple password
+                0x60 => {
+                    let fields = children(request);
+                    let (dn, password) = (text(fields[1].1), text(fields[2].1));
+                    let known = (dn.as_str(), password.as_str()) == SERVICE_ACCOUNT
+                        || USERS.iter().any(|(user, pw)| user_dn(user) == dn && *pw == password);
+                    reply.extend(message_with(id, &result(0x61, if known { 0 } else { INVALID_CREDENTIALS as u8 })));
+                }
+                // SearchRequest: the filter is the seventh field
+                0x63 => {
+                    let mut entries: Vec<(String, Option<&str>)> = Vec::new();
+                    for (attribute, value) in equalities(children(request)[6]) {
+                        match attribute.as_str() {
+                            "uid" if USERS.iter().any(|(user, _)| *user == value) => entries.push((user_dn(&value), None)),
+                            "member" | "uniquemember" | "memberuid" => {
+                                for (group, members) in GROUPS {
+                                    if members.iter().any(|member| user_dn(member) == value || *member == value) {
+                                        entries.push((format!("cn={},ou=groups,{}", group, BASE), Some(group)));
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    entries.dedup();
+                    for (dn, cn) in entries {
+                        let attributes = cn
+                            .map(|cn| tlv(0x30, &[octets("cn"), tlv(0x31, &octets(cn))].concat()))
+                            .unwrap_or_default();
+                        reply.extend(message_with(id, &tlv(0x64, &[octets(&dn), tlv(0x30, &attributes)].concat())));
+                    }
+                    reply.extend(message_with(id, &result(0x65, 0)));
+                }
+                // UnbindRequest, and anything the mock doesn't know, ends the connection
+                _ => return,
+            }
+            if stream.write_all(&reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn read_message(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).ok()?;
+        let mut length = header[1] as usize;
+        if length & 0x80 != 0 {
+            let mut bytes = vec![0u8; length & 0x7f];
+            stream.read_exact(&mut bytes).ok()?;
+            length = bytes.iter().fold(0, |length, byte| length << 8 | *byte as usize);
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).ok()?;
+        Some(body)
+    }
+
+    // The (tag, value) elements a BER-encoded constructed value is made of
+    fn children(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut elements = Vec::new();
+        while data.len() >= 2 {
+            let (tag, mut length, mut start) = (data[0], data[1] as usize, 2);
+            if length & 0x80 != 0 {
+                let count = length & 0x7f;
+                length = data[2..2 + count].iter().fold(0, |length, byte| length << 8 | *byte as usize);
+                start += count;
+            }
+            elements.push((tag, &data[start..start + length]));
+            data = &data[start + length..];
+        }
+        elements
+    }
+
+    // The attribute=value pairs of an equality filter, or of an and/or of them
+    fn equalities((tag, value): (u8, &[u8])) -> Vec<(String, String)> {
+        match tag {
+            0xa0 | 0xa1 => children(value).into_iter().flat_map(equalities).collect(),
+            0xa3 => {
+                let pair = children(value);
+                vec![(text(pair[0].1).to_lowercase(), text(pair[1].1))]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).to_string()
+    }
+
+    fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        if value.len() < 0x80 {
+            encoded.push(value.len() as u8);
+        } else {
+            let length = (value.len() as u32).to_be_bytes();
+            let length = &length[length.iter().position(|byte| *byte != 0).unwrap_or(3)..];
+            encoded.push(0x80 | length.len() as u8);
+            encoded.extend_from_slice(length);
+        }
+        encoded.extend_from_slice(value);
+        encoded
+    }
+
+    fn octets(value: &str) -> Vec<u8> {
+        tlv(0x04, value.as_bytes())
+    }
+
+    // An LDAPResult with `code` and no matched DN or message
+    fn result(operation: u8, code: u8) -> Vec<u8> {
+        tlv(operation, &[tlv(0x0a, &[code]), octets(""), octets("")].concat())
+    }
+
+    fn message_with(id: &[u8], operation: &[u8]) -> Vec<u8> {
+        tlv(0x30, &[tlv(0x02, id), operation.to_vec()].concat())
+    }
+
+    fn provider(url: &str, configure: impl FnOnce(&mut LdapArgs)) -> LdapProvider {
+        let mut args = LdapArgs {
+            ldap_url: Some(url.to_string()),
+            ldap_starttls: false,
+            ldap_user_dn: None,
+            ldap_base_dn: Some(BASE.to_string()),
+            ldap_user_filter: "(uid={username})".to_string(),
+            ldap_bind_dn: Some(SERVICE_ACCOUNT.0.to_string()),
+            ldap_bind_password: Some(SERVICE_ACCOUNT.1.to_string()),
+            ldap_group_filter: "(|(member={dn})(uniqueMember={dn})(memberUid={username}))".to_string(),
+            ldap_group_roles: vec![("cratr-admins".to_string(), Role::Admin), ("staff".to_string(), Role::Editor)],
+            ldap_default_role: None,
+        };
+        configure(&mut args);
+        LdapProvider::from_args(&args).unwrap().unwrap()
+    }
+
+    #[test]
+    fn a_wrong_password_is_rejected() {
+        let url = mock_directory();
+        let ldap = provider(&url, |_| {});
+        assert_eq!(ldap.authenticate("alice", "guess").unwrap(), Outcome::Rejected);
+        // Binding straight to a DN template fails the same way
+        let ldap = provider(&url, |args| args.ldap_user_dn = Some(format!("uid={{username}},ou=people,{}", BASE)));
+        assert_eq!(ldap.authenticate("bob", "alice-pw").unwrap(), Outcome::Rejected);
+    }
+
+    #[test]
+    fn unknown_users_are_left_to_other_providers() {
+        let ldap = provider(&mock_directory(), |_| {});
+        assert_eq!(ldap.authenticate("mallory", "anything").unwrap(), Outcome::Unknown);
+    }
+
+    #[test]
+    fn a_wrong_service_account_password_is_an_error() {
+        let ldap = provider(&mock_directory(), |args| args.ldap_bind_password = Some("wrong".to_string()));
+        assert!(ldap.authenticate("alice", "alice-pw").unwrap_err().contains("service account bind failed"));
+    }
+
+    #[test]
+    fn groups_map_to_roles() {
+        let url = mock_directory();
+        let ldap = provider(&url, |_| {});
+        assert_eq!(ldap.authenticate("alice", "alice-pw").unwrap(), Outcome::Accepted(Role::Admin));
+        assert_eq!(ldap.authenticate("bob", "bob-pw").unwrap(), Outcome::Accepted(Role::Editor));
+        // Group names are matched without case
+        let ldap = provider(&url, |args| args.ldap_group_roles = vec![("Staff".to_string(), Role::Viewer)]);
+        assert_eq!(ldap.authenticate("bob", "bob-pw").unwrap(), Outcome::Accepted(Role::Viewer));
+    }
+
+    #[test]
+    fn any_spelling_of_a_username_is_the_same_account() {
+        let ldap = provider(&mock_directory(), |_| {});
+        assert_eq!(ldap.authenticate(" Alice ", "alice-pw").unwrap(), Outcome::Accepted(Role::Admin));
+        assert_eq!(ldap.canonical_username(" Alice "), "alice");
+        assert_eq!(ldap.canonical_username("ALICE"), ldap.canonical_username("alice"));
+    }
+
+    #[test]
+    fn the_built_in_admin_never_reaches_the_directory() {
+        let scratch = std::env::temp_dir().join(format!("cratr-ldap-{}", uuid::Uuid::new_v4().simple()));
+        let users = Arc::new(UserStore::open(scratch.join("users.json")).unwrap());
+        let ldap = provider(&mock_directory(), |args| args.ldap_default_role = Some(Role::Viewer));
+        let providers = Providers::new(vec![Box::new(LocalProvider::new("admin", "local-pw", users)), Box::new(ldap)]);
+
+        // The directory's "admin" entry and its password don't get in under any spelling
+        for typed in ["admin", "Admin", " admin", "ADMIN "] {
+            let (outcome, provider, username) = providers.authenticate(typed, "admin-pw").unwrap();
+            assert_eq!((outcome, provider, username.as_str()), (Outcome::Rejected, "local", "admin"), "{:?}", typed);
+        }
+        let (outcome, _, username) = providers.authenticate("Admin", "local-pw").unwrap();
+        assert_eq!((outcome, username.as_str()), (Outcome::Accepted(Role::Admin), "admin"));
+        let (outcome, provider, _) = providers.authenticate("Alice", "alice-pw").unwrap();
+        assert_eq!((outcome, provider), (Outcome::Accepted(Role::Admin), "ldap"));
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[test]
+    fn users_in_no_mapped_group_get_no_role() {
+        let url = mock_directory();
+        assert_eq!(provider(&url, |_| {}).authenticate("dave", "dave-pw").unwrap(), Outcome::NoRole);
+        let ldap = provider(&url, |args| args.ldap_default_role = Some(Role::Uploader));
+        assert_eq!(ldap.authenticate("dave", "dave-pw").unwrap(), Outcome::Accepted(Role::Uploader));
+    }
+
+    #[test]
+    fn an_unreachable_directory_is_an_error() {
+        let url = format!("ldap://{}", TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap());
+        assert!(provider(&url, |_| {}).authenticate("alice", "alice-pw").is_err());
+    }
+
+    #[test]
+    fn empty_passwords_never_reach_the_directory() {
+        let ldap = provider("ldap://127.0.0.1:9", |_| {});
+        assert_eq!(ldap.authenticate("alice", "").unwrap(), Outcome::Rejected);
+    }
+}
@@ -1,17 +1,100 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FileInfo {
     pub name: String,
     pub path: String,
+    #[serde(default)]
+    pub folder: String,
     pub size: u64,
     pub file_type: String,
     pub can_preview: bool,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // User-defined key/value metadata
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    // Last modification time, seconds since the Unix epoch
+    #[serde(default)]
+    pub modified: u64,
+    // When the file was uploaded and last downloaded, if known
+    #[serde(default)]
+    pub uploaded_at: Option<u64>,
+    #[serde(default)]
+    pub last_accessed: Option<u64>,
+    // Whether the current user has starred the file
+    #[serde(default)]
+    pub starred: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FilesResponse {
     pub files: Vec<FileInfo>,
+    // Number of files matching the filters across all pages
+    #[serde(default)]
+    pub total: usize,
+    // Pass back as `cursor` to fetch the next page; absent on the last page
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+// One result from the full-text index. `snippet` is HTML: text is escaped and matched
+// words are wrapped in <b>.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub path: String,
+    pub name: String,
+    pub folder: String,
+    pub file_type: String,
+    pub can_preview: bool,
+    pub snippet: String,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+}
+
+// Filters, ordering and paging for `GET /files`. Every field is optional; dates are
+// either Unix timestamps or YYYY-MM-DD.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileQuery {
+    #[serde(default)]
+    pub q: Option<String>,
+    #[serde(default, rename = "type")]
+    pub file_type: Option<String>,
+    #[serde(default)]
+    pub min_size: Option<u64>,
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    #[serde(default)]
+    pub modified_after: Option<String>,
+    #[serde(default)]
+    pub modified_before: Option<String>,
+    // Comma-separated tags; a file must carry all of them
+    #[serde(default)]
+    pub tag: Option<String>,
+    // Only files the current user has starred
+    #[serde(default)]
+    pub starred: Option<bool>,
+    // Only files in this folder or below it
+    #[serde(default)]
+    pub folder: Option<String>,
+    // One of folder (default), name, size, modified, type, recent
+    #[serde(default)]
+    pub sort: Option<String>,
+    // asc (default) or desc
+    #[serde(default)]
+    pub order: Option<String>,
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +122,39 @@ pub struct UploadResponse {
     pub success: bool,
     pub message: String,
     pub files: Vec<FileInfo>,
+    // What the virus scanner said about each file, when scanning is on
+    #[serde(default)]
+    pub scanned: Vec<ScanResult>,
+}
+
+// One file's virus scan. Infected files are moved to the quarantine folder instead of
+// being stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanResult {
+    pub name: String,
+    // Path in the store, or where the file was before it was quarantined
+    pub path: String,
+    pub infected: bool,
+    // What the scanner found
+    pub signature: Option<String>,
+    // Name of the file in the quarantine folder
+    pub quarantined_as: Option<String>,
+}
+
+// Result of rescanning every stored file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RescanReport {
+    pub scanned: usize,
+    pub clean: usize,
+    pub infected: Vec<ScanResult>,
+    // Files the scanner refused, e.g. for being over its size limit
+    pub failed: Vec<ScanFailure>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanFailure {
+    pub path: String,
+    pub error: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,12 +179,471 @@ pub struct LoginResponse {
     pub success: bool,
     pub message: String,
     pub authenticated: bool,
+    // The password was right; send a code to POST /login/2fa to finish logging in
+    #[serde(default)]
+    pub two_factor_required: bool,
+    // The password was right but two-factor authentication is mandatory and not set up
+    // yet; enroll through /2fa/enroll and /2fa/confirm to finish logging in
+    #[serde(default)]
+    pub enrollment_required: bool,
+}
+
+// A TOTP code from the authenticator app, or one of the recovery codes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    // Every account has to use two-factor authentication
+    pub required: bool,
+    pub recovery_codes_left: usize,
+}
+
+// Everything an authenticator app needs to add the account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorEnrollment {
+    // Base32, for typing in by hand
+    pub secret: String,
+    pub otpauth_uri: String,
+    // PNG data URL of the otpauth URI as a QR code
+    pub qr_code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    pub success: bool,
+    pub message: String,
+    // Shown once; only hashes are kept on the server
+    pub recovery_codes: Vec<String>,
+    pub authenticated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorPolicy {
+    pub required: bool,
+}
+
+// A kind of access a request needs. Roles grant a fixed set of scopes; an API token carries
+// its own, and a request with one needs the scope from both the token and its owner's role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // List, search, preview, download and star
+    Read,
+    // Add new files, nothing else
+    Upload,
+    // Upload, move, copy, tag and extract
+    Write,
+    Delete,
+    // The /admin endpoints: users, settings, audit log and scrub
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [Scope::Read, Scope::Upload, Scope::Write, Scope::Delete, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Upload => "upload",
+            Scope::Write => "write",
+            Scope::Delete => "delete",
+            Scope::Admin => "admin",
+        }
+    }
+
+    // Whether holding `scopes` is enough for an action that needs this scope
+    pub fn granted_by(self, scopes: &[Scope]) -> bool {
+        scopes.contains(&self) || (self == Scope::Upload && scopes.contains(&Scope::Write))
+    }
+}
+
+// What an account may do, checked on every request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Everything, including managing users and settings
+    Admin,
+    // Read, upload, change and delete files
+    Editor,
+    // List and download only
+    Viewer,
+    // Upload new files without seeing what is already stored
+    Uploader,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Admin, Role::Editor, Role::Viewer, Role::Uploader];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+            Role::Uploader => "uploader",
+        }
+    }
+
+    pub fn scopes(self) -> &'static [Scope] {
+        match self {
+            Role::Admin => &Scope::ALL,
+            Role::Editor => &[Scope::Read, Scope::Write, Scope::Delete],
+            Role::Viewer => &[Scope::Read],
+            Role::Uploader => &[Scope::Upload],
+        }
+    }
+
+    pub fn allows(self, scope: Scope) -> bool {
+        scope.granted_by(self.scopes())
+    }
+}
+
+// An account as shown to admins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub role: Role,
+    pub two_factor: bool,
+    // The account configured on the command line, which can't be changed or deleted here
+    #[serde(default)]
+    pub builtin: bool,
+    // Provider of an account that logs in through a directory, e.g. "ldap"
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsersResponse {
+    pub users: Vec<UserInfo>,
+}
+
+// How much a folder share lets someone do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    // List, search, preview and download
+    Read,
+    // Also add, change, move out and delete files
+    ReadWrite,
+}
+
+impl Access {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::ReadWrite => "read-write",
+        }
+    }
+}
+
+// Who a folder is shared with. The group "everyone" contains every account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Principal {
+    User(String),
+    Group(String),
+}
+
+// One entry of a folder's access list, e.g. {"user": "alice", "access": "read"}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    #[serde(flatten)]
+    pub principal: Principal,
+    pub access: Access,
+}
+
+// A private folder, who owns it and who else may use it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderShare {
+    pub folder: String,
+    pub owner: String,
+    pub grants: Vec<Grant>,
+    // What the current user may do there, for folders shared with them
+    #[serde(default)]
+    pub access: Option<Access>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharesResponse {
+    // Folders the current user owns
+    pub owned: Vec<FolderShare>,
+    // Folders other people have shared with the current user
+    pub shared_with_me: Vec<FolderShare>,
+}
+
+// Replaces the whole access list of a folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareUpdate {
+    pub grants: Vec<Grant>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupsResponse {
+    pub groups: Vec<Group>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+}
+
+// Fields left out stay as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateUserRequest {
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+// A personal access token as listed to its owner; the secret itself is only shown once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiTokenInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    // Seconds since the Unix epoch
+    pub created: u64,
+    #[serde(default)]
+    pub expires: Option<u64>,
+    #[serde(default)]
+    pub last_used: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    // Never expires when absent
+    #[serde(default)]
+    pub expires_in_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateTokenResponse {
+    pub token: ApiTokenInfo,
+    // Send as "Authorization: Bearer <secret>"
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokensResponse {
+    pub tokens: Vec<ApiTokenInfo>,
+}
+
+// A login session as listed to its owner
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    // Seconds since the Unix epoch
+    pub created: u64,
+    pub last_seen: u64,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    // Whether this is the session the list was asked for with
+    #[serde(default)]
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionsResponse {
+    pub sessions: Vec<SessionInfo>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthStatus {
     pub authenticated: bool,
     pub username: Option<String>,
+    #[serde(default)]
+    pub role: Option<Role>,
+    // Label of the single sign-on button, if single sign-on is configured
+    #[serde(default)]
+    pub single_sign_on: Option<String>,
+    // Whether logins go through an authenticating proxy instead of the login form
+    #[serde(default)]
+    pub proxy_login: bool,
+    // Sent back in the X-CSRF-Token header on requests that change something
+    #[serde(default)]
+    pub csrf_token: Option<String>,
+    // Origin to load uploaded files from, when it isn't the app's own
+    #[serde(default)]
+    pub content_origin: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchTransferRequest {
+    pub ids: Vec<String>,
+    // Folder to move or copy into; empty for the top level
+    #[serde(default)]
+    pub destination: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchTagRequest {
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+// Change the tags and key/value metadata of a single file. Keys in `unset` are removed
+// after `set` is applied.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileMetaUpdate {
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    #[serde(default)]
+    pub unset: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetaResponse {
+    pub success: bool,
+    pub message: String,
+    pub tags: Vec<String>,
+    pub properties: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarRequest {
+    pub starred: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarResponse {
+    pub success: bool,
+    pub starred: bool,
+}
+
+// Something that happened to a file, as shown in the activity feed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityEvent {
+    // Seconds since the Unix epoch
+    pub time: u64,
+    pub actor: String,
+    // upload, delete, move, copy, extract or share
+    pub action: String,
+    pub path: String,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityResponse {
+    pub events: Vec<ActivityEvent>,
+}
+
+// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    // Seconds since the Unix epoch
+    pub time: u64,
+    // The user, or the username tried for a failed login; absent for anonymous requests
+    #[serde(default)]
+    pub actor: Option<String>,
+    pub action: String,
+    #[serde(default)]
+    pub ip: Option<String>,
+    // The file or folder acted on
+    #[serde(default)]
+    pub target: Option<String>,
+    pub success: bool,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagsResponse {
+    pub tags: Vec<TagCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItemResult {
+    pub id: String,
+    pub success: bool,
+    pub message: String,
+    // Path of the file after a move or copy
+    pub new_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResponse {
+    pub success: bool,
+    pub message: String,
+    pub results: Vec<BatchItemResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchiveEntryInfo {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub file_type: String,
+    pub can_preview: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveListing {
+    pub archive: String,
+    pub entries: Vec<ArchiveEntryInfo>,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrubIssue {
+    pub path: String,
+    pub expected: String,
+    pub actual: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub ok: usize,
+    pub corrupted: Vec<ScrubIssue>,
+    pub missing: Vec<String>,
+    pub unhashed: Vec<String>,
+}
+
+impl ScrubReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty()
+    }
 }
 
 #[cfg(feature = "frontend")]
@@ -0,0 +1,342 @@
+use crate::metadata::normalize_tag;
+use crate::storage::display_name;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use cratr::{FileInfo, FileQuery};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+pub struct Page {
+    pub files: Vec<FileInfo>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
+}
+
+// Filter, sort and slice the full file list according to `query`. Errors describe the
+// offending parameter and are meant to be shown to the client.
+pub fn page(files: Vec<FileInfo>, query: &FileQuery) -> Result<Page, String> {
+    let terms: Vec<String> = query.q.as_deref()
+        .unwrap_or("")
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect();
+    let file_type = query.file_type.as_deref().map(str::to_lowercase).filter(|t| !t.is_empty());
+    let tags: Vec<String> = query.tag.as_deref()
+        .unwrap_or("")
+        .split(',')
+        .filter_map(normalize_tag)
+        .collect();
+    let folder = query.folder.as_deref().map(|folder| folder.trim_matches('/')).filter(|folder| !folder.is_empty());
+    let modified_after = query.modified_after.as_deref().map(|value| parse_time(value, false)).transpose()?;
+    let modified_before = query.modified_before.as_deref().map(|value| parse_time(value, true)).transpose()?;
+    let descending = match query.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => return Err(format!("Unknown order '{}', expected asc or desc", other)),
+    };
+    let sort = query.sort.as_deref().unwrap_or("folder");
+    if !SORT_KEYS.contains(&sort) {
+        return Err(format!("Unknown sort key '{}', expected folder, name, size, modified, type or recent", sort));
+    }
+    let after = match query.cursor.as_deref() {
+        None | Some("") => None,
+        Some(cursor) => {
+            let cursor = decode_cursor(cursor).ok_or_else(|| "Invalid cursor".to_string())?;
+            if cursor.sort != sort || cursor.descending != descending {
+                return Err("The cursor belongs to a listing in another order".to_string());
+            }
+            Some(cursor.after)
+        }
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let mut matching: Vec<(Position, FileInfo)> = files
+        .into_iter()
+        .filter(|file| {
+            // The name as shown plus its folder; the stored name's UUID prefix would match
+            // almost any short term
+            let haystack = format!("{}/{}", file.folder, display_name(&file.path)).to_lowercase();
+            terms.iter().all(|term| haystack.contains(term))
+        })
+        .filter(|file| file_type.as_ref().is_none_or(|t| file.file_type.to_lowercase().contains(t)))
+        .filter(|file| tags.iter().all(|tag| file.tags.contains(tag)))
+        .filter(|file| query.starred.is_none_or(|starred| file.starred == starred))
+        .filter(|file| folder.is_none_or(|folder| file.folder == folder || file.folder.starts_with(&format!("{}/", folder))))
+        .filter(|file| query.min_size.is_none_or(|min| file.size >= min))
+        .filter(|file| query.max_size.is_none_or(|max| file.size <= max))
+        .filter(|file| modified_after.is_none_or(|after| file.modified >= after))
+        .filter(|file| modified_before.is_none_or(|before| file.modified <= before))
+        .map(|file| (Position::of(&file, sort), file))
+        .collect();
+
+    // The path is unique, so ties always break the same way and pages never overlap
+    matching.sort_by(|a, b| if descending { b.0.cmp(&a.0) } else { a.0.cmp(&b.0) });
+
+    // The next page starts right after the last row sent, wherever that row is now. Files
+    // added, deleted or moved in between don't shift the rows that follow it.
+    let total = matching.len();
+    let start = after.map_or(0, |after| {
+        matching.partition_point(|(position, _)| if descending { *position >= after } else { *position <= after })
+    });
+    let mut rows: Vec<(Position, FileInfo)> = matching.into_iter().skip(start).take(limit + 1).collect();
+    let more = rows.len() > limit;
+    rows.truncate(limit);
+    let next_cursor = rows.last().filter(|_| more).map(|(position, _)| encode_cursor(&Cursor {
+            sort: sort.to_string(),
+            descending,
+            after: position.clone(),
+        }));
+    let files = rows.into_iter().map(|(_, file)| file).collect();
+
+    Ok(Page { files, total, next_cursor })
+}
+
+const SORT_KEYS: [&str; 6] = ["folder", "name", "size", "modified", "type", "recent"];
+
+// Where a file falls in the listing for one sort key. Fields a key doesn't use stay empty,
+// and the path comes last so that no two files share a position.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Position {
+    number: u64,
+    text: String,
+    name: String,
+    path: String,
+}
+
+impl Position {
+    fn of(file: &FileInfo, sort: &str) -> Self {
+        let (number, text, name) = match sort {
+            "folder" => (0, file.folder.clone(), file.name.to_lowercase()),
+            "name" => (0, String::new(), file.name.to_lowercase()),
+            "size" => (file.size, String::new(), String::new()),
+            "modified" => (file.modified, String::new(), String::new()),
+            "type" => (0, file.file_type.clone(), String::new()),
+            _ => (last_activity(file), String::new(), String::new()),
+        };
+        Self {
+            number,
+            text,
+            name,
+            path: file.path.clone(),
+        }
+    }
+}
+
+// The last row of a page, and the order it was listed in
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    descending: bool,
+    after: Position,
+}
+
+// Most recent of upload and download, falling back to the file's mtime
+fn last_activity(file: &FileInfo) -> u64 {
+    file.uploaded_at.unwrap_or(file.modified).max(file.last_accessed.unwrap_or(0))
+}
+
+// Cursors are opaque to clients: the JSON of a Cursor in URL-safe base64
+fn encode_cursor(cursor: &Cursor) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap_or_default())
+}
+
+fn decode_cursor(cursor: &str) -> Option<Cursor> {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()
+}
+
+// Accept a Unix timestamp or a YYYY-MM-DD date (UTC). A bare date used as an upper bound
+// covers the whole day.
+pub fn parse_time(value: &str, end_of_day: bool) -> Result<u64, String> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(seconds);
+    }
+
+    let invalid = || format!("Invalid date '{}', expected YYYY-MM-DD or a Unix timestamp", value);
+    let mut parts = value.splitn(3, '-').map(|part| part.parse::<i64>());
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + if end_of_day { 86_399 } else { 0 };
+    u64::try_from(seconds).map_err(|_| invalid())
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "3fa85f64-5717-4562-b3fc-2c963f66afa6";
+
+    fn file(folder: &str, name: &str, size: u64, modified: u64) -> FileInfo {
+        let stored = format!("{}_{}", UUID, name);
+        FileInfo {
+            name: name.to_string(),
+            path: if folder.is_empty() { stored } else { format!("{}/{}", folder, stored) },
+            folder: folder.to_string(),
+            size,
+            file_type: "text/plain".to_string(),
+            can_preview: true,
+            sha256: None,
+            tags: Vec::new(),
+            properties: Default::default(),
+            modified,
+            uploaded_at: None,
+            last_accessed: None,
+            starred: false,
+        }
+    }
+
+    fn files() -> Vec<FileInfo> {
+        vec![
+            file("", "notes.txt", 10, 100),
+            file("reports", "q1.pdf", 300, 200),
+            file("reports/2024", "Summary.txt", 20, 300),
+            file("photos", "beach.jpg", 5000, 400),
+        ]
+    }
+
+    fn names(page: &Page) -> Vec<&str> {
+        page.files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    fn query(q: &str) -> FileQuery {
+        FileQuery {
+            q: Some(q.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn search_terms_match_the_shown_name_and_folder() {
+        assert_eq!(names(&page(files(), &query("SUMMARY")).unwrap()), vec!["Summary.txt"]);
+        assert_eq!(names(&page(files(), &query("reports txt")).unwrap()), vec!["Summary.txt"]);
+        assert_eq!(page(files(), &query("reports")).unwrap().total, 2);
+        // Parts of the stored UUID prefix match nothing
+        for term in ["3fa85f64", "b3fc", "-5717-", "_"] {
+            assert_eq!(page(files(), &query(term)).unwrap().total, 0, "{:?}", term);
+        }
+    }
+
+    // Every page of a listing, fetching the next one with `between` applied to the files first
+    fn all_pages(mut files: Vec<FileInfo>, query: &FileQuery, between: impl Fn(&mut Vec<FileInfo>)) -> Vec<String> {
+        let mut query = query.clone();
+        let mut seen = Vec::new();
+        loop {
+            let page = page(files.clone(), &query).unwrap();
+            seen.extend(page.files.iter().map(|file| file.name.clone()));
+            let Some(cursor) = page.next_cursor else {
+                return seen;
+            };
+            query.cursor = Some(cursor);
+            between(&mut files);
+        }
+    }
+
+    #[test]
+    fn pages_follow_each_sort_key_and_order() {
+        let by = |sort: &str, order: &str| FileQuery {
+            sort: Some(sort.to_string()),
+            order: Some(order.to_string()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let no_change = |_: &mut Vec<FileInfo>| {};
+        assert_eq!(all_pages(files(), &by("folder", "asc"), no_change), vec!["notes.txt", "beach.jpg", "q1.pdf", "Summary.txt"]);
+        assert_eq!(all_pages(files(), &by("name", "asc"), no_change), vec!["beach.jpg", "notes.txt", "q1.pdf", "Summary.txt"]);
+        assert_eq!(all_pages(files(), &by("size", "desc"), no_change), vec!["beach.jpg", "q1.pdf", "Summary.txt", "notes.txt"]);
+        assert_eq!(all_pages(files(), &by("modified", "desc"), no_change), vec!["beach.jpg", "Summary.txt", "q1.pdf", "notes.txt"]);
+
+        let mut same_size = files();
+        for file in &mut same_size {
+            file.size = 1;
+        }
+        // Ties are broken by path, the same way on every page
+        let ascending = all_pages(same_size.clone(), &by("size", "asc"), no_change);
+        let mut descending = all_pages(same_size, &by("size", "desc"), no_change);
+        descending.reverse();
+        assert_eq!(ascending, descending);
+        assert_eq!(ascending.len(), 4);
+    }
+
+    #[test]
+    fn changes_between_pages_neither_skip_nor_repeat_rows() {
+        let query = FileQuery {
+            sort: Some("size".to_string()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        // The last row sent is deleted and a file lands before the cursor
+        let seen = all_pages(files(), &query, |files| {
+            files.retain(|file| file.name != "Summary.txt");
+            files.push(file("", "tiny.txt", 1, 500));
+        });
+        assert_eq!(seen, vec!["notes.txt", "Summary.txt", "q1.pdf", "beach.jpg"]);
+
+        // A file lands after the cursor and shows up in its place
+        let seen = all_pages(files(), &query, |files| {
+            if files.iter().all(|file| file.name != "big.bin") {
+                files.push(file("", "big.bin", 1000, 500));
+            }
+        });
+        assert_eq!(seen, vec!["notes.txt", "Summary.txt", "q1.pdf", "big.bin", "beach.jpg"]);
+    }
+
+    #[test]
+    fn cursors_are_checked() {
+        let first = page(files(), &FileQuery { limit: Some(1), ..Default::default() }).unwrap();
+        let cursor = first.next_cursor.unwrap();
+        let with = |cursor: &str, sort: Option<&str>| FileQuery {
+            cursor: Some(cursor.to_string()),
+            sort: sort.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(page(files(), &with(&cursor, None)).unwrap().files.len(), 3);
+        assert!(page(files(), &with(&cursor, Some("size"))).err().unwrap().contains("another order"));
+        assert_eq!(page(files(), &with("o2", None)).err().unwrap(), "Invalid cursor");
+        assert!(page(files(), &FileQuery { sort: Some("colour".to_string()), ..Default::default() }).is_err());
+        assert!(page(files(), &FileQuery { limit: Some(10), ..Default::default() }).unwrap().next_cursor.is_none());
+    }
+
+    #[test]
+    fn dates_and_timestamps_parse() {
+        assert_eq!(parse_time("1700000000", false), Ok(1_700_000_000));
+        assert_eq!(parse_time("1970-01-01", false), Ok(0));
+        assert_eq!(parse_time("1970-01-01", true), Ok(86_399));
+        assert_eq!(parse_time(" 2024-03-01 ", false), Ok(1_709_251_200));
+        assert_eq!(parse_time("2024-02-29", false), Ok(1_709_164_800));
+        assert_eq!(parse_time("2000-02-29", false), Ok(951_782_400));
+    }
+
+    #[test]
+    fn impossible_dates_are_refused() {
+        for value in ["2024-02-30", "2024-02-31", "2023-02-29", "1900-02-29", "2024-04-31", "2024-13-01", "2024-00-10", "2024-01-00", "1969-12-31", "yesterday", "2024-01", ""] {
+            assert!(parse_time(value, false).is_err(), "{:?}", value);
+        }
+    }
+}
@@ -1,22 +1,91 @@
 use actix_files as fs;
 use actix_multipart::Multipart;
 use actix_web::{
-    get, middleware::Logger, post, web, App, HttpResponse, HttpServer, Result as ActixResult,
-    cookie::Key,
+    get, middleware::{DefaultHeaders, Logger}, post, web, App, HttpRequest, HttpResponse, HttpServer, Result as ActixResult,
+    body::{BodySize, MessageBody as _}, cookie::Key, dev::{Service as _, ServiceResponse}, HttpMessage as _, http::{header::{self, ContentDisposition}, StatusCode},
 };
-use actix_session::{SessionMiddleware, storage::CookieSessionStore};
+use actix_session::{SessionExt as _, SessionMiddleware, config::{BrowserSession, TtlExtensionPolicy}};
 use actix_identity::IdentityMiddleware;
 #[cfg(feature = "server")]
 use futures_util::TryStreamExt as _;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs::create_dir_all;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
 use uuid::Uuid;
-use cratr::{FileInfo, StorageInfo, LoginRequest, LoginResponse, AuthStatus};
-use clap::Parser;
+use cratr::{ActivityResponse, ArchiveListing, AuditResponse, RescanReport, ScanFailure, ScanResult, BatchItemResult, BatchRequest, BatchResponse, BatchTagRequest, BatchTransferRequest, FileInfo, FileMetaResponse, FileMetaUpdate, FileQuery, FilesResponse, SearchResponse, StarRequest, StarResponse, StorageInfo, TagCount, TagsResponse, LoginRequest, LoginResponse, AuthStatus, RecoveryCodesResponse, Role, Scope, TwoFactorCode, CreateUserRequest, UpdateUserRequest, UserInfo, UsersResponse, ApiTokenInfo, CreateTokenRequest, CreateTokenResponse, TokensResponse, SessionsResponse, Access, Grant, Group, GroupsResponse, Principal, ShareUpdate, SharesResponse, TwoFactorEnrollment, TwoFactorPolicy, TwoFactorStatus};
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing::{debug, error, info, warn};
+use tracing_actix_web::{RequestId, TracingLogger};
+use tracing_subscriber::EnvFilter;
+
+mod activity;
+mod archive;
+mod audit;
+mod auth;
+mod batch;
+mod headers;
+mod integrity;
+mod ldap;
+mod listing;
+mod metadata;
+mod metrics;
+mod oidc;
+mod proxy;
+mod rules;
+mod scan;
+mod search;
+mod sessions;
+mod shares;
+mod storage;
+mod throttle;
+mod tokens;
+mod users;
+
+use activity::ActivityLog;
+use archive::{ArchiveFormat, ArchiveKind};
+use audit::{AuditFilter, AuditLog};
+use auth::{canonical_username, same_secret, AuthProvider, LocalProvider, Outcome, Providers};
+use headers::{ContentArgs, ContentOrigin};
+use ldap::{LdapArgs, LdapProvider};
+use metadata::{FileMeta, MetadataStore};
+use metrics::{Metrics, MetricsArgs, StorageSample};
+use oidc::{Challenge, OidcArgs, OidcClient};
+use proxy::{ProxyArgs, ProxyAuth};
+use rules::{RuleArgs, UploadRules, SNIFF_LENGTH};
+use scan::{ScanArgs, ScanError, Scanner, Verdict};
+use search::SearchIndex;
+use sessions::{SessionBackend, SessionStore, SESSION_ID};
+use shares::{FolderAccess, ShareStore};
+use throttle::LoginThrottle;
+use tokens::TokenStore;
+use users::{SyncError, UserStore};
+use storage::{display_name, folder_of, resolve, resolve_folder, resolve_relative, sanitize_filename, unix_now, walk_files, Resolved};
 
 const UPLOAD_DIR: &str = "./uploads";
+const INDEX_HTML: &str = include_str!("../static/index.html");
+const DATA_DIR: &str = "./data";
+// Infected files end up here, outside the upload directory
+const QUARANTINE_DIR: &str = "./data/quarantine";
+// Uploads and extracted archives are written and scanned here before they join the store
+const INCOMING_DIR: &str = "./data/incoming";
+const METADATA_FILE: &str = "./data/metadata.json";
+const INDEX_DIR: &str = "./data/index";
+const ACTIVITY_FILE: &str = "./data/events.jsonl";
+const MAX_ACTIVITY_EVENTS: usize = 200;
+const AUDIT_FILE: &str = "./data/audit.jsonl";
+const USERS_FILE: &str = "./data/users.json";
+const TOKENS_FILE: &str = "./data/tokens.json";
+const SHARES_FILE: &str = "./data/shares.json";
+const SESSIONS_FILE: &str = "./data/sessions.json";
+// Session changes reach the disk this long after they happen at the latest
+const SESSION_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+const MAX_AUDIT_ENTRIES: usize = 1000;
+const MAX_SEARCH_RESULTS: usize = 50;
+// Files one archive may unpack into the store
+const MAX_EXTRACTED_FILES: usize = 10_000;
 const MAX_FILE_SIZE: usize = 16384 * 1024 * 1024; // 16384 MB
 const MAX_FILE_COUNT: usize = 10;
 const MAX_STORAGE_SIZE: u64 = 1024 * 1024 * 1024 * 1024; // 1024 GB total storage limit
@@ -24,18 +93,130 @@ const MAX_STORAGE_SIZE: u64 = 1024 * 1024 * 1024 * 1024; // 1024 GB total storag
 // Default credentials - change these in production!
 const DEFAULT_USERNAME: &str = "admin";
 const DEFAULT_PASSWORD: &str = "admin";
+const DEFAULT_BIND: &str = "127.0.0.1:8080";
+
+// Session keys for a login waiting on its second step, and how long it may wait
+const PENDING_2FA: &str = "pending_2fa";
+const PENDING_ENROLLMENT: &str = "pending_enrollment";
+const PENDING_OIDC: &str = "pending_oidc";
+// Session key for the last user a trusted proxy named
+const PROXY_USER: &str = "proxy_user";
+// Session key for the CSRF token, and the header the web interface sends it back in
+const CSRF_TOKEN: &str = "csrf_token";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+const PENDING_LOGIN_TIMEOUT: u64 = 5 * 60;
+// Longest User-Agent kept with a session
+const MAX_USER_AGENT_LENGTH: usize = 200;
+// Longest lifetime an API token can be given, in days
+const MAX_TOKEN_DAYS: u64 = 3650;
+// Shortest password accepted for accounts added by an admin
+const MIN_PASSWORD_LENGTH: usize = 8;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Enable debug mode
+    /// Enable debug mode (also turns on debug logging unless --log-level is given)
     #[arg(long)]
     debug: bool,
+
+    /// Log filter, e.g. "info" or "cratr=debug,actix_web=info" (defaults to RUST_LOG, then "info")
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// Log output format
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Address to listen on
+    #[arg(long, env = "CRATR_BIND", default_value = DEFAULT_BIND)]
+    bind: String,
+
+    /// Login username
+    #[arg(long, env = "CRATR_USERNAME", default_value = DEFAULT_USERNAME)]
+    username: String,
+
+    /// Login password
+    #[arg(long, env = "CRATR_PASSWORD", default_value = DEFAULT_PASSWORD, hide_env_values = true)]
+    password: String,
+
+    /// Start even though the default password is in use on a non-loopback address
+    #[arg(long)]
+    allow_default_password: bool,
+
+    /// Log sessions out after this many minutes without a request
+    #[arg(long, env = "CRATR_SESSION_IDLE_TIMEOUT", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    session_idle_timeout: u64,
+
+    /// Log sessions out this many hours after login, however active they are
+    #[arg(long, env = "CRATR_SESSION_LIFETIME", default_value_t = 24, value_parser = clap::value_parser!(u64).range(1..))]
+    session_lifetime: u64,
+
+    #[command(flatten)]
+    ldap: LdapArgs,
+
+    #[command(flatten)]
+    oidc: OidcArgs,
+
+    #[command(flatten)]
+    proxy: ProxyArgs,
+
+    #[command(flatten)]
+    content: ContentArgs,
+
+    #[command(flatten)]
+    scan: ScanArgs,
+
+    #[command(flatten)]
+    metrics: MetricsArgs,
+
+    #[command(flatten)]
+    rules: RuleArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-hash every stored file and report checksum mismatches (bitrot)
+    Scrub,
 }
 
 #[derive(Clone)]
 struct AppState {
     debug_mode: bool,
+    username: String,
+    login_throttle: Arc<LoginThrottle>,
+    auth: Arc<Providers>,
+    oidc: Option<Arc<OidcClient>>,
+    proxy: Option<Arc<ProxyAuth>>,
+    users: Arc<UserStore>,
+    tokens: Arc<TokenStore>,
+    sessions: Arc<SessionStore>,
+    shares: Arc<ShareStore>,
+    metadata: Arc<MetadataStore>,
+    search: Arc<SearchIndex>,
+    activity: Arc<ActivityLog>,
+    audit: Arc<AuditLog>,
+    metrics: Arc<Metrics>,
+    scanner: Option<Arc<Scanner>>,
+    rules: Arc<UploadRules>,
+    // Content-Security-Policy of the app's page
+    shell_policy: String,
+    content: Option<Arc<ContentOrigin>>,
+}
+
+// Address of the connecting client, as recorded in the audit log
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    req.peer_addr().map(|addr| addr.ip().to_string())
 }
 
 #[derive(Serialize)]
@@ -43,11 +224,8 @@ struct UploadResponse {
     success: bool,
     message: String,
     files: Vec<FileInfo>,
-}
-
-#[derive(Serialize)]
-struct FileListResponse {
-    files: Vec<FileInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    scanned: Vec<ScanResult>,
 }
 
 #[derive(Serialize)]
@@ -55,386 +233,2978 @@ struct DebugInfo {
     debug_mode: bool,
 }
 
-// Helper function to check if user is authenticated
-fn is_authenticated(session: &actix_session::Session) -> bool {
-    session.get::<String>("username").unwrap_or(None).is_some()
+#[derive(Deserialize)]
+struct ArchiveEntryQuery {
+    name: String,
+    #[serde(default)]
+    preview: bool,
+}
+
+// Helper function to get the logged-in user, if any
+fn current_user(session: &actix_session::Session) -> Option<String> {
+    session.get::<String>("username").unwrap_or(None)
 }
 
 // Login endpoint
 #[post("/login")]
 async fn login(
+    req: HttpRequest,
     request: web::Json<LoginRequest>,
     session: actix_session::Session,
+    data: web::Data<AppState>,
 ) -> ActixResult<HttpResponse> {
-    println!("=== LOGIN REQUEST ===");
-    println!("Username: {}", request.username);
-    
-    // Simple credential check (in production, use proper password hashing)
-    if request.username == DEFAULT_USERNAME && request.password == DEFAULT_PASSWORD {
-        // Store user in session
-        match session.insert("username", &request.username) {
-            Ok(_) => {
-                println!("Successfully stored username in session");
-                
-                // Verify it was stored
-                if let Ok(Some(stored_username)) = session.get::<String>("username") {
-                    println!("Verified stored username: {}", stored_username);
-                } else {
-                    println!("WARNING: Could not retrieve username after storing it");
-                }
-            },
-            Err(e) => {
-                println!("Failed to store username in session: {}", e);
-                return Err(actix_web::error::ErrorInternalServerError(format!("Failed to create session: {}", e)));
+    if data.proxy.is_some() {
+        return Ok(proxy_only());
+    }
+    let ip = client_ip(&req);
+    let throttle_ip = ip.as_deref().unwrap_or("unknown");
+
+    if let Err(blocked) = data.login_throttle.check(throttle_ip, &request.username) {
+        return Ok(login_blocked(&data, &request.username, ip.clone(), blocked));
+    }
+
+    // Providers may hash slowly or wait on a directory server, so they run off the async workers
+    let providers = data.auth.clone();
+    let (user, password) = (request.username.clone(), request.password.clone());
+    let (outcome, provider, username) = match web::block(move || providers.authenticate(&user, &password)).await? {
+        Ok(result) => result,
+        Err(e) => {
+            error!(user = %request.username, "Login failed: {}", e);
+            data.audit.record(Some(&request.username), "login", ip, None, false, Some(e));
+            return Ok(HttpResponse::ServiceUnavailable().json(LoginResponse {
+                success: false,
+                message: "Login is unavailable right now. Try again later".to_string(),
+                authenticated: false,
+                two_factor_required: false,
+                enrollment_required: false,
+            }));
+        }
+    };
+
+    // Accounts from a directory are remembered with the role it gave them, so the rest of
+    // cratr treats them like any other account until their next login. One that is gone from
+    // the directory or lost its groups loses access.
+    if provider != "local" && outcome != Outcome::Rejected {
+        // The same check single sign-on and proxy names go through, so a directory entry
+        // named like the built-in admin can't log in as them
+        if let Some(problem) = account_problem(&data, Some(&username), None) {
+            warn!(user = %username, provider, "Login refused: {}", problem);
+            data.login_throttle.failed(throttle_ip, &username);
+            data.audit.record(Some(&username), "login", ip, None, false, Some(format!("{} account: {}", provider, problem)));
+            return Ok(HttpResponse::Forbidden().json(LoginResponse {
+                success: false,
+                message: "This account can't log in here. Ask an admin for help".to_string(),
+                authenticated: false,
+                two_factor_required: false,
+                enrollment_required: false,
+            }));
+        }
+        let role = match outcome {
+            Outcome::Accepted(role) => Some(role),
+            _ => None,
+        };
+        match data.users.sync_external(&username, provider, None, role) {
+            Ok(()) => {}
+            // E.g. a directory entry named like a local account: the password was right, but
+            // not for the account of that name here
+            Err(SyncError::Conflict(problem)) => {
+                warn!(user = %username, provider, "Login refused: {}", problem);
+                data.login_throttle.failed(throttle_ip, &username);
+                data.audit.record(Some(&username), "login", ip, None, false, Some(format!("{} account: {}", provider, problem)));
+                return Ok(HttpResponse::Conflict().json(LoginResponse {
+                    success: false,
+                    message: format!("{}. Ask an admin for help", problem),
+                    authenticated: false,
+                    two_factor_required: false,
+                    enrollment_required: false,
+                }));
+            }
+            Err(SyncError::Store(e)) => {
+                return Err(actix_web::error::ErrorInternalServerError(format!("Failed to store user settings: {}", e)));
             }
         }
-            
-        Ok(HttpResponse::Ok().json(LoginResponse {
-            success: true,
-            message: "Login successful".to_string(),
-            authenticated: true,
-        }))
-    } else {
-        println!("Invalid credentials provided");
-        Ok(HttpResponse::Unauthorized().json(LoginResponse {
+    }
+    if outcome == Outcome::NoRole {
+        warn!(user = %username, provider, "Login refused: no role for this account");
+        data.audit.record(Some(&username), "login", ip, None, false, Some(format!("no role from {}", provider)));
+        return Ok(HttpResponse::Forbidden().json(LoginResponse {
+            success: false,
+            message: "Your account has no access to cratr. Ask an admin to add you to a group".to_string(),
+            authenticated: false,
+            two_factor_required: false,
+            enrollment_required: false,
+        }));
+    }
+    if !matches!(outcome, Outcome::Accepted(_)) {
+        let wait = data.login_throttle.failed(throttle_ip, &username);
+        warn!(user = %username, ip = throttle_ip, "Login failed: invalid credentials, next attempt allowed in {}s", wait.as_secs());
+        data.metrics.login_failures.inc();
+        data.audit.record(Some(&username), "login", ip, None, false, Some("invalid credentials".to_string()));
+        return Ok(HttpResponse::Unauthorized().json(LoginResponse {
             success: false,
             message: "Invalid credentials".to_string(),
             authenticated: false,
-        }))
+            two_factor_required: false,
+            enrollment_required: false,
+        }));
+    }
+
+    // The password alone isn't enough for accounts with two-factor authentication, or
+    // for anyone at all once it is mandatory. Failures stay on the throttle until the
+    // second step succeeds, so knowing the password doesn't buy unlimited code guesses.
+    if data.users.has_2fa(&username) {
+        begin_pending_login(&session, PENDING_2FA, &username)?;
+        debug!(user = %username, "Password accepted, waiting for two-factor code");
+        return Ok(HttpResponse::Ok().json(LoginResponse {
+            success: true,
+            message: "Enter the code from your authenticator app".to_string(),
+            authenticated: false,
+            two_factor_required: true,
+            enrollment_required: false,
+        }));
     }
+    if data.users.require_2fa() {
+        begin_pending_login(&session, PENDING_ENROLLMENT, &username)?;
+        debug!(user = %username, "Password accepted, two-factor enrollment required");
+        return Ok(HttpResponse::Ok().json(LoginResponse {
+            success: true,
+            message: "Two-factor authentication is required. Set it up to continue".to_string(),
+            authenticated: false,
+            two_factor_required: false,
+            enrollment_required: true,
+        }));
+    }
+
+    complete_login(&req, &session, &data, &username, None)?;
+    Ok(HttpResponse::Ok().json(LoginResponse {
+        success: true,
+        message: "Login successful".to_string(),
+        authenticated: true,
+        two_factor_required: false,
+        enrollment_required: false,
+    }))
 }
 
-// Logout endpoint
-#[post("/logout")]
-async fn logout(session: actix_session::Session) -> ActixResult<HttpResponse> {
-    session.clear();
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "Logged out successfully",
-        "authenticated": false
-    })))
+// Reply to a login attempt turned away by the throttle
+fn login_blocked(data: &AppState, username: &str, ip: Option<String>, blocked: throttle::Blocked) -> HttpResponse {
+    let seconds = blocked.retry_after.as_secs().max(1);
+    let reason = if blocked.locked_out { "locked out" } else { "rate limited" };
+    warn!(user = %username, ip = ip.as_deref().unwrap_or("unknown"), "Login blocked: {} for {}s", reason, seconds);
+    data.metrics.login_failures.inc();
+    data.audit.record(Some(username), "login", ip, None, false, Some(format!("blocked: {}", reason)));
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", seconds.to_string()))
+        .json(LoginResponse {
+            success: false,
+            message: format!("Too many login attempts. Try again in {} second{}", seconds, if seconds == 1 { "" } else { "s" }),
+            authenticated: false,
+            two_factor_required: false,
+            enrollment_required: false,
+        })
 }
 
-// Check authentication status
-#[get("/auth/status")]
-async fn auth_status(session: actix_session::Session) -> ActixResult<HttpResponse> {
-    println!("=== AUTH STATUS REQUEST ===");
-    
-    let username = session.get::<String>("username").unwrap_or(None);
-    let authenticated = username.is_some();
-    
-    println!("Username in session: {:?}", username);
-    println!("Authenticated: {}", authenticated);
-    
-    Ok(HttpResponse::Ok().json(AuthStatus {
-        authenticated,
-        username,
-    }))
+// Start the session of a user who got through every login step
+fn complete_login(
+    req: &HttpRequest,
+    session: &actix_session::Session,
+    data: &AppState,
+    username: &str,
+    detail: Option<String>,
+) -> ActixResult<()> {
+    clear_pending_login(session);
+    session.renew();
+    let ip = client_ip(req);
+    // Where the session is used from, for the owner's list of sessions
+    let user_agent = req.headers().get(header::USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>());
+    session.insert("username", username)
+        .and_then(|_| session.insert("ip", &ip))
+        .and_then(|_| session.insert("user_agent", user_agent))
+        .and_then(|_| session.insert(CSRF_TOKEN, new_csrf_token()))
+        .map_err(|e| {
+            error!("Failed to store username in session: {}", e);
+            actix_web::error::ErrorInternalServerError(format!("Failed to create session: {}", e))
+        })?;
+
+    info!(user = %username, "Login succeeded");
+    data.login_throttle.succeeded(ip.as_deref().unwrap_or("unknown"), username);
+    data.audit.record(Some(username), "login", ip, None, true, detail);
+    Ok(())
 }
 
-// Authentication middleware wrapper
-fn require_auth(session: &actix_session::Session) -> ActixResult<()> {
-    if is_authenticated(session) {
-        Ok(())
-    } else {
-        Err(actix_web::error::ErrorUnauthorized("Authentication required"))
+// Remember who entered the right password while they complete the second step
+fn begin_pending_login(session: &actix_session::Session, key: &str, username: &str) -> ActixResult<()> {
+    clear_pending_login(session);
+    session.insert(key, username)
+        .and_then(|_| session.insert("pending_since", unix_now()))
+        .and_then(|_| session.insert(CSRF_TOKEN, new_csrf_token()))
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to create session: {}", e)))
+}
+
+fn pending_login(session: &actix_session::Session, key: &str) -> Option<String> {
+    let since = session.get::<u64>("pending_since").ok().flatten()?;
+    if unix_now().saturating_sub(since) > PENDING_LOGIN_TIMEOUT {
+        return None;
     }
+    session.get::<String>(key).ok().flatten()
 }
 
-// Get storage information
-#[get("/storage")]
-async fn get_storage_info(session: actix_session::Session) -> ActixResult<HttpResponse> {
-    println!("=== STORAGE REQUEST RECEIVED ===");
-    require_auth(&session)?;
-    let mut total_size = 0u64;
-    let mut file_count = 0usize;
-
-    if let Ok(entries) = std::fs::read_dir(UPLOAD_DIR) {
-        for entry in entries.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                if metadata.is_file() {
-                    total_size += metadata.len();
-                    file_count += 1;
-                }
+fn clear_pending_login(session: &actix_session::Session) {
+    session.remove(PENDING_2FA);
+    session.remove(PENDING_ENROLLMENT);
+    session.remove("pending_since");
+}
+
+fn new_csrf_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+// Cookie-authenticated requests that change something must carry the session's CSRF token
+// in a header, which another site can't read or set. API tokens aren't sent by browsers on
+// their own, so requests that `require_auth` will authenticate by token are let through,
+// and so are anonymous ones, which the handlers turn away anyway. Other Authorization
+// headers don't count: browsers attach cached Basic credentials to cross-site requests too.
+// The login itself needs no token: there is no session to ride on yet, and its JSON body
+// can't be posted from another site without CORS allowing it.
+fn check_csrf(req: &HttpRequest) -> ActixResult<()> {
+    if req.method().is_safe() || tokens::bearer_secret(req.headers()).is_some() || req.path() == "/login" {
+        return Ok(());
+    }
+    let session = req.get_session();
+    let expected = session.get::<String>(CSRF_TOKEN).unwrap_or(None);
+    if expected.is_none() && current_user(&session).is_none() {
+        return Ok(());
+    }
+
+    let sent = req.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (expected, sent) {
+        (Some(expected), Some(sent)) if same_secret(&expected, sent) => Ok(()),
+        _ => {
+            warn!(user = ?current_user(&session), path = req.path(), "Request refused: missing or wrong CSRF token");
+            let response = HttpResponse::Forbidden().json(serde_json::json!({
+                "success": false,
+                "message": "Missing or invalid CSRF token. Reload the page and try again"
+            }));
+            Err(actix_web::error::InternalError::from_response("CSRF token mismatch", response).into())
+        }
+    }
+}
+
+// Reply to a login attempt while the proxy in front of cratr does the logging in
+fn proxy_only() -> HttpResponse {
+    HttpResponse::Forbidden().json(LoginResponse {
+        success: false,
+        message: "Logins go through the proxy in front of cratr".to_string(),
+        authenticated: false,
+        two_factor_required: false,
+        enrollment_required: false,
+    })
+}
+
+// Make the session belong to whoever the trusted proxy says is logged in, and to nobody if
+// the request didn't come through it. Accounts are created on first sight with the role
+// their groups map to, and kept up to date on every request. Two-factor authentication is
+// left to the proxy.
+fn proxy_login(req: &HttpRequest, data: &AppState, proxy: &ProxyAuth) {
+    let session = req.get_session();
+    let Some(identity) = proxy.identity(req) else {
+        if current_user(&session).is_some() || session.get::<String>(PROXY_USER).ok().flatten().is_some() {
+            debug!("No user from a trusted proxy, dropping the session");
+            session.purge();
+        }
+        return;
+    };
+    let username = identity.username.as_str();
+    let problem = account_problem(data, Some(username), None)
+        .or_else(|| data.users.sync_external(username, "proxy", None, identity.role).err().map(|e| e.to_string()))
+        .or_else(|| identity.role.is_none().then(|| "no role from proxy".to_string()));
+
+    let seen = session.get::<String>(PROXY_USER).ok().flatten();
+    match problem {
+        None if current_user(&session).as_deref() == Some(username) => {}
+        None => {
+            if let Err(e) = complete_login(req, &session, data, username, Some("proxy".to_string()))
+                .and_then(|_| session.insert(PROXY_USER, username).map_err(Into::into))
+            {
+                error!(user = %username, "Proxy login failed: {}", e);
+            }
+        }
+        Some(problem) => {
+            session.remove("username");
+            // Only the first request of a refused user is worth a log line
+            if seen.as_deref() != Some(username) {
+                warn!(user = %username, "Proxy login refused: {}", problem);
+                data.metrics.login_failures.inc();
+                data.audit.record(Some(username), "login", client_ip(req), None, false, Some(problem));
+                let _ = session.insert(PROXY_USER, username);
             }
         }
     }
+}
 
-    // Get disk space information
-    let (disk_free, disk_total) = get_disk_space(UPLOAD_DIR);
-    let disk_used = disk_total.saturating_sub(disk_free);
-    let disk_used_percentage = if disk_total > 0 {
-        (disk_used as f64 / disk_total as f64) * 100.0
-    } else {
-        0.0
+// Send the browser to the identity provider to log in there
+#[get("/auth/oidc/login")]
+async fn oidc_login(session: actix_session::Session, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let Some(oidc) = data.oidc.as_ref().filter(|_| data.proxy.is_none()) else {
+        return Err(actix_web::error::ErrorNotFound("Single sign-on is not configured"));
     };
 
-    let percentage = (total_size as f64 / MAX_STORAGE_SIZE as f64) * 100.0;
-    let formatted_used = format_bytes(total_size);
-    let formatted_disk_free = format_bytes(disk_free);
-    let formatted_disk_total = format_bytes(disk_total);
+    let challenge = Challenge::generate();
+    let url = match oidc.authorization_url(&challenge).await {
+        Ok(url) => url,
+        Err(e) => {
+            error!("Single sign-on unavailable: {}", e);
+            return Ok(oidc_redirect("/?login_error=unavailable"));
+        }
+    };
+    session.insert(PENDING_OIDC, &challenge)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to create session: {}", e)))?;
+    debug!(issuer = oidc.issuer(), "Redirecting to identity provider");
+    Ok(oidc_redirect(&url))
+}
 
-    Ok(HttpResponse::Ok().json(StorageInfo {
-        used_bytes: total_size,
-        total_files: file_count,
-        used_percentage: percentage,
-        formatted_used,
-        max_size_mb: MAX_STORAGE_SIZE / 1024 / 1024,
-        disk_free_bytes: disk_free,
-        disk_total_bytes: disk_total,
-        disk_used_percentage,
-        formatted_disk_free,
-        formatted_disk_total,
+#[derive(Deserialize)]
+struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+// Where the identity provider sends the browser back to. Accounts are created on their first
+// login with the role the ID token's groups map to, and then go through the same steps as a
+// password login. Problems end up back at the login form with a short reason code.
+#[get("/auth/oidc/callback")]
+async fn oidc_callback(
+    req: HttpRequest,
+    query: web::Query<OidcCallback>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let Some(oidc) = &data.oidc else {
+        return Err(actix_web::error::ErrorNotFound("Single sign-on is not configured"));
+    };
+    let ip = client_ip(&req);
+    let failed = |username: Option<&str>, reason: &str, detail: String| {
+        warn!(user = ?username, "Single sign-on failed: {}", detail);
+        data.metrics.login_failures.inc();
+        data.audit.record(username, "login", ip.clone(), None, false, Some(detail));
+        oidc_redirect(&format!("/?login_error={}", reason))
+    };
+
+    // The state ties the answer to a login this browser started
+    let challenge = session.remove_as::<Challenge>(PENDING_OIDC).and_then(Result::ok);
+    let challenge = match challenge {
+        Some(challenge) if challenge.answered_by(query.state.as_deref(), PENDING_LOGIN_TIMEOUT) => challenge,
+        _ => return Ok(failed(None, "expired", "unknown or expired state".to_string())),
+    };
+    if let Some(error) = &query.error {
+        return Ok(failed(None, "cancelled", format!("identity provider answered {}", error)));
+    }
+    let Some(code) = &query.code else {
+        return Ok(failed(None, "failed", "no code in callback".to_string()));
+    };
+
+    let identity = match oidc.exchange(code, &challenge).await {
+        Ok(identity) => identity,
+        Err(e) => return Ok(failed(None, "failed", e)),
+    };
+    let username = identity.username;
+    if let Some(problem) = account_problem(&data, Some(&username), None) {
+        return Ok(failed(Some(&username), "account", problem));
+    }
+    if let Err(e) = data.users.sync_external(&username, "oidc", identity.subject.as_deref(), identity.role) {
+        return Ok(failed(Some(&username), "account", e.to_string()));
+    }
+    if identity.role.is_none() {
+        return Ok(failed(Some(&username), "no_role", "no role from oidc".to_string()));
+    }
+
+    // The identity provider may have its own second factor, but cratr's still applies
+    if data.users.has_2fa(&username) {
+        begin_pending_login(&session, PENDING_2FA, &username)?;
+        debug!(user = %username, "Single sign-on accepted, waiting for two-factor code");
+        return Ok(oidc_redirect("/?login=2fa"));
+    }
+    if data.users.require_2fa() {
+        begin_pending_login(&session, PENDING_ENROLLMENT, &username)?;
+        debug!(user = %username, "Single sign-on accepted, two-factor enrollment required");
+        return Ok(oidc_redirect("/?login=enroll"));
+    }
+
+    complete_login(&req, &session, &data, &username, Some("oidc".to_string()))?;
+    Ok(oidc_redirect("/"))
+}
+
+fn oidc_redirect(location: &str) -> HttpResponse {
+    HttpResponse::Found().insert_header((header::LOCATION, location)).finish()
+}
+
+// Second login step for accounts with two-factor authentication
+#[post("/login/2fa")]
+async fn login_second_factor(
+    req: HttpRequest,
+    request: web::Json<TwoFactorCode>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    if data.proxy.is_some() {
+        return Ok(proxy_only());
+    }
+    let Some(username) = pending_login(&session, PENDING_2FA) else {
+        return Ok(HttpResponse::Unauthorized().json(LoginResponse {
+            success: false,
+            message: "Log in with your password first".to_string(),
+            authenticated: false,
+            two_factor_required: false,
+            enrollment_required: false,
+        }));
+    };
+
+    let ip = client_ip(&req);
+    let throttle_ip = ip.as_deref().unwrap_or("unknown");
+    if let Err(blocked) = data.login_throttle.check(throttle_ip, &username) {
+        return Ok(login_blocked(&data, &username, ip.clone(), blocked));
+    }
+
+    let users = data.users.clone();
+    let (user, code) = (username.clone(), request.code.clone());
+    if web::block(move || users.verify(&user, &code)).await? {
+        complete_login(&req, &session, &data, &username, Some("two-factor".to_string()))?;
+        return Ok(HttpResponse::Ok().json(LoginResponse {
+            success: true,
+            message: "Login successful".to_string(),
+            authenticated: true,
+            two_factor_required: false,
+            enrollment_required: false,
+        }));
+    }
+
+    let wait = data.login_throttle.failed(throttle_ip, &username);
+    warn!(user = %username, ip = throttle_ip, "Login failed: invalid two-factor code, next attempt allowed in {}s", wait.as_secs());
+    data.metrics.login_failures.inc();
+    data.audit.record(Some(&username), "login", ip, None, false, Some("invalid two-factor code".to_string()));
+    Ok(HttpResponse::Unauthorized().json(LoginResponse {
+        success: false,
+        message: "Invalid code".to_string(),
+        authenticated: false,
+        two_factor_required: true,
+        enrollment_required: false,
     }))
 }
 
-// Serve the main HTML page
-#[get("/")]
-async fn index() -> ActixResult<HttpResponse> {
-    let html = include_str!("../static/index.html");
-    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+// Two-factor state of the logged-in account
+#[get("/2fa")]
+async fn two_factor_status(session: actix_session::Session, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    Ok(HttpResponse::Ok().json(two_factor_state(&data, &username)))
 }
 
-// Get debug configuration
-#[get("/debug")]
-async fn get_debug_info(data: web::Data<AppState>) -> ActixResult<HttpResponse> {
-    Ok(HttpResponse::Ok().json(DebugInfo {
-        debug_mode: data.debug_mode,
+fn two_factor_state(data: &AppState, username: &str) -> TwoFactorStatus {
+    TwoFactorStatus {
+        enabled: data.users.has_2fa(username),
+        required: data.users.require_2fa(),
+        recovery_codes_left: data.users.recovery_codes_left(username),
+    }
+}
+
+// Either a logged-in user or one who must enroll before their login completes
+fn enrolling_user(session: &actix_session::Session) -> ActixResult<String> {
+    current_user(session)
+        .or_else(|| pending_login(session, PENDING_ENROLLMENT))
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Authentication required"))
+}
+
+// Hand out a new TOTP secret; it takes effect once confirmed with POST /2fa/confirm
+#[post("/2fa/enroll")]
+async fn enroll_two_factor(session: actix_session::Session, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = enrolling_user(&session)?;
+    if data.users.has_2fa(&username) {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "success": false,
+            "message": "Two-factor authentication is already enabled; disable it first"
+        })));
+    }
+
+    let users = data.users.clone();
+    let (secret, otpauth_uri, qr) = web::block(move || users.begin_enrollment(&username))
+        .await?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to start enrollment: {}", e)))?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorEnrollment {
+        secret,
+        otpauth_uri,
+        qr_code: format!("data:image/png;base64,{}", qr),
     }))
 }
 
-// Handle file uploads
-#[post("/upload")]
-async fn upload_files(mut payload: Multipart, session: actix_session::Session) -> ActixResult<HttpResponse> {
-    println!("=== UPLOAD REQUEST RECEIVED ===");
-    
-    // Log session info for debugging
-    if let Ok(username) = session.get::<String>("username") {
-        println!("Session username: {:?}", username);
-    } else {
-        println!("No username in session");
+// Finish enrollment with a first code from the app and hand out the recovery codes
+#[post("/2fa/confirm")]
+async fn confirm_two_factor(
+    req: HttpRequest,
+    request: web::Json<TwoFactorCode>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = enrolling_user(&session)?;
+    let logged_in = current_user(&session).is_some();
+    let ip = client_ip(&req);
+    let throttle_ip = ip.as_deref().unwrap_or("unknown");
+    if let Err(blocked) = data.login_throttle.check(throttle_ip, &username) {
+        return Ok(login_blocked(&data, &username, ip.clone(), blocked));
     }
-    
-    // Check authentication first
-    match require_auth(&session) {
-        Ok(_) => println!("Authentication successful"),
-        Err(e) => {
-            println!("Authentication failed: {:?}", e);
-            return Err(e);
+
+    let users = data.users.clone();
+    let (user, code) = (username.clone(), request.code.clone());
+    match web::block(move || users.confirm_enrollment(&user, &code)).await? {
+        Ok(recovery_codes) => {
+            info!(user = %username, "Two-factor authentication enabled");
+            data.audit.record(Some(&username), "2fa_enable", ip, None, true, None);
+            if !logged_in {
+                complete_login(&req, &session, &data, &username, Some("two-factor enrollment".to_string()))?;
+            }
+            Ok(HttpResponse::Ok().json(RecoveryCodesResponse {
+                success: true,
+                message: "Two-factor authentication enabled. Store the recovery codes somewhere safe".to_string(),
+                recovery_codes,
+                authenticated: true,
+            }))
+        }
+        Err(message) => {
+            data.login_throttle.failed(throttle_ip, &username);
+            Ok(HttpResponse::BadRequest().json(RecoveryCodesResponse {
+                success: false,
+                message,
+                recovery_codes: vec![],
+                authenticated: logged_in,
+            }))
         }
     }
-    
-    println!("Upload request received - authentication passed");
-    
-    // Ensure upload directory exists
-    create_dir_all(UPLOAD_DIR).map_err(|e| {
-        println!("Failed to create upload directory: {}", e);
-        actix_web::error::ErrorInternalServerError(format!("Failed to create upload directory: {}", e))
-    })?;
+}
 
-    let mut uploaded_files = Vec::new();
-    let mut file_count = 0;
+// Check a code for an account-changing 2FA action, counting failures like login attempts
+async fn verify_two_factor(req: &HttpRequest, data: &AppState, username: &str, code: &str) -> ActixResult<Option<HttpResponse>> {
+    let ip = client_ip(req);
+    let throttle_ip = ip.as_deref().unwrap_or("unknown");
+    if let Err(blocked) = data.login_throttle.check(throttle_ip, username) {
+        return Ok(Some(login_blocked(data, username, ip.clone(), blocked)));
+    }
 
-    println!("Starting to process multipart payload...");
-    while let Some(mut field) = payload.try_next().await? {
-        println!("Processing field...");
-        let content_disposition = field.content_disposition();
-        
-        if let Some(filename) = content_disposition.and_then(|cd| cd.get_filename()) {
-            println!("Processing file: {}", filename);
-            
-            if file_count >= MAX_FILE_COUNT {
-                println!("Too many files: {}", file_count);
-                return Ok(HttpResponse::BadRequest().json(UploadResponse {
-                    success: false,
-                    message: format!("Maximum {} files allowed", MAX_FILE_COUNT),
-                    files: vec![],
-                }));
-            }
+    let users = data.users.clone();
+    let (user, code) = (username.to_string(), code.to_string());
+    if web::block(move || users.verify(&user, &code)).await? {
+        return Ok(None);
+    }
 
-            // Sanitize filename and add UUID to prevent conflicts
-            let sanitized_filename = sanitize_filename(filename);
-            let unique_filename = format!("{}_{}", Uuid::new_v4(), sanitized_filename);
-            let filepath = PathBuf::from(UPLOAD_DIR).join(&unique_filename);
-            let filepath_clone = filepath.clone();
-            
-            println!("Sanitized filename: {} -> {}", filename, sanitized_filename);
-            println!("Unique filename: {}", unique_filename);
+    data.login_throttle.failed(throttle_ip, username);
+    Ok(Some(HttpResponse::Unauthorized().json(serde_json::json!({
+        "success": false,
+        "message": "Invalid code"
+    }))))
+}
 
-            // Create the file
-            let mut f = web::block(move || std::fs::File::create(filepath))
-                .await?
-                .map_err(|e| {
-                    println!("Failed to create file: {}", e);
-                    actix_web::error::ErrorInternalServerError(format!("Failed to create file: {}", e))
-                })?;
+#[post("/2fa/disable")]
+async fn disable_two_factor(
+    req: HttpRequest,
+    request: web::Json<TwoFactorCode>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    if data.users.require_2fa() {
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "success": false,
+            "message": "Two-factor authentication is required for all accounts"
+        })));
+    }
+    if let Some(rejection) = verify_two_factor(&req, &data, &username, &request.code).await? {
+        return Ok(rejection);
+    }
 
-            let mut file_size = 0;
+    data.users.disable_2fa(&username)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store user settings: {}", e)))?;
+    info!(user = %username, "Two-factor authentication disabled");
+    data.audit.record(Some(&username), "2fa_disable", client_ip(&req), None, true, None);
+    Ok(HttpResponse::Ok().json(two_factor_state(&data, &username)))
+}
 
-            // Write file chunks
-            while let Some(chunk) = field.try_next().await? {
-                file_size += chunk.len();
-                if file_size > MAX_FILE_SIZE {
-                    println!("File too large: {} bytes", file_size);
-                    // Remove the partially written file
-                    let _ = std::fs::remove_file(&filepath_clone);
-                    return Ok(HttpResponse::BadRequest().json(UploadResponse {
-                        success: false,
-                        message: format!("File too large. Maximum size is {} MB", MAX_FILE_SIZE / 1024 / 1024),
-                        files: vec![],
-                    }));
-                }
+// Replace all recovery codes, e.g. after using some of them
+#[post("/2fa/recovery-codes")]
+async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    request: web::Json<TwoFactorCode>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    if let Some(rejection) = verify_two_factor(&req, &data, &username, &request.code).await? {
+        return Ok(rejection);
+    }
 
-                f = web::block(move || f.write_all(&chunk).map(|_| f))
-                    .await?
-                    .map_err(|e| {
-                        println!("Failed to write chunk: {}", e);
-                        actix_web::error::ErrorInternalServerError(format!("Failed to write file: {}", e))
-                    })?;
-            }
-            
-            println!("File written successfully: {} bytes", file_size);
+    let recovery_codes = data.users.regenerate_recovery_codes(&username)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store user settings: {}", e)))?;
+    data.audit.record(Some(&username), "2fa_recovery_codes", client_ip(&req), None, true, None);
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse {
+        success: true,
+        message: "New recovery codes generated; the old ones no longer work".to_string(),
+        recovery_codes,
+        authenticated: true,
+    }))
+}
+
+// Make two-factor authentication mandatory (or optional again) for every account
+#[post("/admin/2fa")]
+async fn set_two_factor_policy(
+    req: HttpRequest,
+    request: web::Json<TwoFactorPolicy>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    check_role(&data, &username, Scope::Admin)?;
+    // Don't let an admin lock themselves out on their next login
+    if request.required && !data.users.has_2fa(&username) {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "success": false,
+            "message": "Set up two-factor authentication for your own account first"
+        })));
+    }
+
+    data.users.set_require_2fa(request.required)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store user settings: {}", e)))?;
+    let policy = if request.required { "required" } else { "optional" };
+    info!(user = %username, "Two-factor authentication is now {}", policy);
+    data.audit.record(Some(&username), "2fa_policy", client_ip(&req), None, true, Some(policy.to_string()));
+    Ok(HttpResponse::Ok().json(two_factor_state(&data, &username)))
+}
+
+// API tokens of the logged-in user
+#[get("/tokens")]
+async fn list_tokens(session: actix_session::Session, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    Ok(HttpResponse::Ok().json(TokensResponse {
+        tokens: data.tokens.list(&username),
+    }))
+}
+
+// Create a personal access token. The secret is in this response only.
+#[post("/tokens")]
+async fn create_token(
+    req: HttpRequest,
+    request: web::Json<CreateTokenRequest>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    let name = request.name.trim();
+    let problem = if name.is_empty() || name.chars().count() > 100 {
+        Some("Token name must be between 1 and 100 characters".to_string())
+    } else if request.scopes.is_empty() {
+        Some("Select at least one scope".to_string())
+    } else if request.expires_in_days.is_some_and(|days| days == 0 || days > MAX_TOKEN_DAYS) {
+        Some(format!("Expiry must be between 1 and {} days", MAX_TOKEN_DAYS))
+    } else {
+        // A token can't do more than its owner
+        let role = role_of(&data, &username);
+        request.scopes.iter()
+            .find(|scope| !role.is_some_and(|role| role.allows(**scope)))
+            .map(|scope| format!("Your role doesn't allow the \"{}\" scope", scope.as_str()))
+    };
+    if let Some(message) = problem {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": message
+        })));
+    }
+
+    let expires = request.expires_in_days.map(|days| unix_now() + days * 24 * 60 * 60);
+    let (token, secret) = data.tokens.create(&username, name, &request.scopes, expires)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store API token: {}", e)))?;
+    info!(user = %username, token = %token.id, "API token created");
+    data.audit.record(Some(&username), "token_create", client_ip(&req), Some(&token.name), true, Some(token_summary(&token)));
+    Ok(HttpResponse::Ok().json(CreateTokenResponse { token, secret }))
+}
+
+#[post("/tokens/{id}/revoke")]
+async fn revoke_token(
+    req: HttpRequest,
+    path: web::Path<String>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    let revoked = data.tokens.revoke(&username, &path)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store API tokens: {}", e)))?;
+    let Some(token) = revoked else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Token not found"
+        })));
+    };
+
+    info!(user = %username, token = %token.id, "API token revoked");
+    data.audit.record(Some(&username), "token_revoke", client_ip(&req), Some(&token.name), true, Some(token_summary(&token)));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("Token \"{}\" revoked", token.name)
+    })))
+}
+
+// Login sessions of the logged-in user, this one marked as current
+#[get("/sessions")]
+async fn list_sessions(session: actix_session::Session, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    let current = session.get::<String>(SESSION_ID).unwrap_or(None);
+    Ok(HttpResponse::Ok().json(SessionsResponse {
+        sessions: data.sessions.list(&username, current.as_deref()),
+    }))
+}
+
+// Log one of your sessions out, e.g. on a lost device
+#[post("/sessions/{id}/revoke")]
+async fn revoke_session(
+    req: HttpRequest,
+    path: web::Path<String>,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    let Some(revoked) = data.sessions.revoke(&username, &path) else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Session not found"
+        })));
+    };
+    if session.get::<String>(SESSION_ID).unwrap_or(None).as_deref() == Some(revoked.id.as_str()) {
+        session.purge();
+    }
+
+    info!(user = %username, session = %revoked.id, "Session revoked");
+    let detail = format!("id={} ip={}", revoked.id, revoked.ip.as_deref().unwrap_or("unknown"));
+    data.audit.record(Some(&username), "session_revoke", client_ip(&req), None, true, Some(detail));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Session logged out"
+    })))
+}
+
+// Log out every session of yours but this one
+#[post("/sessions/revoke-all")]
+async fn revoke_other_sessions(
+    req: HttpRequest,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_session(&session, &data)?;
+    let current = session.get::<String>(SESSION_ID).unwrap_or(None);
+    let ended = data.sessions.revoke_all(&username, current.as_deref());
+
+    info!(user = %username, ended, "Other sessions revoked");
+    data.audit.record(Some(&username), "session_revoke", client_ip(&req), None, true, Some(format!("all others ({})", ended)));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("{} other session{} logged out", ended, if ended == 1 { "" } else { "s" })
+    })))
+}
+
+// Keep the address of a logged-in session current as its device moves between networks
+fn note_session_ip(req: &HttpRequest) {
+    let session = req.get_session();
+    if current_user(&session).is_none() {
+        return;
+    }
+    let ip = client_ip(req);
+    if session.get::<Option<String>>("ip").ok().flatten() != Some(ip.clone()) {
+        let _ = session.insert("ip", ip);
+    }
+}
+
+// "id=… scopes=read,write", for the audit log
+fn token_summary(token: &ApiTokenInfo) -> String {
+    let scopes: Vec<&str> = token.scopes.iter().map(|scope| scope.as_str()).collect();
+    format!("id={} scopes={}", token.id, scopes.join(","))
+}
+
+// Every account, the command-line one first
+#[get("/admin/users")]
+async fn list_users(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    require_auth(&req, Scope::Admin)?;
+    let builtin = UserInfo {
+        username: data.username.clone(),
+        role: Role::Admin,
+        two_factor: data.users.has_2fa(&data.username),
+        builtin: true,
+        source: None,
+    };
+    let others = data.users.accounts().into_iter().map(|(username, role, source)| UserInfo {
+        two_factor: data.users.has_2fa(&username),
+        username,
+        role,
+        builtin: false,
+        source,
+    });
+    Ok(HttpResponse::Ok().json(UsersResponse {
+        users: std::iter::once(builtin).chain(others).collect(),
+    }))
+}
+
+// Why a username or password can't be used, if it can't
+fn account_problem(data: &AppState, username: Option<&str>, password: Option<&str>) -> Option<String> {
+    if let Some(username) = username {
+        let valid_chars = username.chars().all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '@'));
+        if username.is_empty() || username.chars().count() > 64 || !valid_chars {
+            return Some("Usernames are 1 to 64 letters, digits, '.', '_', '-' or '@'".to_string());
+        }
+        if canonical_username(username) == canonical_username(&data.username) {
+            return Some(format!("User \"{}\" already exists", username));
+        }
+    }
+    if password.is_some_and(|password| password.chars().count() < MIN_PASSWORD_LENGTH) {
+        return Some(format!("Passwords need at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+    None
+}
+
+fn bad_request(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "success": false,
+        "message": message
+    }))
+}
+
+#[post("/admin/users")]
+async fn create_user(
+    req: HttpRequest,
+    request: web::Json<CreateUserRequest>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let admin = require_auth(&req, Scope::Admin)?;
+    let username = request.username.trim().to_string();
+    if let Some(message) = account_problem(&data, Some(&username), Some(&request.password)) {
+        return Ok(bad_request(message));
+    }
+
+    let users = data.users.clone();
+    let (user, password, role) = (username.clone(), request.password.clone(), request.role);
+    if let Err(message) = web::block(move || users.create_account(&user, &password, role)).await? {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "success": false,
+            "message": message
+        })));
+    }
+
+    info!(user = %admin, account = %username, role = role.as_str(), "User created");
+    data.audit.record(Some(&admin), "user_create", client_ip(&req), Some(&username), true, Some(format!("role={}", role.as_str())));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("User \"{}\" created", username)
+    })))
+}
+
+// Change the role or reset the password of an account
+#[post("/admin/users/{username}")]
+async fn update_user(
+    req: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<UpdateUserRequest>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let admin = require_auth(&req, Scope::Admin)?;
+    let username = path.into_inner();
+    if username == data.username {
+        return Ok(bad_request("The command-line account is configured with --username and --password".to_string()));
+    }
+    if let Some(message) = account_problem(&data, None, request.password.as_deref()) {
+        return Ok(bad_request(message));
+    }
+    if request.password.is_some() && data.users.role(&username).is_some() && !data.users.is_local(&username) {
+        return Ok(bad_request(format!("The password of \"{}\" is managed by its directory", username)));
+    }
+
+    let users = data.users.clone();
+    let (user, role, password) = (username.clone(), request.role, request.password.clone());
+    let updated = web::block(move || users.update_account(&user, role, password.as_deref()))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !updated {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "User not found"
+        })));
+    }
+
+    let mut changes = Vec::new();
+    if let Some(role) = request.role {
+        changes.push(format!("role={}", role.as_str()));
+    }
+    if request.password.is_some() {
+        changes.push("password reset".to_string());
+        // Whoever knew the old password is logged out
+        data.sessions.revoke_all(&username, None);
+    }
+    info!(user = %admin, account = %username, "User updated: {}", changes.join(", "));
+    data.audit.record(Some(&admin), "user_update", client_ip(&req), Some(&username), true, Some(changes.join(", ")));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("User \"{}\" updated", username)
+    })))
+}
+
+// Delete an account along with its API tokens
+#[post("/admin/users/{username}/delete")]
+async fn delete_user(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let admin = require_auth(&req, Scope::Admin)?;
+    let username = path.into_inner();
+    if username == data.username {
+        return Ok(bad_request("The command-line account can't be deleted".to_string()));
+    }
+
+    let deleted = data.users.delete_account(&username)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store user settings: {}", e)))?;
+    if !deleted {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "User not found"
+        })));
+    }
+    data.tokens.revoke_all(&username)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store API tokens: {}", e)))?;
+    data.sessions.revoke_all(&username, None);
+    // The deleting admin takes over the account's private folders
+    data.shares.forget_user(&username, &admin)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store folder shares: {}", e)))?;
+
+    info!(user = %admin, account = %username, "User deleted");
+    data.audit.record(Some(&admin), "user_delete", client_ip(&req), Some(&username), true, None);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("User \"{}\" deleted", username)
+    })))
+}
+
+// Folders the current user owns and folders others have shared with them
+#[get("/shares")]
+async fn list_shares(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    Ok(HttpResponse::Ok().json(SharesResponse {
+        owned: data.shares.owned_by(&username),
+        shared_with_me: data.shares.shared_with(&username),
+    }))
+}
+
+// The access list of one folder, for its owner and for admins
+#[get("/shares/{folder:.*}")]
+async fn get_share(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let folder = path.into_inner().trim_matches('/').to_string();
+    match data.shares.share(&folder) {
+        Some(share) if share.owner == username || role_of(&data, &username) == Some(Role::Admin) => {
+            Ok(HttpResponse::Ok().json(share))
+        }
+        _ => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Not a private folder of yours"
+        }))),
+    }
+}
+
+// Replace who else may read or change a folder. Only the owner of the folder (or of a folder
+// above it) and admins can do this. Sharing a folder nobody owns makes the admin its owner.
+#[post("/shares/{folder:.*}")]
+async fn update_share(
+    req: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<ShareUpdate>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Write)?;
+    let Some(folder) = resolve(UPLOAD_DIR, &path.into_inner())
+        .filter(|resolved| resolved.path.is_dir())
+        .map(|resolved| resolved.relative)
+    else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Folder not found"
+        })));
+    };
+    if !folder_access(&data, &username, &folder).allows(Access::Read) {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Folder not found"
+        })));
+    }
+
+    let is_admin = role_of(&data, &username) == Some(Role::Admin);
+    let owner = data.shares.owner(&folder);
+    if !is_admin && owner.as_deref() != Some(username.as_str()) {
+        data.audit.record(Some(&username), "share", client_ip(&req), Some(&folder), false, Some("not the owner".to_string()));
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "success": false,
+            "message": "Only the owner of a folder can share it"
+        })));
+    }
+    let owner = owner.unwrap_or_else(|| username.clone());
+
+    // One grant per user or group; a later entry replaces an earlier one
+    let mut grants: Vec<Grant> = Vec::new();
+    for grant in request.into_inner().grants {
+        let problem = match &grant.principal {
+            Principal::User(user) if *user == owner => Some(format!("{} owns this folder already", user)),
+            Principal::User(user) if role_of(&data, user).is_none() => Some(format!("No such user: {}", user)),
+            Principal::Group(group) if !data.shares.group_exists(group) => Some(format!("No such group: {}", group)),
+            _ => None,
+        };
+        if let Some(message) = problem {
+            return Ok(bad_request(message));
+        }
+        grants.retain(|existing| existing.principal != grant.principal);
+        grants.push(grant);
+    }
+
+    let summary = if grants.is_empty() {
+        "private".to_string()
+    } else {
+        grants.iter().map(grant_summary).collect::<Vec<_>>().join(", ")
+    };
+    data.shares.set_grants(&folder, &owner, grants)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store folder shares: {}", e)))?;
+
+    info!(user = %username, folder = %folder, "Folder sharing changed: {}", summary);
+    data.activity.record(&username, "share", &folder, Some(summary.clone()));
+    data.audit.record(Some(&username), "share", client_ip(&req), Some(&folder), true, Some(summary));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("Sharing of {}/ updated", folder)
+    })))
+}
+
+fn grant_summary(grant: &Grant) -> String {
+    match &grant.principal {
+        Principal::User(user) => format!("{}={}", user, grant.access.as_str()),
+        Principal::Group(group) => format!("group:{}={}", group, grant.access.as_str()),
+    }
+}
+
+// Groups folders can be shared with; "everyone" always exists and holds every account
+#[get("/groups")]
+async fn list_groups(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    require_auth(&req, Scope::Read)?;
+    Ok(HttpResponse::Ok().json(GroupsResponse {
+        groups: data.shares.groups(),
+    }))
+}
+
+// Create a group or replace its members
+#[post("/admin/groups")]
+async fn update_group(
+    req: HttpRequest,
+    request: web::Json<Group>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let admin = require_auth(&req, Scope::Admin)?;
+    let Group { name, members } = request.into_inner();
+    let name = name.trim().to_string();
+    let valid_chars = name.chars().all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if name.is_empty() || name.chars().count() > 64 || !valid_chars {
+        return Ok(bad_request("Group names are 1 to 64 letters, digits, '.', '_' or '-'".to_string()));
+    }
+    if name == shares::EVERYONE {
+        return Ok(bad_request(format!("\"{}\" is built in and always holds every account", name)));
+    }
+    let members: Vec<String> = members.iter().map(|member| member.trim().to_string()).filter(|member| !member.is_empty()).collect();
+    if let Some(unknown) = members.iter().find(|member| role_of(&data, member).is_none()) {
+        return Ok(bad_request(format!("No such user: {}", unknown)));
+    }
+
+    let detail = format!("members={}", members.join(","));
+    data.shares.set_group(&name, members)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store folder shares: {}", e)))?;
+
+    info!(user = %admin, group = %name, "Group updated");
+    data.audit.record(Some(&admin), "group_update", client_ip(&req), Some(&name), true, Some(detail));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("Group \"{}\" saved", name)
+    })))
+}
+
+// Delete a group; folders shared with it are no longer shared with its members
+#[post("/admin/groups/{name}/delete")]
+async fn delete_group(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let admin = require_auth(&req, Scope::Admin)?;
+    let name = path.into_inner();
+    let deleted = data.shares.delete_group(&name)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store folder shares: {}", e)))?;
+    if !deleted {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Group not found"
+        })));
+    }
+
+    info!(user = %admin, group = %name, "Group deleted");
+    data.audit.record(Some(&admin), "group_delete", client_ip(&req), Some(&name), true, None);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("Group \"{}\" deleted", name)
+    })))
+}
+
+// Logout endpoint
+#[post("/logout")]
+async fn logout(
+    req: HttpRequest,
+    session: actix_session::Session,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    if let Some(username) = current_user(&session) {
+        data.audit.record(Some(&username), "logout", client_ip(&req), None, true, None);
+    }
+    session.purge();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Logged out successfully",
+        "authenticated": false
+    })))
+}
+
+// Check authentication status
+#[get("/auth/status")]
+async fn auth_status(session: actix_session::Session, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = current_user(&session);
+    // Sessions from before CSRF tokens existed get one here
+    let mut csrf_token = session.get::<String>(CSRF_TOKEN).unwrap_or(None);
+    if csrf_token.is_none() && username.is_some() {
+        let token = new_csrf_token();
+        session.insert(CSRF_TOKEN, &token)
+            .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to update session: {}", e)))?;
+        csrf_token = Some(token);
+    }
+    let role = username.as_deref().and_then(|username| role_of(&data, username));
+    let authenticated = role.is_some();
+    debug!(?username, ?role, authenticated, "Auth status");
+
+    Ok(HttpResponse::Ok().json(AuthStatus {
+        authenticated,
+        username: username.filter(|_| authenticated),
+        role,
+        single_sign_on: data.oidc.as_ref().map(|oidc| oidc.name().to_string()),
+        proxy_login: data.proxy.is_some(),
+        csrf_token,
+        content_origin: data.content.as_ref().map(|content| content.origin().to_string()),
+    }))
+}
+
+// Authentication middleware wrapper, returning the logged-in username. The account's role
+// must allow `scope`. Scripts send an "Authorization: Bearer" API token instead of the
+// session cookie, and then the token must carry `scope` as well. Other Authorization
+// headers belong to whatever proxy sits in front of cratr, so the session decides then.
+fn require_auth(req: &HttpRequest, scope: Scope) -> ActixResult<String> {
+    let data = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Application state missing"))?;
+    let Some(secret) = tokens::bearer_secret(req.headers()) else {
+        let username = require_session(&req.get_session(), data)?;
+        check_role(data, &username, scope)?;
+        return Ok(username);
+    };
+
+    let (username, token) = data
+        .tokens
+        .authenticate(secret)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Invalid or expired API token"))?;
+    // The role is checked again because it may have changed since the token was created
+    check_role(data, &username, scope)?;
+    if !scope.granted_by(&token.scopes) {
+        return Err(actix_web::error::ErrorForbidden(format!(
+            "API token \"{}\" lacks the \"{}\" scope",
+            token.name,
+            scope.as_str()
+        )));
+    }
+    Ok(username)
+}
+
+// Account settings such as two-factor authentication and API tokens can only be changed
+// from a logged-in browser session, never with an API token
+fn require_session(session: &actix_session::Session, data: &AppState) -> ActixResult<String> {
+    current_user(session)
+        // The account may have been deleted since this session was started
+        .filter(|username| role_of(data, username).is_some())
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Authentication required"))
+}
+
+// The account given on the command line is always an admin; other accounts have the role
+// an admin gave them. None if there is no such account.
+fn role_of(data: &AppState, username: &str) -> Option<Role> {
+    if username == data.username {
+        Some(Role::Admin)
+    } else {
+        data.users.role(username)
+    }
+}
+
+fn check_role(data: &AppState, username: &str, scope: Scope) -> ActixResult<Role> {
+    let role = role_of(data, username).ok_or_else(|| actix_web::error::ErrorUnauthorized("Authentication required"))?;
+    if !role.allows(scope) {
+        return Err(actix_web::error::ErrorForbidden(format!(
+            "The {} role doesn't have \"{}\" access",
+            role.as_str(),
+            scope.as_str()
+        )));
+    }
+    Ok(role)
+}
+
+// Access `username` has to the files directly inside `folder`. Admins see every folder.
+fn folder_access(data: &AppState, username: &str, folder: &str) -> FolderAccess {
+    if role_of(data, username) == Some(Role::Admin) {
+        FolderAccess::Owner
+    } else {
+        data.shares.access(username, folder)
+    }
+}
+
+// Check a file against the access list of its folder. `path` must be the canonical relative
+// path from `resolve`. Files in folders the user can't read are reported as missing, so
+// private folder names don't leak.
+fn check_access(data: &AppState, username: &str, path: &str, needed: Access) -> Result<(), HttpResponse> {
+    match folder_access(data, username, &folder_of(path)) {
+        access if access.allows(needed) => Ok(()),
+        FolderAccess::Shared(_) => Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "success": false,
+            "message": "This folder is shared with you read-only"
+        }))),
+        _ => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "File not found"
+        }))),
+    }
+}
+
+// Filter for the files `username` may see, remembering the answer for each folder
+fn readable_files<'a>(data: &'a AppState, username: &'a str) -> impl FnMut(&str) -> bool + 'a {
+    let mut folders = std::collections::HashMap::new();
+    move |path| {
+        let folder = folder_of(path);
+        if let Some(&readable) = folders.get(&folder) {
+            return readable;
+        }
+        let readable = folder_access(data, username, &folder).allows(Access::Read);
+        folders.insert(folder, readable);
+        readable
+    }
+}
+
+// Get storage information. Usage only counts the files the caller can see, so the size of
+// other people's private folders doesn't leak.
+#[get("/storage")]
+async fn get_storage_info(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let mut readable = readable_files(&data, &username);
+    let stored_files: Vec<_> = walk_files(std::path::Path::new(UPLOAD_DIR))
+        .into_iter()
+        .filter(|file| readable(&file.path))
+        .collect();
+    let total_size: u64 = stored_files.iter().map(|file| file.size).sum();
+    let file_count = stored_files.len();
+
+    // Get disk space information
+    let (disk_free, disk_total) = get_disk_space(UPLOAD_DIR);
+    let disk_used = disk_total.saturating_sub(disk_free);
+    let disk_used_percentage = if disk_total > 0 {
+        (disk_used as f64 / disk_total as f64) * 100.0
+    } else {
+        0.0
+    };
+
+    let percentage = (total_size as f64 / MAX_STORAGE_SIZE as f64) * 100.0;
+    let formatted_used = format_bytes(total_size);
+    let formatted_disk_free = format_bytes(disk_free);
+    let formatted_disk_total = format_bytes(disk_total);
+
+    Ok(HttpResponse::Ok().json(StorageInfo {
+        used_bytes: total_size,
+        total_files: file_count,
+        used_percentage: percentage,
+        formatted_used,
+        max_size_mb: MAX_STORAGE_SIZE / 1024 / 1024,
+        disk_free_bytes: disk_free,
+        disk_total_bytes: disk_total,
+        disk_used_percentage,
+        formatted_disk_free,
+        formatted_disk_total,
+    }))
+}
+
+// Serve the main HTML page
+#[get("/")]
+async fn index(data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header((header::CONTENT_SECURITY_POLICY, data.shell_policy.as_str()))
+        .body(INDEX_HTML))
+}
+
+// Get debug configuration
+#[get("/debug")]
+async fn get_debug_info(data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(DebugInfo {
+        debug_mode: data.debug_mode,
+    }))
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    // Folder to upload into, the top level if left out
+    #[serde(default)]
+    folder: Option<String>,
+}
+
+// Why a multipart upload stopped, sent back in place of the files
+type UploadRefusal = (StatusCode, String);
+
+// Turn a file away because of the upload rules, before anything of it is stored
+fn refuse_upload(req: &HttpRequest, data: &AppState, username: &str, target: &str, reason: String) -> UploadRefusal {
+    warn!(user = %username, "Upload refused: {}", reason);
+    data.audit.record(Some(username), "upload", client_ip(req), Some(target), false, Some(reason.clone()));
+    (StatusCode::BAD_REQUEST, reason)
+}
+
+// Why a file of `size` bytes can't go into a folder with a size limit
+fn size_refusal(name: &str, size: u64, limit: Option<(u64, &str)>) -> Option<String> {
+    let (limit, limit_folder) = limit.filter(|(limit, _)| size > *limit)?;
+    let place = if limit_folder.is_empty() { "uploads".to_string() } else { format!("{}/", limit_folder) };
+    Some(format!("{} is larger than the {} limit for {}", name, format_bytes(limit), place))
+}
+
+// Hold a file that is already on disk, an extracted entry or the source of a move or copy,
+// to the upload rules for the folder it is going into
+fn check_rules(rules: &UploadRules, path: &std::path::Path, name: &str, folder: &str, size: u64) -> Result<(), String> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+    rules.check_name(name)?;
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    std::fs::File::open(path)
+        .and_then(|file| file.take(SNIFF_LENGTH as u64).read_to_end(&mut head))
+        .map_err(|e| format!("{}: {}", name, e))?;
+    rules.check_content(name, &head)?;
+    match size_refusal(name, size, rules.size_limit(folder)) {
+        Some(reason) => Err(reason),
+        None => Ok(()),
+    }
+}
+
+// Take back the files an upload had already stored when a later part of it fails, so a
+// refused request leaves nothing behind
+fn discard_uploads(data: &AppState, files: &[FileInfo]) {
+    for file in files {
+        debug!("Discarding {} from the failed upload", file.path);
+        if let Some(path) = resolve_relative(UPLOAD_DIR, &file.path) {
+            let _ = std::fs::remove_file(path);
+        }
+        if let Err(e) = data.metadata.remove(&file.path) {
+            error!("Failed to remove file metadata: {}", e);
+        }
+    }
+}
+
+// Handle file uploads. Either every file in the request is stored, or, if one of them is
+// refused, none are (infected files stay quarantined either way).
+#[post("/upload")]
+async fn upload_files(
+    req: HttpRequest,
+    query: web::Query<UploadQuery>,
+    mut payload: Multipart,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    // Check authentication first
+    let username = require_auth(&req, Scope::Upload)?;
+    let _upload = data.metrics.upload_started();
+
+    // Ensure upload directory exists
+    create_dir_all(UPLOAD_DIR).and_then(|_| create_dir_all(INCOMING_DIR)).map_err(|e| {
+        error!("Failed to create upload directory: {}", e);
+        actix_web::error::ErrorInternalServerError(format!("Failed to create upload directory: {}", e))
+    })?;
+
+    // Uploading into a folder needs write access to it, like moving files there
+    let mut folder = String::new();
+    if let Some(destination) = query.folder.as_deref().filter(|destination| !destination.trim_matches('/').is_empty()) {
+        let created;
+        (folder, created) = match check_destination(&data, &username, destination) {
+            Ok(destination) => destination,
+            Err(response) => return Ok(response),
+        };
+        if created {
+            let Some(path) = resolve_folder(UPLOAD_DIR, &folder) else {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "success": false,
+                    "message": "Invalid destination folder"
+                })));
+            };
+            create_dir_all(path).map_err(|e| {
+                error!("Failed to create folder {}: {}", folder, e);
+                actix_web::error::ErrorInternalServerError(format!("Failed to create folder: {}", e))
+            })?;
+            claim_folder(&data, &folder, &username);
+        }
+    }
+    let mut uploaded_files = Vec::new();
+    let mut scanned = Vec::new();
+    let refusal = match receive_files(&req, &mut payload, &data, &username, &folder, &mut uploaded_files, &mut scanned).await {
+        Ok(refusal) => refusal,
+        Err(e) => {
+            discard_uploads(&data, &uploaded_files);
+            return Err(e);
+        }
+    };
+    if let Some((status, mut message)) = refusal {
+        if !uploaded_files.is_empty() {
+            discard_uploads(&data, &uploaded_files);
+            message.push_str(&format!("; the other {} file(s) in this upload were not kept", uploaded_files.len()));
+        }
+        // Only the quarantined files are still around to report on
+        scanned.retain(|result| result.infected);
+        return Ok(HttpResponse::build(status).json(UploadResponse {
+            success: false,
+            message,
+            files: vec![],
+            scanned,
+        }));
+    }
+
+    let infected = scanned.iter().filter(|result| result.infected).count();
+    if uploaded_files.is_empty() && infected > 0 {
+        Ok(HttpResponse::UnprocessableEntity().json(UploadResponse {
+            success: false,
+            message: format!("{} infected file(s) quarantined, nothing stored", infected),
+            files: vec![],
+            scanned,
+        }))
+    } else if uploaded_files.is_empty() {
+        Ok(HttpResponse::BadRequest().json(UploadResponse {
+            success: false,
+            message: "No files were uploaded".to_string(),
+            files: vec![],
+            scanned: vec![],
+        }))
+    } else {
+        info!(user = %username, "Uploaded {} file(s)", uploaded_files.len());
+        update_search_index(&data, uploaded_files.iter().map(|file| file.path.clone()).collect(), vec![]);
+        for file in &uploaded_files {
+            data.activity.record(&username, "upload", &file.path, None);
+            data.audit.record(Some(&username), "upload", client_ip(&req), Some(&file.path), true, Some(format!("{} bytes", file.size)));
+        }
+        let mut message = format!("Successfully uploaded {} file(s)", uploaded_files.len());
+        if infected > 0 {
+            message.push_str(&format!(", quarantined {} infected file(s)", infected));
+        }
+        Ok(HttpResponse::Ok().json(UploadResponse {
+            success: true,
+            message,
+            files: uploaded_files,
+            scanned,
+        }))
+    }
+}
+
+// A file that is still being received; it is removed again unless the upload keeps it
+struct PartialFile(Option<PathBuf>);
+
+impl PartialFile {
+    fn moved_to(&mut self, path: PathBuf) {
+        self.0 = Some(path);
+    }
+
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Store the files of an upload one by one, stopping at the first one that is refused
+async fn receive_files(
+    req: &HttpRequest,
+    payload: &mut Multipart,
+    data: &AppState,
+    username: &str,
+    folder: &str,
+    uploaded_files: &mut Vec<FileInfo>,
+    scanned: &mut Vec<ScanResult>,
+) -> ActixResult<Option<UploadRefusal>> {
+    let size_limit = data.rules.size_limit(folder);
+    let mut file_count = 0;
+    // Optional client-supplied SHA-256, sent as a "sha256" field right before the file it
+    // covers. One that no file follows is refused rather than quietly checking nothing.
+    let mut expected_checksum: Option<String> = None;
+    let misplaced_checksum = || {
+        warn!("Upload rejected: sha256 field not followed by a file");
+        let message = "A sha256 field has to come right before the file it covers".to_string();
+        Ok(Some((StatusCode::BAD_REQUEST, message)))
+    };
+
+    while let Some(mut field) = payload.try_next().await? {
+        let content_disposition = field.content_disposition();
+        let field_name = content_disposition
+            .and_then(|cd| cd.get_name())
+            .map(|name| name.to_string());
+        
+        if let Some(filename) = content_disposition.and_then(|cd| cd.get_filename()) {
+            debug!("Processing file: {}", filename);
+            
+            if file_count >= MAX_FILE_COUNT {
+                warn!("Upload rejected: more than {} files", MAX_FILE_COUNT);
+                return Ok(Some((StatusCode::BAD_REQUEST, format!("Maximum {} files allowed", MAX_FILE_COUNT))));
+            }
+
+            // Sanitize filename and add UUID to prevent conflicts
+            let sanitized_filename = sanitize_filename(filename);
+            let target = storage::join_relative(folder, &sanitized_filename);
+            if let Err(reason) = data.rules.check_name(&sanitized_filename) {
+                return Ok(Some(refuse_upload(req, data, username, &target, reason)));
+            }
+
+            // Look at how the file starts before any of it is written
+            let mut head = web::BytesMut::new();
+            let mut complete = false;
+            while head.len() < SNIFF_LENGTH && !complete {
+                match field.try_next().await? {
+                    Some(chunk) => head.extend_from_slice(&chunk),
+                    None => complete = true,
+                }
+            }
+            if let Err(reason) = data.rules.check_content(&sanitized_filename, &head[..head.len().min(SNIFF_LENGTH)]) {
+                return Ok(Some(refuse_upload(req, data, username, &target, reason)));
+            }
+
+            let unique_filename = storage::join_relative(folder, &format!("{}_{}", Uuid::new_v4(), sanitized_filename));
+            let Some(filepath) = resolve_relative(UPLOAD_DIR, &unique_filename) else {
+                return Ok(Some((StatusCode::BAD_REQUEST, "Invalid destination folder".to_string())));
+            };
+            debug!("Storing {} as {}", sanitized_filename, unique_filename);
+            // Nothing shows up in the store until it has been checked
+            let staged = std::path::Path::new(INCOMING_DIR).join(Uuid::new_v4().to_string());
+            let mut partial = PartialFile(Some(staged.clone()));
+
+            // Create the file
+            let staged_clone = staged.clone();
+            let mut f = web::block(move || std::fs::File::create(staged_clone))
+                .await?
+                .map_err(|e| {
+                    error!("Failed to create file: {}", e);
+                    actix_web::error::ErrorInternalServerError(format!("Failed to create file: {}", e))
+                })?;
+
+            let mut file_size = 0;
+            let mut hasher = Sha256::new();
+
+            // Write file chunks, hashing them as they stream past, starting with the sniffed head
+            let mut head = Some(head.freeze()).filter(|head| !head.is_empty());
+            loop {
+                let chunk = match head.take() {
+                    Some(chunk) => chunk,
+                    // A field can't be polled again once it has ended
+                    None if complete => break,
+                    None => match field.try_next().await? {
+                        Some(chunk) => chunk,
+                        None => break,
+                    },
+                };
+                file_size += chunk.len();
+                data.metrics.uploaded_bytes.inc_by(chunk.len() as u64);
+                if file_size > MAX_FILE_SIZE {
+                    warn!("Upload rejected: {} is larger than {} bytes", sanitized_filename, MAX_FILE_SIZE);
+                    let message = format!("File too large. Maximum size is {} MB", MAX_FILE_SIZE / 1024 / 1024);
+                    return Ok(Some((StatusCode::BAD_REQUEST, message)));
+                }
+                if let Some(reason) = size_refusal(&sanitized_filename, file_size as u64, size_limit) {
+                    return Ok(Some(refuse_upload(req, data, username, &target, reason)));
+                }
+
+                (f, hasher) = web::block(move || {
+                    hasher.update(&chunk);
+                    f.write_all(&chunk).map(|_| (f, hasher))
+                })
+                .await?
+                .map_err(|e| {
+                    error!("Failed to write chunk: {}", e);
+                    actix_web::error::ErrorInternalServerError(format!("Failed to write file: {}", e))
+                })?;
+            }
+
+            let checksum = format!("{:x}", hasher.finalize());
+            debug!("Wrote {} bytes, SHA-256 {}", file_size, checksum);
+            drop(f);
+
+            if let Some(expected) = expected_checksum.take() {
+                if expected != checksum {
+                    warn!("Checksum mismatch for {}: expected {}, got {}", sanitized_filename, expected, checksum);
+                    data.audit.record(
+                        Some(username),
+                        "upload",
+                        client_ip(req),
+                        Some(&unique_filename),
+                        false,
+                        Some("checksum mismatch".to_string()),
+                    );
+                    let message = format!("Checksum mismatch for {}: expected {}, got {}", sanitized_filename, expected, checksum);
+                    return Ok(Some((StatusCode::BAD_REQUEST, message)));
+                }
+            }
+
+            if let Some(scanner) = data.scanner.clone() {
+                let scan_path = staged.clone();
+                match web::block(move || scanner.scan(&scan_path)).await? {
+                    Ok(Verdict::Clean) => scanned.push(ScanResult {
+                        name: sanitized_filename.clone(),
+                        path: unique_filename.clone(),
+                        infected: false,
+                        signature: None,
+                        quarantined_as: None,
+                    }),
+                    Ok(Verdict::Infected(signature)) => {
+                        warn!(user = %username, "Upload {} is infected with {}", unique_filename, signature);
+                        partial.keep();
+                        let result = quarantine_file(req, data, username, &staged, &unique_filename, signature).await?;
+                        scanned.push(result);
+                        file_count += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Failed to scan {}: {}", unique_filename, e);
+                        data.audit.record(Some(username), "upload", client_ip(req), Some(&unique_filename), false, Some(e.to_string()));
+                        let status = match e {
+                            ScanError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+                            ScanError::Refused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                        };
+                        return Ok(Some((status, format!("{} was not stored: {}", sanitized_filename, e))));
+                    }
+                }
+            }
+
+            let (from, to) = (staged.clone(), filepath.clone());
+            web::block(move || storage::move_file(&from, &to)).await?.map_err(|e| {
+                error!("Failed to move {} into the store: {}", unique_filename, e);
+                actix_web::error::ErrorInternalServerError(format!("Failed to store file: {}", e))
+            })?;
+            partial.moved_to(filepath);
+
+            data.metadata
+                .insert(&unique_filename, FileMeta {
+                    sha256: Some(checksum.clone()),
+                    uploaded_at: Some(unix_now()),
+                    ..Default::default()
+                })
+                .map_err(|e| {
+                    error!("Failed to store file metadata: {}", e);
+                    actix_web::error::ErrorInternalServerError(format!("Failed to store file metadata: {}", e))
+                })?;
+            partial.keep();
+
+            let (file_type, can_preview) = get_file_type_and_preview(&sanitized_filename);
+
+            uploaded_files.push(FileInfo {
+                name: sanitized_filename.clone(),
+                size: file_size as u64,
+                path: unique_filename.clone(),
+                folder: folder.to_string(),
+                file_type,
+                can_preview,
+                sha256: Some(checksum),
+                tags: vec![],
+                properties: Default::default(),
+                modified: unix_now(),
+                uploaded_at: Some(unix_now()),
+                last_accessed: None,
+                starred: false,
+            });
+
+            file_count += 1;
+        } else if field_name.as_deref() == Some("sha256") {
+            let mut value = Vec::new();
+            while let Some(chunk) = field.try_next().await? {
+                value.extend_from_slice(&chunk);
+                if value.len() > 128 {
+                    return Ok(Some((StatusCode::BAD_REQUEST, "Invalid sha256 field".to_string())));
+                }
+            }
+            if expected_checksum.is_some() {
+                return misplaced_checksum();
+            }
+            let checksum = String::from_utf8_lossy(&value).trim().to_lowercase();
+            debug!("Client supplied SHA-256: {}", checksum);
+            expected_checksum = Some(checksum);
+        } else {
+            debug!("Ignoring multipart field without a filename: {:?}", field_name);
+        }
+    }
+
+    if expected_checksum.is_some() {
+        return misplaced_checksum();
+    }
+    Ok(None)
+}
+
+// List all uploaded files
+#[get("/files")]
+async fn list_files(
+    query: web::Query<FileQuery>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let mut files = Vec::new();
+    let mut readable = readable_files(&data, &username);
+
+    for stored in walk_files(std::path::Path::new(UPLOAD_DIR)) {
+        if !readable(&stored.path) {
+            continue;
+        }
+        // Extract original filename (remove UUID prefix)
+        let name = display_name(&stored.path);
+        let (file_type, can_preview) = get_file_type_and_preview(&name);
+        let meta = data.metadata.get(&stored.path).unwrap_or_default();
+
+        files.push(FileInfo {
+            name,
+            size: stored.size,
+            folder: folder_of(&stored.path),
+            path: stored.path,
+            file_type,
+            can_preview,
+            sha256: meta.sha256,
+            tags: meta.tags,
+            properties: meta.properties,
+            modified: stored.modified,
+            uploaded_at: meta.uploaded_at,
+            last_accessed: meta.last_accessed,
+            starred: meta.starred_by.contains(&username),
+        });
+    }
+
+    match listing::page(files, &query) {
+        Ok(page) => Ok(HttpResponse::Ok().json(FilesResponse {
+            files: page.files,
+            total: page.total,
+            next_cursor: page.next_cursor,
+        })),
+        Err(message) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": message
+        }))),
+    }
+}
+
+// Delete a file
+#[post("/delete/{filename:.*}")]
+async fn delete_file(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Delete)?;
+    let Some(Resolved { path: filepath, relative: filename }) = resolve(UPLOAD_DIR, &path.into_inner()) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": "Invalid file path"
+        })));
+    };
+    if let Err(response) = check_access(&data, &username, &filename, Access::ReadWrite) {
+        data.audit.record(Some(&username), "delete", client_ip(&req), Some(&filename), false, Some("permission denied".to_string()));
+        return Ok(response);
+    }
+
+    match std::fs::remove_file(&filepath) {
+        Ok(_) => {
+            storage::remove_empty_parents(UPLOAD_DIR, &filename);
+            forget_removed_folders(&data);
+            if let Err(e) = data.metadata.remove(&filename) {
+                error!("Failed to remove metadata for {}: {}", filename, e);
+            }
+            data.activity.record(&username, "delete", &filename, None);
+            data.audit.record(Some(&username), "delete", client_ip(&req), Some(&filename), true, None);
+            update_search_index(&data, vec![], vec![filename]);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "File deleted successfully"
+            })))
+        }
+        Err(_) => {
+            data.audit.record(Some(&username), "delete", client_ip(&req), Some(&filename), false, Some("file not found".to_string()));
+            Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "success": false,
+                "message": "File not found"
+            })))
+        }
+    }
+}
+
+// Preview text/code files
+#[get("/preview/{filename:.*}")]
+async fn preview_file(
+    path: web::Path<String>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let Some(Resolved { path: filepath, relative: filename }) = resolve(UPLOAD_DIR, &path.into_inner()) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid file path"
+        })));
+    };
+    if check_access(&data, &username, &filename, Access::Read).is_err() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "File not found"
+        })));
+    }
+    
+    // Get original filename for type checking
+    let display_name = display_name(&filename);
+    
+    let (file_type, can_preview) = get_file_type_and_preview(&display_name);
+    
+    if !can_preview || (file_type != "text" && file_type != "code") {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "File cannot be previewed as text"
+        })));
+    }
+    
+    match std::fs::read_to_string(&filepath) {
+        Ok(content) => {
+            // Limit content size for preview (first 10KB)
+            let preview_content = if content.len() > 10240 {
+                format!("{}...\n\n[Content truncated - showing first 10KB of {}]", 
+                    &content[..10240], display_name)
+            } else {
+                content
+            };
+            
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "content": preview_content,
+                "type": file_type,
+                "filename": display_name
+            })))
+        }
+        Err(_) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to read file"
+        })))
+    }
+}
+
+// Download several files and folders at once as a ZIP or tar.gz that is built on the fly.
+// Paths are passed as repeated `paths` parameters: /archive?paths=a&paths=folder&format=zip
+#[get("/archive")]
+async fn download_archive(
+    query: web::Query<Vec<(String, String)>>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+
+    let mut paths = Vec::new();
+    let mut format = ArchiveFormat::Zip;
+    for (key, value) in query.into_inner() {
+        match key.as_str() {
+            "paths" => paths.push(value),
+            "format" => {
+                format = ArchiveFormat::parse(&value).ok_or_else(|| {
+                    actix_web::error::ErrorBadRequest(format!("Unsupported archive format: {}", value))
+                })?;
+            }
+            _ => {}
+        }
+    }
+
+    if paths.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": "No paths given"
+        })));
+    }
+
+    // A single folder is named after itself, anything else gets a generic name
+    let archive_name = match paths.as_slice() {
+        [single] if resolve_relative(UPLOAD_DIR, single).is_some_and(|path| path.is_dir()) => {
+            format!("{}.{}", single.trim_matches('/').rsplit('/').next().unwrap_or("cratr"), format.extension())
+        }
+        _ => format!("cratr-download.{}", format.extension()),
+    };
+
+    // Files in folders the user can't read are left out, even inside a requested folder
+    let state = data.clone();
+    let entries = match web::block(move || {
+        archive::collect_entries(UPLOAD_DIR, &paths, readable_files(&state, &username))
+    })
+    .await?
+    {
+        Ok(entries) => entries,
+        Err(message) => {
+            warn!("Archive request rejected: {}", message);
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "success": false,
+                "message": message
+            })));
+        }
+    };
+    info!("Streaming {} with {} entries", archive_name, entries.len());
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition::attachment(archive_name))
+        .streaming(archive::stream_archive(entries, format)))
+}
+
+// Keep the search index in step with the upload directory. Extracting text from big PDFs
+// is slow, so this runs in the background and never fails the request that changed the files.
+fn update_search_index(data: &AppState, added: Vec<String>, removed: Vec<String>) {
+    let search = data.search.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = search.update(UPLOAD_DIR, &added, &removed) {
+            error!("Failed to update search index: {}", e);
+        }
+    });
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+// Full-text search over file names and contents
+#[get("/search")]
+async fn search_files(
+    query: web::Query<SearchQuery>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let SearchQuery { q, limit } = query.into_inner();
+    let limit = limit.unwrap_or(MAX_SEARCH_RESULTS).min(MAX_SEARCH_RESULTS);
+
+    if q.trim().is_empty() {
+        return Ok(HttpResponse::Ok().json(SearchResponse { query: q, hits: vec![] }));
+    }
+
+    let search = data.search.clone();
+    let terms = q.clone();
+    // Ask for the maximum, since some hits may be in folders the user can't read
+    let hits = web::block(move || search.search(&terms, MAX_SEARCH_RESULTS))
+        .await?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Search failed: {}", e)))?;
+    let mut readable = readable_files(&data, &username);
+    let hits: Vec<_> = hits.into_iter().filter(|hit| readable(&hit.path)).take(limit).collect();
+    debug!("Search for {:?} matched {} file(s)", q, hits.len());
+
+    Ok(HttpResponse::Ok().json(SearchResponse { query: q, hits }))
+}
+
+// Split batch ids into the ones the user may act on and failed results for the rest. The ids
+// that pass come back in their canonical form.
+fn permitted_ids(data: &AppState, username: &str, ids: Vec<String>, needed: Access) -> (Vec<String>, Vec<BatchItemResult>) {
+    let mut allowed = Vec::new();
+    let mut denied = Vec::new();
+    for id in ids {
+        let Some(Resolved { relative, .. }) = resolve(UPLOAD_DIR, &id) else {
+            denied.push(batch::failed(&id, "Invalid file path"));
+            continue;
+        };
+        match folder_access(data, username, &folder_of(&relative)) {
+            access if access.allows(needed) => allowed.push(relative),
+            FolderAccess::Shared(_) => denied.push(batch::failed(&id, "This folder is shared with you read-only")),
+            _ => denied.push(batch::failed(&id, "File not found")),
+        }
+    }
+    (allowed, denied)
+}
+
+// Split batch ids into the files the upload rules allow into `folder` and failed results for
+// the rest, so moves and copies can't bring in what an upload there would refuse
+fn allowed_into(rules: &UploadRules, ids: Vec<String>, folder: &str) -> (Vec<String>, Vec<BatchItemResult>) {
+    let mut allowed = Vec::new();
+    let mut refused = Vec::new();
+    for id in ids {
+        // Missing files are reported by the transfer itself
+        let Some((path, size)) = resolve_relative(UPLOAD_DIR, &id)
+            .and_then(|path| path.metadata().ok().filter(|meta| meta.is_file()).map(|meta| (path, meta.len())))
+        else {
+            allowed.push(id);
+            continue;
+        };
+        match check_rules(rules, &path, &display_name(&id), folder, size) {
+            Ok(()) => allowed.push(id),
+            Err(reason) => refused.push(batch::failed(&id, reason)),
+        }
+    }
+    (allowed, refused)
+}
+
+// Moving or copying into a folder needs write access to it. A folder the transfer creates
+// belongs to the user, like any other new folder.
+fn check_destination(data: &AppState, username: &str, destination: &str) -> Result<(String, bool), HttpResponse> {
+    let folder = storage::sanitize_folder(destination);
+    match folder_access(data, username, &folder) {
+        access if access.allows(Access::ReadWrite) => {
+            let Some(path) = resolve_folder(UPLOAD_DIR, &folder) else {
+                return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                    "success": false,
+                    "message": "Invalid destination folder"
+                })));
+            };
+            Ok((folder, !path.is_dir()))
+        }
+        FolderAccess::Shared(_) => Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "success": false,
+            "message": format!("{}/ is shared with you read-only", folder)
+        }))),
+        _ => Err(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Destination folder not found"
+        }))),
+    }
+}
+
+fn claim_folder(data: &AppState, folder: &str, username: &str) {
+    if let Err(e) = data.shares.claim(folder, username) {
+        error!("Failed to store folder shares: {}", e);
+    }
+}
+
+// Once files are deleted or moved away, the folders they emptied are gone and so are their shares
+fn forget_removed_folders(data: &AppState) {
+    if let Err(e) = data.shares.forget_missing(std::path::Path::new(UPLOAD_DIR)) {
+        error!("Failed to store folder shares: {}", e);
+    }
+}
+
+// Summarise per-item batch results into a single response
+fn batch_response(action: &str, results: Vec<BatchItemResult>) -> HttpResponse {
+    let succeeded = results.iter().filter(|result| result.success).count();
+    let failed = results.len() - succeeded;
+    info!("Batch {}: {} succeeded, {} failed", action, succeeded, failed);
+
+    let message = if failed == 0 {
+        format!("{} {} file(s)", action, succeeded)
+    } else {
+        format!("{} {} file(s), {} failed", action, succeeded, failed)
+    };
+
+    HttpResponse::Ok().json(BatchResponse {
+        success: failed == 0,
+        message,
+        results,
+    })
+}
+
+// Delete several files in one request
+#[post("/batch/delete")]
+async fn batch_delete(
+    req: HttpRequest,
+    request: web::Json<BatchRequest>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Delete)?;
+    let (ids, denied) = permitted_ids(&data, &username, request.into_inner().ids, Access::ReadWrite);
+    let metadata = data.metadata.clone();
+
+    let mut results = web::block(move || batch::delete(UPLOAD_DIR, &metadata, &ids))
+        .await?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to update metadata: {}", e)))?;
+    results.extend(denied);
+    let removed: Vec<String> = results.iter().filter(|result| result.success).map(|result| result.id.clone()).collect();
+    for path in &removed {
+        data.activity.record(&username, "delete", path, None);
+        data.audit.record(Some(&username), "delete", client_ip(&req), Some(path), true, None);
+    }
+    forget_removed_folders(&data);
+    update_search_index(&data, vec![], removed);
+    Ok(batch_response("Deleted", results))
+}
+
+// Move several files into a folder
+#[post("/batch/move")]
+async fn batch_move(
+    req: HttpRequest,
+    request: web::Json<BatchTransferRequest>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Write)?;
+    let BatchTransferRequest { ids, destination } = request.into_inner();
+    let (folder, created) = match check_destination(&data, &username, &destination) {
+        Ok(destination) => destination,
+        Err(response) => return Ok(response),
+    };
+    let (ids, mut denied) = permitted_ids(&data, &username, ids, Access::ReadWrite);
+    let (rules, target_folder) = (data.rules.clone(), folder.clone());
+    let (ids, refused) = web::block(move || allowed_into(&rules, ids, &target_folder)).await?;
+    denied.extend(refused);
+    let metadata = data.metadata.clone();
+
+    let mut results = web::block(move || batch::transfer(UPLOAD_DIR, &metadata, &ids, &destination, false, &Default::default()))
+        .await?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to move files: {}", e)))?;
+    results.extend(denied);
+    if created {
+        claim_folder(&data, &folder, &username);
+    }
+    let (added, removed): (Vec<String>, Vec<String>) = results.iter()
+        .filter(|result| result.success)
+        .filter_map(|result| result.new_id.clone().filter(|new_id| *new_id != result.id).map(|new_id| (new_id, result.id.clone())))
+        .unzip();
+    for (new_id, old_id) in added.iter().zip(&removed) {
+        data.activity.record(&username, "move", new_id, Some(format!("from {}", old_id)));
+        data.audit.record(Some(&username), "move", client_ip(&req), Some(new_id), true, Some(format!("from {}", old_id)));
+    }
+    forget_removed_folders(&data);
+    update_search_index(&data, added, removed);
+    Ok(batch_response("Moved", results))
+}
+
+// Copy several files into a folder
+#[post("/batch/copy")]
+async fn batch_copy(
+    req: HttpRequest,
+    request: web::Json<BatchTransferRequest>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Write)?;
+    let BatchTransferRequest { ids, destination } = request.into_inner();
+    let (folder, created) = match check_destination(&data, &username, &destination) {
+        Ok(destination) => destination,
+        Err(response) => return Ok(response),
+    };
+    let (ids, mut denied) = permitted_ids(&data, &username, ids, Access::Read);
+    let (rules, target_folder) = (data.rules.clone(), folder.clone());
+    let (ids, refused) = web::block(move || allowed_into(&rules, ids, &target_folder)).await?;
+    denied.extend(refused);
+    let (ids, staged, unscanned) = stage_copies(&req, &data, &username, ids).await;
+    let metadata = data.metadata.clone();
+
+    let leftovers: Vec<PathBuf> = staged.values().cloned().collect();
+    let transferred = web::block(move || batch::transfer(UPLOAD_DIR, &metadata, &ids, &destination, true, &staged)).await;
+    // Staged copies that didn't make it into the store, e.g. because of a name clash
+    for path in leftovers {
+        let _ = std::fs::remove_file(path);
+    }
+    let mut results = transferred?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to copy files: {}", e)))?;
+    results.extend(denied);
+    results.extend(unscanned);
+    if created {
+        claim_folder(&data, &folder, &username);
+    }
+    let mut added = Vec::new();
+    for result in results.iter().filter(|result| result.success) {
+        if let Some(new_id) = &result.new_id {
+            data.activity.record(&username, "copy", new_id, Some(format!("from {}", result.id)));
+            data.audit.record(Some(&username), "copy", client_ip(&req), Some(new_id), true, Some(format!("from {}", result.id)));
+            added.push(new_id.clone());
+        }
+    }
+    update_search_index(&data, added, vec![]);
+    Ok(batch_response("Copied", results))
+}
+
+// Stage a copy of each file in the incoming directory and scan it there, so nothing
+// infected is ever copied into the store. An infected copy is quarantined; the
+// original stays where it is, since the user may only be allowed to read it, and is left to
+// an admin through the log and the audit trail. Returns the ids to copy, where each staged
+// copy is, and a failed result for every other file.
+async fn stage_copies(
+    req: &HttpRequest,
+    data: &AppState,
+    actor: &str,
+    ids: Vec<String>,
+) -> (Vec<String>, std::collections::HashMap<String, PathBuf>, Vec<BatchItemResult>) {
+    let mut staged = std::collections::HashMap::new();
+    let Some(scanner) = data.scanner.clone() else {
+        return (ids, staged, vec![]);
+    };
+    let (mut clean, mut refused) = (Vec::new(), Vec::new());
+    for id in ids {
+        // Whatever isn't staged here is never copied, even if it turns up in the meantime
+        let Some(source) = resolve_relative(UPLOAD_DIR, &id).filter(|path| path.is_file()) else {
+            refused.push(batch::failed(&id, "File not found"));
+            continue;
+        };
+        let staging = std::path::Path::new(INCOMING_DIR).join(Uuid::new_v4().to_string());
+        let (scanner, scan_path) = (scanner.clone(), staging.clone());
+        let verdict = web::block(move || {
+            create_dir_all(INCOMING_DIR)
+                .and_then(|_| std::fs::copy(&source, &scan_path))
+                .map_err(|e| ScanError::Refused(format!("can't stage copy: {}", e)))?;
+            scanner.scan(&scan_path)
+        })
+        .await;
+
+        match verdict {
+            Ok(Ok(Verdict::Clean)) => {
+                staged.insert(id.clone(), staging);
+                clean.push(id);
+            }
+            Ok(Ok(Verdict::Infected(signature))) => {
+                warn!(user = %actor, "Copy of {} is infected with {}; the original needs an admin's attention", id, signature);
+                let (copy_path, label) = (staging.clone(), id.clone());
+                let quarantined = web::block(move || scan::quarantine(&copy_path, &label, QUARANTINE_DIR))
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|result| result.map_err(|e| e.to_string()));
+                let detail = match quarantined {
+                    Ok(name) => format!("copy infected with {}; copy quarantined as {}, original left in place", signature, name),
+                    Err(e) => {
+                        error!("Failed to quarantine the copy of {}, deleting it: {}", id, e);
+                        let _ = std::fs::remove_file(&staging);
+                        format!("copy infected with {}; copy deleted, original left in place", signature)
+                    }
+                };
+                data.audit.record(Some(actor), "quarantine", client_ip(req), Some(&id), true, Some(detail));
+                refused.push(batch::failed(&id, format!("Not copied: infected with {}", signature)));
+            }
+            Ok(Err(e)) => {
+                error!("Failed to scan the copy of {}: {}", id, e);
+                let _ = std::fs::remove_file(&staging);
+                refused.push(batch::failed(&id, format!("Not copied: {}", e)));
+            }
+            Err(e) => {
+                error!("Failed to scan the copy of {}: {}", id, e);
+                let _ = std::fs::remove_file(&staging);
+                refused.push(batch::failed(&id, "Not copied: the copy couldn't be scanned"));
+            }
+        }
+    }
+    (clean, staged, refused)
+}
+
+// Add or remove tags on several files
+#[post("/batch/tag")]
+async fn batch_tag(
+    request: web::Json<BatchTagRequest>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Write)?;
+    let BatchTagRequest { ids, add, remove } = request.into_inner();
+    let (ids, denied) = permitted_ids(&data, &username, ids, Access::ReadWrite);
+    let metadata = data.metadata.clone();
+
+    let mut results = web::block(move || batch::tag(UPLOAD_DIR, &metadata, &ids, &add, &remove))
+        .await?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to update tags: {}", e)))?;
+    results.extend(denied);
+    Ok(batch_response("Tagged", results))
+}
+
+// Change the tags and key/value metadata of one file
+#[post("/meta/{filename:.*}")]
+async fn update_file_meta(
+    path: web::Path<String>,
+    request: web::Json<FileMetaUpdate>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Write)?;
+    let Some(filename) = resolve(UPLOAD_DIR, &path.into_inner())
+        .filter(|resolved| resolved.path.is_file())
+        .map(|resolved| resolved.relative)
+    else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "File not found"
+        })));
+    };
+    if let Err(response) = check_access(&data, &username, &filename, Access::ReadWrite) {
+        return Ok(response);
+    }
+
+    let update = request.into_inner();
+    let outcome = data.metadata
+        .edit(|entries| {
+            let mut meta = entries.get(&filename).cloned().unwrap_or_default();
+            meta.apply(&update)?;
+            entries.insert(filename.clone(), meta.clone());
+            Ok::<_, String>(meta)
+        })
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store file metadata: {}", e)))?;
+
+    match outcome {
+        Ok(meta) => Ok(HttpResponse::Ok().json(FileMetaResponse {
+            success: true,
+            message: "Metadata updated".to_string(),
+            tags: meta.tags,
+            properties: meta.properties,
+        })),
+        Err(message) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": message
+        }))),
+    }
+}
+
+// Star or unstar a file for the current user
+#[post("/star/{filename:.*}")]
+async fn star_file(
+    path: web::Path<String>,
+    request: web::Json<StarRequest>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let Some(filename) = resolve(UPLOAD_DIR, &path.into_inner())
+        .filter(|resolved| resolved.path.is_file())
+        .map(|resolved| resolved.relative)
+    else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "File not found"
+        })));
+    };
+    if let Err(response) = check_access(&data, &username, &filename, Access::Read) {
+        return Ok(response);
+    }
+
+    let starred = request.starred;
+    data.metadata
+        .edit(|entries| {
+            let meta = entries.entry(filename.clone()).or_default();
+            meta.starred_by.retain(|user| *user != username);
+            if starred {
+                meta.starred_by.push(username.clone());
+            }
+        })
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store file metadata: {}", e)))?;
+
+    Ok(HttpResponse::Ok().json(StarResponse { success: true, starred }))
+}
+
+#[derive(Deserialize)]
+struct ActivityQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+    // Only events about this file or folder
+    #[serde(default)]
+    path: Option<String>,
+}
+
+// Latest uploads, moves, copies, extractions and deletions, newest first
+#[get("/activity")]
+async fn list_activity(
+    query: web::Query<ActivityQuery>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let limit = query.limit.unwrap_or(50).min(MAX_ACTIVITY_EVENTS);
+    let path = query.path.as_deref().map(|path| path.trim_matches('/')).filter(|path| !path.is_empty());
+    let mut readable = readable_files(&data, &username);
+    let events = data
+        .activity
+        .recent(MAX_ACTIVITY_EVENTS, path)
+        .into_iter()
+        .filter(|event| readable(&event.path))
+        .take(limit)
+        .collect();
+    Ok(HttpResponse::Ok().json(ActivityResponse { events }))
+}
+
+// Audit every file download and remember when the file was last fetched; runs after each
+// successful /download response
+fn record_download(data: &AppState, request_path: &str, actor: Option<String>, ip: Option<String>) {
+    let Some(relative) = download_relative(request_path)
+        .and_then(|relative| resolve(UPLOAD_DIR, &relative))
+        .filter(|resolved| resolved.path.is_file())
+        .map(|resolved| resolved.relative)
+    else {
+        return;
+    };
+
+    data.audit.record(actor.as_deref(), "download", ip, Some(&relative), true, None);
+
+    let metadata = data.metadata.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = metadata.record_access(&relative, unix_now()) {
+            error!("Failed to record access to {}: {}", relative, e);
+        }
+    });
+}
+
+// Path of the requested file relative to the upload directory
+fn download_relative(request_path: &str) -> Option<String> {
+    let encoded = request_path.strip_prefix("/download/")?;
+    Some(percent_encoding::percent_decode_str(encoded).decode_utf8_lossy().to_string())
+}
+
+// Downloads need the same login and folder access as every other way of reading a file
+fn download_user(req: &HttpRequest, request_path: &str) -> ActixResult<String> {
+    let username = require_auth(req, Scope::Read)?;
+    let data = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Application state missing"))?;
+    // The file service refuses `..` itself but would follow a symlink out of the upload
+    // directory, and it quietly serves "a//b" and "a/./b" as "a/b"
+    let Some(Resolved { relative, .. }) = resolve(UPLOAD_DIR, &download_relative(request_path).unwrap_or_default()) else {
+        return Err(actix_web::error::ErrorNotFound("File not found"));
+    };
+    if !folder_access(data, &username, &folder_of(&relative)).allows(Access::Read) {
+        return Err(actix_web::error::ErrorNotFound("File not found"));
+    }
+    Ok(username)
+}
+
+// Every tag in use, most common first
+#[get("/tags")]
+async fn list_tags(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let mut counts = std::collections::HashMap::<String, usize>::new();
+    let mut readable = readable_files(&data, &username);
+    for (path, meta) in data.metadata.snapshot() {
+        if !readable(&path) {
+            continue;
+        }
+        for tag in meta.tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    let mut tags: Vec<TagCount> = counts.into_iter().map(|(tag, count)| TagCount { tag, count }).collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    Ok(HttpResponse::Ok().json(TagsResponse { tags }))
+}
+
+// Resolve a stored archive the user has `needed` access to and work out how to read it.
+// Returns where it is, its canonical relative path and its kind.
+fn resolve_archive(data: &AppState, username: &str, filename: &str, needed: Access) -> Result<(PathBuf, String, ArchiveKind), HttpResponse> {
+    let Some(Resolved { path: filepath, relative }) = resolve(UPLOAD_DIR, filename).filter(|resolved| resolved.path.is_file()) else {
+        return Err(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "File not found"
+        })));
+    };
+    check_access(data, username, &relative, needed)?;
+
+    match ArchiveKind::from_name(&display_name(&relative)) {
+        Some(kind) => Ok((filepath, relative, kind)),
+        None => Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": "Unsupported archive format (zip, tar, tar.gz and tar.bz2 can be opened)"
+        }))),
+    }
+}
+
+// List the entries inside a zip or tar archive
+#[get("/archive/entries/{filename:.*}")]
+async fn list_archive_entries(
+    path: web::Path<String>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let (filepath, filename, kind) = match resolve_archive(&data, &username, &path.into_inner(), Access::Read) {
+        Ok(archive) => archive,
+        Err(response) => return Ok(response),
+    };
+
+    match web::block(move || archive::list_entries(&filepath, kind)).await? {
+        Ok((entries, truncated)) => Ok(HttpResponse::Ok().json(ArchiveListing {
+            archive: display_name(&filename),
+            entries,
+            truncated,
+        })),
+        Err(e) => {
+            warn!("Failed to read archive {}: {}", filename, e);
+            Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                "success": false,
+                "message": format!("Failed to read archive: {}", e)
+            })))
+        }
+    }
+}
+
+// Download a single file from inside an archive, or preview it as text with `preview=true`
+#[get("/archive/entry/{filename:.*}")]
+async fn get_archive_entry(
+    path: web::Path<String>,
+    query: web::Query<ArchiveEntryQuery>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Read)?;
+    let ArchiveEntryQuery { name, preview } = query.into_inner();
+    let (filepath, _, kind) = match resolve_archive(&data, &username, &path.into_inner(), Access::Read) {
+        Ok(archive) => archive,
+        Err(response) => return Ok(response),
+    };
+    let entry_name = name.rsplit('/').next().unwrap_or(&name).to_string();
+
+    if preview {
+        let (file_type, can_preview) = get_file_type_and_preview(&entry_name);
+        if !can_preview || (file_type != "text" && file_type != "code") {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "File cannot be previewed as text"
+            })));
+        }
+
+        // Read one byte past the preview limit to know whether we truncated
+        let lookup_name = name.clone();
+        let mut content = Vec::new();
+        let found = web::block(move || {
+            archive::copy_entry(&filepath, kind, &lookup_name, &mut content, 10241).map(|found| (found, content))
+        })
+        .await?;
+
+        return match found {
+            Ok((true, content)) => {
+                let text = String::from_utf8_lossy(&content[..content.len().min(10240)]).to_string();
+                let preview_content = if content.len() > 10240 {
+                    format!("{}...\n\n[Content truncated - showing first 10KB of {}]", text, entry_name)
+                } else {
+                    text
+                };
+                Ok(HttpResponse::Ok().json(serde_json::json!({
+                    "content": preview_content,
+                    "type": file_type,
+                    "filename": entry_name
+                })))
+            }
+            Ok((false, _)) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Entry not found in archive"
+            }))),
+            Err(e) => Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                "error": format!("Failed to read archive: {}", e)
+            }))),
+        };
+    }
+
+    // Check the entry exists before committing to a streaming response
+    let lookup_path = filepath.clone();
+    let lookup_name = name.clone();
+    let found = web::block(move || archive::copy_entry(&lookup_path, kind, &lookup_name, &mut std::io::sink(), 0)).await?;
+    match found {
+        Ok(true) => Ok(HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .insert_header(ContentDisposition::attachment(entry_name))
+            .streaming(archive::stream_entry(filepath, kind, name))),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Entry not found in archive"
+        }))),
+        Err(e) => Ok(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "success": false,
+            "message": format!("Failed to read archive: {}", e)
+        }))),
+    }
+}
+
+// Expand an archive into a new folder next to it
+#[post("/archive/extract/{filename:.*}")]
+async fn extract_archive(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    let username = require_auth(&req, Scope::Write)?;
+    // The new folder goes next to the archive, so that folder must be writable
+    let (filepath, filename, kind) = match resolve_archive(&data, &username, &path.into_inner(), Access::ReadWrite) {
+        Ok(archive) => archive,
+        Err(response) => return Ok(response),
+    };
+
+    // Pick a folder name that doesn't exist yet: "photos", "photos-1", "photos-2", ...
+    let base_name = sanitize_filename(&ArchiveKind::folder_name(&display_name(&filename)));
+    let base_name = if base_name.is_empty() { "extracted".to_string() } else { base_name };
+    let parent = folder_of(&filename);
+    let mut folder = storage::join_relative(&parent, &base_name);
+    let mut counter = 1;
+    while resolve_relative(UPLOAD_DIR, &folder).is_none_or(|path| path.exists()) {
+        folder = storage::join_relative(&parent, &format!("{}-{}", base_name, counter));
+        counter += 1;
+    }
+
+    // Whatever the quota allows, extraction can't outgrow the disk itself
+    let used: u64 = walk_files(std::path::Path::new(UPLOAD_DIR)).iter().map(|file| file.size).sum();
+    let (disk_free, _) = get_disk_space(UPLOAD_DIR);
+    let budget = MAX_STORAGE_SIZE.saturating_sub(used).min(disk_free);
+    let Some(destination) = resolve_relative(UPLOAD_DIR, &folder) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "message": "Invalid destination folder"
+        })));
+    };
+    let target_folder = folder.clone();
+
+    // Entries are unpacked and checked away from the store; the folder only appears once
+    // everything in it has passed
+    let staging = std::path::Path::new(INCOMING_DIR).join(Uuid::new_v4().to_string());
+    let staging_clone = staging.clone();
+    let extracted = match web::block(move || archive::extract(&filepath, kind, &staging_clone, &target_folder, budget, MAX_EXTRACTED_FILES)).await? {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            warn!("Failed to extract {}: {}", filename, e);
+            return Ok(HttpResponse::UnprocessableEntity().json(UploadResponse {
+                success: false,
+                message: format!("Failed to extract archive: {}", e),
+                files: vec![],
+                scanned: vec![],
+            }));
+        }
+    };
+
+    // An archive can't bring in anything an upload into the new folder would refuse
+    let rules = data.rules.clone();
+    let (mut extracted, refusal) = web::block(move || {
+        let refusal = extracted.iter().find_map(|file| {
+            check_rules(&rules, &file.location, &display_name(&file.path), &folder_of(&file.path), file.size).err()
+        });
+        (extracted, refusal)
+    })
+    .await?;
+    if let Some(reason) = refusal {
+        warn!(user = %username, "Extraction of {} refused: {}", filename, reason);
+        let _ = std::fs::remove_dir_all(&staging);
+        data.audit.record(Some(&username), "extract", client_ip(&req), Some(&filename), false, Some(reason.clone()));
+        return Ok(HttpResponse::BadRequest().json(UploadResponse {
+            success: false,
+            message: format!("Nothing was extracted: {}", reason),
+            files: vec![],
+            scanned: vec![],
+        }));
+    }
+
+    let mut scanned = Vec::new();
+    if let Some(scanner) = data.scanner.clone() {
+        let mut clean = Vec::new();
+        for file in extracted {
+            let (scanner, scan_path) = (scanner.clone(), file.location.clone());
+            match web::block(move || scanner.scan(&scan_path)).await? {
+                Ok(Verdict::Clean) => {
+                    scanned.push(ScanResult {
+                        name: display_name(&file.path),
+                        path: file.path.clone(),
+                        infected: false,
+                        signature: None,
+                        quarantined_as: None,
+                    });
+                    clean.push(file);
+                }
+                Ok(Verdict::Infected(signature)) => {
+                    warn!(user = %username, "{} in {} is infected with {}", file.path, filename, signature);
+                    scanned.push(quarantine_file(&req, &data, &username, &file.location, &file.path, signature).await?);
+                }
+                Err(e) => {
+                    error!("Failed to scan {}: {}", file.path, e);
+                    let _ = std::fs::remove_dir_all(&staging);
+                    data.audit.record(Some(&username), "extract", client_ip(&req), Some(&filename), false, Some(e.to_string()));
+                    let status = match e {
+                        ScanError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+                        ScanError::Refused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                    };
+                    scanned.retain(|result| result.infected);
+                    return Ok(HttpResponse::build(status).json(UploadResponse {
+                        success: false,
+                        message: format!("Nothing was extracted: {} could not be scanned: {}", file.path, e),
+                        files: vec![],
+                        scanned,
+                    }));
+                }
+            }
+        }
+        extracted = clean;
+    }
 
-            let (file_type, can_preview) = get_file_type_and_preview(&sanitized_filename);
-            println!("File type: {}, can_preview: {}", file_type, can_preview);
+    let infected = scanned.iter().filter(|result| result.infected).count();
+    if extracted.is_empty() && infected > 0 {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Ok(HttpResponse::UnprocessableEntity().json(UploadResponse {
+            success: false,
+            message: format!("{} infected file(s) quarantined, nothing extracted", infected),
+            files: vec![],
+            scanned,
+        }));
+    }
+    let extracted = web::block(move || archive::install(&staging, &destination, &extracted).map(|_| extracted))
+        .await?
+        .map_err(|e| {
+            error!("Failed to move extracted files into {}: {}", folder, e);
+            actix_web::error::ErrorInternalServerError(format!("Failed to store extracted files: {}", e))
+        })?;
 
-            uploaded_files.push(FileInfo {
-                name: sanitized_filename.clone(),
-                size: file_size as u64,
-                path: unique_filename.clone(),
+    data.metadata
+        .insert_many(
+            extracted
+                .iter()
+                .map(|file| (file.path.clone(), FileMeta {
+                    sha256: Some(file.sha256.clone()),
+                    uploaded_at: Some(unix_now()),
+                    ..Default::default()
+                }))
+                .collect(),
+        )
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to store file metadata: {}", e)))?;
+    claim_folder(&data, &folder, &username);
+
+    let files: Vec<FileInfo> = extracted
+        .into_iter()
+        .map(|file| {
+            let name = display_name(&file.path);
+            let (file_type, can_preview) = get_file_type_and_preview(&name);
+            FileInfo {
+                name,
+                folder: folder_of(&file.path),
+                path: file.path,
+                size: file.size,
                 file_type,
                 can_preview,
-            });
+                sha256: Some(file.sha256),
+                tags: vec![],
+                properties: Default::default(),
+                modified: unix_now(),
+                uploaded_at: Some(unix_now()),
+                last_accessed: None,
+                starred: false,
+            }
+        })
+        .collect();
+    info!("Extracted {} file(s) into {}", files.len(), folder);
+    data.activity.record(&username, "extract", &filename, Some(format!("{} file(s) into {}/", files.len(), folder)));
+    data.audit.record(
+        Some(&username),
+        "extract",
+        client_ip(&req),
+        Some(&filename),
+        true,
+        Some(format!("{} file(s) into {}/", files.len(), folder)),
+    );
+    update_search_index(&data, files.iter().map(|file| file.path.clone()).collect(), vec![]);
 
-            file_count += 1;
-        } else {
-            println!("No filename found in field");
-        }
+    let mut message = format!("Extracted {} file(s) into {}/", files.len(), folder);
+    if infected > 0 {
+        message.push_str(&format!(", quarantined {} infected file(s)", infected));
     }
+    Ok(HttpResponse::Ok().json(UploadResponse {
+        success: true,
+        message,
+        files,
+        scanned,
+    }))
+}
 
-    println!("Upload complete: {} files", uploaded_files.len());
-
-    if uploaded_files.is_empty() {
-        println!("No files were uploaded");
-        let response = HttpResponse::BadRequest().json(UploadResponse {
-            success: false,
-            message: "No files were uploaded".to_string(),
-            files: vec![],
-        });
-        println!("Sending response: {:?}", response);
-        Ok(response)
-    } else {
-        println!("Successfully uploaded {} file(s)", uploaded_files.len());
-        let response = HttpResponse::Ok().json(UploadResponse {
-            success: true,
-            message: format!("Successfully uploaded {} file(s)", uploaded_files.len()),
-            files: uploaded_files,
-        });
-        println!("Sending success response");
-        Ok(response)
-    }
+#[derive(Deserialize)]
+struct AuditQuery {
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    action: Option<String>,
+    // YYYY-MM-DD or Unix timestamps
+    #[serde(default)]
+    since: Option<String>,
+    #[serde(default)]
+    until: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
 }
 
-// List all uploaded files
-#[get("/files")]
-async fn list_files(session: actix_session::Session) -> ActixResult<HttpResponse> {
-    println!("=== FILES REQUEST RECEIVED ===");
-    require_auth(&session)?;
-    let mut files = Vec::new();
+// Search the audit log, newest first
+#[get("/admin/audit")]
+async fn query_audit(
+    query: web::Query<AuditQuery>,
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> ActixResult<HttpResponse> {
+    require_auth(&req, Scope::Admin)?;
+    let AuditQuery { user, action, since, until, limit } = query.into_inner();
 
-    if let Ok(entries) = std::fs::read_dir(UPLOAD_DIR) {
-        for entry in entries.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                if metadata.is_file() {
-                    let filename = entry.file_name().to_string_lossy().to_string();
-                    
-                    // Extract original filename (remove UUID prefix)
-                    let display_name = if let Some(pos) = filename.find('_') {
-                        filename[pos + 1..].to_string()
-                    } else {
-                        filename.clone()
-                    };
+    let parse = |value: Option<String>, end_of_day: bool| value.map(|value| listing::parse_time(&value, end_of_day)).transpose();
+    let (since, until) = match (parse(since, false), parse(until, true)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(message), _) | (_, Err(message)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "success": false,
+                "message": message
+            })));
+        }
+    };
 
-                    let (file_type, can_preview) = get_file_type_and_preview(&display_name);
+    let filter = AuditFilter { actor: user, action, since, until };
+    let limit = limit.unwrap_or(100).clamp(1, MAX_AUDIT_ENTRIES);
+    let audit = data.audit.clone();
+    let entries = web::block(move || audit.query(&filter, limit))
+        .await?
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to read audit log: {}", e)))?;
 
-                    files.push(FileInfo {
-                        name: display_name,
-                        size: metadata.len(),
-                        path: filename,
-                        file_type,
-                        can_preview,
-                    });
-                }
-            }
-        }
+    Ok(HttpResponse::Ok().json(AuditResponse { entries }))
+}
+
+// Prometheus scrape endpoint, only for the configured bearer token. Without a token there is
+// no endpoint: behind a reverse proxy on the same machine every client looks local, so the
+// address can't tell a scraper apart. Storage figures are measured at most once a minute.
+#[get("/metrics")]
+async fn export_metrics(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let Some(token) = data.metrics.token() else {
+        return Ok(HttpResponse::NotFound().body("Metrics are off. Start cratr with --metrics-token to turn them on"));
+    };
+    let allowed = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|sent| same_secret(token, sent.trim()));
+    if !allowed {
+        warn!(client = ?client_ip(&req), "Metrics scrape refused");
+        return Ok(HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .body("Metrics need the configured bearer token"));
     }
 
-    // Sort files by name
-    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let metrics = data.metrics.clone();
+    let body = web::block(move || {
+        metrics.refresh_storage(|| {
+            let stored_files = walk_files(std::path::Path::new(UPLOAD_DIR));
+            let (disk_free, disk_total) = get_disk_space(UPLOAD_DIR);
+            StorageSample {
+                files: stored_files.len() as u64,
+                used_bytes: stored_files.iter().map(|file| file.size).sum(),
+                disk_free_bytes: disk_free,
+                disk_total_bytes: disk_total,
+            }
+        });
+        metrics.render()
+    })
+    .await?
+    .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Failed to encode metrics: {}", e)))?;
 
-    Ok(HttpResponse::Ok().json(FileListResponse { files }))
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
 }
 
-// Delete a file
-#[post("/delete/{filename}")]
-async fn delete_file(path: web::Path<String>, session: actix_session::Session) -> ActixResult<HttpResponse> {
-    require_auth(&session)?;
-    let filename = path.into_inner();
-    let filepath = PathBuf::from(UPLOAD_DIR).join(&filename);
+// Re-hash the whole store and report any files whose bytes no longer match their checksum
+#[post("/admin/scrub")]
+async fn scrub_store(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    require_auth(&req, Scope::Admin)?;
 
-    match std::fs::remove_file(&filepath) {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "message": "File deleted successfully"
-        }))),
-        Err(_) => Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "success": false,
-            "message": "File not found"
-        }))),
-    }
+    let metadata = data.metadata.clone();
+    let report = web::block(move || integrity::scrub(UPLOAD_DIR, &metadata)).await?;
+    info!(
+        "Scrub complete: {} checked, {} corrupted, {} missing",
+        report.checked,
+        report.corrupted.len(),
+        report.missing.len()
+    );
+
+    Ok(HttpResponse::Ok().json(report))
 }
 
-// Preview text/code files
-#[get("/preview/{filename}")]
-async fn preview_file(path: web::Path<String>) -> ActixResult<HttpResponse> {
-    let filename = path.into_inner();
-    let filepath = PathBuf::from(UPLOAD_DIR).join(&filename);
-    
-    // Get original filename for type checking
-    let display_name = if let Some(pos) = filename.find('_') {
-        filename[pos + 1..].to_string()
-    } else {
-        filename.clone()
+// Move an infected file into quarantine. The audit log keeps where it came from and what
+// was found; if the move fails the file is deleted instead.
+async fn quarantine_file(
+    req: &HttpRequest,
+    data: &AppState,
+    actor: &str,
+    source: &std::path::Path,
+    relative: &str,
+    signature: String,
+) -> ActixResult<ScanResult> {
+    let (source_path, moved_path) = (source.to_path_buf(), relative.to_string());
+    let quarantined_as = match web::block(move || scan::quarantine(&source_path, &moved_path, QUARANTINE_DIR)).await? {
+        Ok(name) => Some(name),
+        Err(e) => {
+            error!("Failed to quarantine {}, deleting it: {}", relative, e);
+            let _ = std::fs::remove_file(source);
+            None
+        }
     };
-    
-    let (file_type, can_preview) = get_file_type_and_preview(&display_name);
-    
-    if !can_preview || (file_type != "text" && file_type != "code") {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "File cannot be previewed as text"
+    let detail = match &quarantined_as {
+        Some(name) => format!("{}; quarantined as {}", signature, name),
+        None => format!("{}; deleted", signature),
+    };
+    data.audit.record(Some(actor), "quarantine", client_ip(req), Some(relative), true, Some(detail));
+
+    Ok(ScanResult {
+        name: display_name(relative),
+        path: relative.to_string(),
+        infected: true,
+        signature: Some(signature),
+        quarantined_as,
+    })
+}
+
+// Scan every stored file again, e.g. after the virus definitions were updated, and
+// quarantine whatever is found now
+#[post("/admin/rescan")]
+async fn rescan_store(req: HttpRequest, data: web::Data<AppState>) -> ActixResult<HttpResponse> {
+    let admin = require_auth(&req, Scope::Admin)?;
+    let Some(scanner) = data.scanner.clone() else {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "success": false,
+            "message": "Virus scanning is off; start cratr with --clamd"
         })));
-    }
-    
-    match std::fs::read_to_string(&filepath) {
-        Ok(content) => {
-            // Limit content size for preview (first 10KB)
-            let preview_content = if content.len() > 10240 {
-                format!("{}...\n\n[Content truncated - showing first 10KB of {}]", 
-                    &content[..10240], display_name)
-            } else {
-                content
-            };
-            
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "content": preview_content,
-                "type": file_type,
-                "filename": display_name
-            })))
+    };
+
+    let mut report = RescanReport::default();
+    let mut removed = Vec::new();
+    for stored in walk_files(std::path::Path::new(UPLOAD_DIR)) {
+        let Some(path) = resolve_relative(UPLOAD_DIR, &stored.path) else {
+            continue;
+        };
+        let (scanner, scan_path) = (scanner.clone(), path.clone());
+        match web::block(move || scanner.scan(&scan_path)).await? {
+            Ok(Verdict::Clean) => report.clean += 1,
+            Ok(Verdict::Infected(signature)) => {
+                warn!(user = %admin, "Stored file {} is infected with {}", stored.path, signature);
+                report.infected.push(quarantine_file(&req, &data, &admin, &path, &stored.path, signature).await?);
+                storage::remove_empty_parents(UPLOAD_DIR, &stored.path);
+                if let Err(e) = data.metadata.remove(&stored.path) {
+                    error!("Failed to remove metadata for {}: {}", stored.path, e);
+                }
+                removed.push(stored.path);
+            }
+            Err(ScanError::Refused(error)) => report.failed.push(ScanFailure { path: stored.path, error }),
+            // Without the scanner every other file would fail the same way
+            Err(e) => {
+                error!("Rescan stopped after {} file(s): {}", report.scanned, e);
+                forget_removed_folders(&data);
+                update_search_index(&data, vec![], removed);
+                return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "success": false,
+                    "message": format!("Rescan stopped after {} file(s), {} quarantined: {}", report.scanned, report.infected.len(), e)
+                })));
+            }
         }
-        Err(_) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to read file"
-        })))
+        report.scanned += 1;
     }
+    forget_removed_folders(&data);
+    update_search_index(&data, vec![], removed);
+
+    info!(
+        "Rescan complete: {} scanned, {} infected, {} failed",
+        report.scanned,
+        report.infected.len(),
+        report.failed.len()
+    );
+    let summary = format!("{} scanned, {} infected, {} failed", report.scanned, report.infected.len(), report.failed.len());
+    data.audit.record(Some(&admin), "rescan", client_ip(&req), None, true, Some(summary));
+    Ok(HttpResponse::Ok().json(report))
 }
 
 fn get_disk_space(path: &str) -> (u64, u64) {
@@ -487,16 +3257,6 @@ fn format_bytes(bytes: u64) -> String {
     }
 }
 
-fn sanitize_filename(filename: &str) -> String {
-    // Remove path separators and other potentially dangerous characters
-    filename
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '-' || *c == '_')
-        .collect::<String>()
-        .trim_start_matches('.')
-        .to_string()
-}
-
 fn get_file_type_and_preview(filename: &str) -> (String, bool) {
     let extension = filename
         .rfind('.')
@@ -525,38 +3285,256 @@ fn get_file_type_and_preview(filename: &str) -> (String, bool) {
     }
 }
 
+// `cratr scrub`: check the store from the command line and exit non-zero on bitrot
+fn run_scrub(metadata: &MetadataStore) -> std::io::Result<()> {
+    println!("Scrubbing {}...", UPLOAD_DIR);
+    let report = integrity::scrub(UPLOAD_DIR, metadata);
+
+    for issue in &report.corrupted {
+        match (&issue.actual, &issue.error) {
+            (Some(actual), _) => println!("CORRUPTED {}: expected {}, got {}", issue.path, issue.expected, actual),
+            (None, Some(error)) => println!("UNREADABLE {}: {}", issue.path, error),
+            (None, None) => println!("CORRUPTED {}", issue.path),
+        }
+    }
+    for path in &report.missing {
+        println!("MISSING {}", path);
+    }
+    for path in &report.unhashed {
+        println!("NO CHECKSUM {}", path);
+    }
+
+    println!(
+        "Checked {} file(s): {} ok, {} corrupted, {} missing, {} without checksum",
+        report.checked,
+        report.ok,
+        report.corrupted.len(),
+        report.missing.len(),
+        report.unhashed.len()
+    );
+
+    if report.is_clean() {
+        Ok(())
+    } else {
+        std::process::exit(1);
+    }
+}
+
+// The default password is only acceptable while nothing but this machine can reach the
+// server. Anywhere else, refuse to start unless explicitly told otherwise.
+fn check_default_password(args: &Args) -> std::io::Result<()> {
+    if args.password != DEFAULT_PASSWORD {
+        return Ok(());
+    }
+
+    let loopback_only = std::net::ToSocketAddrs::to_socket_addrs(args.bind.as_str())
+        .map(|mut addrs| addrs.all(|addr| addr.ip().is_loopback()))
+        .unwrap_or(false);
+    if loopback_only {
+        warn!("The default password is in use. Set --password or CRATR_PASSWORD before exposing this server");
+        Ok(())
+    } else if args.allow_default_password {
+        warn!("!!! The default password is in use while listening on {}. Anyone who can reach this address can log in !!!", args.bind);
+        Ok(())
+    } else {
+        error!("Refusing to listen on {} with the default password. Set --password or CRATR_PASSWORD, or pass --allow-default-password", args.bind);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "default password on a non-loopback address",
+        ))
+    }
+}
+
+// Send all logs, including those of actix and other crates using `log`, to stdout
+fn init_logging(args: &Args) {
+    let directives = args.log_level.clone()
+        .or_else(|| std::env::var(EnvFilter::DEFAULT_ENV).ok())
+        .unwrap_or_else(|| if args.debug { "debug" } else { "info" }.to_string());
+    let mut filter = EnvFilter::new(&directives);
+    // The request span carries the request ID; keep it even when the level is raised to warn
+    if !directives.contains("tracing_actix_web") {
+        filter = filter.add_directive("tracing_actix_web=info".parse().unwrap());
+    }
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match args.log_format {
+        LogFormat::Text => builder.init(),
+        // Flatten event fields and include the request span (request_id, method, path, ...)
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).with_span_list(false).init(),
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let args = Args::parse();
-    
-    env_logger::init();
 
-    // Create uploads directory if it doesn't exist
+    init_logging(&args);
+
+    // Create uploads and data directories if they don't exist
     create_dir_all(UPLOAD_DIR)?;
+    create_dir_all(DATA_DIR)?;
+    // Anything still staged was left by an upload that never finished
+    if std::path::Path::new(INCOMING_DIR).exists() {
+        std::fs::remove_dir_all(INCOMING_DIR)?;
+    }
+    create_dir_all(INCOMING_DIR)?;
+
+    let metadata = Arc::new(MetadataStore::open(METADATA_FILE)?);
+
+    if let Some(Command::Scrub) = args.command {
+        return run_scrub(&metadata);
+    }
+
+    check_default_password(&args)?;
+
+    info!("Starting file server at http://{}", args.bind);
+    info!("Upload directory: {}", UPLOAD_DIR);
 
-    println!("Starting file server at http://127.0.0.1:8080");
-    println!("Upload directory: {}", UPLOAD_DIR);
-    
     if args.debug {
-        println!("Debug mode enabled");
+        info!("Debug mode enabled");
+    }
+
+    let search = Arc::new(
+        SearchIndex::open(INDEX_DIR)
+            .map_err(|e| std::io::Error::other(format!("Failed to open search index: {}", e)))?,
+    );
+
+    // Catch up on anything that changed while the server was down
+    let startup_search = search.clone();
+    tokio::task::spawn_blocking(move || match startup_search.sync(UPLOAD_DIR) {
+        Ok((indexed, removed)) => info!("Search index ready: {} file(s) indexed, {} removed", indexed, removed),
+        Err(e) => error!("Failed to sync search index: {}", e),
+    });
+
+    let activity = Arc::new(ActivityLog::open(ACTIVITY_FILE)?);
+    let audit = Arc::new(AuditLog::open(AUDIT_FILE)?);
+    let metrics = Arc::new(
+        Metrics::new(MAX_STORAGE_SIZE, &args.metrics)
+            .map_err(|e| std::io::Error::other(format!("Failed to set up metrics: {}", e)))?,
+    );
+
+    // Local accounts are checked first, then the directory if one is configured
+    let users = Arc::new(UserStore::open(USERS_FILE)?);
+    let mut providers: Vec<Box<dyn AuthProvider>> =
+        vec![Box::new(LocalProvider::new(&args.username, &args.password, users.clone()))];
+    if let Some(ldap) = LdapProvider::from_args(&args.ldap).map_err(std::io::Error::other)? {
+        info!("Checking passwords against LDAP server {}", ldap.url());
+        providers.push(Box::new(ldap));
+    }
+    let auth = Arc::new(Providers::new(providers));
+    info!("Login providers: {}", auth.names().join(", "));
+    let oidc = OidcClient::from_args(&args.oidc).map_err(std::io::Error::other)?.map(Arc::new);
+    if let Some(oidc) = &oidc {
+        info!("Offering single sign-on with {}", oidc.issuer());
+    }
+    let proxy = ProxyAuth::from_args(&args.proxy).map_err(std::io::Error::other)?.map(Arc::new);
+    if let Some(proxy) = &proxy {
+        if oidc.is_some() {
+            return Err(std::io::Error::other("--trusted-proxy can't be combined with --oidc-issuer"));
+        }
+        info!("Taking users from the {} header of trusted proxies; the login form is off", proxy.user_header());
+    }
+
+    let content = ContentOrigin::from_args(&args.content).map_err(std::io::Error::other)?.map(Arc::new);
+    if let Some(content) = &content {
+        info!("Serving downloads from {}", content.origin());
     }
+    let scanner = Scanner::from_args(&args.scan).map(Arc::new);
+    if let Some(scanner) = &scanner {
+        info!("Scanning uploads with clamd at {}", scanner.address());
+    }
+    let rules = Arc::new(UploadRules::from_args(&args.rules));
+    if !rules.is_empty() {
+        info!("Filtering uploads by type, extension, name and size");
+    }
+    let shell_policy = headers::shell_policy(INDEX_HTML, content.as_ref().map(|content| content.origin()));
+
+    let sessions = Arc::new(SessionStore::open(SESSIONS_FILE, args.session_lifetime * 60 * 60)?);
+    sessions.flush_every(SESSION_FLUSH_INTERVAL);
+    let idle_timeout = actix_web::cookie::time::Duration::minutes(args.session_idle_timeout as i64);
 
     let app_state = AppState {
         debug_mode: args.debug,
+        username: args.username.clone(),
+        login_throttle: Arc::new(LoginThrottle::default()),
+        auth,
+        oidc,
+        proxy,
+        users,
+        tokens: Arc::new(TokenStore::open(TOKENS_FILE)?),
+        sessions: sessions.clone(),
+        shares: Arc::new(ShareStore::open(SHARES_FILE)?),
+        metadata,
+        search,
+        activity,
+        audit,
+        metrics,
+        scanner,
+        rules,
+        shell_policy,
+        content,
     };
 
-    HttpServer::new(move || {
+    let stored_sessions = sessions.clone();
+    let mut server = HttpServer::new(move || {
         // Use a fixed secret key for development (in production, use a persistent secret from env)
         let secret_key = Key::from(&[0; 64]); // Fixed key for development
         
         App::new()
             .app_data(web::Data::new(app_state.clone()))
-            .wrap(Logger::default())
+            // The content origin serves downloads and nothing else, so a file opened there
+            // can't read the API with the user's cookie
+            .wrap_fn(|req, srv| {
+                let content = req.app_data::<web::Data<AppState>>().and_then(|data| data.content.clone());
+                let refused = content.is_some_and(|content| content.is_content_request(&req))
+                    && !req.path().starts_with("/download/");
+                let response = if refused {
+                    Err(req.error_response(actix_web::error::ErrorNotFound("Not found")))
+                } else {
+                    Ok(srv.call(req))
+                };
+                async move {
+                    match response {
+                        Ok(response) => response.await.map(ServiceResponse::map_into_left_body),
+                        Err(refusal) => Ok(refusal.map_into_right_body()),
+                    }
+                }
+            })
+            // Registered before (so run after) the proxy login below, which may start the session
+            .wrap_fn(|req, srv| {
+                let response = match check_csrf(req.request()) {
+                    Ok(()) => Ok(srv.call(req)),
+                    Err(e) => Err(req.error_response(e)),
+                };
+                async move {
+                    match response {
+                        Ok(response) => response.await.map(ServiceResponse::map_into_left_body),
+                        Err(refusal) => Ok(refusal.map_into_right_body()),
+                    }
+                }
+            })
+            // Behind an authenticating proxy the session follows its user header. This runs
+            // inside the session middleware so the session is available.
+            .wrap_fn(|req, srv| {
+                if let Some(data) = req.app_data::<web::Data<AppState>>() {
+                    if let Some(proxy) = &data.proxy {
+                        proxy_login(req.request(), data, proxy);
+                    }
+                }
+                note_session_ip(req.request());
+                srv.call(req)
+            })
             .wrap(
                 SessionMiddleware::builder(
-                    CookieSessionStore::default(),
+                    SessionBackend(sessions.clone()),
                     secret_key,
                 )
+                // Every request pushes the idle timeout back; the store enforces the lifetime
+                .session_lifecycle(
+                    BrowserSession::default()
+                        .state_ttl(idle_timeout)
+                        .state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+                )
                 .cookie_secure(false) // Set to true in production with HTTPS
                 .cookie_http_only(true)
                 .cookie_same_site(actix_web::cookie::SameSite::Lax)
@@ -564,22 +3542,575 @@ async fn main() -> std::io::Result<()> {
                 .build(),
             )
             .wrap(IdentityMiddleware::default())
+            .wrap(
+                DefaultHeaders::new()
+                    .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+                    .add((header::REFERRER_POLICY, "same-origin")),
+            )
+            // Registered last so they run outermost and see every response, including the
+            // refusals of the middlewares above
+            // One access line per request, tagged with the ID TracingLogger assigned
+            .wrap(
+                Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{request_id}xi"#)
+                    .custom_request_replace("request_id", |req| {
+                        req.extensions().get::<RequestId>().map(|id| id.to_string()).unwrap_or_default()
+                    }),
+            )
+            .wrap(TracingLogger::default())
+            // Count and time every request under its route pattern
+            .wrap_fn(|req, srv| {
+                let data = req.app_data::<web::Data<AppState>>().cloned();
+                let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+                let method = req.method().to_string();
+                let started = std::time::Instant::now();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    if let Some(data) = data {
+                        let status = response.status().as_u16();
+                        data.metrics.observe_request(&route, &method, status, started.elapsed().as_secs_f64());
+                    }
+                    Ok(response)
+                }
+            })
             .service(index)
             .service(get_debug_info)
             .service(login)
             .service(logout)
+            .service(login_second_factor)
+            .service(oidc_login)
+            .service(oidc_callback)
+            .service(two_factor_status)
+            .service(enroll_two_factor)
+            .service(confirm_two_factor)
+            .service(disable_two_factor)
+            .service(regenerate_recovery_codes)
+            .service(set_two_factor_policy)
+            .service(list_tokens)
+            .service(create_token)
+            .service(revoke_token)
+            .service(list_sessions)
+            .service(revoke_session)
+            .service(revoke_other_sessions)
+            .service(list_users)
+            .service(create_user)
+            .service(update_user)
+            .service(delete_user)
+            .service(list_shares)
+            .service(get_share)
+            .service(update_share)
+            .service(list_groups)
+            .service(update_group)
+            .service(delete_group)
             .service(auth_status)
             .service(upload_files)
             .service(list_files)
             .service(get_storage_info)
             .service(delete_file)
             .service(preview_file)
-            // Serve uploaded files for download
-            .service(fs::Files::new("/download", UPLOAD_DIR).show_files_listing())
+            .service(search_files)
+            .service(batch_delete)
+            .service(batch_move)
+            .service(batch_copy)
+            .service(batch_tag)
+            .service(update_file_meta)
+            .service(list_tags)
+            .service(star_file)
+            .service(list_activity)
+            .service(download_archive)
+            .service(list_archive_entries)
+            .service(get_archive_entry)
+            .service(extract_archive)
+            .service(scrub_store)
+            .service(rescan_store)
+            .service(query_audit)
+            .service(export_metrics)
+            // Serve uploaded files for download, noting when each file was last fetched
+            .service(
+                web::scope("/download")
+                    .wrap_fn(|req, srv| {
+                        let data = req.app_data::<web::Data<AppState>>().cloned();
+                        // With a content origin, what could run script is never served from the app's own
+                        let content = data.as_ref().and_then(|data| data.content.clone()).filter(|content| !content.is_content_request(&req));
+                        let path_and_query = req.uri().path_and_query().map(|path| path.to_string()).unwrap_or_default();
+                        let request_path = req.path().to_string();
+                        let ip = client_ip(req.request());
+                        let force_attachment = req.query_string().split('&').any(|pair| pair.split('=').next() == Some("download"));
+                        let (actor, response) = match download_user(req.request(), &request_path) {
+                            Ok(username) => (Some(username), Ok(srv.call(req))),
+                            Err(e) => (None, Err(e)),
+                        };
+                        async move {
+                            let mut response = response?.await?;
+                            let relocated = content
+                                .filter(|_| response.status().is_success())
+                                .and_then(|content| content.relocate(&path_and_query, response.headers()));
+                            if let Some(location) = relocated {
+                                let redirect = HttpResponse::TemporaryRedirect().insert_header((header::LOCATION, location)).finish();
+                                return Ok(response.into_response(redirect));
+                            }
+                            headers::harden_download(response.headers_mut(), force_attachment);
+                            if let (Some(data), true) = (data, response.status().is_success()) {
+                                if let BodySize::Sized(size) = response.response().body().size() {
+                                    data.metrics.downloaded_bytes.inc_by(size);
+                                }
+                                record_download(&data, &request_path, actor, ip);
+                            }
+                            Ok(response)
+                        }
+                    })
+                    .service(fs::Files::new("", UPLOAD_DIR)),
+            )
             // Serve static files (CSS, JS)
             .service(fs::Files::new("/static", "./static"))
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .bind(&args.bind)?;
+    if let Some(content_bind) = &args.content.content_bind {
+        server = server.bind(content_bind)?;
+    }
+    let result = server.run().await;
+    if let Err(e) = stored_sessions.close() {
+        error!("Failed to store sessions: {}", e);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_session::storage::CookieSessionStore;
+    use actix_web::{test, App};
+
+    // App state with every store in a scratch directory, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+        state: AppState,
+    }
+
+    impl Scratch {
+        // `args` as given on the command line; `directory` is asked after local accounts
+        fn new(args: &[&str], directory: Option<Box<dyn AuthProvider>>) -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-main-{}", Uuid::new_v4().simple()));
+            create_dir_all(&base).unwrap();
+            let args = Args::parse_from(["cratr", "--username", "admin", "--password", "local-pw"].iter().chain(args));
+            let users = Arc::new(UserStore::open(base.join("users.json")).unwrap());
+            let mut providers: Vec<Box<dyn AuthProvider>> =
+                vec![Box::new(LocalProvider::new(&args.username, &args.password, users.clone()))];
+            providers.extend(directory);
+            let state = AppState {
+                debug_mode: false,
+                username: args.username.clone(),
+                login_throttle: Arc::new(LoginThrottle::default()),
+                auth: Arc::new(Providers::new(providers)),
+                oidc: None,
+                proxy: None,
+                users,
+                tokens: Arc::new(TokenStore::open(base.join("tokens.json")).unwrap()),
+                sessions: Arc::new(SessionStore::open(base.join("sessions.json"), 3600).unwrap()),
+                shares: Arc::new(ShareStore::open(base.join("shares.json")).unwrap()),
+                metadata: Arc::new(MetadataStore::open(base.join("metadata.json")).unwrap()),
+                search: Arc::new(SearchIndex::open(base.join("index")).unwrap()),
+                activity: Arc::new(ActivityLog::open(base.join("events.jsonl")).unwrap()),
+                audit: Arc::new(AuditLog::open(base.join("audit.jsonl")).unwrap()),
+                metrics: Arc::new(Metrics::new(MAX_STORAGE_SIZE, &args.metrics).unwrap()),
+                scanner: None,
+                rules: Arc::new(UploadRules::from_args(&args.rules)),
+                shell_policy: String::new(),
+                content: None,
+            };
+            Self { base, state }
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    // A directory that, like LDAP, matches names without case and has an "admin" entry
+    struct Directory;
+
+    impl AuthProvider for Directory {
+        fn name(&self) -> &'static str {
+            "ldap"
+        }
+
+        fn authenticate(&self, username: &str, password: &str) -> Result<Outcome, String> {
+            Ok(match (canonical_username(username).as_str(), password) {
+                ("admin", "directory-pw") | ("bob", "bob-directory-pw") | ("carol", "carol-pw") => Outcome::Accepted(Role::Viewer),
+                ("admin", _) | ("bob", _) | ("carol", _) => Outcome::Rejected,
+                _ => Outcome::Unknown,
+            })
+        }
+
+        fn canonical_username(&self, username: &str) -> String {
+            canonical_username(username)
+        }
+    }
+
+    // Status of a login attempt and the user its session ends up with
+    async fn log_in(state: &AppState, username: &str, password: &str) -> (u16, Option<String>) {
+        async fn whoami(session: actix_session::Session) -> HttpResponse {
+            HttpResponse::Ok().body(current_user(&session).unwrap_or_default())
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .wrap(SessionMiddleware::new(CookieSessionStore::default(), Key::generate()))
+                .service(login)
+                .route("/whoami", web::get().to(whoami)),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/login")
+            .peer_addr("192.0.2.1:4000".parse().unwrap())
+            .set_json(serde_json::json!({ "username": username, "password": password }));
+        let response = test::call_service(&app, request.to_request()).await;
+        let status = response.status().as_u16();
+        let Some(cookie) = response.response().cookies().next().map(|cookie| cookie.into_owned()) else {
+            return (status, None);
+        };
+        let whoami = test::call_and_read_body(&app, test::TestRequest::get().uri("/whoami").cookie(cookie).to_request()).await;
+        (status, Some(String::from_utf8(whoami.to_vec()).unwrap()).filter(|user| !user.is_empty()))
+    }
+
+    #[actix_web::test]
+    async fn a_directory_entry_named_like_the_admin_is_not_the_admin() {
+        let scratch = Scratch::new(&[], Some(Box::new(Directory)));
+        for typed in ["admin", "Admin", " admin", "ADMIN "] {
+            assert_eq!(log_in(&scratch.state, typed, "directory-pw").await, (401, None), "{:?}", typed);
+            scratch.state.login_throttle.succeeded("192.0.2.1", "admin");
+        }
+        assert_eq!(log_in(&scratch.state, "Admin", "local-pw").await, (200, Some("admin".to_string())));
+        assert_eq!(log_in(&scratch.state, "Carol", "carol-pw").await, (200, Some("carol".to_string())));
+        assert_eq!(scratch.state.users.role("admin"), None);
+    }
+
+    #[actix_web::test]
+    async fn directory_logins_are_refused_under_the_admins_name() {
+        // Even if a provider in front of the directory didn't catch the name
+        let mut scratch = Scratch::new(&[], None);
+        scratch.state.auth = Arc::new(Providers::new(vec![Box::new(Directory)]));
+        assert_eq!(log_in(&scratch.state, "Admin", "directory-pw").await, (403, None));
+        assert_eq!(scratch.state.users.role("admin"), None);
+    }
+
+    #[actix_web::test]
+    async fn a_directory_entry_named_like_a_local_account_is_refused() {
+        let mut scratch = Scratch::new(&[], None);
+        scratch.state.auth = Arc::new(Providers::new(vec![Box::new(Directory)]));
+        scratch.state.users.create_account("bob", "bob-local-pw", Role::Editor).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(log_in(&scratch.state, "bob", "bob-directory-pw").await, (409, None));
+        }
+        // Each one counted as a failed attempt, so the next has to wait
+        assert_eq!(log_in(&scratch.state, "bob", "bob-directory-pw").await.0, 429);
+        assert_eq!(scratch.state.users.role("bob"), Some(Role::Editor));
+
+        let logins = audit::AuditFilter { action: Some("login".to_string()), ..Default::default() };
+        let entries = scratch.state.audit.query(&logins, 10).unwrap();
+        assert!(entries.iter().all(|entry| !entry.success));
+        let conflicts = entries.iter().filter(|entry| entry.detail.as_deref().is_some_and(|detail| detail.contains("local account")));
+        assert_eq!(conflicts.count(), 3);
+    }
+
+    // Log in the way the real handlers do: a username and a CSRF token in the session
+    async fn start_session(session: actix_session::Session) -> ActixResult<HttpResponse> {
+        session.insert("username", "alice")?;
+        session.insert(CSRF_TOKEN, "expected-token")?;
+        Ok(HttpResponse::Ok().finish())
+    }
+
+    // Handlers keep files under ./uploads and ./data. Tests that reach them all run from one
+    // scratch directory, set up by whichever gets there first.
+    fn in_scratch_dir() {
+        static SCRATCH_DIR: std::sync::Once = std::sync::Once::new();
+        SCRATCH_DIR.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("cratr-cwd-{}", Uuid::new_v4().simple()));
+            create_dir_all(dir.join(UPLOAD_DIR)).unwrap();
+            create_dir_all(dir.join(INCOMING_DIR)).unwrap();
+            std::env::set_current_dir(dir).unwrap();
+        });
+    }
+
+    // A multipart/form-data body of (field name, file name, contents) parts
+    fn multipart(parts: &[(&str, Option<&str>, &[u8])]) -> (String, Vec<u8>) {
+        let boundary = "cratr-test-boundary";
+        let mut body = Vec::new();
+        for (name, filename, contents) in parts {
+            let filename = filename.map(|filename| format!("; filename=\"{}\"", filename)).unwrap_or_default();
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"{}\r\n\r\n", boundary, name, filename).bytes());
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", boundary).bytes());
+        (format!("multipart/form-data; boundary={}", boundary), body)
+    }
+
+    // Status of an upload by the admin, and how many files the store holds afterwards
+    async fn upload(scratch: &Scratch, parts: &[(&str, Option<&str>, &[u8])]) -> (u16, usize) {
+        in_scratch_dir();
+        let (_, secret) = scratch.state.tokens.create("admin", "test", &[Scope::Upload], None).unwrap();
+        let app = test::init_service(App::new().app_data(web::Data::new(scratch.state.clone())).service(upload_files)).await;
+        let (content_type, body) = multipart(parts);
+        let request = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body);
+        let status = test::call_service(&app, request.to_request()).await.status().as_u16();
+        (status, scratch.state.metadata.snapshot().len())
+    }
+
+    #[actix_web::test]
+    async fn batch_items_are_checked_against_their_own_folder() {
+        in_scratch_dir();
+        let scratch = Scratch::new(&[], None);
+        let data = &scratch.state;
+        data.users.create_account("alice", "alice-password", Role::Editor).unwrap();
+        let (_, secret) = data.tokens.create("alice", "test", &[Scope::Write, Scope::Delete], None).unwrap();
+
+        // Folders of alice's own, shared with her read-only and private to the admin. The
+        // working directory is shared with other tests, so the names are unique.
+        let prefix = Uuid::new_v4().simple().to_string();
+        let [own, shared, private] = ["own", "shared", "private"].map(|name| format!("{}-{}", prefix, name));
+        for folder in [&own, &shared, &private] {
+            create_dir_all(format!("{}/{}", UPLOAD_DIR, folder)).unwrap();
+            for file in ["a.txt", "b.txt"] {
+                std::fs::write(format!("{}/{}/{}", UPLOAD_DIR, folder, file), file).unwrap();
+            }
+        }
+        data.shares.claim(&own, "alice").unwrap();
+        let read_only = Grant { principal: Principal::User("alice".to_string()), access: Access::Read };
+        data.shares.set_grants(&shared, "admin", vec![read_only]).unwrap();
+        data.shares.set_grants(&private, "admin", vec![]).unwrap();
+
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(data.clone())).service(batch_delete).service(batch_move),
+        )
+        .await;
+        let post = |uri: &str, body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+                .set_json(body)
+                .to_request()
+        };
+        let exists = |path: &str| std::path::Path::new(UPLOAD_DIR).join(path).is_file();
+
+        let ids = [format!("{}/a.txt", own), format!("{}/a.txt", shared), format!("{}/a.txt", private), "../a.txt".to_string()];
+        let response: BatchResponse = test::call_and_read_body_json(&app, post("/batch/delete", serde_json::json!({ "ids": ids }))).await;
+        let results: Vec<(&str, bool, &str)> = response.results.iter()
+            .map(|result| (result.id.as_str(), result.success, result.message.as_str()))
+            .collect();
+        assert_eq!(results, [
+            (ids[0].as_str(), true, "Deleted"),
+            (ids[1].as_str(), false, "This folder is shared with you read-only"),
+            (ids[2].as_str(), false, "File not found"),
+            (ids[3].as_str(), false, "Invalid file path"),
+        ]);
+        assert!(!response.success);
+        assert!(!exists(&ids[0]) && exists(&ids[1]) && exists(&ids[2]));
+
+        // Moving out of a read-only folder fails per file; moving into one fails as a whole
+        let ids = [format!("{}/b.txt", shared), format!("{}/b.txt", private)];
+        let response: BatchResponse = test::call_and_read_body_json(&app, post("/batch/move", serde_json::json!({ "ids": ids, "destination": own }))).await;
+        assert!(response.results.iter().all(|result| !result.success));
+        assert!(exists(&ids[0]) && exists(&ids[1]));
+        let into_shared = post("/batch/move", serde_json::json!({ "ids": [format!("{}/b.txt", own)], "destination": shared }));
+        assert_eq!(test::call_service(&app, into_shared).await.status(), StatusCode::FORBIDDEN);
+        assert!(exists(&format!("{}/b.txt", own)));
+
+        for folder in [&own, &shared, &private] {
+            let _ = std::fs::remove_dir_all(format!("{}/{}", UPLOAD_DIR, folder));
+        }
+    }
+
+    #[actix_web::test]
+    async fn search_follows_access_deletes_and_moves() {
+        in_scratch_dir();
+        let scratch = Scratch::new(&[], None);
+        let data = &scratch.state;
+        data.users.create_account("alice", "alice-password", Role::Editor).unwrap();
+        let scopes = [Scope::Read, Scope::Write, Scope::Delete];
+        let (_, alice) = data.tokens.create("alice", "test", &scopes, None).unwrap();
+        let (_, admin) = data.tokens.create("admin", "test", &scopes, None).unwrap();
+
+        // A word only these files contain, since the working directory is shared with other tests
+        let word = format!("w{}", Uuid::new_v4().simple());
+        let [own, shared, private, archive] = ["own", "shared", "private", "archive"].map(|name| format!("{}-{}", word, name));
+        let mut added = Vec::new();
+        for folder in [&own, &shared, &private] {
+            create_dir_all(format!("{}/{}", UPLOAD_DIR, folder)).unwrap();
+            for file in ["a.txt", "b.txt"] {
+                std::fs::write(format!("{}/{}/{}", UPLOAD_DIR, folder, file), format!("{} {}", word, file)).unwrap();
+                added.push(format!("{}/{}", folder, file));
+            }
+        }
+        data.shares.claim(&own, "alice").unwrap();
+        let read_only = Grant { principal: Principal::User("alice".to_string()), access: Access::Read };
+        data.shares.set_grants(&shared, "admin", vec![read_only]).unwrap();
+        data.shares.set_grants(&private, "admin", vec![]).unwrap();
+        data.search.update(UPLOAD_DIR, &added, &[]).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(data.clone()))
+                .service(search_files)
+                .service(batch_delete)
+                .service(batch_move),
+        )
+        .await;
+        let search = |secret: &str| {
+            let request = test::TestRequest::get()
+                .uri(&format!("/search?q={}", word))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+                .to_request();
+            let app = &app;
+            async move {
+                let response: SearchResponse = test::call_and_read_body_json(app, request).await;
+                let mut paths: Vec<String> = response.hits.into_iter().map(|hit| hit.path).collect();
+                paths.sort();
+                paths
+            }
+        };
+        let paths = |files: &[(&String, &str)]| files.iter().map(|(folder, file)| format!("{}/{}", folder, file)).collect::<Vec<_>>();
+
+        let everything = paths(&[(&own, "a.txt"), (&own, "b.txt"), (&private, "a.txt"), (&private, "b.txt"), (&shared, "a.txt"), (&shared, "b.txt")]);
+        assert_eq!(search(&admin).await, everything);
+        assert_eq!(search(&alice).await, paths(&[(&own, "a.txt"), (&own, "b.txt"), (&shared, "a.txt"), (&shared, "b.txt")]));
+
+        let post = |uri: &str, body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", alice)))
+                .set_json(body)
+                .to_request()
+        };
+        test::call_service(&app, post("/batch/delete", serde_json::json!({ "ids": [format!("{}/b.txt", own)] }))).await;
+        let moved = serde_json::json!({ "ids": [format!("{}/a.txt", own)], "destination": archive });
+        test::call_service(&app, post("/batch/move", moved)).await;
+
+        // The handlers update the index in the background
+        let expected = paths(&[(&archive, "a.txt"), (&shared, "a.txt"), (&shared, "b.txt")]);
+        let mut found = Vec::new();
+        for _ in 0..50 {
+            found = search(&alice).await;
+            if found == expected {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(found, expected);
+
+        for folder in [&own, &shared, &private, &archive] {
+            let _ = std::fs::remove_dir_all(format!("{}/{}", UPLOAD_DIR, folder));
+        }
+    }
+
+    const CONTENTS: &[u8] = b"hello";
+    const CONTENTS_SHA256: &[u8] = b"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[actix_web::test]
+    async fn a_checksum_covers_the_file_after_it() {
+        let scratch = Scratch::new(&[], None);
+        assert_eq!(upload(&scratch, &[("sha256", None, CONTENTS_SHA256), ("file", Some("a.txt"), CONTENTS)]).await, (200, 1));
+        let wrong = [("sha256", None, &b"00"[..]), ("file", Some("b.txt"), CONTENTS)];
+        assert_eq!(upload(&scratch, &wrong).await, (400, 1));
+    }
+
+    #[actix_web::test]
+    async fn a_checksum_without_a_file_after_it_is_refused() {
+        let scratch = Scratch::new(&[], None);
+        // Sent after the file, where it would check nothing
+        let after = [("file", Some("a.txt"), CONTENTS), ("sha256", None, CONTENTS_SHA256)];
+        assert_eq!(upload(&scratch, &after).await, (400, 0));
+        let twice = [("sha256", None, CONTENTS_SHA256), ("sha256", None, CONTENTS_SHA256), ("file", Some("a.txt"), CONTENTS)];
+        assert_eq!(upload(&scratch, &twice).await, (400, 0));
+        assert_eq!(upload(&scratch, &[("sha256", None, CONTENTS_SHA256)]).await, (400, 0));
+    }
+
+    // Status of a /metrics scrape from `peer` with `authorization`, if any
+    async fn scrape(state: &AppState, peer: &str, authorization: Option<&str>) -> u16 {
+        let app = test::init_service(App::new().app_data(web::Data::new(state.clone())).service(export_metrics)).await;
+        let mut request = test::TestRequest::get().uri("/metrics").peer_addr(peer.parse().unwrap());
+        if let Some(authorization) = authorization {
+            request = request.insert_header((header::AUTHORIZATION, authorization));
+        }
+        test::call_service(&app, request.to_request()).await.status().as_u16()
+    }
+
+    #[actix_web::test]
+    async fn metrics_are_off_without_a_token() {
+        let scratch = Scratch::new(&[], None);
+        assert_eq!(scrape(&scratch.state, "203.0.113.9:4000", None).await, 404);
+        // What every request looks like behind a reverse proxy on the same machine
+        assert_eq!(scrape(&scratch.state, "127.0.0.1:4000", None).await, 404);
+    }
+
+    #[actix_web::test]
+    async fn metrics_need_the_token() {
+        let scratch = Scratch::new(&["--metrics-token", "scrape-secret"], None);
+        assert_eq!(scrape(&scratch.state, "203.0.113.9:4000", None).await, 401);
+        assert_eq!(scrape(&scratch.state, "127.0.0.1:4000", None).await, 401);
+        assert_eq!(scrape(&scratch.state, "203.0.113.9:4000", Some("Bearer guessed")).await, 401);
+        assert_eq!(scrape(&scratch.state, "203.0.113.9:4000", Some("Bearer scrape-secret")).await, 200);
+    }
+
+    // Runs `request` through the CSRF middleware with a logged-in session and returns the status
+    async fn csrf_status(request: test::TestRequest) -> u16 {
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let response = check_csrf(req.request()).map(|_| srv.call(req));
+                    async move { response?.await }
+                })
+                .wrap(SessionMiddleware::new(CookieSessionStore::default(), Key::generate()))
+                .route("/session", web::get().to(start_session))
+                .route("/change", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        let started = test::call_service(&app, test::TestRequest::get().uri("/session").to_request()).await;
+        let cookie = started.response().cookies().next().unwrap().into_owned();
+        match test::try_call_service(&app, request.uri("/change").cookie(cookie).to_request()).await {
+            Ok(response) => response.status().as_u16(),
+            Err(e) => e.error_response().status().as_u16(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn csrf_accepts_the_session_token() {
+        assert_eq!(csrf_status(test::TestRequest::post().insert_header((CSRF_HEADER, "expected-token"))).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn csrf_lets_api_tokens_through() {
+        let request = test::TestRequest::post().insert_header((header::AUTHORIZATION, "Bearer cratr_0123456789"));
+        assert_eq!(csrf_status(request).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn csrf_refuses_a_session_without_the_header() {
+        assert_eq!(csrf_status(test::TestRequest::post()).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn csrf_refuses_a_session_with_the_wrong_header() {
+        assert_eq!(csrf_status(test::TestRequest::post().insert_header((CSRF_HEADER, "guessed-token"))).await, 403);
+    }
+
+    #[actix_web::test]
+    async fn csrf_ignores_foreign_authorization_headers() {
+        // What a browser would attach by itself behind a Basic-auth proxy
+        for authorization in ["Basic YWxpY2U6c2VjcmV0", "Bearer not-a-cratr-token"] {
+            let request = test::TestRequest::post().insert_header((header::AUTHORIZATION, authorization));
+            assert_eq!(csrf_status(request).await, 403, "{}", authorization);
+        }
+        let request = test::TestRequest::post()
+            .insert_header((header::AUTHORIZATION, "Basic YWxpY2U6c2VjcmV0"))
+            .insert_header((CSRF_HEADER, "expected-token"));
+        assert_eq!(csrf_status(request).await, 200);
+    }
 }
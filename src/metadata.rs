@@ -0,0 +1,154 @@
+use crate::storage::{read_json_or_default, write_json_atomic};
+use cratr::FileMetaUpdate;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+// Everything the server knows about a stored file beyond what the filesystem tells us
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileMeta {
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    // Usernames that starred the file
+    #[serde(default)]
+    pub starred_by: Vec<String>,
+    // Seconds since the Unix epoch
+    #[serde(default)]
+    pub uploaded_at: Option<u64>,
+    #[serde(default)]
+    pub last_accessed: Option<u64>,
+}
+
+// Downloads within this many seconds of the last recorded one don't rewrite the store
+const ACCESS_RESOLUTION: u64 = 60;
+
+pub const MAX_PROPERTIES: usize = 50;
+pub const MAX_PROPERTY_KEY_LEN: usize = 64;
+pub const MAX_PROPERTY_VALUE_LEN: usize = 1024;
+
+// Tags are lowercase and limited to letters, digits, '-', '_' and '.'; spaces become '-'
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag: String = tag
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .take(64)
+        .collect();
+    (!tag.is_empty()).then_some(tag)
+}
+
+// Property keys are free text but trimmed, short and free of control characters
+pub fn normalize_property_key(key: &str) -> Option<String> {
+    let key = key.trim();
+    let valid = !key.is_empty()
+        && key.chars().count() <= MAX_PROPERTY_KEY_LEN
+        && !key.chars().any(char::is_control);
+    valid.then(|| key.to_string())
+}
+
+impl FileMeta {
+    // Apply a metadata update, refusing it as a whole if any key or value is unacceptable
+    pub fn apply(&mut self, update: &FileMetaUpdate) -> Result<(), String> {
+        let mut properties = self.properties.clone();
+        for (key, value) in &update.set {
+            let key = normalize_property_key(key).ok_or_else(|| format!("Invalid metadata key {:?}", key))?;
+            if value.chars().count() > MAX_PROPERTY_VALUE_LEN {
+                return Err(format!("Value for {:?} is longer than {} characters", key, MAX_PROPERTY_VALUE_LEN));
+            }
+            properties.insert(key, value.trim().to_string());
+        }
+        for key in &update.unset {
+            properties.remove(key.trim());
+        }
+        if properties.len() > MAX_PROPERTIES {
+            return Err(format!("A file can have at most {} metadata keys", MAX_PROPERTIES));
+        }
+
+        let remove: Vec<String> = update.remove_tags.iter().filter_map(|tag| normalize_tag(tag)).collect();
+        self.tags.retain(|tag| !remove.contains(tag));
+        self.tags.extend(update.add_tags.iter().filter_map(|tag| normalize_tag(tag)));
+        self.tags.sort();
+        self.tags.dedup();
+        self.properties = properties;
+        Ok(())
+    }
+}
+
+// Metadata for all stored files, keyed by path relative to the upload directory.
+// The whole map is kept in memory and rewritten to a single JSON file on every change.
+pub struct MetadataStore {
+    path: PathBuf,
+    entries: RwLock<HashMap<String, FileMeta>>,
+}
+
+impl MetadataStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries = read_json_or_default(&path)?;
+
+        Ok(Self {
+            path,
+            entries: RwLock::new(entries),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<FileMeta> {
+        self.entries.read().unwrap().get(key).cloned()
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, FileMeta> {
+        self.entries.read().unwrap().clone()
+    }
+
+    pub fn insert(&self, key: &str, meta: FileMeta) -> io::Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        entries.insert(key.to_string(), meta);
+        self.persist(&entries)
+    }
+
+    pub fn insert_many(&self, items: Vec<(String, FileMeta)>) -> io::Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        entries.extend(items);
+        self.persist(&entries)
+    }
+
+    // Apply several changes at once and write the store a single time
+    pub fn edit<R>(&self, f: impl FnOnce(&mut HashMap<String, FileMeta>) -> R) -> io::Result<R> {
+        let mut entries = self.entries.write().unwrap();
+        let result = f(&mut entries);
+        self.persist(&entries)?;
+        Ok(result)
+    }
+
+    pub fn remove(&self, key: &str) -> io::Result<Option<FileMeta>> {
+        let mut entries = self.entries.write().unwrap();
+        let removed = entries.remove(key);
+        if removed.is_some() {
+            self.persist(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    // Note that a file was just downloaded. Only files that exist get an entry.
+    pub fn record_access(&self, key: &str, now: u64) -> io::Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        let meta = entries.entry(key.to_string()).or_default();
+        if meta.last_accessed.is_some_and(|last| now.saturating_sub(last) < ACCESS_RESOLUTION) {
+            return Ok(());
+        }
+        meta.last_accessed = Some(now);
+        self.persist(&entries)
+    }
+
+    fn persist(&self, entries: &HashMap<String, FileMeta>) -> io::Result<()> {
+        write_json_atomic(&self.path, entries)
+    }
+}
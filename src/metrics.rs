@@ -0,0 +1,146 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Request latency buckets in seconds, from fast JSON calls to multi-minute transfers
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+// How long measured storage figures are served before the upload directory is walked again
+const STORAGE_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MetricsArgs {
+    /// Token Prometheus has to send as "Authorization: Bearer <token>" to scrape /metrics.
+    /// Without one, /metrics is off
+    #[arg(long, env = "CRATR_METRICS_TOKEN")]
+    pub metrics_token: Option<String>,
+}
+
+// What a scrape reports about the upload volume
+pub struct StorageSample {
+    pub files: u64,
+    pub used_bytes: u64,
+    pub disk_free_bytes: u64,
+    pub disk_total_bytes: u64,
+}
+
+// Everything exported on /metrics. Counters are updated as requests come in; the storage
+// gauges are refreshed by a scrape once they are older than STORAGE_REFRESH.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    pub uploaded_bytes: IntCounter,
+    pub downloaded_bytes: IntCounter,
+    active_uploads: IntGauge,
+    pub login_failures: IntCounter,
+    storage_used_bytes: IntGauge,
+    files: IntGauge,
+    disk_free_bytes: IntGauge,
+    disk_total_bytes: IntGauge,
+    storage_measured: Mutex<Option<Instant>>,
+    token: Option<String>,
+}
+
+impl Metrics {
+    pub fn new(storage_limit: u64, args: &MetricsArgs) -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("cratr".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route, method and status"),
+            &["route", "method", "status"],
+        )?;
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time to produce a response, by route and method")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["route", "method"],
+        )?;
+        let uploaded_bytes = IntCounter::new("uploaded_bytes_total", "Bytes received in file uploads")?;
+        let downloaded_bytes = IntCounter::new("downloaded_bytes_total", "Bytes sent for file downloads")?;
+        let active_uploads = IntGauge::new("active_uploads", "Upload requests currently in progress")?;
+        let login_failures = IntCounter::new("login_failures_total", "Rejected login attempts")?;
+        let storage_used_bytes = IntGauge::new("storage_used_bytes", "Total size of stored files")?;
+        let storage_limit_bytes = IntGauge::new("storage_limit_bytes", "Configured storage limit")?;
+        let files = IntGauge::new("files", "Number of stored files")?;
+        let disk_free_bytes = IntGauge::new("disk_free_bytes", "Free space on the upload volume")?;
+        let disk_total_bytes = IntGauge::new("disk_total_bytes", "Size of the upload volume")?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_duration.clone()))?;
+        registry.register(Box::new(uploaded_bytes.clone()))?;
+        registry.register(Box::new(downloaded_bytes.clone()))?;
+        registry.register(Box::new(active_uploads.clone()))?;
+        registry.register(Box::new(login_failures.clone()))?;
+        registry.register(Box::new(storage_used_bytes.clone()))?;
+        registry.register(Box::new(storage_limit_bytes.clone()))?;
+        registry.register(Box::new(files.clone()))?;
+        registry.register(Box::new(disk_free_bytes.clone()))?;
+        registry.register(Box::new(disk_total_bytes.clone()))?;
+
+        storage_limit_bytes.set(storage_limit as i64);
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_duration,
+            uploaded_bytes,
+            downloaded_bytes,
+            active_uploads,
+            login_failures,
+            storage_used_bytes,
+            files,
+            disk_free_bytes,
+            disk_total_bytes,
+            storage_measured: Mutex::new(None),
+            token: args.metrics_token.clone().filter(|token| !token.is_empty()),
+        })
+    }
+
+    // The bearer token scrapers must send, if one is configured
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    // Update the storage gauges with `measure`, unless that was done recently. Concurrent
+    // scrapes wait for the one measuring instead of walking the store themselves.
+    pub fn refresh_storage(&self, measure: impl FnOnce() -> StorageSample) {
+        let mut measured = self.storage_measured.lock().unwrap_or_else(|e| e.into_inner());
+        if measured.is_some_and(|at| at.elapsed() < STORAGE_REFRESH) {
+            return;
+        }
+        let sample = measure();
+        self.files.set(sample.files as i64);
+        self.storage_used_bytes.set(sample.used_bytes as i64);
+        self.disk_free_bytes.set(sample.disk_free_bytes as i64);
+        self.disk_total_bytes.set(sample.disk_total_bytes as i64);
+        *measured = Some(Instant::now());
+    }
+
+    // `route` is the matched pattern (e.g. "/download/{filename:.*}"), never the raw path,
+    // so the number of series stays bounded
+    pub fn observe_request(&self, route: &str, method: &str, status: u16, seconds: f64) {
+        self.http_requests.with_label_values(&[route, method, &status.to_string()]).inc();
+        self.http_duration.with_label_values(&[route, method]).observe(seconds);
+    }
+
+    // Count an upload for as long as the returned guard lives
+    pub fn upload_started(&self) -> UploadGuard {
+        self.active_uploads.inc();
+        UploadGuard(self.active_uploads.clone())
+    }
+
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+}
+
+pub struct UploadGuard(IntGauge);
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
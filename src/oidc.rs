@@ -0,0 +1,563 @@
+use crate::auth::{canonical_username, parse_group_role, parse_role, role_for_groups, Identity};
+use crate::storage::unix_now;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use cratr::Role;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+// How long to wait for the identity provider before giving up on a login
+const TIMEOUT: Duration = Duration::from_secs(10);
+// Leeway for clocks that disagree with the identity provider's
+const CLOCK_SKEW: u64 = 60;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct OidcArgs {
+    /// OpenID Connect issuer to offer single sign-on with, e.g. https://id.example.org/realms/main
+    /// (turns single sign-on on). Has to be https, except on this machine
+    #[arg(long, env = "CRATR_OIDC_ISSUER")]
+    pub oidc_issuer: Option<String>,
+
+    /// Client ID cratr is registered under at the issuer
+    #[arg(long, env = "CRATR_OIDC_CLIENT_ID")]
+    pub oidc_client_id: Option<String>,
+
+    /// Client secret, for confidential clients (public clients rely on PKCE alone)
+    #[arg(long, env = "CRATR_OIDC_CLIENT_SECRET", hide_env_values = true)]
+    pub oidc_client_secret: Option<String>,
+
+    /// Where the issuer sends users back to, as registered there,
+    /// e.g. https://files.example.org/auth/oidc/callback
+    #[arg(long, env = "CRATR_OIDC_REDIRECT_URL")]
+    pub oidc_redirect_url: Option<String>,
+
+    /// Scopes to ask for
+    #[arg(long, env = "CRATR_OIDC_SCOPES", default_value = "openid profile email")]
+    pub oidc_scopes: String,
+
+    /// ID token claim used as the cratr username. Pick one users can't change themselves
+    #[arg(long, env = "CRATR_OIDC_USERNAME_CLAIM", default_value = "preferred_username")]
+    pub oidc_username_claim: String,
+
+    /// ID token claim listing the user's groups
+    #[arg(long, env = "CRATR_OIDC_GROUPS_CLAIM", default_value = "groups")]
+    pub oidc_groups_claim: String,
+
+    /// Give members of a group from the groups claim a role, e.g. "cratr-admins=admin". Repeat
+    /// for more groups; a user in several gets the first of admin, editor, viewer, uploader
+    #[arg(long = "oidc-group-role", env = "CRATR_OIDC_GROUP_ROLES", value_delimiter = ',', value_parser = parse_group_role)]
+    pub oidc_group_roles: Vec<(String, Role)>,
+
+    /// Role for users in none of the mapped groups (by default they can't log in)
+    #[arg(long, env = "CRATR_OIDC_DEFAULT_ROLE", value_parser = parse_role)]
+    pub oidc_default_role: Option<Role>,
+
+    /// Name of the identity provider on the login button
+    #[arg(long, env = "CRATR_OIDC_NAME", default_value = "single sign-on")]
+    pub oidc_name: String,
+}
+
+// The parts of the issuer's discovery document cratr uses
+#[derive(Deserialize, Debug)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+// What a login attempt has to remember between leaving for the issuer and coming back.
+// It is kept in the session, like a login waiting on its second step.
+#[derive(Serialize, Deserialize)]
+pub struct Challenge {
+    pub state: String,
+    pub nonce: String,
+    pub verifier: String,
+    pub started: u64,
+}
+
+impl Challenge {
+    pub fn generate() -> Self {
+        // Two v4 UUIDs give 244 random bits, and hex is a valid PKCE verifier alphabet
+        let random = || format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        Self {
+            state: random(),
+            nonce: random(),
+            verifier: random(),
+            started: unix_now(),
+        }
+    }
+
+    // Whether the issuer's answer belongs to this login: the state has to come back unchanged,
+    // which stops an attacker from logging a victim into the attacker's account, and in time
+    pub fn answered_by(&self, state: Option<&str>, max_age: u64) -> bool {
+        state == Some(self.state.as_str()) && unix_now().saturating_sub(self.started) <= max_age
+    }
+}
+
+// Logs users in with the authorization code flow and PKCE. The issuer's endpoints are
+// discovered on first use, so cratr starts even while the issuer is down.
+pub struct OidcClient {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    username_claim: String,
+    groups_claim: String,
+    group_roles: Vec<(String, Role)>,
+    default_role: Option<Role>,
+    name: String,
+    http: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+}
+
+impl OidcClient {
+    // None unless an issuer is configured
+    pub fn from_args(args: &OidcArgs) -> Result<Option<Self>, String> {
+        let Some(issuer) = args.oidc_issuer.clone() else {
+            return Ok(None);
+        };
+        let (Some(client_id), Some(redirect_url)) = (args.oidc_client_id.clone(), args.oidc_redirect_url.clone()) else {
+            return Err("OIDC needs --oidc-client-id and --oidc-redirect-url".to_string());
+        };
+        if args.oidc_group_roles.is_empty() && args.oidc_default_role.is_none() {
+            return Err("OIDC needs --oidc-group-role or --oidc-default-role, or nobody could log in".to_string());
+        }
+        if !secure_endpoint(&issuer) {
+            return Err(format!("OIDC issuer {} has to use https", issuer));
+        }
+        let http = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            // The token endpoint must answer itself; following redirects could leak the code
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: args.oidc_client_secret.clone(),
+            redirect_url,
+            scopes: args.oidc_scopes.clone(),
+            username_claim: args.oidc_username_claim.clone(),
+            groups_claim: args.oidc_groups_claim.clone(),
+            group_roles: args.oidc_group_roles.clone(),
+            default_role: args.oidc_default_role,
+            name: args.oidc_name.clone(),
+            http,
+            discovery: OnceCell::new(),
+        }))
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn discovery(&self) -> Result<&Discovery, String> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                let discovery: Discovery = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| format!("discovery failed: {}", e))?
+                    .json()
+                    .await
+                    .map_err(|e| format!("discovery failed: {}", e))?;
+                if discovery.issuer.trim_end_matches('/') != self.issuer {
+                    return Err(format!("discovery document is for issuer {}", discovery.issuer));
+                }
+                // The ID token's signature isn't checked, so it must come over TLS
+                if !secure_endpoint(&discovery.token_endpoint) {
+                    return Err(format!("token endpoint {} doesn't use https", discovery.token_endpoint));
+                }
+                Ok(discovery)
+            })
+            .await
+    }
+
+    // Where to send the browser to log in
+    pub async fn authorization_url(&self, challenge: &Challenge) -> Result<String, String> {
+        let discovery = self.discovery().await?;
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(challenge.verifier.as_bytes()));
+        let mut url = reqwest::Url::parse(&discovery.authorization_endpoint)
+            .map_err(|e| format!("bad authorization endpoint: {}", e))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", &challenge.state)
+            .append_pair("nonce", &challenge.nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    // Trade the code the browser came back with for an ID token and read the user from it
+    pub async fn exchange(&self, code: &str, challenge: &Challenge) -> Result<Identity, String> {
+        let discovery = self.discovery().await?;
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.client_id),
+            ("code_verifier", &challenge.verifier),
+        ];
+        let mut request = self.http.post(&discovery.token_endpoint).form(&form);
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, Some(secret));
+        }
+        let response = request.send().await.map_err(|e| format!("token request failed: {}", e))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("token request failed: {} {}", status, body.chars().take(200).collect::<String>()));
+        }
+        let tokens: TokenResponse = response.json().await.map_err(|e| format!("bad token response: {}", e))?;
+
+        let claims = self.validate(&tokens.id_token, &discovery.issuer, &challenge.nonce)?;
+        self.identity(&claims)
+    }
+
+    // Claims of an ID token meant for this login. The token came straight from the token
+    // endpoint over TLS, so like OIDC Core 3.1.3.7 allows, its signature isn't checked.
+    fn validate(&self, id_token: &str, issuer: &str, nonce: &str) -> Result<Value, String> {
+        let payload = id_token.split('.').nth(1).ok_or("ID token is not a JWT")?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .map_err(|e| format!("bad ID token: {}", e))?;
+        let claims: Value = serde_json::from_slice(&payload).map_err(|e| format!("bad ID token: {}", e))?;
+
+        if claims["iss"].as_str() != Some(issuer) {
+            return Err(format!("ID token is from {}", claims["iss"]));
+        }
+        let audience_ok = match &claims["aud"] {
+            Value::String(aud) => *aud == self.client_id,
+            Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(&self.client_id)),
+            _ => false,
+        };
+        if !audience_ok {
+            return Err(format!("ID token is for {}", claims["aud"]));
+        }
+        if claims["exp"].as_u64().is_none_or(|exp| exp + CLOCK_SKEW < unix_now()) {
+            return Err("ID token has expired".to_string());
+        }
+        if claims["nonce"].as_str() != Some(nonce) {
+            return Err("ID token nonce doesn't match the login".to_string());
+        }
+        Ok(claims)
+    }
+
+    // The username is lower-cased, so an issuer that keeps the case people typed at sign-up
+    // doesn't split one person into several accounts. Names like preferred_username can be
+    // changed by their owner and reused, so the account is tied to the issuer and subject.
+    fn identity(&self, claims: &Value) -> Result<Identity, String> {
+        let subject = claims["sub"]
+            .as_str()
+            .filter(|sub| !sub.is_empty())
+            .ok_or("ID token has no \"sub\" claim")?;
+        let username = claims[self.username_claim.as_str()]
+            .as_str()
+            .map(canonical_username)
+            .filter(|username| !username.is_empty())
+            .ok_or_else(|| format!("ID token has no \"{}\" claim", self.username_claim))?;
+        // Some issuers send a lone group as a plain string
+        let groups: Vec<String> = match &claims[self.groups_claim.as_str()] {
+            Value::Array(groups) => groups.iter().filter_map(|group| group.as_str().map(str::to_string)).collect(),
+            Value::String(group) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+        let role = role_for_groups(&self.group_roles, &groups).or(self.default_role);
+        Ok(Identity {
+            username,
+            subject: Some(format!("{} {}", self.issuer, subject)),
+            role,
+        })
+    }
+}
+
+// An https URL, or a plain http one that stays on this machine (for a local issuer or tests)
+fn secure_endpoint(url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    match (url.scheme(), url.host_str()) {
+        ("https", Some(_)) => true,
+        ("http", Some(host)) => {
+            host.eq_ignore_ascii_case("localhost")
+                || host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const CLIENT_ID: &str = "cratr";
+    const CODE: &str = "the-code";
+
+    // What the mock issuer hands out for CODE: an ID token with these claims, but only to a
+    // client that proves it holds the verifier behind this PKCE challenge
+    struct Grant {
+        code_challenge: String,
+        claims: Value,
+    }
+
+    // A stand-in for an identity provider with a discovery document and a token endpoint.
+    // `issuer` and `token_endpoint` override what the discovery document names.
+    fn mock_idp(issuer: Option<&str>, token_endpoint: Option<&str>) -> (String, Arc<Mutex<Option<Grant>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let grant: Arc<Mutex<Option<Grant>>> = Arc::new(Mutex::new(None));
+        let discovery = json!({
+            "issuer": issuer.unwrap_or(&url),
+            "authorization_endpoint": format!("{}/authorize", url),
+            "token_endpoint": token_endpoint.map_or_else(|| format!("{}/token", url), str::to_string),
+        });
+        let issued = grant.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (discovery, grant) = (discovery.clone(), issued.clone());
+                thread::spawn(move || serve(stream, &discovery, &grant));
+            }
+        });
+        (url, grant)
+    }
+
+    fn serve(stream: TcpStream, discovery: &Value, grant: &Mutex<Option<Grant>>) {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let (status, reply) = if request_line.starts_with("GET /.well-known/openid-configuration ") {
+            ("200 OK", discovery.clone())
+        } else if request_line.starts_with("POST /token ") {
+            let form: std::collections::HashMap<String, String> =
+                reqwest::Url::parse(&format!("http://form/?{}", String::from_utf8_lossy(&body)))
+                    .unwrap()
+                    .query_pairs()
+                    .into_owned()
+                    .collect();
+            let grant = grant.lock().unwrap();
+            let verified = grant.as_ref().filter(|grant| {
+                let verifier = form.get("code_verifier").map(String::as_str).unwrap_or_default();
+                form.get("code").map(String::as_str) == Some(CODE)
+                    && form.get("client_id").map(String::as_str) == Some(CLIENT_ID)
+                    && URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) == grant.code_challenge
+            });
+            match verified {
+                Some(grant) => ("200 OK", json!({ "access_token": "opaque", "id_token": jwt(&grant.claims) })),
+                None => ("400 Bad Request", json!({ "error": "invalid_grant" })),
+            }
+        } else {
+            ("404 Not Found", json!({}))
+        };
+        let reply = reply.to_string();
+        let mut stream = reader.into_inner();
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reply.len(),
+            reply
+        );
+    }
+
+    // An unsigned JWT carrying `claims`
+    fn jwt(claims: &Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"none"}"#);
+        format!("{}.{}.", header, URL_SAFE_NO_PAD.encode(claims.to_string()))
+    }
+
+    fn args(issuer: &str) -> OidcArgs {
+        OidcArgs {
+            oidc_issuer: Some(issuer.to_string()),
+            oidc_client_id: Some(CLIENT_ID.to_string()),
+            oidc_client_secret: None,
+            oidc_redirect_url: Some("http://localhost:8080/auth/oidc/callback".to_string()),
+            oidc_scopes: "openid profile".to_string(),
+            oidc_username_claim: "preferred_username".to_string(),
+            oidc_groups_claim: "groups".to_string(),
+            oidc_group_roles: vec![("cratr-admins".to_string(), Role::Admin)],
+            oidc_default_role: None,
+            oidc_name: "Mock".to_string(),
+        }
+    }
+
+    fn client(issuer: &str) -> OidcClient {
+        OidcClient::from_args(&args(issuer)).unwrap().unwrap()
+    }
+
+    fn claims(issuer: &str, nonce: &str) -> Value {
+        json!({
+            "iss": issuer,
+            "aud": CLIENT_ID,
+            "exp": unix_now() + 300,
+            "nonce": nonce,
+            "sub": "f3b1c2",
+            "preferred_username": "alice",
+            "groups": ["cratr-admins", "staff"],
+        })
+    }
+
+    // Start a login and let the mock issuer grant CODE for the challenge the browser was sent with
+    async fn start_login(oidc: &OidcClient, issuer: &str, grant: &Mutex<Option<Grant>>) -> Challenge {
+        let challenge = Challenge::generate();
+        let url = reqwest::Url::parse(&oidc.authorization_url(&challenge).await.unwrap()).unwrap();
+        let query: std::collections::HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(url.path(), "/authorize");
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["state"], challenge.state);
+        assert_eq!(query["code_challenge_method"], "S256");
+        *grant.lock().unwrap() = Some(Grant {
+            code_challenge: query["code_challenge"].clone(),
+            claims: claims(issuer, &query["nonce"]),
+        });
+        challenge
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_code_and_pkce() {
+        let (issuer, grant) = mock_idp(None, None);
+        let oidc = client(&issuer);
+        let challenge = start_login(&oidc, &issuer, &grant).await;
+        let identity = oidc.exchange(CODE, &challenge).await.unwrap();
+        assert_eq!(identity.username, "alice");
+        assert_eq!(identity.role, Some(Role::Admin));
+    }
+
+    #[tokio::test]
+    async fn the_token_endpoint_wants_the_right_verifier_and_code() {
+        let (issuer, grant) = mock_idp(None, None);
+        let oidc = client(&issuer);
+        let challenge = start_login(&oidc, &issuer, &grant).await;
+        // Someone who intercepted the code but not the verifier gets nothing
+        let stolen = Challenge { verifier: Challenge::generate().verifier, ..challenge };
+        assert!(oidc.exchange(CODE, &stolen).await.unwrap_err().contains("invalid_grant"));
+        let challenge = start_login(&oidc, &issuer, &grant).await;
+        assert!(oidc.exchange("another-code", &challenge).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_discovery_document_for_another_issuer() {
+        let (issuer, _) = mock_idp(Some("https://evil.example.org"), None);
+        let error = client(&issuer).authorization_url(&Challenge::generate()).await.unwrap_err();
+        assert!(error.contains("discovery document is for issuer https://evil.example.org"));
+    }
+
+    #[tokio::test]
+    async fn refuses_a_plain_http_token_endpoint() {
+        let (issuer, _) = mock_idp(None, Some("http://id.example.org/token"));
+        let error = client(&issuer).authorization_url(&Challenge::generate()).await.unwrap_err();
+        assert!(error.contains("token endpoint http://id.example.org/token doesn't use https"));
+    }
+
+    #[test]
+    fn the_issuer_has_to_use_https() {
+        let mut args = args("http://id.example.org");
+        assert!(OidcClient::from_args(&args).is_err());
+        args.oidc_issuer = Some("https://id.example.org".to_string());
+        assert!(OidcClient::from_args(&args).is_ok());
+        assert!(secure_endpoint("http://localhost:8080"));
+        assert!(secure_endpoint("http://127.0.0.1:8080"));
+        assert!(secure_endpoint("http://[::1]:8080"));
+        assert!(!secure_endpoint("http://10.0.0.1"));
+        assert!(!secure_endpoint("ftp://id.example.org"));
+        assert!(!secure_endpoint("not a url"));
+    }
+
+    #[test]
+    fn validates_id_token_claims() {
+        let issuer = "https://id.example.org";
+        let oidc = client(issuer);
+        let check = |change: &dyn Fn(&mut Value)| {
+            let mut token = claims(issuer, "n0nce");
+            change(&mut token);
+            oidc.validate(&jwt(&token), issuer, "n0nce")
+        };
+
+        assert!(check(&|_| {}).is_ok());
+        assert!(check(&|claims| claims["aud"] = json!(["other", CLIENT_ID])).is_ok());
+        // Inside the allowed clock skew
+        assert!(check(&|claims| claims["exp"] = json!(unix_now() - 10)).is_ok());
+
+        assert!(check(&|claims| claims["iss"] = json!("https://evil.example.org")).unwrap_err().contains("is from"));
+        assert!(check(&|claims| claims["aud"] = json!("someone-else")).unwrap_err().contains("is for"));
+        assert!(check(&|claims| claims["aud"] = json!(["a", "b"])).is_err());
+        assert!(check(&|claims| claims["exp"] = json!(unix_now() - CLOCK_SKEW - 10)).unwrap_err().contains("expired"));
+        assert!(check(&|claims| {
+            claims.as_object_mut().unwrap().remove("exp");
+        }).is_err());
+        assert!(check(&|claims| claims["nonce"] = json!("replayed")).unwrap_err().contains("nonce"));
+        assert!(check(&|claims| {
+            claims.as_object_mut().unwrap().remove("nonce");
+        }).is_err());
+        assert!(oidc.validate("not a token", issuer, "n0nce").is_err());
+    }
+
+    #[test]
+    fn maps_claims_to_an_identity() {
+        let oidc = client("https://id.example.org");
+        let identity = oidc.identity(&json!({ "sub": "1", "preferred_username": "bob", "groups": "cratr-admins" })).unwrap();
+        assert_eq!((identity.username.as_str(), identity.role), ("bob", Some(Role::Admin)));
+        assert_eq!(identity.subject.as_deref(), Some("https://id.example.org 1"));
+        let identity = oidc.identity(&json!({ "sub": "2", "preferred_username": "carol", "groups": ["staff"] })).unwrap();
+        assert_eq!(identity.role, None);
+        assert!(oidc.identity(&json!({ "sub": "3", "preferred_username": "" })).is_err());
+        assert!(oidc.identity(&json!({ "sub": "3", "preferred_username": "  " })).is_err());
+        assert!(oidc.identity(&json!({ "preferred_username": "dave" })).unwrap_err().contains("\"sub\""));
+        let identity = oidc.identity(&json!({ "sub": "4", "preferred_username": " Bob " })).unwrap();
+        assert_eq!(identity.username, "bob");
+    }
+
+    #[test]
+    fn the_state_has_to_come_back_in_time() {
+        let challenge = Challenge::generate();
+        assert!(challenge.answered_by(Some(&challenge.state), 300));
+        assert!(!challenge.answered_by(Some("forged"), 300));
+        assert!(!challenge.answered_by(None, 300));
+        let stale = Challenge { started: unix_now() - 301, ..Challenge::generate() };
+        assert!(!stale.answered_by(Some(&stale.state), 300));
+    }
+}
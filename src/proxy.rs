@@ -0,0 +1,214 @@
+use crate::auth::{parse_group_role, parse_role, role_for_groups, Identity};
+use actix_web::HttpRequest;
+use cratr::Role;
+use std::net::IpAddr;
+use tracing::debug;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ProxyArgs {
+    /// Address or network (e.g. 10.0.0.0/8) of an authenticating reverse proxy whose user header
+    /// is trusted. Repeat for more; turns proxy login on and the login form off
+    #[arg(long = "trusted-proxy", env = "CRATR_TRUSTED_PROXIES", value_delimiter = ',', value_parser = parse_network)]
+    pub trusted_proxies: Vec<Network>,
+
+    /// Header the proxy puts the logged-in username in
+    #[arg(long, env = "CRATR_PROXY_USER_HEADER", default_value = "Remote-User")]
+    pub proxy_user_header: String,
+
+    /// Header the proxy lists the user's groups in, separated by commas
+    #[arg(long, env = "CRATR_PROXY_GROUPS_HEADER", default_value = "Remote-Groups")]
+    pub proxy_groups_header: String,
+
+    /// Give members of a group from the groups header a role, e.g. "cratr-admins=admin". Repeat
+    /// for more groups; a user in several gets the first of admin, editor, viewer, uploader
+    #[arg(long = "proxy-group-role", env = "CRATR_PROXY_GROUP_ROLES", value_delimiter = ',', value_parser = parse_group_role)]
+    pub proxy_group_roles: Vec<(String, Role)>,
+
+    /// Role for users in none of the mapped groups (by default they can't get in)
+    #[arg(long, env = "CRATR_PROXY_DEFAULT_ROLE", value_parser = parse_role)]
+    pub proxy_default_role: Option<Role>,
+}
+
+// An address with a prefix length; a plain address is a network of one
+#[derive(Debug, Clone, Copy)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn contains(&self, ip: IpAddr) -> bool {
+        // An IPv4 client may show up as an IPv4-mapped IPv6 address on a dual-stack socket
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            _ => ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_network(value: &str) -> Result<Network, String> {
+    let (address, prefix) = match value.trim().split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value.trim(), None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| format!("\"{}\" is not an IP address", address))?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .parse()
+            .ok()
+            .filter(|prefix| *prefix <= max)
+            .ok_or_else(|| format!("\"{}\" is not a prefix length from 0 to {}", prefix, max))?,
+        None => max,
+    };
+    Ok(Network { address, prefix })
+}
+
+// Takes the username from a header set by an authenticating reverse proxy, but only on
+// requests that come straight from one of the trusted proxies. Anyone else could send the
+// header themselves.
+pub struct ProxyAuth {
+    trusted: Vec<Network>,
+    user_header: String,
+    groups_header: String,
+    group_roles: Vec<(String, Role)>,
+    default_role: Option<Role>,
+}
+
+impl ProxyAuth {
+    // None unless a trusted proxy is configured
+    pub fn from_args(args: &ProxyArgs) -> Result<Option<Self>, String> {
+        if args.trusted_proxies.is_empty() {
+            return Ok(None);
+        }
+        if args.proxy_group_roles.is_empty() && args.proxy_default_role.is_none() {
+            return Err("Proxy login needs --proxy-group-role or --proxy-default-role, or nobody could get in".to_string());
+        }
+        Ok(Some(Self {
+            trusted: args.trusted_proxies.clone(),
+            user_header: args.proxy_user_header.clone(),
+            groups_header: args.proxy_groups_header.clone(),
+            group_roles: args.proxy_group_roles.clone(),
+            default_role: args.proxy_default_role,
+        }))
+    }
+
+    pub fn user_header(&self) -> &str {
+        &self.user_header
+    }
+
+    // The user the proxy vouches for, or None if the request didn't come through a trusted
+    // proxy or the proxy named nobody
+    pub fn identity(&self, req: &HttpRequest) -> Option<Identity> {
+        let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+        let username = header(&self.user_header).filter(|username| !username.is_empty())?;
+        let peer = req.peer_addr()?.ip();
+        if !self.trusted.iter().any(|network| network.contains(peer)) {
+            debug!(%peer, "Ignoring {} header from an untrusted address", self.user_header);
+            return None;
+        }
+
+        let groups: Vec<String> = header(&self.groups_header)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .map(str::to_string)
+            .collect();
+        let role = role_for_groups(&self.group_roles, &groups).or(self.default_role);
+        Some(Identity {
+            username: username.to_string(),
+            subject: None,
+            role,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::net::SocketAddr;
+
+    fn proxy(trusted: &[&str]) -> ProxyAuth {
+        let args = ProxyArgs {
+            trusted_proxies: trusted.iter().map(|network| parse_network(network).unwrap()).collect(),
+            proxy_user_header: "Remote-User".to_string(),
+            proxy_groups_header: "Remote-Groups".to_string(),
+            proxy_group_roles: vec![("cratr-admins".to_string(), Role::Admin), ("staff".to_string(), Role::Editor)],
+            proxy_default_role: None,
+        };
+        ProxyAuth::from_args(&args).unwrap().unwrap()
+    }
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        let mut request = TestRequest::default().peer_addr(peer.parse::<SocketAddr>().unwrap());
+        for header in headers {
+            request = request.insert_header(*header);
+        }
+        request.to_http_request()
+    }
+
+    fn contains(network: &str, ip: &str) -> bool {
+        parse_network(network).unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn networks_match_by_prefix() {
+        assert!(contains("10.0.0.0/8", "10.255.1.2"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("192.0.2.7", "192.0.2.7"));
+        assert!(!contains("192.0.2.7", "192.0.2.8"));
+        assert!(contains("0.0.0.0/0", "203.0.113.9"));
+        assert!(contains("fd00::/8", "fd12:3456::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        // IPv4 clients on a dual-stack socket
+        assert!(contains("10.0.0.0/8", "::ffff:10.1.2.3"));
+        assert!(!contains("fd00::/8", "10.1.2.3"));
+    }
+
+    #[test]
+    fn bad_networks_are_refused() {
+        assert!(parse_network("10.0.0.0/33").is_err());
+        assert!(parse_network("fd00::/129").is_err());
+        assert!(parse_network("proxy.example.org").is_err());
+        assert!(parse_network("10.0.0.0/").is_err());
+    }
+
+    #[test]
+    fn trusted_proxies_name_the_user_and_groups() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let identity = proxy
+            .identity(&request("10.0.0.2:4000", &[("Remote-User", " alice "), ("Remote-Groups", "staff, cratr-admins")]))
+            .unwrap();
+        assert_eq!((identity.username.as_str(), identity.role), ("alice", Some(Role::Admin)));
+        let identity = proxy.identity(&request("10.0.0.2:4000", &[("Remote-User", "bob")])).unwrap();
+        assert_eq!(identity.role, None);
+        assert!(proxy.identity(&request("10.0.0.2:4000", &[("Remote-User", "")])).is_none());
+        assert!(proxy.identity(&request("10.0.0.2:4000", &[])).is_none());
+    }
+
+    #[test]
+    fn the_header_is_ignored_from_anywhere_else() {
+        let proxy = proxy(&["10.0.0.2"]);
+        assert!(proxy.identity(&request("10.0.0.3:4000", &[("Remote-User", "alice")])).is_none());
+        assert!(proxy.identity(&request("[2001:db8::1]:4000", &[("Remote-User", "alice")])).is_none());
+        // Claiming to come through the proxy doesn't make a request trusted
+        let spoofed = request(
+            "203.0.113.9:4000",
+            &[("Remote-User", "alice"), ("X-Forwarded-For", "10.0.0.2"), ("X-Real-IP", "10.0.0.2"), ("Forwarded", "for=10.0.0.2")],
+        );
+        assert!(proxy.identity(&spoofed).is_none());
+    }
+}
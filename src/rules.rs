@@ -0,0 +1,461 @@
+use crate::storage::sanitize_folder;
+
+// Bytes of each upload held back and sniffed before anything is written. Tar's magic sits
+// at offset 257.
+pub const SNIFF_LENGTH: usize = 512;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RuleArgs {
+    /// Only accept uploads with one of these extensions, e.g. "pdf,jpg,tar.gz"
+    #[arg(long = "allow-extension", env = "CRATR_ALLOW_EXTENSIONS", value_delimiter = ',', value_parser = parse_extension)]
+    pub allow_extensions: Vec<String>,
+
+    /// Refuse uploads with one of these extensions, e.g. "exe,bat"
+    #[arg(long = "deny-extension", env = "CRATR_DENY_EXTENSIONS", value_delimiter = ',', value_parser = parse_extension)]
+    pub deny_extensions: Vec<String>,
+
+    /// Only accept uploads whose content looks like one of these types, e.g. "image/*,application/pdf"
+    #[arg(long = "allow-type", env = "CRATR_ALLOW_TYPES", value_delimiter = ',', value_parser = parse_type)]
+    pub allow_types: Vec<String>,
+
+    /// Refuse uploads whose content looks like one of these types, whatever their name says,
+    /// e.g. "application/x-executable,text/html"
+    #[arg(long = "deny-type", env = "CRATR_DENY_TYPES", value_delimiter = ',', value_parser = parse_type)]
+    pub deny_types: Vec<String>,
+
+    /// Only accept filenames matching one of these patterns, where * is any run of characters
+    /// and ? any one, e.g. "invoice-*.pdf"
+    #[arg(long = "allow-name", env = "CRATR_ALLOW_NAMES", value_delimiter = ',')]
+    pub allow_names: Vec<String>,
+
+    /// Refuse filenames matching one of these patterns, e.g. "*.tmp,~$*,Thumbs.db"
+    #[arg(long = "deny-name", env = "CRATR_DENY_NAMES", value_delimiter = ',')]
+    pub deny_names: Vec<String>,
+
+    /// Largest upload into a folder and its subfolders, e.g. "scans=50MB"; "/=1GB" covers
+    /// the top level. The most specific folder wins
+    #[arg(long = "folder-size-limit", env = "CRATR_FOLDER_SIZE_LIMITS", value_delimiter = ',', value_parser = parse_folder_limit)]
+    pub folder_size_limits: Vec<(String, u64)>,
+}
+
+fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.trim().trim_start_matches('.').to_lowercase();
+    if extension.is_empty() {
+        return Err("empty extension".to_string());
+    }
+    Ok(extension)
+}
+
+fn parse_type(value: &str) -> Result<String, String> {
+    let mime = value.trim().to_lowercase();
+    match mime.split_once('/') {
+        Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() => Ok(mime),
+        _ => Err(format!("\"{}\" is not a type like image/png or image/*", value)),
+    }
+}
+
+fn parse_folder_limit(value: &str) -> Result<(String, u64), String> {
+    let (folder, size) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("\"{}\" is not folder=size", value))?;
+    Ok((sanitize_folder(folder), parse_size(size)?))
+}
+
+// "500", "64KB", "10M", "1.5 GB"; units are powers of 1024
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let digits = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let number: f64 = number.parse().map_err(|_| format!("\"{}\" is not a size", value))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return Err(format!("\"{}\" has an unknown unit", value)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+// What an upload may be, beyond the global size limit. Names are checked before any bytes
+// are read, content once the first SNIFF_LENGTH bytes are in, and size as the file streams.
+pub struct UploadRules {
+    allow_extensions: Vec<String>,
+    deny_extensions: Vec<String>,
+    allow_types: Vec<String>,
+    deny_types: Vec<String>,
+    allow_names: Vec<String>,
+    deny_names: Vec<String>,
+    folder_size_limits: Vec<(String, u64)>,
+}
+
+impl UploadRules {
+    pub fn from_args(args: &RuleArgs) -> Self {
+        Self {
+            allow_extensions: args.allow_extensions.clone(),
+            deny_extensions: args.deny_extensions.clone(),
+            allow_types: args.allow_types.clone(),
+            deny_types: args.deny_types.clone(),
+            allow_names: args.allow_names.clone(),
+            deny_names: args.deny_names.clone(),
+            folder_size_limits: args.folder_size_limits.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow_extensions.is_empty()
+            && self.deny_extensions.is_empty()
+            && self.allow_types.is_empty()
+            && self.deny_types.is_empty()
+            && self.allow_names.is_empty()
+            && self.deny_names.is_empty()
+            && self.folder_size_limits.is_empty()
+    }
+
+    // Why a (sanitized) filename may not be uploaded, if it may not
+    pub fn check_name(&self, name: &str) -> Result<(), String> {
+        let lower = name.to_lowercase();
+        let has_extension = |extension: &String| lower.ends_with(&format!(".{}", extension));
+        if let Some(extension) = self.deny_extensions.iter().find(|extension| has_extension(extension)) {
+            return Err(format!("{}: .{} files are not allowed", name, extension));
+        }
+        if !self.allow_extensions.is_empty() && !self.allow_extensions.iter().any(has_extension) {
+            return Err(format!("{}: only .{} files are allowed", name, self.allow_extensions.join(", .")));
+        }
+        if let Some(pattern) = self.deny_names.iter().find(|pattern| wildcard_match(pattern, name)) {
+            return Err(format!("{}: names like \"{}\" are not allowed", name, pattern));
+        }
+        if !self.allow_names.is_empty() && !self.allow_names.iter().any(|pattern| wildcard_match(pattern, name)) {
+            return Err(format!("{}: only names like \"{}\" are allowed", name, self.allow_names.join("\", \"")));
+        }
+        Ok(())
+    }
+
+    // Why content starting with `head` may not be uploaded, if it may not
+    pub fn check_content(&self, name: &str, head: &[u8]) -> Result<(), String> {
+        if self.allow_types.is_empty() && self.deny_types.is_empty() {
+            return Ok(());
+        }
+        let mime = sniff(head);
+        if self.deny_types.iter().any(|pattern| type_matches(pattern, mime)) {
+            return Err(format!("{}: {} content is not allowed", name, mime));
+        }
+        if !self.allow_types.is_empty() && !self.allow_types.iter().any(|pattern| type_matches(pattern, mime)) {
+            return Err(format!("{}: {} content is not allowed, only {}", name, mime, self.allow_types.join(", ")));
+        }
+        Ok(())
+    }
+
+    // The size limit for uploads into `folder` and the folder whose rule set it, if any
+    pub fn size_limit(&self, folder: &str) -> Option<(u64, &str)> {
+        self.folder_size_limits
+            .iter()
+            .filter(|(limit_folder, _)| {
+                limit_folder.is_empty()
+                    || folder == limit_folder
+                    || folder.strip_prefix(limit_folder.as_str()).is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|(limit_folder, _)| limit_folder.len())
+            .map(|(limit_folder, limit)| (*limit, limit_folder.as_str()))
+    }
+}
+
+fn type_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(kind) => kind == "*" || mime.split('/').next() == Some(kind),
+        None => pattern == mime,
+    }
+}
+
+// Case-insensitive match where * stands for any run of characters and ? for one
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last * was and how much of the name it has swallowed so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    n = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Guess a type from the first bytes of a file, going by magic numbers and falling back to
+// text/plain for anything that reads as text. Markup and scripts are told apart from plain
+// text, since they are what a deny list usually wants to catch.
+pub fn sniff(head: &[u8]) -> &'static str {
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+
+    if head.is_empty() {
+        return "application/x-empty";
+    }
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        return "image/png";
+    }
+    if starts(b"\xff\xd8\xff") {
+        return "image/jpeg";
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return "image/gif";
+    }
+    if starts(b"RIFF") {
+        if at(8, b"WEBP") {
+            return "image/webp";
+        }
+        if at(8, b"WAVE") {
+            return "audio/wav";
+        }
+        if at(8, b"AVI ") {
+            return "video/x-msvideo";
+        }
+    }
+    if starts(b"BM") && at(6, b"\0\0\0\0") {
+        return "image/bmp";
+    }
+    if starts(b"\0\0\x01\0") {
+        return "image/x-icon";
+    }
+    if at(4, b"ftyp") {
+        return match head.get(8..12) {
+            Some(b"M4A ") => "audio/mp4",
+            Some(b"heic" | b"heix" | b"mif1") => "image/heic",
+            Some(b"avif") => "image/avif",
+            _ => "video/mp4",
+        };
+    }
+    if starts(b"\x1a\x45\xdf\xa3") {
+        return "video/webm";
+    }
+    if starts(b"OggS") {
+        return "audio/ogg";
+    }
+    if starts(b"fLaC") {
+        return "audio/flac";
+    }
+    if starts(b"ID3") || starts(b"\xff\xfb") || starts(b"\xff\xf3") || starts(b"\xff\xf2") {
+        return "audio/mpeg";
+    }
+    if starts(b"%PDF-") {
+        return "application/pdf";
+    }
+    if starts(b"PK\x03\x04") || starts(b"PK\x05\x06") {
+        return "application/zip";
+    }
+    if starts(b"\x1f\x8b") {
+        return "application/gzip";
+    }
+    if starts(b"BZh") {
+        return "application/x-bzip2";
+    }
+    if starts(b"\xfd7zXZ\0") {
+        return "application/x-xz";
+    }
+    if starts(b"\x28\xb5\x2f\xfd") {
+        return "application/zstd";
+    }
+    if starts(b"7z\xbc\xaf\x27\x1c") {
+        return "application/x-7z-compressed";
+    }
+    if starts(b"Rar!\x1a\x07") {
+        return "application/vnd.rar";
+    }
+    if at(257, b"ustar") {
+        return "application/x-tar";
+    }
+    if starts(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+        return "application/x-ole-storage";
+    }
+    if starts(b"SQLite format 3\0") {
+        return "application/vnd.sqlite3";
+    }
+    if starts(b"\x7fELF") {
+        return "application/x-executable";
+    }
+    if starts(b"MZ") {
+        return "application/x-msdownload";
+    }
+    if [b"\xfe\xed\xfa\xce", b"\xce\xfa\xed\xfe", b"\xfe\xed\xfa\xcf", b"\xcf\xfa\xed\xfe", b"\xca\xfe\xba\xbe"]
+        .iter()
+        .any(|magic| starts(*magic))
+    {
+        return "application/x-mach-binary";
+    }
+    if starts(b"\0asm") {
+        return "application/wasm";
+    }
+    if starts(b"{\\rtf") {
+        return "application/rtf";
+    }
+
+    match std::str::from_utf8(head) {
+        // A multi-byte character cut off by the end of the sample is still text
+        Ok(text) => sniff_text(text),
+        Err(e) if e.error_len().is_none() => sniff_text(std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()),
+        Err(_) => "application/octet-stream",
+    }
+}
+
+fn sniff_text(text: &str) -> &'static str {
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b')) {
+        return "application/octet-stream";
+    }
+    let start = text.trim_start_matches('\u{feff}').trim_start().to_lowercase();
+    let starts = |prefix: &str| start.starts_with(prefix);
+    if starts("#!") {
+        "text/x-shellscript"
+    } else if ["<!doctype html", "<html", "<head", "<body", "<script", "<iframe"].iter().any(|tag| starts(tag)) {
+        "text/html"
+    } else if starts("<svg") || (starts("<?xml") || starts("<!doctype svg")) && start.contains("<svg") {
+        "image/svg+xml"
+    } else if starts("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(configure: impl FnOnce(&mut RuleArgs)) -> UploadRules {
+        let mut args = RuleArgs {
+            allow_extensions: vec![],
+            deny_extensions: vec![],
+            allow_types: vec![],
+            deny_types: vec![],
+            allow_names: vec![],
+            deny_names: vec![],
+            folder_size_limits: vec![],
+        };
+        configure(&mut args);
+        UploadRules::from_args(&args)
+    }
+
+    #[test]
+    fn sniffs_common_types() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), "image/jpeg");
+        assert_eq!(sniff(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(sniff(b"PK\x03\x04\x14\0"), "application/zip");
+        assert_eq!(sniff(b"\x7fELF\x02\x01\x01"), "application/x-executable");
+        assert_eq!(sniff(b"MZ\x90\0\x03\0"), "application/x-msdownload");
+        assert_eq!(sniff(b"\0\0\0\x20ftypisom"), "video/mp4");
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar), "application/x-tar");
+        assert_eq!(sniff(b""), "application/x-empty");
+        assert_eq!(sniff(b"\0\x01\x02\x03binary"), "application/octet-stream");
+    }
+
+    #[test]
+    fn tells_markup_and_scripts_from_plain_text() {
+        assert_eq!(sniff("Grüße, plain notes\n".as_bytes()), "text/plain");
+        assert_eq!(sniff(b"  <!DOCTYPE html><html>"), "text/html");
+        assert_eq!(sniff(b"\xef\xbb\xbf<HTML><body>"), "text/html");
+        assert_eq!(sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"), "image/svg+xml");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>\n<svg>"), "image/svg+xml");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>\n<feed>"), "application/xml");
+        assert_eq!(sniff(b"#!/bin/sh\nrm -rf /"), "text/x-shellscript");
+        // A character cut in half at the end of the sample
+        assert_eq!(sniff(&"é".as_bytes()[..1]), "text/plain");
+    }
+
+    #[test]
+    fn checks_extensions() {
+        let rules = rules(|args| {
+            args.deny_extensions = vec!["exe".to_string()];
+            args.allow_extensions = vec!["pdf".to_string(), "tar.gz".to_string(), "exe".to_string()];
+        });
+        assert!(rules.check_name("report.PDF").is_ok());
+        assert!(rules.check_name("backup.tar.gz").is_ok());
+        assert_eq!(rules.check_name("setup.exe").unwrap_err(), "setup.exe: .exe files are not allowed");
+        assert!(rules.check_name("notes.txt").unwrap_err().contains("only .pdf, .tar.gz, .exe files"));
+        assert!(rules.check_name("pdf").is_err());
+    }
+
+    #[test]
+    fn checks_name_patterns() {
+        let rules = rules(|args| {
+            args.deny_names = vec!["*.tmp".to_string(), "~$*".to_string(), "thumbs.db".to_string()];
+            args.allow_names = vec!["*-20??-*".to_string(), "Thumbs.db".to_string()];
+        });
+        assert!(rules.check_name("invoice-2024-03.pdf").is_ok());
+        assert!(rules.check_name("invoice-2024-03.tmp").is_err());
+        assert!(rules.check_name("~$invoice-2024-03.docx").is_err());
+        assert!(rules.check_name("Thumbs.db").is_err());
+        assert!(rules.check_name("invoice.pdf").unwrap_err().contains("only names like"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(wildcard_match("a*b", "abab"));
+        assert!(wildcard_match("*.TXT", "notes.txt"));
+        assert!(wildcard_match("??.md", "éx.md"));
+        assert!(!wildcard_match("a*b", "abc"));
+        assert!(!wildcard_match("?", ""));
+        assert!(!wildcard_match("report", "report.pdf"));
+    }
+
+    #[test]
+    fn checks_sniffed_content_not_the_name() {
+        let rules = rules(|args| {
+            args.allow_types = vec!["image/*".to_string(), "application/pdf".to_string()];
+            args.deny_types = vec!["image/svg+xml".to_string()];
+        });
+        assert!(rules.check_content("cat.jpg", b"\x89PNG\r\n\x1a\n").is_ok());
+        assert!(rules.check_content("doc.pdf", b"%PDF-1.4").is_ok());
+        assert_eq!(rules.check_content("cat.jpg", b"MZ\x90\0").unwrap_err(), "cat.jpg: application/x-msdownload content is not allowed, only image/*, application/pdf");
+        assert_eq!(rules.check_content("logo.png", b"<svg onload=alert(1)>").unwrap_err(), "logo.png: image/svg+xml content is not allowed");
+    }
+
+    #[test]
+    fn picks_the_most_specific_folder_limit() {
+        let rules = rules(|args| {
+            args.folder_size_limits = vec![
+                parse_folder_limit("/=1GB").unwrap(),
+                parse_folder_limit("scans=50MB").unwrap(),
+                parse_folder_limit("scans/hq=200MB").unwrap(),
+            ];
+        });
+        assert_eq!(rules.size_limit(""), Some((1 << 30, "")));
+        assert_eq!(rules.size_limit("scans"), Some((50 << 20, "scans")));
+        assert_eq!(rules.size_limit("scans/2024"), Some((50 << 20, "scans")));
+        assert_eq!(rules.size_limit("scans/hq/raw"), Some((200 << 20, "scans/hq")));
+        assert_eq!(rules.size_limit("scansextra"), Some((1 << 30, "")));
+        assert_eq!(self::rules(|_| {}).size_limit("scans"), None);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("64KB"), Ok(64 << 10));
+        assert_eq!(parse_size("10m"), Ok(10 << 20));
+        assert_eq!(parse_size("1.5 GiB"), Ok(3 << 29));
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+        assert_eq!(parse_folder_limit("a/b=1K"), Ok(("a/b".to_string(), 1024)));
+        assert!(parse_folder_limit("1K").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use crate::storage::{display_name, move_file};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+// How long clamd may take to answer; big archives take a while to unpack
+const TIMEOUT: Duration = Duration::from_secs(120);
+// Size of the chunks a file is streamed to clamd in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ScanArgs {
+    /// ClamAV daemon to scan uploads with: the path of its local socket, e.g.
+    /// /run/clamav/clamd.ctl, or host:port for its TCP socket (turns scanning on)
+    #[arg(long, env = "CRATR_CLAMD")]
+    pub clamd: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    // With the name of what clamd found
+    Infected(String),
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    // clamd couldn't be reached or didn't answer
+    Unavailable(String),
+    // clamd answered with an error about the file, e.g. that it is over StreamMaxLength
+    Refused(String),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Unavailable(message) => write!(f, "virus scanner unavailable: {}", message),
+            ScanError::Refused(message) => write!(f, "virus scanner refused the file: {}", message),
+        }
+    }
+}
+
+enum Address {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+// Talks to clamd with its INSTREAM command: the file goes over the socket in
+// length-prefixed chunks, so clamd needn't be able to read cratr's files.
+pub struct Scanner {
+    address: Address,
+}
+
+impl Scanner {
+    // None unless clamd is configured. Anything with a slash is taken as a socket path.
+    pub fn from_args(args: &ScanArgs) -> Option<Self> {
+        let clamd = args.clamd.as_deref()?;
+        let address = if clamd.contains('/') {
+            Address::Unix(PathBuf::from(clamd))
+        } else {
+            Address::Tcp(clamd.to_string())
+        };
+        Some(Self { address })
+    }
+
+    pub fn address(&self) -> String {
+        match &self.address {
+            Address::Unix(path) => path.display().to_string(),
+            Address::Tcp(address) => address.clone(),
+        }
+    }
+
+    pub fn scan(&self, path: &Path) -> Result<Verdict, ScanError> {
+        let mut file = std::fs::File::open(path).map_err(|e| ScanError::Refused(format!("can't read file: {}", e)))?;
+        let unavailable = |e: io::Error| ScanError::Unavailable(e.to_string());
+        match &self.address {
+            Address::Unix(socket) => {
+                let stream = std::os::unix::net::UnixStream::connect(socket).map_err(unavailable)?;
+                stream.set_read_timeout(Some(TIMEOUT)).map_err(unavailable)?;
+                stream.set_write_timeout(Some(TIMEOUT)).map_err(unavailable)?;
+                instream(stream, &mut file)
+            }
+            Address::Tcp(address) => {
+                let stream = TcpStream::connect(address).map_err(unavailable)?;
+                stream.set_read_timeout(Some(TIMEOUT)).map_err(unavailable)?;
+                stream.set_write_timeout(Some(TIMEOUT)).map_err(unavailable)?;
+                instream(stream, &mut file)
+            }
+        }
+    }
+}
+
+fn instream(mut stream: impl Read + Write, file: &mut impl Read) -> Result<Verdict, ScanError> {
+    let sent = send_file(&mut stream, file);
+
+    // clamd stops reading once a stream is over its limit, but still says why; that beats
+    // the broken pipe the write ends with
+    let mut reply = Vec::new();
+    let read = Read::by_ref(&mut stream).take(4096).read_to_end(&mut reply);
+    if reply.is_empty() {
+        let error = sent.err().or(read.err()).map(|e| e.to_string()).unwrap_or_else(|| "no answer".to_string());
+        return Err(ScanError::Unavailable(error));
+    }
+    parse_reply(&String::from_utf8_lossy(&reply))
+}
+
+fn send_file(stream: &mut impl Write, file: &mut impl Read) -> io::Result<()> {
+    // The "z" prefix means the command and reply end with a NUL byte
+    stream.write_all(b"zINSTREAM\0")?;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&(read as u32).to_be_bytes())?;
+        stream.write_all(&buffer[..read])?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    stream.flush()
+}
+
+// "stream: OK", "stream: Eicar-Test-Signature FOUND" or "<message> ERROR"
+fn parse_reply(reply: &str) -> Result<Verdict, ScanError> {
+    let reply = reply.split('\0').next().unwrap_or_default().trim();
+    let result = reply.strip_prefix("stream:").map(str::trim).unwrap_or(reply);
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(ScanError::Refused(result.trim_end_matches(" ERROR").to_string()))
+    }
+}
+
+// Move an infected file out of the upload directory into `quarantine_dir`, under a fresh
+// name so nothing there is ever overwritten. Returns the name it got.
+pub fn quarantine(source: &Path, relative: &str, quarantine_dir: &str) -> io::Result<String> {
+    std::fs::create_dir_all(quarantine_dir)?;
+    let name = format!("{}_{}", Uuid::new_v4(), display_name(relative));
+    let target = Path::new(quarantine_dir).join(&name);
+    move_file(source, &target)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // A stand-in for clamd that reads one INSTREAM request and answers it with `reply`,
+    // handing back the bytes it received
+    fn stub_clamd(reply: &'static [u8]) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; 10];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+            let mut received = Vec::new();
+            loop {
+                let mut length = [0u8; 4];
+                stream.read_exact(&mut length).unwrap();
+                let length = u32::from_be_bytes(length) as usize;
+                if length == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; length];
+                stream.read_exact(&mut chunk).unwrap();
+                received.extend_from_slice(&chunk);
+            }
+            stream.write_all(reply).unwrap();
+            received
+        });
+        (address, handle)
+    }
+
+    fn scanner(address: &str) -> Scanner {
+        Scanner::from_args(&ScanArgs { clamd: Some(address.to_string()) }).unwrap()
+    }
+
+    fn scratch_file(content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cratr-scan-{}", Uuid::new_v4().simple()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn streams_the_file_and_reads_a_clean_verdict() {
+        let (address, clamd) = stub_clamd(b"stream: OK\0");
+        let content: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let path = scratch_file(&content);
+        assert_eq!(scanner(&address).scan(&path).unwrap(), Verdict::Clean);
+        assert_eq!(clamd.join().unwrap(), content);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn reports_what_was_found() {
+        let (address, clamd) = stub_clamd(b"stream: Eicar-Test-Signature FOUND\0");
+        let path = scratch_file(b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR");
+        assert_eq!(scanner(&address).scan(&path).unwrap(), Verdict::Infected("Eicar-Test-Signature".to_string()));
+        clamd.join().unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn clamd_errors_are_refusals() {
+        let (address, clamd) = stub_clamd(b"INSTREAM size limit exceeded. ERROR\0");
+        let path = scratch_file(b"too big");
+        match scanner(&address).scan(&path) {
+            Err(ScanError::Refused(message)) => assert_eq!(message, "INSTREAM size limit exceeded."),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        clamd.join().unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_missing_daemon_is_unavailable() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let path = scratch_file(b"data");
+        assert!(matches!(scanner(&address).scan(&path), Err(ScanError::Unavailable(_))));
+        let socket = std::env::temp_dir().join("cratr-no-such-clamd.sock");
+        assert!(matches!(scanner(&socket.to_string_lossy()).scan(&path), Err(ScanError::Unavailable(_))));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn parses_replies() {
+        assert_eq!(parse_reply("stream: OK").unwrap(), Verdict::Clean);
+        assert_eq!(parse_reply("stream: OK\n").unwrap(), Verdict::Clean);
+        assert_eq!(parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap(), Verdict::Infected("Win.Test.EICAR_HDB-1".to_string()));
+        assert!(matches!(parse_reply("UNKNOWN COMMAND"), Err(ScanError::Refused(_))));
+        assert!(matches!(parse_reply(""), Err(ScanError::Refused(_))));
+    }
+
+    #[test]
+    fn quarantine_moves_the_file_out() {
+        let source = scratch_file(b"infected");
+        let dir = std::env::temp_dir().join(format!("cratr-quarantine-{}", Uuid::new_v4().simple()));
+        let name = quarantine(&source, "docs/1b4e28ba-2fa1-11d2-883f-0016d3cca427_invoice.pdf", &dir.to_string_lossy()).unwrap();
+        assert!(name.ends_with("_invoice.pdf"));
+        assert!(!source.exists());
+        assert_eq!(std::fs::read(dir.join(&name)).unwrap(), b"infected");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
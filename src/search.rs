@@ -0,0 +1,441 @@
+use crate::storage::{display_name, folder_of, walk_files};
+use cratr::SearchHit;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::query::{AllQuery, QueryParser};
+use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+// Only this much extracted text per file goes into the index
+const MAX_INDEXED_TEXT: usize = 2 * 1024 * 1024;
+// PDFs and office documents bigger than this are indexed by name only
+const MAX_EXTRACT_FILE_SIZE: u64 = 64 * 1024 * 1024;
+const WRITER_MEMORY: usize = 50_000_000;
+const SNIPPET_CHARS: usize = 200;
+
+struct Fields {
+    path: Field,
+    name: Field,
+    content: Field,
+    modified: Field,
+}
+
+// Full-text index over file names and contents, stored under the data directory.
+// Documents are keyed by their path relative to the upload directory.
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+}
+
+impl SearchIndex {
+    pub fn open(dir: impl AsRef<Path>) -> tantivy::Result<Self> {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            path: builder.add_text_field("path", STRING | STORED),
+            name: builder.add_text_field("name", TEXT | STORED),
+            content: builder.add_text_field("content", TEXT | STORED),
+            modified: builder.add_u64_field("modified", STORED),
+        };
+        let schema = builder.build();
+
+        std::fs::create_dir_all(dir.as_ref())?;
+        let directory = tantivy::directory::MmapDirectory::open(dir.as_ref())?;
+        let index = Index::open_or_create(directory, schema)?;
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        let writer = index.writer(WRITER_MEMORY)?;
+
+        Ok(Self {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+        })
+    }
+
+    // Re-index `added` (new or changed files) and drop `removed`, committing once
+    pub fn update(&self, upload_dir: &str, added: &[String], removed: &[String]) -> tantivy::Result<()> {
+        if added.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+        for path in removed.iter().chain(added) {
+            writer.delete_term(Term::from_field_text(self.fields.path, path));
+        }
+        for path in added {
+            let filepath = Path::new(upload_dir).join(path);
+            let Ok(metadata) = std::fs::metadata(&filepath) else {
+                continue;
+            };
+            let modified = metadata.modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            let name = display_name(path);
+            let content = extract_text(&filepath, &name, metadata.len()).unwrap_or_default();
+
+            writer.add_document(doc!(
+                self.fields.path => path.as_str(),
+                self.fields.name => name,
+                self.fields.content => content,
+                self.fields.modified => modified,
+            ))?;
+        }
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    // Bring the index in line with what is on disk: index new or modified files and
+    // forget files that no longer exist. Returns (indexed, removed).
+    pub fn sync(&self, upload_dir: &str) -> tantivy::Result<(usize, usize)> {
+        let mut indexed = self.indexed_files()?;
+        let mut added = Vec::new();
+
+        for file in walk_files(Path::new(upload_dir)) {
+            match indexed.remove(&file.path) {
+                Some(modified) if modified == file.modified => {}
+                _ => added.push(file.path),
+            }
+        }
+        let removed: Vec<String> = indexed.into_keys().collect();
+
+        self.update(upload_dir, &added, &removed)?;
+        Ok((added.len(), removed.len()))
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> tantivy::Result<Vec<SearchHit>> {
+        let searcher = self.reader.searcher();
+        let mut parser = QueryParser::for_index(&self.index, vec![self.fields.name, self.fields.content]);
+        parser.set_field_boost(self.fields.name, 2.0);
+        // Users type plain words, not query syntax; let stray quotes and colons through
+        let (query, _errors) = parser.parse_query_lenient(query);
+
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit.max(1)).order_by_score())?;
+        let mut snippets = SnippetGenerator::create(&searcher, &*query, self.fields.content)?;
+        snippets.set_max_num_chars(SNIPPET_CHARS);
+
+        let mut hits = Vec::new();
+        for (score, address) in top_docs {
+            let document = searcher.doc::<TantivyDocument>(address)?;
+            let Some(path) = document.get_first(self.fields.path).and_then(|value| value.as_str()) else {
+                continue;
+            };
+            let name = display_name(path);
+            let (file_type, can_preview) = crate::get_file_type_and_preview(&name);
+
+            hits.push(SearchHit {
+                folder: folder_of(path),
+                path: path.to_string(),
+                name,
+                file_type,
+                can_preview,
+                snippet: snippets.snippet_from_doc(&document).to_html(),
+                score,
+            });
+        }
+        Ok(hits)
+    }
+
+    fn indexed_files(&self) -> tantivy::Result<HashMap<String, u64>> {
+        let searcher = self.reader.searcher();
+        let mut files = HashMap::new();
+        for address in searcher.search(&AllQuery, &DocSetCollector)? {
+            let document = searcher.doc::<TantivyDocument>(address)?;
+            if let Some(path) = document.get_first(self.fields.path).and_then(|value| value.as_str()) {
+                let modified = document.get_first(self.fields.modified).and_then(|value| value.as_u64()).unwrap_or(0);
+                files.insert(path.to_string(), modified);
+            }
+        }
+        Ok(files)
+    }
+}
+
+// Pull searchable text out of a file. Formats we can't read are indexed by name only.
+fn extract_text(path: &Path, name: &str, size: u64) -> Option<String> {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+    let (file_type, _) = crate::get_file_type_and_preview(name);
+
+    let text = match (file_type.as_str(), extension.as_str()) {
+        ("text" | "code", _) => read_text(path)?,
+        _ if size > MAX_EXTRACT_FILE_SIZE => return None,
+        ("pdf", _) => extract_pdf(path)?,
+        (_, "docx") => extract_office(path, |name| name == "word/document.xml")?,
+        (_, "xlsx") => extract_office(path, |name| name == "xl/sharedStrings.xml")?,
+        (_, "pptx") => extract_office(path, |name| name.starts_with("ppt/slides/slide") && name.ends_with(".xml"))?,
+        (_, "odt" | "ods" | "odp") => extract_office(path, |name| name == "content.xml")?,
+        _ => return None,
+    };
+    Some(truncate(text))
+}
+
+fn read_text(path: &Path) -> Option<String> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path).ok()?
+        .take(MAX_INDEXED_TEXT as u64)
+        .read_to_end(&mut bytes)
+        .ok()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn extract_pdf(path: &Path) -> Option<String> {
+    // The PDF parser panics on some malformed files; a bad upload must not take the indexer down
+    let path = path.to_path_buf();
+    std::panic::catch_unwind(move || pdf_extract::extract_text(&path).ok()).ok().flatten()
+}
+
+// OOXML and OpenDocument files are zip archives of XML parts; collect the text nodes of
+// the parts that hold the document body
+fn extract_office(path: &Path, wanted: impl Fn(&str) -> bool) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut archive = zip::ZipArchive::new(file).ok()?;
+    let mut names: Vec<String> = archive.file_names().filter(|name| wanted(name)).map(str::to_string).collect();
+    names.sort_by_key(|name| (name.len(), name.clone()));
+
+    let mut text = String::new();
+    for name in names {
+        let mut xml = String::new();
+        let entry = archive.by_name(&name).ok()?;
+        entry.take(MAX_INDEXED_TEXT as u64 * 4).read_to_string(&mut xml).ok()?;
+        text.push_str(&xml_text(&xml));
+        text.push('\n');
+        if text.len() >= MAX_INDEXED_TEXT {
+            break;
+        }
+    }
+    Some(text)
+}
+
+// Strip tags from an XML document, keeping paragraph and cell boundaries as whitespace
+fn xml_text(xml: &str) -> String {
+    let mut text = String::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..start]));
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        let tag_name = tag.trim_start_matches('/').split([' ', '/', '\t', '\n']).next().unwrap_or("");
+        if tag.starts_with('/') || tag.ends_with('/') {
+            match tag_name {
+                "w:p" | "a:p" | "text:p" | "text:h" | "si" | "w:br" | "text:line-break" => text.push('\n'),
+                "w:tab" | "text:tab" | "table:table-cell" | "w:tc" => text.push('\t'),
+                _ => {}
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let after = &rest[start..];
+        let Some(end) = after.find(';').filter(|end| *end <= 10) else {
+            decoded.push('&');
+            rest = &after[1..];
+            continue;
+        };
+        let entity = &after[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse::<u32>))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &after[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &after[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_INDEXED_TEXT {
+        let mut end = MAX_INDEXED_TEXT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    // An upload directory and an index over it, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+        index: SearchIndex,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-search-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(base.join("uploads")).unwrap();
+            let index = SearchIndex::open(base.join("index")).unwrap();
+            Self { base, index }
+        }
+
+        fn uploads(&self) -> String {
+            self.base.join("uploads").to_string_lossy().to_string()
+        }
+
+        fn write(&self, path: &str, contents: &[u8]) {
+            let filepath = self.base.join("uploads").join(path);
+            std::fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+            std::fs::write(filepath, contents).unwrap();
+        }
+
+        // Move a stored file and tell the index, the way a move handler does
+        fn rename(&self, from: &str, to: &str) {
+            let uploads = self.base.join("uploads");
+            std::fs::create_dir_all(uploads.join(to).parent().unwrap()).unwrap();
+            std::fs::rename(uploads.join(from), uploads.join(to)).unwrap();
+            self.index.update(&self.uploads(), &[to.to_string()], &[from.to_string()]).unwrap();
+        }
+
+        fn paths(&self, query: &str) -> Vec<String> {
+            let mut paths: Vec<String> = self.index.search(query, 10).unwrap().into_iter().map(|hit| hit.path).collect();
+            paths.sort();
+            paths
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn xml_text_keeps_paragraphs_and_cells_apart() {
+        let docx = r#"<w:body><w:p><w:r><w:t>First</w:t></w:r><w:r><w:tab/><w:t xml:space="preserve">line</w:t></w:r></w:p><w:p><w:r><w:t>Second</w:t><w:br/><w:t>line</w:t></w:r></w:p></w:body>"#;
+        assert_eq!(xml_text(docx), "First\tline\nSecond\nline\n");
+
+        let odt = r#"<office:text><text:h>Title</text:h><text:p>A &amp; B<text:tab/>C</text:p><table:table-cell><text:p>cell</text:p></table:table-cell></office:text>"#;
+        assert_eq!(xml_text(odt), "Title\nA & B\tC\ncell\n\t");
+
+        // Text between tags is kept, and an unterminated tag ends the text
+        assert_eq!(xml_text("before<p>inside</p>after<broken"), "beforeinsideafter");
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(decode_entities("no entities"), "no entities");
+        assert_eq!(decode_entities("&lt;a href=&quot;x&quot;&gt; &amp; &apos;"), "<a href=\"x\"> & '");
+        assert_eq!(decode_entities("&#233;t&#xE9; &#X41;"), "été &#X41;");
+        // Anything that isn't a known entity stays as written
+        assert_eq!(decode_entities("AT&T; fish & chips &nbsp; &#xD800; &#99999999;"), "AT&T; fish & chips &nbsp; &#xD800; &#99999999;");
+        assert_eq!(decode_entities("&verylongentityname; &"), "&verylongentityname; &");
+        assert_eq!(decode_entities("&amp;amp;"), "&amp;");
+    }
+
+    #[test]
+    fn finds_files_by_name_and_content() {
+        let scratch = Scratch::new();
+        scratch.write("notes/groceries.txt", b"apples and pears");
+        scratch.write("report.md", b"Quarterly numbers for the board");
+        scratch.write("photo.jpg", b"pears in binary");
+        let added = ["notes/groceries.txt", "report.md", "photo.jpg"].map(str::to_string);
+        scratch.index.update(&scratch.uploads(), &added, &[]).unwrap();
+
+        assert_eq!(scratch.paths("pears"), ["notes/groceries.txt"]);
+        assert_eq!(scratch.paths("groceries"), ["notes/groceries.txt"]);
+        assert_eq!(scratch.paths("photo"), ["photo.jpg"]);
+        assert_eq!(scratch.paths("QUARTERLY board"), ["report.md"]);
+        // Query syntax typed by accident doesn't fail the search
+        assert_eq!(scratch.paths("\"board:"), ["report.md"]);
+
+        let hit = scratch.index.search("apples", 10).unwrap().remove(0);
+        assert_eq!((hit.name.as_str(), hit.folder.as_str()), ("groceries.txt", "notes"));
+        assert!(hit.snippet.contains("<b>apples</b>"));
+    }
+
+    #[test]
+    fn indexes_office_documents() {
+        let scratch = Scratch::new();
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(scratch.base.join("uploads/letter.docx")).unwrap());
+        zip.start_file("word/document.xml", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"<w:document><w:p><w:t>Dear shareholders</w:t></w:p></w:document>").unwrap();
+        zip.finish().unwrap();
+        scratch.index.update(&scratch.uploads(), &["letter.docx".to_string()], &[]).unwrap();
+
+        assert_eq!(scratch.paths("shareholders"), ["letter.docx"]);
+    }
+
+    #[test]
+    fn follows_changes_deletions_and_moves() {
+        let scratch = Scratch::new();
+        scratch.write("a.txt", b"original words");
+        scratch.write("b.txt", b"other words");
+        scratch.index.update(&scratch.uploads(), &["a.txt".to_string(), "b.txt".to_string()], &[]).unwrap();
+
+        // A changed file is indexed again rather than twice
+        scratch.write("a.txt", b"replacement words");
+        scratch.index.update(&scratch.uploads(), &["a.txt".to_string()], &[]).unwrap();
+        assert!(scratch.paths("original").is_empty());
+        assert_eq!(scratch.paths("replacement"), ["a.txt"]);
+        assert_eq!(scratch.paths("words"), ["a.txt", "b.txt"]);
+
+        scratch.rename("a.txt", "archive/a.txt");
+        assert_eq!(scratch.paths("replacement"), ["archive/a.txt"]);
+
+        std::fs::remove_file(scratch.base.join("uploads/b.txt")).unwrap();
+        scratch.index.update(&scratch.uploads(), &[], &["b.txt".to_string()]).unwrap();
+        assert_eq!(scratch.paths("words"), ["archive/a.txt"]);
+
+    }
+
+    #[test]
+    fn sync_catches_up_with_the_disk() {
+        let scratch = Scratch::new();
+        scratch.write("kept.txt", b"unchanged");
+        scratch.write("gone.txt", b"vanishing");
+        assert_eq!(scratch.index.sync(&scratch.uploads()).unwrap(), (2, 0));
+        // Nothing to do when nothing changed
+        assert_eq!(scratch.index.sync(&scratch.uploads()).unwrap(), (0, 0));
+
+        // Changes made behind the server's back, e.g. while it was down
+        std::fs::remove_file(scratch.base.join("uploads/gone.txt")).unwrap();
+        scratch.write("new/added.txt", b"arriving");
+        assert_eq!(scratch.index.sync(&scratch.uploads()).unwrap(), (1, 1));
+        assert!(scratch.paths("vanishing").is_empty());
+        assert_eq!(scratch.paths("arriving"), ["new/added.txt"]);
+        assert_eq!(scratch.paths("unchanged"), ["kept.txt"]);
+    }
+}
@@ -0,0 +1,529 @@
+use crate::storage::{read_json_or_default, unix_now, write_json_atomic};
+use actix_session::storage::{LoadError, SaveError, SessionKey, UpdateError};
+use actix_web::cookie::time::Duration;
+use cratr::SessionInfo;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::error;
+use uuid::Uuid;
+
+// Session state keys the store reads: whose session it is and where it is used from. Values
+// are JSON, as actix-session stores them.
+const USERNAME: &str = "username";
+const IP: &str = "ip";
+const USER_AGENT: &str = "user_agent";
+// Added to every session's state so handlers can tell which listed session is their own
+pub const SESSION_ID: &str = "session_id";
+// last_seen only moves in steps of this much, to spare the lock a write per request
+const LAST_SEEN_RESOLUTION: u64 = 60;
+// Sessions without a logged-in user, e.g. halfway through a single sign-on, kept at once
+const MAX_ANONYMOUS_SESSIONS: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredSession {
+    id: String,
+    created: u64,
+    last_seen: u64,
+    // Seconds without a request before the session expires
+    idle: u64,
+    state: HashMap<String, String>,
+}
+
+impl StoredSession {
+    fn username(&self) -> Option<String> {
+        serde_json::from_str(self.state.get(USERNAME)?).ok()
+    }
+
+    // Cheaper than `username()`, for checks that run over every session
+    fn logged_in(&self) -> bool {
+        self.state.contains_key(USERNAME)
+    }
+
+    fn info(&self, current: Option<&str>) -> SessionInfo {
+        let field = |key: &str| self.state.get(key).and_then(|value| serde_json::from_str(value).ok());
+        SessionInfo {
+            id: self.id.clone(),
+            created: self.created,
+            last_seen: self.last_seen,
+            ip: field(IP),
+            user_agent: field(USER_AGENT),
+            current: current == Some(self.id.as_str()),
+        }
+    }
+}
+
+// Login sessions kept on the server, so the cookie only carries a random key and a session
+// can be ended from anywhere. Sessions expire after a while without requests and, however
+// busy, some time after they started. Logged-in sessions are written to one JSON file keyed
+// by a SHA-256 of the cookie's key, so the file alone can't be used to take over a session.
+// Sessions nobody has logged in to yet stay in memory, and there can only be so many.
+pub struct SessionStore {
+    path: PathBuf,
+    sessions: RwLock<HashMap<String, StoredSession>>,
+    // Seconds after creation at which every session ends
+    lifetime: u64,
+    // Set when a logged-in session changed since the file was last written
+    dirty: AtomicBool,
+    // Held while writing, so two flushes never share the temporary file
+    writing: Mutex<()>,
+    // How many sessions without a logged-in user are kept at once
+    max_anonymous: usize,
+}
+
+impl SessionStore {
+    pub fn open(path: impl AsRef<Path>, lifetime: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut sessions: HashMap<String, StoredSession> = read_json_or_default(&path)?;
+        let now = unix_now();
+        sessions.retain(|_, session| !expired(session, now, lifetime));
+
+        Ok(Self {
+            path,
+            sessions: RwLock::new(sessions),
+            lifetime,
+            dirty: AtomicBool::new(false),
+            writing: Mutex::new(()),
+            max_anonymous: MAX_ANONYMOUS_SESSIONS,
+        })
+    }
+
+    // Write changes to disk every `interval` from a blocking thread, away from the requests
+    // that made them
+    pub fn flush_every(self: &Arc<Self>, interval: std::time::Duration) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let store = store.clone();
+                let _ = tokio::task::spawn_blocking(move || store.flush_or_log()).await;
+            }
+        });
+    }
+
+    // Write the logged-in sessions to disk if any changed. Last-seen times ride along with
+    // other changes and are written on shutdown, but never cause a write of their own.
+    pub fn flush(&self) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let persisted: HashMap<String, StoredSession> = self
+            .sessions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, session)| session.logged_in())
+            .map(|(hash, session)| (hash.clone(), session.clone()))
+            .collect();
+        write_json_atomic(&self.path, &persisted).inspect_err(|_| self.dirty.store(true, Ordering::Release))
+    }
+
+    // Write everything, last-seen times included, before the server stops
+    pub fn close(&self) -> io::Result<()> {
+        self.dirty.store(true, Ordering::Release);
+        self.flush()
+    }
+
+    fn flush_or_log(&self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to store sessions: {}", e);
+        }
+    }
+
+    // Note that what is on disk is out of date. Only logged-in sessions are written, so
+    // changes to other sessions don't count.
+    fn changed(&self, session: &StoredSession) {
+        if session.logged_in() {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    // Sessions of `username`, most recently used first. `current` is the id of the session
+    // asking, which gets marked.
+    pub fn list(&self, username: &str, current: Option<&str>) -> Vec<SessionInfo> {
+        let now = unix_now();
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|session| !expired(session, now, self.lifetime))
+            .filter(|session| session.username().as_deref() == Some(username))
+            .map(|session| session.info(current))
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen));
+        sessions
+    }
+
+    // Returns the ended session, or None if `username` has no session with that id
+    pub fn revoke(&self, username: &str, id: &str) -> Option<SessionInfo> {
+        let mut sessions = self.sessions.write().unwrap();
+        let hash = sessions
+            .iter()
+            .find(|(_, session)| session.id == id && session.username().as_deref() == Some(username))
+            .map(|(hash, _)| hash.clone())?;
+        let removed = sessions.remove(&hash)?;
+        self.changed(&removed);
+        Some(removed.info(None))
+    }
+
+    // End every session of `username` but the one with id `keep`. Returns how many ended.
+    pub fn revoke_all(&self, username: &str, keep: Option<&str>) -> usize {
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| {
+            session.username().as_deref() != Some(username) || keep == Some(session.id.as_str())
+        });
+        let ended = before - sessions.len();
+        if ended > 0 {
+            self.dirty.store(true, Ordering::Release);
+        }
+        ended
+    }
+
+    fn load(&self, key: &str) -> Option<HashMap<String, String>> {
+        let hash = hash_key(key);
+        let now = unix_now();
+        {
+            let sessions = self.sessions.read().unwrap();
+            let session = sessions.get(&hash)?;
+            if !expired(session, now, self.lifetime) {
+                return Some(session.state.clone());
+            }
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(removed) = sessions.remove(&hash) {
+            self.changed(&removed);
+        }
+        None
+    }
+
+    // A new session under a fresh key
+    fn save(&self, mut state: HashMap<String, String>, idle: u64) -> io::Result<String> {
+        let key = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+        state.insert(SESSION_ID.to_string(), serde_json::to_string(&id)?);
+        let now = unix_now();
+        let session = StoredSession {
+            id,
+            created: now,
+            last_seen: now,
+            idle,
+            state,
+        };
+
+        let mut sessions = self.sessions.write().unwrap();
+        // Anonymous sessions are never revoked, so this is where the old ones get dropped
+        sessions.retain(|_, session| {
+            let keep = !expired(session, now, self.lifetime);
+            if !keep {
+                self.changed(session);
+            }
+            keep
+        });
+        if !session.logged_in() {
+            make_room(&mut sessions, self.max_anonymous);
+        }
+        self.changed(&session);
+        sessions.insert(hash_key(&key), session);
+        Ok(key)
+    }
+
+    // Replace the state of a session. One revoked while its request ran stays ended rather
+    // than coming back.
+    fn update(&self, key: &str, mut state: HashMap<String, String>, idle: u64) -> io::Result<()> {
+        let mut sessions = self.sessions.write().unwrap();
+        let Some(session) = sessions.get_mut(&hash_key(key)) else {
+            return Ok(());
+        };
+        state.insert(SESSION_ID.to_string(), serde_json::to_string(&session.id)?);
+        // Logging out turns a stored session into one that must disappear from the file
+        self.changed(session);
+        session.state = state;
+        session.last_seen = unix_now();
+        session.idle = idle;
+        self.changed(session);
+        Ok(())
+    }
+
+    // Note a request on a session whose state didn't change
+    fn touch(&self, key: &str, idle: u64) {
+        let hash = hash_key(key);
+        let now = unix_now();
+        {
+            let sessions = self.sessions.read().unwrap();
+            match sessions.get(&hash) {
+                Some(session) if now < session.last_seen + LAST_SEEN_RESOLUTION && session.idle == idle => return,
+                Some(_) => {}
+                None => return,
+            }
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(session) = sessions.get_mut(&hash) {
+            session.last_seen = now;
+            session.idle = idle;
+        }
+    }
+
+    fn delete(&self, key: &str) {
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(removed) = sessions.remove(&hash_key(key)) {
+            self.changed(&removed);
+        }
+    }
+}
+
+// Drop the least recently used anonymous sessions until there is room for one more, so
+// requests that start sessions without logging in can't grow the store without bound
+fn make_room(sessions: &mut HashMap<String, StoredSession>, max: usize) {
+    let count = sessions.values().filter(|session| !session.logged_in()).count();
+    if count < max {
+        return;
+    }
+    let mut anonymous: Vec<(u64, &String)> = sessions
+        .iter()
+        .filter(|(_, session)| !session.logged_in())
+        .map(|(hash, session)| (session.last_seen, hash))
+        .collect();
+    anonymous.sort();
+    let oldest: Vec<String> = anonymous
+        .into_iter()
+        .take(count + 1 - max)
+        .map(|(_, hash)| hash.clone())
+        .collect();
+    for hash in oldest {
+        sessions.remove(&hash);
+    }
+}
+
+fn expired(session: &StoredSession, now: u64, lifetime: u64) -> bool {
+    now >= session.last_seen + session.idle || now >= session.created + lifetime
+}
+
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn seconds(ttl: &Duration) -> u64 {
+    ttl.whole_seconds().max(0) as u64
+}
+
+// What the session middleware talks to. The idle timeout is the state TTL it is built with.
+pub struct SessionBackend(pub Arc<SessionStore>);
+
+impl actix_session::storage::SessionStore for SessionBackend {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        Ok(self.0.load(session_key.as_ref()))
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        let key = self.0.save(session_state, seconds(ttl)).map_err(|e| SaveError::Other(e.into()))?;
+        SessionKey::try_from(key).map_err(|e| SaveError::Other(e.into()))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.0
+            .update(session_key.as_ref(), session_state, seconds(ttl))
+            .map_err(|e| UpdateError::Other(e.into()))?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        self.0.touch(session_key.as_ref(), seconds(ttl));
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.0.delete(session_key.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A session store in a scratch directory, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+        store: SessionStore,
+    }
+
+    impl Scratch {
+        fn new(lifetime: u64) -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-sessions-{}", Uuid::new_v4().simple()));
+            let store = SessionStore::open(base.join("sessions.json"), lifetime).unwrap();
+            Self { base, store }
+        }
+
+        fn file(&self) -> PathBuf {
+            self.base.join("sessions.json")
+        }
+
+        fn on_disk(&self) -> HashMap<String, StoredSession> {
+            read_json_or_default(&self.file()).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn state(username: Option<&str>) -> HashMap<String, String> {
+        let mut state = HashMap::new();
+        if let Some(username) = username {
+            state.insert(USERNAME.to_string(), serde_json::to_string(username).unwrap());
+        }
+        state
+    }
+
+    #[test]
+    fn only_logged_in_sessions_reach_the_disk() {
+        let scratch = Scratch::new(3600);
+        let anonymous = scratch.store.save(state(None), 600).unwrap();
+        scratch.store.flush().unwrap();
+        assert!(!scratch.file().exists());
+        assert!(scratch.store.load(&anonymous).is_some());
+
+        // Logging in is what makes a session worth keeping
+        scratch.store.update(&anonymous, state(Some("alice")), 600).unwrap();
+        scratch.store.flush().unwrap();
+        assert_eq!(scratch.on_disk().len(), 1);
+
+        // And logging out takes it off the disk again
+        scratch.store.update(&anonymous, state(None), 600).unwrap();
+        scratch.store.flush().unwrap();
+        assert!(scratch.on_disk().is_empty());
+    }
+
+    #[test]
+    fn touching_a_session_writes_nothing() {
+        let scratch = Scratch::new(3600);
+        let key = scratch.store.save(state(Some("alice")), 600).unwrap();
+        scratch.store.flush().unwrap();
+        std::fs::remove_file(scratch.file()).unwrap();
+
+        scratch.store.touch(&key, 900);
+        scratch.store.flush().unwrap();
+        assert!(!scratch.file().exists());
+
+        // The new idle timeout is kept and written on shutdown
+        scratch.store.close().unwrap();
+        assert_eq!(scratch.on_disk().values().next().unwrap().idle, 900);
+    }
+
+    #[test]
+    fn anonymous_sessions_are_capped() {
+        let mut scratch = Scratch::new(3600);
+        scratch.store.max_anonymous = 5;
+        let alice = scratch.store.save(state(Some("alice")), 600).unwrap();
+        let first = scratch.store.save(state(None), 600).unwrap();
+        // The oldest anonymous session is the one that goes
+        scratch.store.sessions.write().unwrap().get_mut(&hash_key(&first)).unwrap().last_seen -= 10;
+        for _ in 0..5 {
+            scratch.store.save(state(None), 600).unwrap();
+        }
+
+        let sessions = scratch.store.sessions.read().unwrap();
+        let anonymous = sessions.values().filter(|session| !session.logged_in()).count();
+        assert_eq!(anonymous, 5);
+        assert!(!sessions.contains_key(&hash_key(&first)));
+        assert!(sessions.contains_key(&hash_key(&alice)));
+    }
+
+    // Move a session's clock back, as if `seconds` had passed since it was last used
+    fn age(scratch: &Scratch, key: &str, seconds: u64) {
+        let mut sessions = scratch.store.sessions.write().unwrap();
+        let session = sessions.get_mut(&hash_key(key)).unwrap();
+        session.created -= seconds;
+        session.last_seen -= seconds;
+    }
+
+    fn id(scratch: &Scratch, key: &str) -> String {
+        scratch.store.sessions.read().unwrap()[&hash_key(key)].id.clone()
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let scratch = Scratch::new(3600);
+        let key = scratch.store.save(state(Some("alice")), 600).unwrap();
+        age(&scratch, &key, 599);
+        assert!(scratch.store.load(&key).is_some());
+        age(&scratch, &key, 1);
+        assert!(scratch.store.load(&key).is_none());
+        assert!(scratch.store.list("alice", None).is_empty());
+    }
+
+    #[test]
+    fn busy_sessions_still_end_after_their_lifetime() {
+        let scratch = Scratch::new(3600);
+        let key = scratch.store.save(state(Some("alice")), 600).unwrap();
+        for _ in 0..7 {
+            age(&scratch, &key, 500);
+            assert!(scratch.store.load(&key).is_some());
+            scratch.store.touch(&key, 600);
+        }
+        // Used a moment ago, but started an hour ago
+        age(&scratch, &key, 100);
+        assert!(scratch.store.load(&key).is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_dropped_when_the_file_is_read() {
+        let scratch = Scratch::new(3600);
+        let old = scratch.store.save(state(Some("alice")), 600).unwrap();
+        let current = scratch.store.save(state(Some("alice")), 600).unwrap();
+        age(&scratch, &old, 600);
+        scratch.store.close().unwrap();
+
+        let reopened = SessionStore::open(scratch.file(), 3600).unwrap();
+        assert!(reopened.load(&old).is_none());
+        assert_eq!(reopened.load(&current).unwrap()[USERNAME], "\"alice\"");
+    }
+
+    #[test]
+    fn revoked_sessions_stay_ended() {
+        let scratch = Scratch::new(3600);
+        let phone = scratch.store.save(state(Some("alice")), 600).unwrap();
+        let laptop = scratch.store.save(state(Some("alice")), 600).unwrap();
+        let bobs = scratch.store.save(state(Some("bob")), 600).unwrap();
+        age(&scratch, &phone, 120);
+
+        let listed = scratch.store.list("alice", Some(&id(&scratch, &laptop)));
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].current && listed[0].id == id(&scratch, &laptop));
+        assert!(!listed[1].current);
+
+        // Only the owner can end a session
+        assert!(scratch.store.revoke("bob", &id(&scratch, &phone)).is_none());
+        assert!(scratch.store.revoke("alice", &id(&scratch, &phone)).is_some());
+        assert!(scratch.store.load(&phone).is_none());
+        // A request that was running on it can't bring it back
+        scratch.store.update(&phone, state(Some("alice")), 600).unwrap();
+        assert!(scratch.store.load(&phone).is_none());
+        scratch.store.flush().unwrap();
+        assert_eq!(scratch.on_disk().len(), 2);
+
+        let other = scratch.store.save(state(Some("alice")), 600).unwrap();
+        assert_eq!(scratch.store.revoke_all("alice", Some(&id(&scratch, &laptop))), 1);
+        assert!(scratch.store.load(&other).is_none());
+        assert!(scratch.store.load(&laptop).is_some());
+        assert_eq!(scratch.store.revoke_all("alice", None), 1);
+        assert!(scratch.store.load(&laptop).is_none());
+        assert!(scratch.store.load(&bobs).is_some());
+    }
+}
+
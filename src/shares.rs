@@ -0,0 +1,459 @@
+use crate::storage::{read_json_or_default, write_json_atomic};
+use cratr::{Access, FolderShare, Grant, Group, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+// Built-in group that every account belongs to
+pub const EVERYONE: &str = "everyone";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FolderAcl {
+    owner: String,
+    #[serde(default)]
+    grants: Vec<Grant>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SharesFile {
+    // Keyed by folder path relative to the upload directory
+    #[serde(default)]
+    folders: BTreeMap<String, FolderAcl>,
+    // Group name to member usernames
+    #[serde(default)]
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+// What someone may do with the files in a folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FolderAccess {
+    // Not a private folder; only the role applies
+    Open,
+    Owner,
+    Shared(Access),
+    Denied,
+}
+
+impl FolderAccess {
+    pub fn allows(self, needed: Access) -> bool {
+        match self {
+            FolderAccess::Open | FolderAccess::Owner => true,
+            FolderAccess::Shared(access) => access >= needed,
+            FolderAccess::Denied => false,
+        }
+    }
+}
+
+// Private folders and their access lists, plus the groups they can be shared with.
+//
+// A folder created by a user belongs to them and only they can see it. An owner can share it,
+// or any folder below it, with users and groups. Grants add up along the path, so access to a
+// folder includes everything below it. Folders that no one owns (the top level and anything
+// from before sharing existed) stay open to everyone whose role allows it.
+pub struct ShareStore {
+    path: PathBuf,
+    state: RwLock<SharesFile>,
+}
+
+impl ShareStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = read_json_or_default(&path)?;
+
+        Ok(Self {
+            path,
+            state: RwLock::new(state),
+        })
+    }
+
+    // Access `username` has to the files directly inside `folder` ("" is the top level)
+    pub fn access(&self, username: &str, folder: &str) -> FolderAccess {
+        let state = self.state.read().unwrap();
+        let mut owner: Option<&str> = None;
+        let mut best: Option<Access> = None;
+        for ancestor in ancestors(folder) {
+            let Some(acl) = state.folders.get(ancestor) else {
+                continue;
+            };
+            owner.get_or_insert(&acl.owner);
+            for grant in &acl.grants {
+                if matches(&state, &grant.principal, username) {
+                    best = best.max(Some(grant.access));
+                }
+            }
+        }
+
+        match (owner, best) {
+            (None, _) => FolderAccess::Open,
+            (Some(owner), _) if owner == username => FolderAccess::Owner,
+            (Some(_), Some(access)) => FolderAccess::Shared(access),
+            (Some(_), None) => FolderAccess::Denied,
+        }
+    }
+
+    // Owner of the private tree `folder` is in, if any
+    pub fn owner(&self, folder: &str) -> Option<String> {
+        let state = self.state.read().unwrap();
+        ancestors(folder).find_map(|ancestor| state.folders.get(ancestor).map(|acl| acl.owner.clone()))
+    }
+
+    // Make a newly created folder private to its creator, unless it already sits inside
+    // someone's private folder
+    pub fn claim(&self, folder: &str, username: &str) -> io::Result<()> {
+        let folder = folder.trim_matches('/');
+        if folder.is_empty() || self.owner(folder).is_some() {
+            return Ok(());
+        }
+        let mut state = self.state.write().unwrap();
+        state.folders.insert(folder.to_string(), FolderAcl {
+            owner: username.to_string(),
+            grants: Vec::new(),
+        });
+        self.persist(&state)
+    }
+
+    // The access list set on `folder` itself, if it is private
+    pub fn share(&self, folder: &str) -> Option<FolderShare> {
+        let owner = self.owner(folder)?;
+        let state = self.state.read().unwrap();
+        let grants = state.folders.get(folder).map(|acl| acl.grants.clone()).unwrap_or_default();
+        Some(FolderShare {
+            folder: folder.to_string(),
+            owner,
+            grants,
+            access: None,
+        })
+    }
+
+    // Replace the access list of `folder`. An open folder becomes private to `owner`.
+    pub fn set_grants(&self, folder: &str, owner: &str, grants: Vec<Grant>) -> io::Result<()> {
+        let mut state = self.state.write().unwrap();
+        let acl = state.folders.entry(folder.to_string()).or_insert_with(|| FolderAcl {
+            owner: owner.to_string(),
+            grants: Vec::new(),
+        });
+        acl.grants = grants;
+
+        // A subfolder entry with nothing granted adds nothing to its parent's list
+        let redundant = acl.grants.is_empty()
+            && ancestors(folder).any(|ancestor| ancestor != folder && state.folders.contains_key(ancestor));
+        if redundant {
+            state.folders.remove(folder);
+        }
+        self.persist(&state)
+    }
+
+    // Folders owned by `username` that have an access list of their own
+    pub fn owned_by(&self, username: &str) -> Vec<FolderShare> {
+        let state = self.state.read().unwrap();
+        state
+            .folders
+            .iter()
+            .filter(|(_, acl)| acl.owner == username)
+            .map(|(folder, acl)| FolderShare {
+                folder: folder.clone(),
+                owner: acl.owner.clone(),
+                grants: acl.grants.clone(),
+                access: None,
+            })
+            .collect()
+    }
+
+    // Folders someone else shared with `username`, directly or through a group
+    pub fn shared_with(&self, username: &str) -> Vec<FolderShare> {
+        let state = self.state.read().unwrap();
+        state
+            .folders
+            .iter()
+            .filter(|(_, acl)| acl.owner != username)
+            .filter_map(|(folder, acl)| {
+                let access = acl
+                    .grants
+                    .iter()
+                    .filter(|grant| matches(&state, &grant.principal, username))
+                    .map(|grant| grant.access)
+                    .max()?;
+                Some(FolderShare {
+                    folder: folder.clone(),
+                    owner: acl.owner.clone(),
+                    grants: Vec::new(),
+                    access: Some(access),
+                })
+            })
+            .collect()
+    }
+
+    pub fn groups(&self) -> Vec<Group> {
+        self.state
+            .read()
+            .unwrap()
+            .groups
+            .iter()
+            .map(|(name, members)| Group {
+                name: name.clone(),
+                members: members.clone(),
+            })
+            .collect()
+    }
+
+    pub fn group_exists(&self, name: &str) -> bool {
+        name == EVERYONE || self.state.read().unwrap().groups.contains_key(name)
+    }
+
+    // Create a group or replace its members
+    pub fn set_group(&self, name: &str, mut members: Vec<String>) -> io::Result<()> {
+        members.sort();
+        members.dedup();
+        let mut state = self.state.write().unwrap();
+        state.groups.insert(name.to_string(), members);
+        self.persist(&state)
+    }
+
+    // Delete a group along with every grant to it. Returns false if there was no such group.
+    pub fn delete_group(&self, name: &str) -> io::Result<bool> {
+        let mut state = self.state.write().unwrap();
+        if state.groups.remove(name).is_none() {
+            return Ok(false);
+        }
+        let principal = Principal::Group(name.to_string());
+        for acl in state.folders.values_mut() {
+            acl.grants.retain(|grant| grant.principal != principal);
+        }
+        self.persist(&state)?;
+        Ok(true)
+    }
+
+    // Drop the access lists of folders that are gone. Folders are deleted along with their
+    // last file, and one created later at the same path must not inherit the old owner and grants.
+    pub fn forget_missing(&self, root: &Path) -> io::Result<()> {
+        let mut state = self.state.write().unwrap();
+        let before = state.folders.len();
+        state.folders.retain(|folder, _| root.join(folder).is_dir());
+        if state.folders.len() == before {
+            return Ok(());
+        }
+        self.persist(&state)
+    }
+
+    // Clean up after a deleted account: drop its grants and group memberships and hand the
+    // folders it owned to `new_owner`, so a later account of the same name inherits nothing
+    pub fn forget_user(&self, username: &str, new_owner: &str) -> io::Result<()> {
+        let mut state = self.state.write().unwrap();
+        let principal = Principal::User(username.to_string());
+        for acl in state.folders.values_mut() {
+            acl.grants.retain(|grant| grant.principal != principal);
+            if acl.owner == username {
+                acl.owner = new_owner.to_string();
+            }
+        }
+        for members in state.groups.values_mut() {
+            members.retain(|member| member != username);
+        }
+        self.persist(&state)
+    }
+
+    fn persist(&self, state: &SharesFile) -> io::Result<()> {
+        write_json_atomic(&self.path, state)
+    }
+}
+
+fn matches(state: &SharesFile, principal: &Principal, username: &str) -> bool {
+    match principal {
+        Principal::User(user) => user == username,
+        Principal::Group(group) if group == EVERYONE => true,
+        Principal::Group(group) => state.groups.get(group).is_some_and(|members| members.iter().any(|m| m == username)),
+    }
+}
+
+// "a/b/c" -> "a", "a/b", "a/b/c"
+fn ancestors(folder: &str) -> impl Iterator<Item = &str> {
+    let folder = folder.trim_matches('/');
+    folder
+        .match_indices('/')
+        .map(move |(index, _)| &folder[..index])
+        .chain((!folder.is_empty()).then_some(folder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{folder_of, resolve};
+    use uuid::Uuid;
+
+    // A scratch directory holding an upload root and a shares file, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-shares-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(base.join("uploads/a/b")).unwrap();
+            std::fs::write(base.join("uploads/a/b/secret.txt"), "secret").unwrap();
+            std::fs::write(base.join("uploads/a/open.txt"), "open").unwrap();
+            Self { base }
+        }
+
+        fn root(&self) -> String {
+            self.base.join("uploads").to_string_lossy().to_string()
+        }
+
+        fn store(&self) -> ShareStore {
+            ShareStore::open(self.base.join("shares.json")).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn odd_spellings_of_a_private_subfolder_never_reach_it() {
+        let scratch = Scratch::new();
+        let shares = scratch.store();
+        // "a" is open to everyone, "a/b" is private to alice
+        shares.claim("a/b", "alice").unwrap();
+        assert_eq!(shares.access("bob", "a"), FolderAccess::Open);
+
+        for path in [
+            "a/b/secret.txt",
+            "/a/b/secret.txt",
+            "a/b/secret.txt/",
+            "a//b/secret.txt",
+            "a/./b/secret.txt",
+            "a/b//secret.txt",
+            "a/b/./secret.txt",
+            "./a/b/secret.txt",
+            "a/b/secret.txt//",
+        ] {
+            // Either the path is refused outright or it is checked under its canonical folder
+            if let Some(resolved) = resolve(&scratch.root(), path) {
+                assert!(resolved.relative.starts_with("a/b"), "{:?}", path);
+                assert_eq!(shares.access("bob", &folder_of(&resolved.relative)), FolderAccess::Denied, "{:?}", path);
+                assert_eq!(shares.access("alice", &folder_of(&resolved.relative)), FolderAccess::Owner, "{:?}", path);
+            }
+        }
+        assert!(resolve(&scratch.root(), "a//b/secret.txt").is_none());
+        assert!(resolve(&scratch.root(), "a/./b/secret.txt").is_none());
+    }
+
+    fn user(name: &str, access: Access) -> Grant {
+        Grant {
+            principal: Principal::User(name.to_string()),
+            access,
+        }
+    }
+
+    fn group(name: &str, access: Access) -> Grant {
+        Grant {
+            principal: Principal::Group(name.to_string()),
+            access,
+        }
+    }
+
+    #[test]
+    fn grants_reach_every_folder_below() {
+        let scratch = Scratch::new();
+        let shares = scratch.store();
+        shares.claim("a", "alice").unwrap();
+        // A folder inside someone's private folder stays theirs
+        shares.claim("a/b", "bob").unwrap();
+        assert_eq!(shares.owner("a/b"), Some("alice".to_string()));
+
+        shares.set_grants("a", "alice", vec![user("bob", Access::Read)]).unwrap();
+        assert_eq!(shares.access("alice", "a/b/c"), FolderAccess::Owner);
+        assert_eq!(shares.access("bob", "a"), FolderAccess::Shared(Access::Read));
+        assert_eq!(shares.access("bob", "a/b/c"), FolderAccess::Shared(Access::Read));
+        assert_eq!(shares.access("carol", "a/b"), FolderAccess::Denied);
+        assert_eq!(shares.access("carol", "elsewhere"), FolderAccess::Open);
+        assert!(!FolderAccess::Shared(Access::Read).allows(Access::ReadWrite));
+
+        let reopened = scratch.store();
+        assert_eq!(reopened.access("bob", "a/b"), FolderAccess::Shared(Access::Read));
+    }
+
+    #[test]
+    fn a_subfolder_grant_adds_to_its_parents_but_never_takes_away() {
+        let scratch = Scratch::new();
+        let shares = scratch.store();
+        shares.claim("a", "alice").unwrap();
+        shares.set_group("team", vec!["carol".to_string(), "bob".to_string(), "bob".to_string()]).unwrap();
+        shares.set_grants("a", "alice", vec![group("team", Access::Read)]).unwrap();
+        shares.set_grants("a/b", "alice", vec![user("bob", Access::ReadWrite), user("carol", Access::Read)]).unwrap();
+
+        assert_eq!(shares.access("bob", "a"), FolderAccess::Shared(Access::Read));
+        assert_eq!(shares.access("bob", "a/b"), FolderAccess::Shared(Access::ReadWrite));
+        assert_eq!(shares.access("carol", "a/b"), FolderAccess::Shared(Access::Read));
+        assert_eq!(shares.groups()[0].members, vec!["bob", "carol"]);
+
+        // Write access to the parent isn't lowered by a read grant further down
+        shares.set_grants("a", "alice", vec![group(EVERYONE, Access::ReadWrite)]).unwrap();
+        assert_eq!(shares.access("carol", "a/b"), FolderAccess::Shared(Access::ReadWrite));
+        assert_eq!(shares.access("dave", "a/b/c"), FolderAccess::Shared(Access::ReadWrite));
+
+        // An emptied subfolder list is dropped, the parent's still applies
+        shares.set_grants("a/b", "alice", Vec::new()).unwrap();
+        assert!(shares.share("a/b").unwrap().grants.is_empty());
+        assert_eq!(shares.owned_by("alice").len(), 1);
+        assert_eq!(shares.access("bob", "a/b"), FolderAccess::Shared(Access::ReadWrite));
+    }
+
+    #[test]
+    fn deleting_a_group_drops_its_grants() {
+        let scratch = Scratch::new();
+        let shares = scratch.store();
+        shares.set_group("team", vec!["bob".to_string()]).unwrap();
+        shares.set_grants("a", "alice", vec![group("team", Access::Read)]).unwrap();
+        assert_eq!(shares.shared_with("bob")[0].access, Some(Access::Read));
+
+        assert!(shares.delete_group("team").unwrap());
+        assert!(!shares.delete_group("team").unwrap());
+        assert_eq!(shares.access("bob", "a"), FolderAccess::Denied);
+        assert!(shares.shared_with("bob").is_empty());
+        // A new group of the same name gets nothing back
+        shares.set_group("team", vec!["bob".to_string()]).unwrap();
+        assert_eq!(shares.access("bob", "a"), FolderAccess::Denied);
+    }
+
+    #[test]
+    fn folders_that_are_gone_are_forgotten() {
+        let scratch = Scratch::new();
+        let shares = scratch.store();
+        shares.claim("a/b", "alice").unwrap();
+        shares.claim("gone", "alice").unwrap();
+        shares.forget_missing(Path::new(&scratch.root())).unwrap();
+
+        assert_eq!(shares.owner("a/b"), Some("alice".to_string()));
+        assert_eq!(shares.owner("gone"), None);
+        assert_eq!(scratch.store().owner("gone"), None);
+        // A folder created later at the same path belongs to whoever creates it
+        shares.claim("gone", "bob").unwrap();
+        assert_eq!(shares.access("bob", "gone"), FolderAccess::Owner);
+        assert_eq!(shares.access("alice", "gone"), FolderAccess::Denied);
+    }
+
+    #[test]
+    fn a_deleted_account_leaves_nothing_to_a_new_one_of_that_name() {
+        let scratch = Scratch::new();
+        let shares = scratch.store();
+        shares.claim("a", "alice").unwrap();
+        shares.claim("bobs", "bob").unwrap();
+        shares.set_grants("bobs", "bob", vec![user("alice", Access::ReadWrite)]).unwrap();
+        shares.set_group("team", vec!["alice".to_string(), "carol".to_string()]).unwrap();
+
+        shares.forget_user("alice", "admin").unwrap();
+        assert_eq!(shares.owner("a"), Some("admin".to_string()));
+        assert_eq!(shares.access("alice", "a"), FolderAccess::Denied);
+        assert_eq!(shares.access("alice", "bobs"), FolderAccess::Denied);
+        assert_eq!(shares.groups()[0].members, vec!["carol"]);
+        assert!(shares.owned_by("alice").is_empty());
+
+        let reopened = scratch.store();
+        assert_eq!(reopened.owner("a"), Some("admin".to_string()));
+        assert!(reopened.shared_with("alice").is_empty());
+    }
+}
@@ -0,0 +1,581 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+// A regular file somewhere below the upload directory
+pub struct StoredFile {
+    // Path relative to the upload directory, always using '/' separators
+    pub path: String,
+    pub size: u64,
+    // Seconds since the Unix epoch
+    pub modified: u64,
+}
+
+// Recursively collect every regular file below `root`. Symlinks are skipped so the
+// walk can never leave the upload directory.
+pub fn walk_files(root: &Path) -> Vec<StoredFile> {
+    let mut files = Vec::new();
+    walk_dir(root, "", &mut files);
+    files
+}
+
+fn walk_dir(dir: &Path, prefix: &str, files: &mut Vec<StoredFile>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative = join_relative(prefix, &name);
+
+        if file_type.is_dir() {
+            walk_dir(&entry.path(), &relative, files);
+        } else if file_type.is_file() {
+            if let Ok(metadata) = entry.metadata() {
+                let modified = metadata.modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or(0);
+                files.push(StoredFile {
+                    path: relative,
+                    size: metadata.len(),
+                    modified,
+                });
+            }
+        }
+    }
+}
+
+// Current time in seconds since the Unix epoch, matching `StoredFile::modified`
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn join_relative(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+// A user-supplied path once resolved: where it is on disk, and the same place relative to the
+// root with '/' separators and symlinks followed. Access checks and metadata keys use
+// `relative`, so one file can't hide behind a second spelling of its path.
+pub struct Resolved {
+    pub path: PathBuf,
+    pub relative: String,
+}
+
+// The one way a user-supplied relative path becomes a location on disk. The path must be
+// plain: not absolute, no empty, `.` or `..` components, no backslashes or NUL bytes. Only
+// leading and trailing slashes are dropped. Symlinks along it are followed and the result
+// must still be inside `root`, so a link in the upload directory can't lead anywhere else.
+// The location is the canonical one, with the parts that don't exist yet appended as given.
+pub fn resolve(root: &str, relative: &str) -> Option<Resolved> {
+    let relative = relative.trim_matches('/');
+    if relative.is_empty() || relative.contains(['\\', '\0']) {
+        return None;
+    }
+    // `components()` would quietly fold "a//b" and "a/./b" into "a/b"
+    if relative.split('/').any(|part| part.is_empty() || part == ".") {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            _ => return None,
+        }
+    }
+    let root = Path::new(root).canonicalize().ok()?;
+    let path = confine(&root, &parts)?;
+    let relative = path
+        .strip_prefix(&root)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?
+        .join("/");
+    // A symlink to the root itself leaves nothing to name
+    if relative.is_empty() {
+        return None;
+    }
+    Some(Resolved { path, relative })
+}
+
+// Just the location on disk, for callers that already hold a canonical path
+pub fn resolve_relative(root: &str, relative: &str) -> Option<PathBuf> {
+    resolve(root, relative).map(|resolved| resolved.path)
+}
+
+// Like `resolve_relative`, but an empty folder is the root itself
+pub fn resolve_folder(root: &str, folder: &str) -> Option<PathBuf> {
+    if folder.trim_matches('/').is_empty() {
+        Path::new(root).canonicalize().ok()
+    } else {
+        resolve_relative(root, folder)
+    }
+}
+
+fn confine(root: &Path, parts: &[&OsStr]) -> Option<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    for (index, part) in parts.iter().enumerate() {
+        let next = resolved.join(part);
+        match std::fs::symlink_metadata(&next) {
+            Ok(_) => {
+                // A dangling link fails here too: whatever gets created through it could be anywhere
+                resolved = next.canonicalize().ok()?;
+                if !resolved.starts_with(root) {
+                    return None;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                resolved = next;
+                resolved.extend(&parts[index + 1..]);
+                return Some(resolved);
+            }
+            Err(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+// Characters dropped from filenames besides control and invisible formatting characters:
+// path separators, what Windows refuses in names, and what would end or escape a URL path
+const UNSAFE_FILENAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|', '#', '%'];
+// Filesystems allow 255 bytes per name; uploads also get a 37-byte UUID prefix
+const MAX_FILENAME_BYTES: usize = 200;
+
+// Make a user-supplied name safe to store. Letters from any script, spaces and ordinary
+// punctuation stay; separators, control characters and bidi overrides (which can make
+// "exe.txt" look like "txt.exe") go. Runs of whitespace become one space, and leading dots,
+// trailing dots and surrounding spaces are trimmed. Overlong names are cut, keeping the
+// extension.
+pub fn sanitize_filename(filename: &str) -> String {
+    let mut sanitized = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        if c.is_whitespace() {
+            if !sanitized.is_empty() && !sanitized.ends_with(' ') {
+                sanitized.push(' ');
+            }
+        } else if !c.is_control() && !is_format_char(c) && !UNSAFE_FILENAME_CHARS.contains(&c) {
+            sanitized.push(c);
+        }
+    }
+    let sanitized = sanitized.trim_start_matches(['.', ' ']).trim_end_matches(['.', ' ']);
+    truncate_filename(sanitized, MAX_FILENAME_BYTES)
+}
+
+// Zero-width and direction-changing characters that render as nothing
+fn is_format_char(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{061C}' | '\u{180E}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{206F}' | '\u{FEFF}' | '\u{FFF9}'..='\u{FFFB}')
+}
+
+fn truncate_filename(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= 16 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = max - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end_matches(['.', ' ']), extension)
+}
+
+// Clean up a user-supplied folder path: every component is sanitized like a filename and
+// empty or dot-only components are dropped, so the result always stays below the root
+pub fn sanitize_folder(folder: &str) -> String {
+    folder
+        .split('/')
+        .map(sanitize_filename)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// After a file is removed or moved, delete any folders it leaves empty (but never the root)
+pub fn remove_empty_parents(root: &str, relative: &str) {
+    let mut folder = folder_of(relative);
+    while !folder.is_empty() {
+        if std::fs::remove_dir(Path::new(root).join(&folder)).is_err() {
+            break;
+        }
+        folder = folder_of(&folder);
+    }
+}
+
+// Move a file, copying it instead when the two places are on different filesystems
+pub fn move_file(source: &Path, target: &Path) -> io::Result<()> {
+    if std::fs::rename(source, target).is_err() {
+        std::fs::copy(source, target)?;
+        std::fs::remove_file(source)?;
+    }
+    Ok(())
+}
+
+// Load a JSON store, starting empty when the file doesn't exist yet
+pub fn read_json_or_default<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e),
+    }
+}
+
+// Replace a JSON store. The new contents go to a temporary file that is then renamed over
+// the old one, so a crash never leaves a truncated store behind.
+pub fn write_json_atomic<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let bytes = serde_json::to_vec_pretty(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, bytes)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+// Older generation `n` of a size-rotated log: audit.jsonl -> audit.1.jsonl, audit.2.jsonl, ...
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("log");
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("jsonl");
+    path.with_file_name(format!("{}.{}.{}", stem, n, extension))
+}
+
+// Shift every generation of a log one older, dropping the oldest beyond `keep`, so the
+// caller can start `path` afresh
+pub fn rotate_files(path: &Path, keep: usize) -> io::Result<()> {
+    let _ = std::fs::remove_file(rotated_path(path, keep));
+    for n in (1..keep).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            std::fs::rename(&from, rotated_path(path, n + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated_path(path, 1))
+}
+
+// Folder part of a relative path ("" for files at the top level)
+pub fn folder_of(path: &str) -> String {
+    path.rsplit_once('/')
+        .map(|(folder, _)| folder.to_string())
+        .unwrap_or_default()
+}
+
+// Name to show for a stored file: the last path component without the UUID prefix
+// that uploads get to avoid collisions
+pub fn display_name(path: &str) -> String {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    match filename.split_once('_') {
+        Some((prefix, rest)) if Uuid::parse_str(prefix).is_ok() && !rest.is_empty() => rest.to_string(),
+        _ => filename.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A scratch upload directory with a secret file next to it, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-storage-{}", Uuid::new_v4().simple()));
+            fs::create_dir_all(base.join("uploads/docs")).unwrap();
+            fs::create_dir_all(base.join("outside")).unwrap();
+            fs::write(base.join("uploads/docs/report.txt"), "report").unwrap();
+            fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+            Self { base }
+        }
+
+        fn root(&self) -> String {
+            self.base.join("uploads").to_string_lossy().to_string()
+        }
+
+        fn canonical_root(&self) -> PathBuf {
+            self.base.join("uploads").canonicalize().unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn resolves_plain_paths_inside_the_root() {
+        let scratch = Scratch::new();
+        let root = scratch.canonical_root();
+        assert_eq!(resolve_relative(&scratch.root(), "docs/report.txt"), Some(root.join("docs/report.txt")));
+        assert_eq!(resolve_relative(&scratch.root(), "/docs/report.txt/"), Some(root.join("docs/report.txt")));
+        assert_eq!(resolve_relative(&scratch.root(), "docs/new/file.txt"), Some(root.join("docs/new/file.txt")));
+        assert_eq!(resolve_relative(&scratch.root(), "Résumé 2024/日本語.txt"), Some(root.join("Résumé 2024/日本語.txt")));
+    }
+
+    #[test]
+    fn gives_the_canonical_relative_path() {
+        let scratch = Scratch::new();
+        for (path, relative) in [
+            ("docs/report.txt", "docs/report.txt"),
+            ("/docs/report.txt", "docs/report.txt"),
+            ("docs/report.txt/", "docs/report.txt"),
+            ("//docs/", "docs"),
+            ("docs/new/file.txt", "docs/new/file.txt"),
+        ] {
+            assert_eq!(resolve(&scratch.root(), path).unwrap().relative, relative, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        let scratch = Scratch::new();
+        for path in [
+            "..",
+            "../outside/secret.txt",
+            "docs/../../outside/secret.txt",
+            "docs/..",
+            "docs/report.txt/..",
+            "./docs/report.txt",
+            ".",
+            "/..",
+            "//../outside",
+            "docs//report.txt",
+            "docs/./report.txt",
+            "docs/.",
+            "docs/report.txt/.",
+        ] {
+            assert_eq!(resolve_relative(&scratch.root(), path), None, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_and_odd_paths() {
+        let scratch = Scratch::new();
+        let secret = scratch.base.join("outside/secret.txt").to_string_lossy().to_string();
+        for path in [
+            "",
+            "/",
+            "///",
+            "..\\outside\\secret.txt",
+            "docs\\..\\..\\outside",
+            "C:\\Windows\\win.ini",
+            "docs/report.txt\0.png",
+            "\0",
+        ] {
+            assert_eq!(resolve_relative(&scratch.root(), path), None, "{:?}", path);
+        }
+        // A leading slash only means "from the root", never the real filesystem root
+        let absolute = resolve_relative(&scratch.root(), &secret).unwrap();
+        assert!(absolute.starts_with(scratch.canonical_root()));
+        assert!(!absolute.exists());
+    }
+
+    #[test]
+    fn encoded_traversal_stays_literal() {
+        let scratch = Scratch::new();
+        let root = scratch.canonical_root();
+        // Paths arrive decoded; whatever encoding is left is just part of a name
+        for path in ["%2e%2e/outside", "..%2fsecret", "%2e%2e%5csecret", "..../outside", "...", "docs/..."] {
+            let resolved = resolve_relative(&scratch.root(), path).unwrap();
+            assert!(resolved.starts_with(&root), "{:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_the_root() {
+        use std::os::unix::fs::symlink;
+        let scratch = Scratch::new();
+        let uploads = scratch.base.join("uploads");
+        symlink(scratch.base.join("outside"), uploads.join("escape")).unwrap();
+        symlink(scratch.base.join("outside/secret.txt"), uploads.join("docs/secret.txt")).unwrap();
+        symlink("/", uploads.join("slash")).unwrap();
+        symlink(scratch.base.join("nowhere"), uploads.join("dangling")).unwrap();
+        symlink("..", uploads.join("docs/up")).unwrap();
+
+        for path in [
+            "escape",
+            "escape/secret.txt",
+            "escape/new.txt",
+            "docs/secret.txt",
+            "slash/etc/passwd",
+            "dangling",
+            "dangling/file.txt",
+            "docs/up/../outside/secret.txt",
+            "docs/up/docs/up/escape",
+        ] {
+            assert_eq!(resolve_relative(&scratch.root(), path), None, "{:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_that_stay_inside() {
+        use std::os::unix::fs::symlink;
+        let scratch = Scratch::new();
+        let uploads = scratch.base.join("uploads");
+        symlink(uploads.join("docs"), uploads.join("alias")).unwrap();
+        symlink("..", uploads.join("docs/up")).unwrap();
+        let root = scratch.canonical_root();
+        assert_eq!(resolve_relative(&scratch.root(), "alias/report.txt"), Some(root.join("docs/report.txt")));
+        assert_eq!(resolve_relative(&scratch.root(), "docs/up/docs/report.txt"), Some(root.join("docs/report.txt")));
+        // Access checks see where the link leads, not what it is called
+        assert_eq!(resolve(&scratch.root(), "alias/report.txt").unwrap().relative, "docs/report.txt");
+        assert!(resolve(&scratch.root(), "docs/up").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlinked_root_is_fine() {
+        use std::os::unix::fs::symlink;
+        let scratch = Scratch::new();
+        let link = scratch.base.join("uploads-link");
+        symlink(scratch.base.join("uploads"), &link).unwrap();
+        let resolved = resolve_relative(&link.to_string_lossy(), "docs/report.txt").unwrap();
+        assert_eq!(resolved, scratch.canonical_root().join("docs/report.txt"));
+    }
+
+    #[test]
+    fn missing_root_resolves_nothing() {
+        let scratch = Scratch::new();
+        let missing = scratch.base.join("missing").to_string_lossy().to_string();
+        assert_eq!(resolve_relative(&missing, "file.txt"), None);
+        assert_eq!(resolve_folder(&missing, ""), None);
+    }
+
+    #[test]
+    fn empty_folder_is_the_root() {
+        let scratch = Scratch::new();
+        assert_eq!(resolve_folder(&scratch.root(), ""), Some(scratch.canonical_root()));
+        assert_eq!(resolve_folder(&scratch.root(), "/"), Some(scratch.canonical_root()));
+        assert_eq!(resolve_folder(&scratch.root(), "docs"), Some(scratch.canonical_root().join("docs")));
+        assert_eq!(resolve_folder(&scratch.root(), "../outside"), None);
+    }
+
+    #[test]
+    fn sanitize_keeps_unicode_and_spaces() {
+        assert_eq!(sanitize_filename("Résumé 2024.pdf"), "Résumé 2024.pdf");
+        assert_eq!(sanitize_filename("日本語のファイル.txt"), "日本語のファイル.txt");
+        assert_eq!(sanitize_filename("Привет мир.docx"), "Привет мир.docx");
+        assert_eq!(sanitize_filename("notes (final) [v2] & more+1, 'draft'!.md"), "notes (final) [v2] & more+1, 'draft'!.md");
+        assert_eq!(sanitize_filename("café.txt"), "café.txt");
+        assert_eq!(sanitize_filename("🦀 crab.png"), "🦀 crab.png");
+    }
+
+    #[test]
+    fn sanitize_strips_separators_and_traversal() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "etcpasswd");
+        assert_eq!(sanitize_filename("..\\..\\windows\\win.ini"), "windowswin.ini");
+        assert_eq!(sanitize_filename("/absolute/path.txt"), "absolutepath.txt");
+        assert_eq!(sanitize_filename(".."), "");
+        assert_eq!(sanitize_filename("."), "");
+        assert_eq!(sanitize_filename("...hidden"), "hidden");
+        assert_eq!(sanitize_filename(".htaccess"), "htaccess");
+        assert_eq!(sanitize_filename("C:evil.txt"), "Cevil.txt");
+        assert_eq!(sanitize_filename("what?*<>|\".txt"), "what.txt");
+        assert_eq!(sanitize_filename("100% #1.txt"), "100 1.txt");
+    }
+
+    #[test]
+    fn sanitize_drops_control_and_invisible_characters() {
+        assert_eq!(sanitize_filename("report\0.txt"), "report.txt");
+        assert_eq!(sanitize_filename("a\u{7}b\u{1b}[31mc.txt"), "ab[31mc.txt");
+        // Right-to-left override would show "invoice\u{202E}fdp.exe" as "invoiceexe.pdf"
+        assert_eq!(sanitize_filename("invoice\u{202E}fdp.exe"), "invoicefdp.exe");
+        assert_eq!(sanitize_filename("zero\u{200B}width\u{FEFF}.txt"), "zerowidth.txt");
+        assert_eq!(sanitize_filename("soft\u{AD}hyphen.txt"), "softhyphen.txt");
+        assert_eq!(sanitize_filename("\u{2066}isolate\u{2069}.txt"), "isolate.txt");
+    }
+
+    #[test]
+    fn sanitize_tidies_whitespace_and_dots() {
+        assert_eq!(sanitize_filename("  spaced   out  .txt  "), "spaced out .txt");
+        assert_eq!(sanitize_filename("line\nbreak\ttab.txt"), "line break tab.txt");
+        assert_eq!(sanitize_filename("no\u{A0}break.txt"), "no break.txt");
+        assert_eq!(sanitize_filename("trailing..."), "trailing");
+        assert_eq!(sanitize_filename("trailing. . ."), "trailing");
+        assert_eq!(sanitize_filename(" . . "), "");
+        assert_eq!(sanitize_filename("\u{202E}\u{200B}"), "");
+    }
+
+    #[test]
+    fn sanitize_limits_length_and_keeps_the_extension() {
+        let long = format!("{}.pdf", "a".repeat(500));
+        let sanitized = sanitize_filename(&long);
+        assert_eq!(sanitized.len(), MAX_FILENAME_BYTES);
+        assert!(sanitized.ends_with(".pdf"));
+
+        // Never cut a multi-byte character in half
+        let sanitized = sanitize_filename(&format!("{}.txt", "é".repeat(300)));
+        assert!(sanitized.len() <= MAX_FILENAME_BYTES);
+        assert!(sanitized.ends_with("é.txt"));
+
+        // Something after the last dot that is too long to be an extension is just cut
+        let sanitized = sanitize_filename(&format!("x.{}", "b".repeat(300)));
+        assert_eq!(sanitized.len(), MAX_FILENAME_BYTES);
+    }
+
+    #[test]
+    fn sanitized_names_always_resolve_inside_the_root() {
+        let scratch = Scratch::new();
+        let root = scratch.canonical_root();
+        for name in ["../../etc/passwd", "..", "/", "\\..\\", "a/../../b", "\0", "%2e%2e", "… ..", "docs"] {
+            let sanitized = sanitize_filename(name);
+            if sanitized.is_empty() {
+                continue;
+            }
+            let resolved = resolve_relative(&scratch.root(), &sanitized).unwrap();
+            assert_eq!(resolved.parent(), Some(root.as_path()), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn sanitize_folder_keeps_structure_but_drops_traversal() {
+        assert_eq!(sanitize_folder("photos/2024 trip"), "photos/2024 trip");
+        assert_eq!(sanitize_folder("../../etc"), "etc");
+        assert_eq!(sanitize_folder("/a//./b/../c/"), "a/b/c");
+        assert_eq!(sanitize_folder("Ünïcödé/名前"), "Ünïcödé/名前");
+        // Backslashes go, leaving one harmless name rather than a way up
+        assert_eq!(sanitize_folder("a\\..\\b"), "a..b");
+        assert_eq!(sanitize_folder("..."), "");
+    }
+
+    #[test]
+    fn json_stores_round_trip_atomically() {
+        let scratch = Scratch::new();
+        let path = scratch.base.join("data/store.json");
+        let empty: Vec<String> = read_json_or_default(&path).unwrap();
+        assert!(empty.is_empty());
+
+        write_json_atomic(&path, &vec!["one".to_string(), "two".to_string()]).unwrap();
+        let loaded: Vec<String> = read_json_or_default(&path).unwrap();
+        assert_eq!(loaded, ["one", "two"]);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // A damaged store is an error, not silently an empty one
+        fs::write(&path, "{ not json").unwrap();
+        let damaged: io::Result<Vec<String>> = read_json_or_default(&path);
+        assert_eq!(damaged.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::auth::canonical_username;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Failures allowed before each further attempt has to wait
+const FREE_FAILURES: u32 = 3;
+// The wait doubles with every failure past FREE_FAILURES, starting here...
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+// ...up to this much
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// This many consecutive failures lock the IP or username out entirely for LOCKOUT
+const LOCKOUT_AFTER: u32 = 10;
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+// Attempts per IP per window, successful or not
+const MAX_ATTEMPTS_PER_WINDOW: u32 = 20;
+const WINDOW: Duration = Duration::from_secs(60);
+// Quiet keys are forgotten after this long
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+// Upper bound on tracked keys, so rotating usernames can't grow the map forever
+const MAX_TRACKED: usize = 10_000;
+
+struct Attempts {
+    failures: u32,
+    blocked_until: Option<Instant>,
+    window_start: Instant,
+    window_attempts: u32,
+    last_seen: Instant,
+}
+
+impl Attempts {
+    fn new(now: Instant) -> Self {
+        Self {
+            failures: 0,
+            blocked_until: None,
+            window_start: now,
+            window_attempts: 0,
+            last_seen: now,
+        }
+    }
+}
+
+// Why a login attempt was turned away, and for how long
+pub struct Blocked {
+    pub retry_after: Duration,
+    pub locked_out: bool,
+}
+
+// Brute-force protection for /login. Failures are counted per client IP and per
+// username: after a few of them each new attempt has to wait exponentially longer, and a
+// long run of failures locks the key out for a while. Each IP is also limited to a fixed
+// number of attempts per minute.
+#[derive(Default)]
+pub struct LoginThrottle {
+    entries: Mutex<HashMap<String, Attempts>>,
+}
+
+impl LoginThrottle {
+    // Call before checking credentials. Counts the attempt against the IP's rate limit.
+    pub fn check(&self, ip: &str, username: &str) -> Result<(), Blocked> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        let mut blocked: Option<Blocked> = None;
+        for key in [ip_key(ip), user_key(username)] {
+            let Some(attempts) = entries.get(&key) else {
+                continue;
+            };
+            if let Some(until) = attempts.blocked_until.filter(|until| *until > now) {
+                let retry_after = until - now;
+                if blocked.as_ref().is_none_or(|b| retry_after > b.retry_after) {
+                    blocked = Some(Blocked {
+                        retry_after,
+                        locked_out: attempts.failures >= LOCKOUT_AFTER,
+                    });
+                }
+            }
+        }
+        if let Some(blocked) = blocked {
+            return Err(blocked);
+        }
+
+        let attempts = entries.entry(ip_key(ip)).or_insert_with(|| Attempts::new(now));
+        if now.duration_since(attempts.window_start) >= WINDOW {
+            attempts.window_start = now;
+            attempts.window_attempts = 0;
+        }
+        attempts.window_attempts += 1;
+        attempts.last_seen = now;
+        if attempts.window_attempts > MAX_ATTEMPTS_PER_WINDOW {
+            return Err(Blocked {
+                retry_after: WINDOW - now.duration_since(attempts.window_start),
+                locked_out: false,
+            });
+        }
+        Ok(())
+    }
+
+    // Record a wrong password. Returns how long the IP or username now has to wait.
+    pub fn failed(&self, ip: &str, username: &str) -> Duration {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_TRACKED {
+            entries.retain(|_, attempts| !is_stale(attempts, now));
+        }
+
+        let mut wait = Duration::ZERO;
+        for key in [ip_key(ip), user_key(username)] {
+            if entries.len() >= MAX_TRACKED && !entries.contains_key(&key) {
+                continue;
+            }
+            let attempts = entries.entry(key).or_insert_with(|| Attempts::new(now));
+            attempts.failures += 1;
+            attempts.last_seen = now;
+            let delay = backoff(attempts.failures);
+            if !delay.is_zero() {
+                attempts.blocked_until = Some(now + delay);
+            }
+            wait = wait.max(delay);
+        }
+        wait
+    }
+
+    // A successful login clears the failures for both the IP and the username
+    pub fn succeeded(&self, ip: &str, username: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(attempts) = entries.get_mut(&ip_key(ip)) {
+            attempts.failures = 0;
+            attempts.blocked_until = None;
+        }
+        entries.remove(&user_key(username));
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    if failures >= LOCKOUT_AFTER {
+        LOCKOUT
+    } else if failures < FREE_FAILURES {
+        Duration::ZERO
+    } else {
+        BASE_BACKOFF.saturating_mul(1 << (failures - FREE_FAILURES).min(16)).min(MAX_BACKOFF)
+    }
+}
+
+fn is_stale(attempts: &Attempts, now: Instant) -> bool {
+    now.duration_since(attempts.last_seen) >= FORGET_AFTER
+        && attempts.blocked_until.is_none_or(|until| until <= now)
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+// Keyed on the canonical name, so changing the case of a username doesn't start afresh
+fn user_key(username: &str) -> String {
+    format!("user:{}", canonical_username(username).chars().take(64).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: &str = "192.0.2.1";
+
+    // Move every record's clock back, as if `elapsed` had passed. Blocks that would be over
+    // are dropped rather than moved into the past, which might be before the machine booted.
+    fn wait(throttle: &LoginThrottle, elapsed: Duration) {
+        let now = Instant::now();
+        for attempts in throttle.entries.lock().unwrap().values_mut() {
+            attempts.window_start = attempts.window_start.checked_sub(elapsed.min(WINDOW)).unwrap();
+            attempts.blocked_until = attempts.blocked_until.filter(|until| *until > now + elapsed).map(|until| until - elapsed);
+        }
+    }
+
+    #[test]
+    fn the_wait_doubles_after_the_free_failures() {
+        assert_eq!(backoff(1), Duration::ZERO);
+        assert_eq!(backoff(FREE_FAILURES - 1), Duration::ZERO);
+        assert_eq!(backoff(FREE_FAILURES), BASE_BACKOFF);
+        assert_eq!(backoff(FREE_FAILURES + 1), BASE_BACKOFF * 2);
+        assert_eq!(backoff(FREE_FAILURES + 2), BASE_BACKOFF * 4);
+        assert_eq!(backoff(LOCKOUT_AFTER - 1), MAX_BACKOFF);
+        assert_eq!(backoff(LOCKOUT_AFTER), LOCKOUT);
+        assert_eq!(backoff(u32::MAX), LOCKOUT);
+    }
+
+    #[test]
+    fn failures_block_the_username_from_any_ip() {
+        let throttle = LoginThrottle::default();
+        for _ in 0..FREE_FAILURES - 1 {
+            assert!(throttle.check(IP, "alice").is_ok());
+            assert_eq!(throttle.failed(IP, "alice"), Duration::ZERO);
+        }
+        assert!(throttle.check(IP, "alice").is_ok());
+        assert_eq!(throttle.failed(IP, "alice"), BASE_BACKOFF);
+
+        let blocked = throttle.check("198.51.100.7", "alice").err().unwrap();
+        assert!(!blocked.locked_out);
+        assert!(blocked.retry_after <= BASE_BACKOFF);
+        // Changing the case of the name doesn't get around it
+        assert!(throttle.check("198.51.100.7", " ALICE").is_err());
+        assert!(throttle.check("198.51.100.7", "bob").is_ok());
+
+        wait(&throttle, BASE_BACKOFF);
+        assert!(throttle.check(IP, "alice").is_ok());
+    }
+
+    #[test]
+    fn a_long_run_of_failures_locks_the_account_out() {
+        let throttle = LoginThrottle::default();
+        for _ in 0..LOCKOUT_AFTER {
+            wait(&throttle, MAX_BACKOFF);
+            assert!(throttle.check(IP, "alice").is_ok());
+            throttle.failed(IP, "alice");
+        }
+        let blocked = throttle.check(IP, "alice").err().unwrap();
+        assert!(blocked.locked_out);
+        assert!(blocked.retry_after > MAX_BACKOFF);
+
+        wait(&throttle, LOCKOUT);
+        assert!(throttle.check(IP, "alice").is_ok());
+    }
+
+    #[test]
+    fn success_clears_the_failures() {
+        let throttle = LoginThrottle::default();
+        for _ in 0..FREE_FAILURES {
+            throttle.failed(IP, "alice");
+        }
+        assert!(throttle.check(IP, "alice").is_err());
+        throttle.succeeded(IP, "alice");
+        assert!(throttle.check(IP, "alice").is_ok());
+        assert_eq!(throttle.failed(IP, "alice"), Duration::ZERO);
+    }
+
+    #[test]
+    fn each_ip_gets_a_fixed_number_of_attempts_per_window() {
+        let throttle = LoginThrottle::default();
+        for attempt in 0..MAX_ATTEMPTS_PER_WINDOW {
+            assert!(throttle.check(IP, &format!("user{}", attempt)).is_ok());
+        }
+        let blocked = throttle.check(IP, "someone").err().unwrap();
+        assert!(!blocked.locked_out);
+        assert!(blocked.retry_after <= WINDOW);
+        assert!(throttle.check("198.51.100.7", "someone").is_ok());
+
+        // A new window starts afresh
+        wait(&throttle, WINDOW);
+        assert!(throttle.check(IP, "someone").is_ok());
+    }
+}
@@ -0,0 +1,517 @@
+use crate::storage::{read_json_or_default, write_json_atomic};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use cratr::Role;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use totp_rs::{Builder, Secret, Totp};
+use tracing::error;
+use uuid::Uuid;
+
+const ISSUER: &str = "cratr";
+const RECOVERY_CODES: usize = 10;
+
+#[derive(Debug)]
+pub enum SyncError {
+    // The name belongs to a local account, another provider or another identity
+    Conflict(String),
+    // users.json couldn't be written
+    Store(io::Error),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Conflict(message) => f.write_str(message),
+            SyncError::Store(e) => write!(f, "failed to store user settings: {}", e),
+        }
+    }
+}
+
+// Per-account settings. Accounts added by an admin also carry a password and a role, and
+// accounts from a directory carry where they came from and the role it gave them last time.
+// The account configured on the command line only ever has security settings here.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserRecord {
+    // Argon2 PHC string
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    // Provider that checks the password of an external account, e.g. "ldap"
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub role: Option<Role>,
+    // Identity at the provider an external account is tied to, e.g. an OIDC issuer and subject
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    // Base32 TOTP secret, present once enrollment has been confirmed
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    // Secret handed out by /2fa/enroll that hasn't been confirmed with a code yet
+    #[serde(default)]
+    pub pending_totp_secret: Option<String>,
+    // SHA-256 of each unused recovery code
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    // Last TOTP time step accepted, so a code can't be replayed within its window
+    #[serde(default)]
+    pub last_totp_step: Option<u64>,
+}
+
+impl UserRecord {
+    // An account added by an admin or by an external provider, rather than just the security
+    // settings of the command-line account
+    fn is_account(&self) -> bool {
+        self.password_hash.is_some() || self.source.is_some()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsersFile {
+    // Every account must use two-factor authentication
+    #[serde(default)]
+    require_2fa: bool,
+    #[serde(default)]
+    users: HashMap<String, UserRecord>,
+}
+
+// Account settings that outlive a session, stored as one JSON file and rewritten on change
+pub struct UserStore {
+    path: PathBuf,
+    state: RwLock<UsersFile>,
+}
+
+impl UserStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = read_json_or_default(&path)?;
+
+        Ok(Self {
+            path,
+            state: RwLock::new(state),
+        })
+    }
+
+    pub fn require_2fa(&self) -> bool {
+        self.state.read().unwrap().require_2fa
+    }
+
+    pub fn set_require_2fa(&self, required: bool) -> io::Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.require_2fa = required;
+        self.persist(&state)
+    }
+
+    // Accounts added through the admin API or by an external provider, sorted by name, with
+    // the provider of external ones
+    pub fn accounts(&self) -> Vec<(String, Role, Option<String>)> {
+        let state = self.state.read().unwrap();
+        let mut accounts: Vec<(String, Role, Option<String>)> = state
+            .users
+            .iter()
+            .filter(|(_, user)| user.is_account())
+            .filter_map(|(name, user)| Some((name.clone(), user.role?, user.source.clone())))
+            .collect();
+        accounts.sort_by(|a, b| a.0.cmp(&b.0));
+        accounts
+    }
+
+    pub fn role(&self, username: &str) -> Option<Role> {
+        let state = self.state.read().unwrap();
+        state.users.get(username).filter(|user| user.is_account())?.role
+    }
+
+    // Whether an admin added this account with a password of its own
+    pub fn is_local(&self, username: &str) -> bool {
+        self.state.read().unwrap().users.get(username).is_some_and(|user| user.password_hash.is_some())
+    }
+
+    // Record the role an external provider gave an account at login. None takes the account's
+    // access away without forgetting its settings. Fails for accounts added by an admin or
+    // by another provider, so one provider can't take over another's users, and for accounts
+    // tied to a different `subject` than the one logging in.
+    pub fn sync_external(&self, username: &str, source: &str, subject: Option<&str>, role: Option<Role>) -> Result<(), SyncError> {
+        let mut state = self.state.write().unwrap();
+        if role.is_none() && !state.users.contains_key(username) {
+            return Ok(());
+        }
+        let user = state.users.entry(username.to_string()).or_default();
+        if user.password_hash.is_some() {
+            return Err(SyncError::Conflict(format!("User \"{}\" is a local account", username)));
+        }
+        if let Some(other) = user.source.as_deref().filter(|other| *other != source) {
+            return Err(SyncError::Conflict(format!("User \"{}\" comes from {}", username, other)));
+        }
+        if user.subject.is_some() && subject.is_some() && user.subject.as_deref() != subject {
+            return Err(SyncError::Conflict(format!("User \"{}\" belongs to another {} identity", username, source)));
+        }
+        let subject = user.subject.clone().or(subject.map(str::to_string));
+        if user.source.as_deref() == Some(source) && user.role == role && user.subject == subject {
+            return Ok(());
+        }
+        user.source = Some(source.to_string());
+        user.subject = subject;
+        user.role = role;
+        self.persist(&state).map_err(SyncError::Store)
+    }
+
+    pub fn create_account(&self, username: &str, password: &str, role: Role) -> Result<(), String> {
+        let hash = hash_password(password)?;
+        let mut state = self.state.write().unwrap();
+        let user = state.users.entry(username.to_string()).or_default();
+        if user.is_account() {
+            return Err(format!("User \"{}\" already exists", username));
+        }
+        // Start clean rather than inheriting settings left by an earlier account of that name
+        *user = UserRecord {
+            password_hash: Some(hash),
+            role: Some(role),
+            ..Default::default()
+        };
+        self.persist(&state).map_err(|e| e.to_string())
+    }
+
+    // Change the role and/or password of an existing account. Returns false if there is none.
+    // The password of an external account can't be set here, and its role only lasts until
+    // its next login.
+    pub fn update_account(&self, username: &str, role: Option<Role>, password: Option<&str>) -> Result<bool, String> {
+        let hash = password.map(hash_password).transpose()?;
+        let mut state = self.state.write().unwrap();
+        let Some(user) = state.users.get_mut(username).filter(|user| user.is_account()) else {
+            return Ok(false);
+        };
+        if let (Some(source), Some(_)) = (&user.source, &hash) {
+            return Err(format!("The password of \"{}\" is managed by {}", username, source));
+        }
+        if let Some(role) = role {
+            user.role = Some(role);
+        }
+        if let Some(hash) = hash {
+            user.password_hash = Some(hash);
+        }
+        self.persist(&state).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    // Returns false if there was no such account
+    pub fn delete_account(&self, username: &str) -> io::Result<bool> {
+        let mut state = self.state.write().unwrap();
+        if !state.users.get(username).is_some_and(UserRecord::is_account) {
+            return Ok(false);
+        }
+        state.users.remove(username);
+        self.persist(&state)?;
+        Ok(true)
+    }
+
+    // Check the password of an account added through the admin API. Slow on purpose.
+    pub fn verify_password(&self, username: &str, password: &str) -> bool {
+        let Some(stored) = self.state.read().unwrap().users.get(username).and_then(|user| user.password_hash.clone()) else {
+            return false;
+        };
+        PasswordHash::new(&stored)
+            .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+    }
+
+    pub fn has_2fa(&self, username: &str) -> bool {
+        self.state.read().unwrap().users.get(username).is_some_and(|user| user.totp_secret.is_some())
+    }
+
+    pub fn recovery_codes_left(&self, username: &str) -> usize {
+        self.state.read().unwrap().users.get(username).map_or(0, |user| user.recovery_codes.len())
+    }
+
+    // Generate a fresh secret for `username` and keep it aside until it is confirmed.
+    // Returns the secret (base32), the otpauth URI and a PNG QR code as base64.
+    pub fn begin_enrollment(&self, username: &str) -> Result<(String, String, String), String> {
+        let secret = Secret::generate().to_base32();
+        let totp = build_totp(username, &secret)?;
+        let uri = totp.to_url().map_err(|e| e.to_string())?;
+        let qr = totp.to_qr_base64().map_err(|e| e.to_string())?;
+
+        let mut state = self.state.write().unwrap();
+        state.users.entry(username.to_string()).or_default().pending_totp_secret = Some(secret.clone());
+        self.persist(&state).map_err(|e| e.to_string())?;
+        Ok((secret, uri, qr))
+    }
+
+    // Turn on two-factor authentication once the user proves their app produces the right
+    // codes. Returns the recovery codes, which are never shown again.
+    pub fn confirm_enrollment(&self, username: &str, code: &str) -> Result<Vec<String>, String> {
+        let mut state = self.state.write().unwrap();
+        let user = state.users.get_mut(username).ok_or("Start enrollment first")?;
+        let secret = user.pending_totp_secret.clone().ok_or("Start enrollment first")?;
+        let step = build_totp(username, &secret)?
+            .check_current(code.trim())
+            .ok_or("Invalid code")?;
+
+        let codes = new_recovery_codes();
+        user.totp_secret = Some(secret);
+        user.pending_totp_secret = None;
+        user.last_totp_step = Some(step);
+        user.recovery_codes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+        self.persist(&state).map_err(|e| e.to_string())?;
+        Ok(codes)
+    }
+
+    // Check a TOTP code or use up a recovery code
+    pub fn verify(&self, username: &str, code: &str) -> bool {
+        let mut state = self.state.write().unwrap();
+        let Some(user) = state.users.get_mut(username) else {
+            return false;
+        };
+        let Some(secret) = user.totp_secret.clone() else {
+            return false;
+        };
+
+        let code = code.trim();
+        let accepted = match build_totp(username, &secret).ok().and_then(|totp| totp.check_current(code)) {
+            Some(step) if user.last_totp_step.is_none_or(|last| step > last) => {
+                user.last_totp_step = Some(step);
+                true
+            }
+            Some(_) => false,
+            None => {
+                let hash = hash_recovery_code(code);
+                let before = user.recovery_codes.len();
+                user.recovery_codes.retain(|stored| *stored != hash);
+                user.recovery_codes.len() < before
+            }
+        };
+
+        if accepted {
+            if let Err(e) = self.persist(&state) {
+                error!("Failed to store user settings: {}", e);
+            }
+        }
+        accepted
+    }
+
+    pub fn regenerate_recovery_codes(&self, username: &str) -> io::Result<Vec<String>> {
+        let mut state = self.state.write().unwrap();
+        let codes = new_recovery_codes();
+        state.users.entry(username.to_string()).or_default().recovery_codes =
+            codes.iter().map(|code| hash_recovery_code(code)).collect();
+        self.persist(&state)?;
+        Ok(codes)
+    }
+
+    pub fn disable_2fa(&self, username: &str) -> io::Result<()> {
+        let mut state = self.state.write().unwrap();
+        if let Some(user) = state.users.get_mut(username) {
+            user.totp_secret = None;
+            user.pending_totp_secret = None;
+            user.recovery_codes.clear();
+            user.last_totp_step = None;
+        }
+        self.persist(&state)
+    }
+
+    fn persist(&self, state: &UsersFile) -> io::Result<()> {
+        write_json_atomic(&self.path, state)
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+fn build_totp(username: &str, secret: &str) -> Result<Totp, String> {
+    let secret = Secret::try_from_base32(secret).map_err(|e| e.to_string())?;
+    // ':' separates issuer and account in otpauth labels
+    Builder::new()
+        .with_secret(secret)
+        .with_account_name(username.replace(':', "_"))
+        .with_issuer(Some(ISSUER))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// Ten random codes like "3f9a1-c07be"
+fn new_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let hex = Uuid::new_v4().simple().to_string();
+            format!("{}-{}", &hex[..5], &hex[5..10])
+        })
+        .collect()
+}
+
+// Recovery codes are compared without case, spaces or dashes
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A user store in a scratch directory, removed when dropped
+    struct Scratch {
+        base: PathBuf,
+        store: UserStore,
+    }
+
+    impl Scratch {
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!("cratr-users-{}", Uuid::new_v4().simple()));
+            let store = UserStore::open(base.join("users.json")).unwrap();
+            Self { base, store }
+        }
+
+        fn reopen(&self) -> UserStore {
+            UserStore::open(self.base.join("users.json")).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn external_accounts_stay_with_the_identity_that_made_them() {
+        let scratch = Scratch::new();
+        let users = &scratch.store;
+        users.sync_external("alice", "oidc", Some("issuer 1"), Some(Role::Editor)).unwrap();
+        // Someone else who took the name "alice" at the issuer
+        let error = users.sync_external("alice", "oidc", Some("issuer 2"), Some(Role::Admin)).unwrap_err();
+        assert!(matches!(&error, SyncError::Conflict(message) if message.contains("another oidc identity")));
+        assert!(scratch.reopen().sync_external("alice", "oidc", Some("issuer 2"), None).is_err());
+        assert_eq!(users.role("alice"), Some(Role::Editor));
+
+        users.sync_external("alice", "oidc", Some("issuer 1"), Some(Role::Viewer)).unwrap();
+        assert_eq!(scratch.reopen().role("alice"), Some(Role::Viewer));
+        // A new account of the same name starts untied
+        users.delete_account("alice").unwrap();
+        users.sync_external("alice", "oidc", Some("issuer 2"), Some(Role::Viewer)).unwrap();
+    }
+
+    #[test]
+    fn providers_cant_take_over_each_others_accounts() {
+        let scratch = Scratch::new();
+        let users = &scratch.store;
+        users.create_account("bob", "bob-password", Role::Editor).unwrap();
+        users.sync_external("carol", "ldap", None, Some(Role::Viewer)).unwrap();
+        assert!(users.sync_external("bob", "ldap", None, Some(Role::Admin)).unwrap_err().to_string().contains("local account"));
+        assert!(users.sync_external("carol", "oidc", Some("issuer 3"), Some(Role::Admin)).unwrap_err().to_string().contains("comes from ldap"));
+        assert_eq!(users.role("bob"), Some(Role::Editor));
+        assert_eq!(users.role("carol"), Some(Role::Viewer));
+        // Nobody is created just to be told they have no role
+        users.sync_external("dave", "ldap", None, None).unwrap();
+        assert!(users.accounts().iter().all(|(name, _, _)| name != "dave"));
+    }
+
+    // The code an authenticator app shows `steps` time steps from now
+    fn code(username: &str, secret: &str, steps: i64) -> String {
+        let totp = build_totp(username, secret).unwrap();
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        totp.generate(now.saturating_add_signed(steps * totp.step() as i64)).to_string()
+    }
+
+    // Enrolled in 2FA, with the secret and recovery codes handed out
+    fn enrolled(users: &UserStore, username: &str) -> (String, Vec<String>) {
+        let (secret, _, _) = users.begin_enrollment(username).unwrap();
+        let codes = users.confirm_enrollment(username, &code(username, &secret, 0)).unwrap();
+        (secret, codes)
+    }
+
+    #[test]
+    fn enrollment_needs_a_valid_code() {
+        let scratch = Scratch::new();
+        let users = &scratch.store;
+        assert!(users.confirm_enrollment("alice", "123456").is_err());
+
+        let (secret, uri, qr) = users.begin_enrollment("alice").unwrap();
+        assert!(uri.starts_with("otpauth://totp/") && uri.contains(&secret));
+        assert!(!qr.is_empty());
+        // Not on until confirmed, and a code from another secret doesn't confirm it
+        assert!(!users.has_2fa("alice"));
+        let (other, _, _) = users.begin_enrollment("bob").unwrap();
+        assert!(users.confirm_enrollment("alice", &code("alice", &other, 0)).is_err());
+        assert!(!users.has_2fa("alice"));
+
+        let codes = users.confirm_enrollment("alice", &code("alice", &secret, 0)).unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(users.has_2fa("alice") && scratch.reopen().has_2fa("alice"));
+        assert_eq!(scratch.reopen().recovery_codes_left("alice"), RECOVERY_CODES);
+        // The pending secret is used up
+        assert!(users.confirm_enrollment("alice", &code("alice", &secret, 1)).is_err());
+    }
+
+    #[test]
+    fn a_code_is_accepted_once_per_time_step() {
+        let scratch = Scratch::new();
+        let users = &scratch.store;
+        let (secret, _) = enrolled(users, "alice");
+
+        // The code that confirmed enrollment, and any older one, can't be replayed
+        assert!(!users.verify("alice", &code("alice", &secret, 0)));
+        assert!(!users.verify("alice", &code("alice", &secret, -1)));
+
+        let next = code("alice", &secret, 1);
+        assert!(users.verify("alice", &next));
+        assert!(!users.verify("alice", &next));
+        // Not after a restart either
+        assert!(!scratch.reopen().verify("alice", &next));
+        assert!(!users.verify("alice", "not a code"));
+        assert!(!users.verify("bob", &next));
+    }
+
+    #[test]
+    fn recovery_codes_are_hashed_and_single_use() {
+        let scratch = Scratch::new();
+        let users = &scratch.store;
+        let (_, codes) = enrolled(users, "alice");
+
+        let stored = std::fs::read_to_string(scratch.base.join("users.json")).unwrap();
+        for code in &codes {
+            assert!(!stored.contains(code.as_str()) && !stored.contains(&code.replace('-', "")));
+            assert!(stored.contains(&hash_recovery_code(code)));
+        }
+
+        // Case, spaces and the dash don't matter
+        assert!(users.verify("alice", &format!(" {} ", codes[0].to_uppercase().replace('-', ""))));
+        assert!(!users.verify("alice", &codes[0]));
+        assert_eq!(users.recovery_codes_left("alice"), RECOVERY_CODES - 1);
+        assert!(!scratch.reopen().verify("alice", &codes[0]));
+
+        // New codes replace the old ones
+        let fresh = users.regenerate_recovery_codes("alice").unwrap();
+        assert!(!users.verify("alice", &codes[1]));
+        assert!(users.verify("alice", &fresh[1]));
+        assert_eq!(users.recovery_codes_left("alice"), RECOVERY_CODES - 1);
+    }
+
+    #[test]
+    fn disabling_forgets_the_secret_and_codes() {
+        let scratch = Scratch::new();
+        let users = &scratch.store;
+        let (secret, codes) = enrolled(users, "alice");
+        users.disable_2fa("alice").unwrap();
+
+        for users in [users, &scratch.reopen()] {
+            assert!(!users.has_2fa("alice"));
+            assert_eq!(users.recovery_codes_left("alice"), 0);
+            assert!(!users.verify("alice", &code("alice", &secret, 1)));
+            assert!(!users.verify("alice", &codes[0]));
+        }
+
+        // Enrolling again starts from a new secret
+        let (again, _) = enrolled(users, "alice");
+        assert_ne!(again, secret);
+        assert!(users.verify("alice", &code("alice", &again, 1)));
+    }
+}